@@ -113,7 +113,7 @@ export function NetworkStats() {
       title: 'Difficulty',
       value: loading ? '...' : formatNumber(stats?.difficulty || 0),
       icon: <TrendingUp className="w-5 h-5" />,
-      subtitle: `Adjusts every 60 blocks`
+      subtitle: `Adjusts every block`
     },
     {
       title: 'Network Hashrate',
@@ -1,35 +1,389 @@
-//! I2P SAM client for BlackSilk node (production-ready scaffold)
-//! Provides session management, destination/key generation, and streaming/datagram support
+//! I2P SAM v3 client for BlackSilk node
+//! Session management, persistent destinations, streaming and datagram support
+//! through an I2P router's SAM bridge.
+//!
+//! Every command connection starts with `HELLO VERSION`, which settles on the
+//! highest protocol version both sides speak. A session lives as long as its
+//! control socket; streams are opened on sockets of their own, with `STREAM
+//! CONNECT` for outgoing and `STREAM ACCEPT` for incoming ones, and carry the
+//! stream's data once the bridge has confirmed them. Repliable (`DATAGRAM`)
+//! and anonymous (`RAW`) datagrams travel over the session's control socket.
 
-use std::io::{BufRead, BufReader, Write, Read};
-use std::net::{TcpStream, ToSocketAddrs};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::Path;
 use std::time::Duration;
 use rand::Rng;
 
 /// Default SAM bridge address (localhost)
 pub const DEFAULT_SAM_ADDR: &str = "127.0.0.1:7656";
 
-#[derive(Debug, Clone)]
+/// Oldest SAM version we speak
+pub const MIN_VERSION: SamVersion = SamVersion { major: 3, minor: 0 };
+
+/// Newest SAM version we speak
+pub const MAX_VERSION: SamVersion = SamVersion { major: 3, minor: 3 };
+
+/// First SAM version with `FROM_PORT`/`TO_PORT`
+pub const PORTS_VERSION: SamVersion = SamVersion { major: 3, minor: 2 };
+
+/// Signature type of generated destinations
+const SIGNATURE_TYPE: &str = "EdDSA_SHA512_Ed25519";
+
+/// Time the bridge gets to answer a command
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time allowed for commands that build tunnels first
+const TUNNEL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest datagram the bridge accepts
+pub const MAX_DATAGRAM_SIZE: usize = 31744;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SamVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl SamVersion {
+    fn parse(text: &str) -> Option<Self> {
+        let (major, minor) = text.split_once('.').unwrap_or((text, "0"));
+        Some(SamVersion { major: major.parse().ok()?, minor: minor.parse().ok()? })
+    }
+}
+
+impl std::fmt::Display for SamVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Virtual ports of a stream or datagram; 0 leaves a port unspecified.
+/// Needs SAM 3.2.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ports {
+    pub from: u16,
+    pub to: u16,
+}
+
+impl Ports {
+    /// ` FROM_PORT=.. TO_PORT=..` for a command, empty if both are unspecified
+    fn options(&self, version: SamVersion) -> io::Result<String> {
+        if *self == Ports::default() {
+            return Ok(String::new());
+        }
+        if version < PORTS_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("SAM {} has no ports, {} needed", version, PORTS_VERSION),
+            ));
+        }
+        Ok(format!(" FROM_PORT={} TO_PORT={}", self.from, self.to))
+    }
+
+    fn from_reply(reply: &Reply) -> Self {
+        let port = |key| reply.get(key).and_then(|port| port.parse().ok()).unwrap_or(0);
+        Ports { from: port("FROM_PORT"), to: port("TO_PORT") }
+    }
+}
+
+/// One reply line: a topic and kind such as `STREAM STATUS`, then `KEY=VALUE`
+/// fields whose values may be quoted
+#[derive(Debug)]
+struct Reply {
+    head: String,
+    fields: HashMap<String, String>,
+}
+
+impl Reply {
+    fn parse(line: &str) -> Reply {
+        let mut head = Vec::new();
+        let mut fields = HashMap::new();
+        for token in tokenize(line) {
+            match token.split_once('=') {
+                Some((key, value)) => {
+                    fields.insert(key.to_string(), value.to_string());
+                }
+                None if fields.is_empty() => head.push(token),
+                None => {}
+            }
+        }
+        Reply { head: head.join(" "), fields }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(String::as_str)
+    }
+
+    /// The reply if it is `head` with `RESULT=OK`, otherwise the error it reports
+    fn expect(self, head: &str) -> io::Result<Reply> {
+        if self.head != head {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected {} from SAM bridge, got {}", head, self.head)));
+        }
+        match self.get("RESULT") {
+            Some("OK") => Ok(self),
+            result => Err(result_error(head, result.unwrap_or("NONE"), self.get("MESSAGE"))),
+        }
+    }
+}
+
+/// Split a reply line on spaces outside double quotes, unquoting values
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => token.extend(chars.next()),
+            ' ' if !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Error for a failed SAM command
+fn result_error(head: &str, result: &str, message: Option<&str>) -> io::Error {
+    let kind = match result {
+        "CANT_REACH_PEER" | "PEER_NOT_FOUND" => io::ErrorKind::ConnectionRefused,
+        "TIMEOUT" => io::ErrorKind::TimedOut,
+        "INVALID_KEY" | "INVALID_ID" => io::ErrorKind::InvalidInput,
+        "DUPLICATED_ID" | "DUPLICATED_DEST" => io::ErrorKind::AlreadyExists,
+        "KEY_NOT_FOUND" => io::ErrorKind::NotFound,
+        "NOVERSION" => io::ErrorKind::Unsupported,
+        _ => io::ErrorKind::Other,
+    };
+    match message {
+        Some(message) => io::Error::new(kind, format!("SAM {} failed: {} ({})", head, result, message)),
+        None => io::Error::new(kind, format!("SAM {} failed: {}", head, result)),
+    }
+}
+
+/// Read one SAM line without buffering past it, so whatever follows on
+/// a stream connection is left for the caller
+fn read_reply(stream: &mut impl Read) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    while byte[0] != b'\n' {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "SAM bridge closed the connection"));
+        }
+        line.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+/// Send `cmd` and read the reply, which must be `head` with `RESULT=OK`
+fn command(stream: &mut TcpStream, cmd: &str, head: &str) -> io::Result<Reply> {
+    stream.write_all(format!("{}\n", cmd).as_bytes())?;
+    Reply::parse(&read_reply(stream)?).expect(head)
+}
+
+fn resolve(sam_addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+    sam_addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "SAM bridge address did not resolve"))
+}
+
+/// Open a connection to the SAM bridge and agree on the protocol version
+fn sam_hello(sam_addr: &SocketAddr) -> io::Result<(TcpStream, SamVersion)> {
+    let mut stream = TcpStream::connect_timeout(sam_addr, COMMAND_TIMEOUT)?;
+    stream.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+    stream.set_write_timeout(Some(COMMAND_TIMEOUT))?;
+    let hello = format!("HELLO VERSION MIN={} MAX={}", MIN_VERSION, MAX_VERSION);
+    let reply = command(&mut stream, &hello, "HELLO REPLY")?;
+    let version = reply
+        .get("VERSION")
+        .and_then(SamVersion::parse)
+        .filter(|version| (MIN_VERSION..=MAX_VERSION).contains(version))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, format!("SAM bridge offered version {:?}", reply.get("VERSION"))))?;
+    Ok((stream, version))
+}
+
+/// Look up the destination of `name`: a `.b32.i2p` address or a host name
+/// from the router's address book
+pub fn naming_lookup(sam_addr: impl ToSocketAddrs, name: &str) -> io::Result<String> {
+    let (mut stream, _) = sam_hello(&resolve(sam_addr)?)?;
+    lookup(&mut stream, name)
+}
+
+fn lookup(stream: &mut TcpStream, name: &str) -> io::Result<String> {
+    let reply = command(stream, &format!("NAMING LOOKUP NAME={}", name), "NAMING REPLY")?;
+    reply
+        .get("VALUE")
+        .map(str::to_string)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "SAM naming reply without a value"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct I2pDestination {
     pub public: String,
     pub private: String,
 }
 
+impl I2pDestination {
+    /// New destination keys from the bridge
+    pub fn generate(sam_addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let (mut stream, _) = sam_hello(&resolve(sam_addr)?)?;
+        stream.write_all(format!("DEST GENERATE SIGNATURE_TYPE={}\n", SIGNATURE_TYPE).as_bytes())?;
+        let reply = Reply::parse(&read_reply(&mut stream)?);
+        // `DEST REPLY` only has a `RESULT` when generating failed
+        if reply.head != "DEST REPLY" || reply.get("RESULT").is_some() {
+            return Err(result_error("DEST REPLY", reply.get("RESULT").unwrap_or(&reply.head), reply.get("MESSAGE")));
+        }
+        match (reply.get("PUB"), reply.get("PRIV")) {
+            (Some(public), Some(private)) => Ok(I2pDestination { public: public.to_string(), private: private.to_string() }),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "SAM destination reply without keys")),
+        }
+    }
+
+    /// Destination saved by `save`: the public key, then the private key
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let mut lines = text.lines().map(str::trim);
+        match (lines.next(), lines.next()) {
+            (Some(public), Some(private)) if !public.is_empty() && !private.is_empty() => {
+                Ok(I2pDestination { public: public.to_string(), private: private.to_string() })
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not an I2P destination", path.display()))),
+        }
+    }
+
+    /// Write the keys to `path`, readable only by the owner
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        write_secret(&tmp_path, format!("{}\n{}\n", self.public, self.private).as_bytes())?;
+        std::fs::rename(&tmp_path, path)
+    }
+
+    /// The destination kept at `path`, generating and saving one on first use
+    pub fn load_or_generate(path: &Path, sam_addr: impl ToSocketAddrs) -> io::Result<Self> {
+        if path.exists() {
+            return Self::load(path);
+        }
+        let dest = Self::generate(sam_addr)?;
+        dest.save(path)?;
+        Ok(dest)
+    }
+}
+
+#[cfg(unix)]
+fn write_secret(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_secret(path: &Path, contents: &[u8]) -> io::Result<()> {
+    std::fs::write(path, contents)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionStyle {
+    Stream,
+    /// Repliable, signed datagrams
+    Datagram,
+    /// Anonymous datagrams
+    Raw,
+}
+
+impl SessionStyle {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SessionStyle::Stream => "STREAM",
+            SessionStyle::Datagram => "DATAGRAM",
+            SessionStyle::Raw => "RAW",
+        }
+    }
+}
+
+/// A datagram received on a `DATAGRAM` or `RAW` session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    /// Sender's destination; raw datagrams do not say
+    pub source: Option<String>,
+    pub ports: Ports,
+    /// I2CP protocol of a raw datagram
+    pub protocol: Option<u8>,
+    pub data: Vec<u8>,
+}
+
+/// An I2P stream: a socket to the bridge that carries the stream's data
+#[derive(Debug)]
+pub struct I2pStream {
+    /// Destination at the other end
+    pub peer: String,
+    pub ports: Ports,
+    stream: TcpStream,
+}
+
+impl I2pStream {
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// The socket, e.g. to hand it to an async runtime
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+impl Read for I2pStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for I2pStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
 #[derive(Debug)]
 pub struct SamSession {
     pub nickname: String,
     pub dest: I2pDestination,
-    pub sam_addr: String,
+    pub sam_addr: SocketAddr,
+    /// Version agreed with the bridge
+    pub version: SamVersion,
+    pub style: SessionStyle,
     stream: TcpStream,
 }
 
 impl SamSession {
-    /// Connect to SAM bridge and create a session (STREAM or DATAGRAM)
-    pub fn connect<A: ToSocketAddrs>(sam_addr: A, session_nick: &str, style: &str) -> std::io::Result<Self> {
-        let mut stream = TcpStream::connect(sam_addr)?;
-        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
-        stream.set_write_timeout(Some(Duration::from_secs(10)))?;
-        let mut reader = BufReader::new(stream.try_clone()?);
+    /// Connect to SAM bridge and create a session with a new, throwaway
+    /// destination
+    pub fn connect<A: ToSocketAddrs>(sam_addr: A, session_nick: &str, style: SessionStyle) -> io::Result<Self> {
+        Self::create(sam_addr, session_nick, style, None)
+    }
+
+    /// Create a session on `destination`, which keeps the same I2P address
+    /// across runs, or on a throwaway one if `None`
+    pub fn create<A: ToSocketAddrs>(
+        sam_addr: A,
+        session_nick: &str,
+        style: SessionStyle,
+        destination: Option<&I2pDestination>,
+    ) -> io::Result<Self> {
+        let sam_addr = resolve(sam_addr)?;
+        let (mut stream, version) = sam_hello(&sam_addr)?;
 
         // Generate a random nickname if not provided
         let nickname = if session_nick.is_empty() {
@@ -39,57 +393,121 @@ impl SamSession {
             session_nick.to_string()
         };
 
-        // Create session
-        let cmd = format!("SESSION CREATE STYLE={} ID={} DESTINATION=TRANSIENT\n", style, nickname);
-        stream.write_all(cmd.as_bytes())?;
-        stream.flush()?;
-        let mut response = String::new();
-        reader.read_line(&mut response)?;
-        if !response.contains("RESULT=OK") {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("SAM session failed: {}", response)));
-        }
-
-        // Extract destination keys
-        let mut pubkey = String::new();
-        let mut privkey = String::new();
-        for part in response.split_whitespace() {
-            if part.starts_with("DESTINATION=") {
-                let dest = part.trim_start_matches("DESTINATION=");
-                pubkey = dest.to_string();
-                privkey = dest.to_string(); // For TRANSIENT, both are the same; for PERSISTENT, parse keys
-            }
+        let keys = match destination {
+            Some(dest) => dest.private.clone(),
+            None => format!("TRANSIENT SIGNATURE_TYPE={}", SIGNATURE_TYPE),
+        };
+        let cmd = format!("SESSION CREATE STYLE={} ID={} DESTINATION={}", style.as_str(), nickname, keys);
+        // The session's tunnels are built before the bridge answers
+        stream.set_read_timeout(Some(TUNNEL_TIMEOUT))?;
+        let reply = command(&mut stream, &cmd, "SESSION STATUS")?;
+        stream.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+        let private = reply
+            .get("DESTINATION")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "SAM session reply without a destination"))?
+            .to_string();
+        // The reply only has the private keys; `ME` is the session's own destination
+        let public = match destination {
+            Some(dest) => dest.public.clone(),
+            None => lookup(&mut stream, "ME")?,
+        };
+        // Datagrams arrive on this socket whenever they come
+        stream.set_read_timeout(None)?;
+        let dest = I2pDestination { public, private };
+        Ok(Self { nickname, dest, sam_addr, version, style, stream })
+    }
+
+    /// Open an I2P streaming connection to `destination` (base64 or
+    /// `.b32.i2p`) through this session
+    pub fn stream_connect(&self, destination: &str) -> io::Result<I2pStream> {
+        self.stream_connect_ports(destination, Ports::default())
+    }
+
+    /// `stream_connect` from and to the given virtual ports
+    pub fn stream_connect_ports(&self, destination: &str, ports: Ports) -> io::Result<I2pStream> {
+        let (mut stream, _) = sam_hello(&self.sam_addr)?;
+        let cmd = format!(
+            "STREAM CONNECT ID={} DESTINATION={} SILENT=false{}",
+            self.nickname,
+            destination,
+            ports.options(self.version)?
+        );
+        // Building tunnels to a destination can take a while
+        stream.set_read_timeout(Some(TUNNEL_TIMEOUT))?;
+        command(&mut stream, &cmd, "STREAM STATUS")?;
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        Ok(I2pStream { peer: destination.to_string(), ports, stream })
+    }
+
+    /// Wait for the next incoming stream to this session's destination
+    pub fn stream_accept(&self) -> io::Result<I2pStream> {
+        let (mut stream, _) = sam_hello(&self.sam_addr)?;
+        command(&mut stream, &format!("STREAM ACCEPT ID={} SILENT=false", self.nickname), "STREAM STATUS")?;
+        // The bridge announces the peer once one connects, which may be never
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        let announcement = read_reply(&mut stream)?;
+        let reply = Reply::parse(&announcement);
+        if reply.head.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "SAM stream without a peer destination"));
         }
-        let dest = I2pDestination { public: pubkey, private: privkey };
-        Ok(Self { nickname: nickname.clone(), dest, sam_addr: nickname.clone(), stream })
+        Ok(I2pStream { peer: reply.head.clone(), ports: Ports::from_reply(&reply), stream })
+    }
+
+    /// Look up the destination of `name`
+    pub fn naming_lookup(&self, name: &str) -> io::Result<String> {
+        naming_lookup(self.sam_addr, name)
     }
 
-    /// Send a datagram to a destination
-    pub fn send_datagram(&mut self, dest: &str, data: &[u8]) -> std::io::Result<()> {
-        let cmd = format!("DATAGRAM SEND DESTINATION={} SIZE={}\n", dest, data.len());
+    /// Send a datagram to a destination from a `DATAGRAM` or `RAW` session
+    pub fn send_datagram(&mut self, dest: &str, ports: Ports, data: &[u8]) -> io::Result<()> {
+        if self.style == SessionStyle::Stream {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "datagrams need a DATAGRAM or RAW session"));
+        }
+        if data.len() > MAX_DATAGRAM_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("datagram of {} bytes is too large", data.len())));
+        }
+        let cmd = format!(
+            "{} SEND DESTINATION={} SIZE={}{}\n",
+            self.style.as_str(),
+            dest,
+            data.len(),
+            ports.options(self.version)?
+        );
         self.stream.write_all(cmd.as_bytes())?;
         self.stream.write_all(data)?;
         self.stream.flush()
     }
 
-    /// Receive a datagram (blocking)
-    pub fn recv_datagram(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.stream.read(buf)
+    /// Receive the next datagram (blocking)
+    pub fn recv_datagram(&mut self) -> io::Result<Datagram> {
+        let reply = Reply::parse(&read_reply(&mut self.stream)?);
+        let expected = format!("{} RECEIVED", self.style.as_str());
+        if reply.head != expected {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected {} from SAM bridge, got {}", expected, reply.head)));
+        }
+        let size: usize = reply
+            .get("SIZE")
+            .and_then(|size| size.parse().ok())
+            .filter(|size| *size <= MAX_DATAGRAM_SIZE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "SAM datagram with a bad size"))?;
+        let mut data = vec![0u8; size];
+        self.stream.read_exact(&mut data)?;
+        Ok(Datagram {
+            source: reply.get("DESTINATION").map(str::to_string),
+            ports: Ports::from_reply(&reply),
+            protocol: reply.get("PROTOCOL").and_then(|protocol| protocol.parse().ok()),
+            data,
+        })
     }
 
     /// Send a streaming message (I2P streaming)
-    pub fn send_stream(&mut self, dest: &str, data: &[u8]) -> std::io::Result<()> {
-        // For streaming, open a new connection to SAM STREAM FORWARD
-        let mut stream = TcpStream::connect(&self.sam_addr)?;
-        let cmd = format!("STREAM CONNECT ID={} DESTINATION={} SILENT=false\n", self.nickname, dest);
-        stream.write_all(cmd.as_bytes())?;
+    pub fn send_stream(&self, dest: &str, data: &[u8]) -> io::Result<()> {
+        let mut stream = self.stream_connect(dest)?;
         stream.write_all(data)?;
         stream.flush()
     }
-
-    /// Receive from stream (blocking)
-    pub fn recv_stream(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.stream.read(buf)
-    }
 }
 
 /// High-level API for node integration
@@ -98,7 +516,7 @@ pub struct I2pClient {
 }
 
 impl I2pClient {
-    pub fn new(sam_addr: &str, session_nick: &str, style: &str) -> std::io::Result<Self> {
+    pub fn new(sam_addr: &str, session_nick: &str, style: SessionStyle) -> io::Result<Self> {
         let session = SamSession::connect(sam_addr, session_nick, style)?;
         Ok(Self { session })
     }
@@ -107,11 +525,284 @@ impl I2pClient {
         &self.session.dest
     }
 
-    pub fn send_to(&mut self, dest: &str, data: &[u8]) -> std::io::Result<()> {
-        self.session.send_datagram(dest, data)
+    pub fn send_to(&mut self, dest: &str, data: &[u8]) -> io::Result<()> {
+        self.session.send_datagram(dest, Ports::default(), data)
+    }
+
+    pub fn receive(&mut self) -> io::Result<Datagram> {
+        self.session.recv_datagram()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::net::{Shutdown, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    /// Session the fake bridge runs; keys are `priv-<n>` and `pub-<n>`
+    struct FakeSession {
+        public: String,
+        control: TcpStream,
+    }
+
+    #[derive(Default)]
+    struct Bridge {
+        sessions: HashMap<String, FakeSession>,
+        /// `STREAM ACCEPT` sockets waiting for a peer, by session ID
+        acceptors: HashMap<String, VecDeque<TcpStream>>,
+        generated: u32,
+    }
+
+    impl Bridge {
+        fn generate(&mut self) -> (String, String) {
+            self.generated += 1;
+            (format!("pub-{}", self.generated), format!("priv-{}", self.generated))
+        }
+
+        fn session_of(&self, public: &str) -> Option<String> {
+            self.sessions.iter().find(|(_, s)| s.public == public).map(|(id, _)| id.clone())
+        }
+    }
+
+    /// Copy bytes both ways between two sockets until either side closes
+    fn pipe(a: TcpStream, b: TcpStream) {
+        let (mut a2, mut b2) = (a.try_clone().unwrap(), b.try_clone().unwrap());
+        let (mut a, mut b) = (a, b);
+        std::thread::spawn(move || {
+            let _ = io::copy(&mut a2, &mut b2);
+            let _ = b2.shutdown(Shutdown::Both);
+        });
+        std::thread::spawn(move || {
+            let _ = io::copy(&mut b, &mut a);
+            let _ = a.shutdown(Shutdown::Both);
+        });
+    }
+
+    /// In-process SAM bridge speaking versions up to `max_version`. The
+    /// address book knows `<session ID>.i2p` for every running session.
+    fn fake_bridge(max_version: &str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let max_version = SamVersion::parse(max_version).unwrap();
+        let bridge = Arc::new(Mutex::new(Bridge::default()));
+        std::thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = client.unwrap();
+                let bridge = bridge.clone();
+                std::thread::spawn(move || serve(client, max_version, bridge));
+            }
+        });
+        addr
+    }
+
+    fn serve(mut client: TcpStream, max_version: SamVersion, bridge: Arc<Mutex<Bridge>>) {
+        let mut own: Option<String> = None;
+        while let Ok(line) = read_reply(&mut client) {
+            let cmd = Reply::parse(&line);
+            let field = |key| cmd.get(key).unwrap_or_default().to_string();
+            let reply = match cmd.head.as_str() {
+                "HELLO VERSION" => {
+                    let min = SamVersion::parse(&field("MIN")).unwrap();
+                    let version = SamVersion::parse(&field("MAX")).unwrap().min(max_version);
+                    if version < min {
+                        "HELLO REPLY RESULT=NOVERSION".to_string()
+                    } else {
+                        format!("HELLO REPLY RESULT=OK VERSION={}", version)
+                    }
+                }
+                "DEST GENERATE" => {
+                    assert_eq!(field("SIGNATURE_TYPE"), SIGNATURE_TYPE);
+                    let (public, private) = bridge.lock().unwrap().generate();
+                    format!("DEST REPLY PUB={} PRIV={}", public, private)
+                }
+                "SESSION CREATE" => {
+                    let mut bridge = bridge.lock().unwrap();
+                    let id = field("ID");
+                    let (public, private) = match line.contains("DESTINATION=TRANSIENT") {
+                        true => bridge.generate(),
+                        false => (field("DESTINATION").replace("priv-", "pub-"), field("DESTINATION")),
+                    };
+                    if bridge.sessions.contains_key(&id) {
+                        "SESSION STATUS RESULT=DUPLICATED_ID MESSAGE=\"ID in use\"".to_string()
+                    } else {
+                        bridge.sessions.insert(id.clone(), FakeSession { public, control: client.try_clone().unwrap() });
+                        own = Some(id);
+                        format!("SESSION STATUS RESULT=OK DESTINATION={}", private)
+                    }
+                }
+                "NAMING LOOKUP" => {
+                    let bridge = bridge.lock().unwrap();
+                    let name = field("NAME");
+                    let id = if name == "ME" { own.clone() } else { name.strip_suffix(".i2p").map(str::to_string) };
+                    match id.and_then(|id| bridge.sessions.get(&id)) {
+                        Some(session) => format!("NAMING REPLY RESULT=OK NAME={} VALUE={}", name, session.public),
+                        None => format!("NAMING REPLY RESULT=KEY_NOT_FOUND NAME={}", name),
+                    }
+                }
+                "STREAM ACCEPT" => {
+                    let mut bridge = bridge.lock().unwrap();
+                    if !bridge.sessions.contains_key(&field("ID")) {
+                        "STREAM STATUS RESULT=INVALID_ID".to_string()
+                    } else {
+                        client.write_all(b"STREAM STATUS RESULT=OK\n").unwrap();
+                        bridge.acceptors.entry(field("ID")).or_default().push_back(client);
+                        return;
+                    }
+                }
+                "STREAM CONNECT" => {
+                    let (source, target) = {
+                        let bridge = bridge.lock().unwrap();
+                        (bridge.sessions[&field("ID")].public.clone(), bridge.session_of(&field("DESTINATION")))
+                    };
+                    // Give the other side time to start accepting
+                    let acceptor = target.and_then(|target| {
+                        (0..500).find_map(|_| {
+                            let acceptor = bridge.lock().unwrap().acceptors.get_mut(&target).and_then(VecDeque::pop_front);
+                            if acceptor.is_none() {
+                                std::thread::sleep(Duration::from_millis(10));
+                            }
+                            acceptor
+                        })
+                    });
+                    match acceptor {
+                        Some(mut acceptor) => {
+                            let ports = Ports::from_reply(&cmd);
+                            client.write_all(b"STREAM STATUS RESULT=OK\n").unwrap();
+                            let announcement = format!("{} FROM_PORT={} TO_PORT={}\n", source, ports.from, ports.to);
+                            acceptor.write_all(announcement.as_bytes()).unwrap();
+                            pipe(client, acceptor);
+                            return;
+                        }
+                        None => "STREAM STATUS RESULT=CANT_REACH_PEER".to_string(),
+                    }
+                }
+                "DATAGRAM SEND" | "RAW SEND" => {
+                    let mut data = vec![0u8; field("SIZE").parse().unwrap()];
+                    client.read_exact(&mut data).unwrap();
+                    let bridge = bridge.lock().unwrap();
+                    let source = &bridge.sessions[own.as_ref().unwrap()].public;
+                    let ports = Ports::from_reply(&cmd);
+                    let header = match cmd.head.as_str() {
+                        "RAW SEND" => format!("RAW RECEIVED SIZE={} FROM_PORT={} TO_PORT={} PROTOCOL=18\n", data.len(), ports.from, ports.to),
+                        _ => format!(
+                            "DATAGRAM RECEIVED DESTINATION={} SIZE={} FROM_PORT={} TO_PORT={}\n",
+                            source,
+                            data.len(),
+                            ports.from,
+                            ports.to
+                        ),
+                    };
+                    // Datagrams to unknown destinations are lost
+                    if let Some(target) = bridge.session_of(&field("DESTINATION")) {
+                        let mut control = &bridge.sessions[&target].control;
+                        control.write_all(header.as_bytes()).unwrap();
+                        control.write_all(&data).unwrap();
+                    }
+                    continue;
+                }
+                _ => format!("{} RESULT=I2P_ERROR MESSAGE=\"unknown command\"", cmd.head),
+            };
+            client.write_all(format!("{}\n", reply).as_bytes()).unwrap();
+        }
+        if let Some(id) = own {
+            bridge.lock().unwrap().sessions.remove(&id);
+        }
+    }
+
+    #[test]
+    fn test_hello_negotiates_the_version() {
+        let session = SamSession::connect(fake_bridge("3.1"), "old", SessionStyle::Stream).unwrap();
+        assert_eq!(session.version, SamVersion { major: 3, minor: 1 });
+        // Ports came with 3.2
+        let err = session.stream_connect_ports("pub-1", Ports { from: 1, to: 2 }).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let session = SamSession::connect(fake_bridge("3.3"), "new", SessionStyle::Stream).unwrap();
+        assert_eq!(session.version, MAX_VERSION);
+
+        let err = SamSession::connect(fake_bridge("2.0"), "ancient", SessionStyle::Stream).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_destination_is_kept_across_sessions() {
+        let sam = fake_bridge("3.3");
+        let path = std::env::temp_dir().join(format!("i2p_dest_test_{}.dat", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let dest = I2pDestination::load_or_generate(&path, sam).unwrap();
+        assert_ne!(dest.public, dest.private);
+        assert_eq!(I2pDestination::load_or_generate(&path, sam).unwrap(), dest);
+
+        let session = SamSession::create(sam, "node", SessionStyle::Stream, Some(&dest)).unwrap();
+        assert_eq!(session.dest, dest);
+        // A throwaway destination gets its public key from the bridge
+        let transient = SamSession::connect(sam, "", SessionStyle::Stream).unwrap();
+        assert!(transient.nickname.starts_with("bsilk-"));
+        assert_ne!(transient.dest.public, transient.dest.private);
+        assert_eq!(transient.naming_lookup(&format!("{}.i2p", transient.nickname)).unwrap(), transient.dest.public);
+
+        let err = SamSession::create(sam, "node", SessionStyle::Stream, Some(&dest)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(err.to_string().contains("ID in use"), "{}", err);
+
+        std::fs::write(&path, "pub-only\n").unwrap();
+        assert!(I2pDestination::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_streams_connect_and_accept() {
+        let sam = fake_bridge("3.3");
+        let alice = SamSession::connect(sam, "alice", SessionStyle::Stream).unwrap();
+        let bob = SamSession::connect(sam, "bob", SessionStyle::Stream).unwrap();
+        let alice_dest = naming_lookup(sam, "alice.i2p").unwrap();
+        assert_eq!(alice_dest, alice.dest.public);
+        assert_eq!(naming_lookup(sam, "carol.i2p").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let accepted = std::thread::spawn(move || {
+            let mut stream = alice.stream_accept().unwrap();
+            let mut ping = [0u8; 4];
+            stream.read_exact(&mut ping).unwrap();
+            assert_eq!(&ping, b"ping");
+            stream.write_all(b"pong").unwrap();
+            (stream.peer.clone(), stream.ports)
+        });
+        let mut stream = bob.stream_connect_ports(&alice_dest, Ports { from: 1776, to: 1777 }).unwrap();
+        stream.write_all(b"ping").unwrap();
+        let mut pong = [0u8; 4];
+        stream.read_exact(&mut pong).unwrap();
+        assert_eq!(&pong, b"pong");
+        assert_eq!(accepted.join().unwrap(), (bob.dest.public.clone(), Ports { from: 1776, to: 1777 }));
+
+        let err = bob.stream_connect("pub-unknown").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
     }
 
-    pub fn receive(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.session.recv_datagram(buf)
+    #[test]
+    fn test_datagrams_carry_ports() {
+        let sam = fake_bridge("3.3");
+        let mut alice = SamSession::connect(sam, "alice", SessionStyle::Datagram).unwrap();
+        let mut bob = SamSession::connect(sam, "bob", SessionStyle::Datagram).unwrap();
+        alice.send_datagram(&bob.dest.public, Ports { from: 7, to: 9 }, b"hello").unwrap();
+        let datagram = bob.recv_datagram().unwrap();
+        assert_eq!(datagram.source.as_deref(), Some(alice.dest.public.as_str()));
+        assert_eq!(datagram.ports, Ports { from: 7, to: 9 });
+        assert_eq!(datagram.data, b"hello");
+
+        let mut anonymous = SamSession::connect(sam, "raw-a", SessionStyle::Raw).unwrap();
+        let mut listener = SamSession::connect(sam, "raw-b", SessionStyle::Raw).unwrap();
+        anonymous.send_datagram(&listener.dest.public, Ports::default(), b"whisper").unwrap();
+        let datagram = listener.recv_datagram().unwrap();
+        assert_eq!(datagram.source, None);
+        assert_eq!(datagram.protocol, Some(18));
+        assert_eq!(datagram.data, b"whisper");
+
+        let mut streaming = SamSession::connect(sam, "streaming", SessionStyle::Stream).unwrap();
+        let err = streaming.send_datagram(&bob.dest.public, Ports::default(), b"x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        let err = alice.send_datagram(&bob.dest.public, Ports::default(), &vec![0u8; MAX_DATAGRAM_SIZE + 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
     }
 }
@@ -400,7 +400,10 @@ impl NodeClient {
             hash_array.copy_from_slice(&hash_bytes);
             Ok(Hash::from(hash_array))
         } else {
-            Err(anyhow!("Failed to submit marketplace data: {}", response.status()))
+            // The node explains why, e.g. that data must travel in a funded transaction
+            let status = response.status();
+            let reason = response.text().await.unwrap_or_default();
+            Err(anyhow!("Failed to submit marketplace data: {} {}", status, reason))
         }
     }
 
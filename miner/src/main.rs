@@ -828,6 +828,7 @@ fn handle_config(cli: &Cli, action: &ConfigCommands) {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct GetBlockTemplateResponse {
+    /// Canonical header encoding (`BlockHeader::signing_bytes`); the PoW input is `header || nonce_le`
     header: Vec<u8>,
     difficulty: u64,
     seed: Vec<u8>,
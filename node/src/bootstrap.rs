@@ -0,0 +1,290 @@
+//! Portable bootstrap files
+//!
+//! A bootstrap file carries a contiguous range of main-chain blocks so a new
+//! node can be seeded from disk instead of syncing from peers. All integers are
+//! little-endian:
+//!
+//! ```text
+//! "BSBOOT" | version u8 | network magic u32 | from u64 | to u64
+//! (block length u32 | canonical block encoding) for every height in from..=to
+//! SHA-256 of everything above
+//! ```
+//!
+//! The checksum is verified before any block is imported, then the blocks are
+//! streamed into the chain one at a time.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use primitives::Block;
+
+use crate::{BlockValidationError, Chain};
+
+pub const BOOTSTRAP_MAGIC: &[u8; 6] = b"BSBOOT";
+pub const BOOTSTRAP_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 6 + 1 + 4 + 8 + 8;
+const CHECKSUM_LEN: u64 = 32;
+
+/// Upper bound on a single encoded block in a bootstrap file
+const MAX_BLOCK_LEN: u32 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootstrapHeader {
+    pub network_magic: u32,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl BootstrapHeader {
+    pub fn block_count(&self) -> u64 {
+        self.to - self.from + 1
+    }
+
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0u8; HEADER_LEN];
+        out[..6].copy_from_slice(BOOTSTRAP_MAGIC);
+        out[6] = BOOTSTRAP_VERSION;
+        out[7..11].copy_from_slice(&self.network_magic.to_le_bytes());
+        out[11..19].copy_from_slice(&self.from.to_le_bytes());
+        out[19..27].copy_from_slice(&self.to.to_le_bytes());
+        out
+    }
+
+    fn decode(bytes: &[u8; HEADER_LEN]) -> Result<Self, BootstrapError> {
+        if &bytes[..6] != BOOTSTRAP_MAGIC {
+            return Err(BootstrapError::BadFormat("not a bootstrap file".to_string()));
+        }
+        if bytes[6] != BOOTSTRAP_VERSION {
+            return Err(BootstrapError::BadFormat(format!("unsupported version {}", bytes[6])));
+        }
+        let header = BootstrapHeader {
+            network_magic: u32::from_le_bytes(bytes[7..11].try_into().unwrap()),
+            from: u64::from_le_bytes(bytes[11..19].try_into().unwrap()),
+            to: u64::from_le_bytes(bytes[19..27].try_into().unwrap()),
+        };
+        if header.from > header.to {
+            return Err(BootstrapError::BadFormat(format!("empty block range {}..={}", header.from, header.to)));
+        }
+        Ok(header)
+    }
+}
+
+/// How thoroughly imported blocks are validated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Every consensus rule, including transaction signatures
+    Full,
+    /// Header rules (links, PoW, difficulty, timestamps, merkle root) and
+    /// key images, without re-checking transaction signatures
+    HeadersOnly,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: u64,
+    /// Blocks the chain already had
+    pub skipped: u64,
+    pub tip_height: u64,
+}
+
+#[derive(Debug)]
+pub enum BootstrapError {
+    Io(io::Error),
+    BadFormat(String),
+    ChecksumMismatch,
+    WrongNetwork { expected: u32, got: u32 },
+    InvalidRange { from: u64, to: u64 },
+    BlockUnavailable { height: u64 },
+    ConflictsWithChain { height: u64 },
+    InvalidBlock { height: u64, error: BlockValidationError },
+}
+
+impl std::fmt::Display for BootstrapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BootstrapError::Io(e) => write!(f, "I/O error: {}", e),
+            BootstrapError::BadFormat(msg) => write!(f, "malformed bootstrap file: {}", msg),
+            BootstrapError::ChecksumMismatch => write!(f, "bootstrap file checksum does not match"),
+            BootstrapError::WrongNetwork { expected, got } => write!(f, "bootstrap file is for network 0x{:X}, expected 0x{:X}", got, expected),
+            BootstrapError::InvalidRange { from, to } => write!(f, "invalid block range {}..={}", from, to),
+            BootstrapError::BlockUnavailable { height } => write!(f, "block {} is not available (pruned?)", height),
+            BootstrapError::ConflictsWithChain { height } => write!(f, "block {} conflicts with the local chain", height),
+            BootstrapError::InvalidBlock { height, error } => write!(f, "block {} rejected: {}", height, error),
+        }
+    }
+}
+
+impl std::error::Error for BootstrapError {}
+
+impl From<io::Error> for BootstrapError {
+    fn from(e: io::Error) -> Self {
+        BootstrapError::Io(e)
+    }
+}
+
+/// Writer that hashes everything passing through it
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Write main-chain blocks `from..=to` (default: up to the tip) to `path`
+pub fn export(chain: &Chain, path: &Path, from: u64, to: Option<u64>) -> Result<BootstrapHeader, BootstrapError> {
+    let tip = chain.tip().header.height;
+    let to = to.unwrap_or(tip);
+    if from > to || to > tip {
+        return Err(BootstrapError::InvalidRange { from, to });
+    }
+    let header = BootstrapHeader { network_magic: chain.network.get_magic(), from, to };
+
+    let mut out = HashingWriter { inner: BufWriter::new(File::create(path)?), hasher: Sha256::new() };
+    out.write_all(&header.encode())?;
+    for height in from..=to {
+        let block = chain.block_at(height).ok_or(BootstrapError::BlockUnavailable { height })?;
+        let bytes = block.encode();
+        out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        out.write_all(&bytes)?;
+    }
+    let checksum: [u8; 32] = out.hasher.finalize().into();
+    let mut file = out.inner;
+    file.write_all(&checksum)?;
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(header)
+}
+
+/// Streams blocks out of a bootstrap file whose checksum has been verified
+pub struct BootstrapReader<R: Read> {
+    reader: R,
+    header: BootstrapHeader,
+    next_height: u64,
+}
+
+impl BootstrapReader<io::Take<BufReader<File>>> {
+    pub fn open(path: &Path) -> Result<Self, BootstrapError> {
+        // First pass: checksum over the whole file
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < HEADER_LEN as u64 + CHECKSUM_LEN {
+            return Err(BootstrapError::BadFormat("file too short".to_string()));
+        }
+        let mut hasher = Sha256::new();
+        io::copy(&mut (&mut file).take(len - CHECKSUM_LEN), &mut hasher)?;
+        let mut expected = [0u8; 32];
+        file.read_exact(&mut expected)?;
+        if <[u8; 32]>::from(hasher.finalize()) != expected {
+            return Err(BootstrapError::ChecksumMismatch);
+        }
+
+        // Second pass: stream the blocks
+        let mut reader = BufReader::new(File::open(path)?).take(len - CHECKSUM_LEN);
+        let mut header_bytes = [0u8; HEADER_LEN];
+        reader.read_exact(&mut header_bytes)?;
+        let header = BootstrapHeader::decode(&header_bytes)?;
+        Ok(BootstrapReader { reader, header, next_height: header.from })
+    }
+}
+
+impl<R: Read> BootstrapReader<R> {
+    pub fn header(&self) -> &BootstrapHeader {
+        &self.header
+    }
+
+    fn read_block(&mut self) -> Result<Block, BootstrapError> {
+        let mut len = [0u8; 4];
+        self.reader.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len);
+        if len > MAX_BLOCK_LEN {
+            return Err(BootstrapError::BadFormat(format!("block of {} bytes exceeds limit", len)));
+        }
+        let mut bytes = vec![0u8; len as usize];
+        self.reader.read_exact(&mut bytes)?;
+        let block = Block::decode(&bytes).map_err(|e| BootstrapError::BadFormat(format!("undecodable block: {}", e)))?;
+        if block.header.height != self.next_height {
+            return Err(BootstrapError::BadFormat(format!(
+                "expected block {}, found {}",
+                self.next_height, block.header.height
+            )));
+        }
+        self.next_height += 1;
+        Ok(block)
+    }
+}
+
+impl<R: Read> Iterator for BootstrapReader<R> {
+    type Item = Result<Block, BootstrapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_height > self.header.to {
+            return None;
+        }
+        let result = self.read_block();
+        if result.is_err() {
+            // Stop after the first error
+            self.next_height = self.header.to + 1;
+        }
+        Some(result)
+    }
+}
+
+/// Import a bootstrap file into `chain`. `progress` is called after every
+/// block with the number processed so far and the total in the file.
+pub fn import(
+    chain: &mut Chain,
+    path: &Path,
+    mode: ImportMode,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<ImportReport, BootstrapError> {
+    let reader = BootstrapReader::open(path)?;
+    let header = *reader.header();
+    if header.network_magic != chain.network.get_magic() {
+        return Err(BootstrapError::WrongNetwork { expected: chain.network.get_magic(), got: header.network_magic });
+    }
+
+    let previous = chain.skip_tx_verification;
+    chain.skip_tx_verification = mode == ImportMode::HeadersOnly;
+    let result = import_blocks(chain, reader, &mut progress);
+    chain.skip_tx_verification = previous;
+    result
+}
+
+fn import_blocks<R: Read>(
+    chain: &mut Chain,
+    reader: BootstrapReader<R>,
+    progress: &mut impl FnMut(u64, u64),
+) -> Result<ImportReport, BootstrapError> {
+    let total = reader.header().block_count();
+    let mut report = ImportReport::default();
+    for (done, block) in reader.enumerate() {
+        let block = block?;
+        let height = block.header.height;
+        if height <= chain.tip().header.height {
+            // Already known blocks must match ours; the file cannot reorg the chain
+            if !chain.contains_block(&block.hash()) {
+                return Err(BootstrapError::ConflictsWithChain { height });
+            }
+            report.skipped += 1;
+        } else {
+            chain.add_block(block).map_err(|error| BootstrapError::InvalidBlock { height, error })?;
+            report.imported += 1;
+        }
+        progress(done as u64 + 1, total);
+    }
+    report.tip_height = chain.tip().header.height;
+    Ok(report)
+}
@@ -0,0 +1,218 @@
+//! Chain specifications
+//!
+//! A chain spec is the JSON file under `config/<network>/chain_spec.json`
+//! that fixes a network's consensus parameters: genesis block, block time,
+//! difficulty window, emission and minimum fee, plus its default ports and
+//! bootnodes. The specs of the built-in networks are compiled in; a node can
+//! run on a different one with `--chain-spec` (or `[consensus] genesis_file`),
+//! which is how regtest chains for integration tests are set up.
+
+use std::path::Path;
+
+use primitives::types::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::EmissionSchedule;
+
+pub const MAINNET_SPEC_JSON: &str = include_str!("../../config/mainnet/chain_spec.json");
+pub const TESTNET_SPEC_JSON: &str = include_str!("../../config/testnet/chain_spec.json");
+pub const REGTEST_SPEC_JSON: &str = include_str!("../../config/regtest/chain_spec.json");
+
+#[derive(Debug)]
+pub enum ChainSpecError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    /// A parameter that would make the chain unusable
+    Invalid(String),
+}
+
+impl std::fmt::Display for ChainSpecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainSpecError::Io(e) => write!(f, "cannot read chain spec: {}", e),
+            ChainSpecError::Parse(e) => write!(f, "malformed chain spec: {}", e),
+            ChainSpecError::Invalid(msg) => write!(f, "invalid chain spec: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ChainSpecError {}
+
+impl From<std::io::Error> for ChainSpecError {
+    fn from(e: std::io::Error) -> Self {
+        ChainSpecError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ChainSpecError {
+    fn from(e: serde_json::Error) -> Self {
+        ChainSpecError::Parse(e)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisSpec {
+    pub timestamp: u64,
+    /// Hex, with or without `0x`
+    pub parent_hash: String,
+    pub difficulty: u64,
+    pub nonce: u64,
+    pub block_time_seconds: u64,
+    #[serde(default)]
+    pub initial_utxos: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsensusSpec {
+    pub block_time_seconds: u64,
+    /// Blocks averaged by the difficulty retarget (see `difficulty`)
+    pub difficulty_adjustment_window: u64,
+    /// Keep every block at the genesis difficulty, for test networks
+    #[serde(default)]
+    pub fixed_difficulty: bool,
+    pub halving_interval_blocks: u64,
+    pub supply_cap_atomic: u64,
+    pub initial_block_reward_atomic: u64,
+    #[serde(default)]
+    pub tail_emission_atomic: u64,
+    pub coinbase_maturity_blocks: u64,
+    #[serde(default)]
+    pub min_tx_fee_per_byte: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkSpec {
+    pub p2p_port: u16,
+    pub rpc_port: u16,
+    pub tor_port: u16,
+    /// `<peer_id>@<host>:<port>`, as in `bootnodes.txt`
+    #[serde(default)]
+    pub bootnodes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainSpec {
+    pub network_id: String,
+    pub network_name: String,
+    pub genesis: GenesisSpec,
+    pub consensus: ConsensusSpec,
+    pub network: NetworkSpec,
+    #[serde(default)]
+    pub features: serde_json::Value,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+impl ChainSpec {
+    /// Parse and validate a spec
+    pub fn from_json(json: &str) -> Result<Self, ChainSpecError> {
+        let spec: ChainSpec = serde_json::from_str(json)?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ChainSpecError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    fn validate(&self) -> Result<(), ChainSpecError> {
+        let invalid = |msg: &str| Err(ChainSpecError::Invalid(msg.to_string()));
+        let consensus = &self.consensus;
+        if consensus.block_time_seconds == 0 {
+            return invalid("consensus.block_time_seconds must be positive");
+        }
+        if self.genesis.block_time_seconds != consensus.block_time_seconds {
+            return invalid("genesis.block_time_seconds differs from consensus.block_time_seconds");
+        }
+        if consensus.difficulty_adjustment_window == 0 {
+            return invalid("consensus.difficulty_adjustment_window must be positive");
+        }
+        if consensus.halving_interval_blocks == 0 {
+            return invalid("consensus.halving_interval_blocks must be positive");
+        }
+        if self.genesis.difficulty == 0 {
+            return invalid("genesis.difficulty must be positive");
+        }
+        if consensus.initial_block_reward_atomic > consensus.supply_cap_atomic {
+            return invalid("consensus.initial_block_reward_atomic exceeds the supply cap");
+        }
+        self.genesis_parent_hash()?;
+        Ok(())
+    }
+
+    /// `genesis.parent_hash` decoded
+    pub fn genesis_parent_hash(&self) -> Result<Hash, ChainSpecError> {
+        let hex_str = self.genesis.parent_hash.trim_start_matches("0x");
+        hex::decode(hex_str)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| ChainSpecError::Invalid(format!("genesis.parent_hash {} is not a 32-byte hex hash", self.genesis.parent_hash)))
+    }
+
+    pub fn emission(&self) -> EmissionSchedule {
+        EmissionSchedule {
+            genesis_reward: self.consensus.initial_block_reward_atomic,
+            halving_interval: self.consensus.halving_interval_blocks,
+            supply_cap: self.consensus.supply_cap_atomic,
+            tail_emission: self.consensus.tail_emission_atomic,
+        }
+    }
+
+    /// Bootnode addresses without their peer ids
+    pub fn bootnode_addresses(&self) -> Vec<String> {
+        self.network
+            .bootnodes
+            .iter()
+            .map(|node| node.rsplit('@').next().unwrap_or(node).to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_specs_parse() {
+        let testnet = ChainSpec::from_json(TESTNET_SPEC_JSON).unwrap();
+        assert_eq!(testnet.consensus.halving_interval_blocks, 210_240);
+        assert_eq!(testnet.consensus.coinbase_maturity_blocks, 10);
+        assert_eq!(testnet.emission().block_reward(210_240), 2_500_000);
+        assert_eq!(testnet.bootnode_addresses()[0], "testnet-seed1.blacksilk.io:19334");
+        assert_eq!(testnet.genesis_parent_hash().unwrap(), [0; 32]);
+
+        let mainnet = ChainSpec::from_json(MAINNET_SPEC_JSON).unwrap();
+        assert_eq!(mainnet.consensus.min_tx_fee_per_byte, 10);
+        let regtest = ChainSpec::from_json(REGTEST_SPEC_JSON).unwrap();
+        assert_eq!(regtest.network.p2p_port, 18444);
+    }
+
+    #[test]
+    fn test_spec_drives_genesis_and_emission() {
+        let network = crate::Network::Regtest;
+        let spec = network.spec();
+        let chain = crate::Chain::new_for_network(network.clone());
+        let genesis = &chain.tip().header;
+        assert_eq!(genesis.timestamp, spec.genesis.timestamp);
+        assert_eq!(genesis.difficulty, spec.genesis.difficulty);
+        assert_eq!(chain.emission.halving_interval, spec.consensus.halving_interval_blocks);
+        assert_eq!(network.get_ports().http, spec.network.rpc_port);
+        assert_ne!(genesis.timestamp, crate::Chain::new_for_network(crate::Network::Testnet).tip().header.timestamp);
+    }
+
+    #[test]
+    fn test_unusable_specs_are_rejected() {
+        let mut spec: serde_json::Value = serde_json::from_str(REGTEST_SPEC_JSON).unwrap();
+        spec["consensus"]["halving_interval_blocks"] = 0.into();
+        assert!(matches!(ChainSpec::from_json(&spec.to_string()), Err(ChainSpecError::Invalid(_))));
+
+        let mut spec: serde_json::Value = serde_json::from_str(REGTEST_SPEC_JSON).unwrap();
+        spec["genesis"]["parent_hash"] = "0x1234".into();
+        assert!(matches!(ChainSpec::from_json(&spec.to_string()), Err(ChainSpecError::Invalid(_))));
+
+        assert!(matches!(ChainSpec::from_json("{}"), Err(ChainSpecError::Parse(_))));
+        assert!(matches!(ChainSpec::load(Path::new("/nonexistent/chain_spec.json")), Err(ChainSpecError::Io(_))));
+    }
+}
@@ -0,0 +1,173 @@
+//! Difficulty retargeting
+//!
+//! Every block's difficulty is a linearly weighted moving average (LWMA) of
+//! the last `difficulty_adjustment_window` blocks: the average difficulty is
+//! scaled by how far the weighted solve times are from the block time, the
+//! newest solve time weighing N times as much as the oldest. With 120 second
+//! blocks and a 60 block window it follows hashrate swings within a few
+//! blocks without oscillating the way a once-per-window retarget does.
+//!
+//! Solve times are taken between consecutive timestamps, forced to be at
+//! least one second (a timestamp earlier than its predecessor counts as
+//! coming right after it) and capped at `MAX_SOLVE_TIME_FACTOR` block times
+//! so a single stalled or forged timestamp cannot crash the difficulty.
+
+use primitives::BlockHeader;
+
+/// Longest solve time counted, in block times
+pub const MAX_SOLVE_TIME_FACTOR: u64 = 6;
+
+/// Largest rise in one block: the weighted solve time is never taken as
+/// less than a tenth of the target
+const MAX_RISE: u128 = 10;
+
+/// Difficulty of the block after `recent[0]`, from the latest headers,
+/// newest first. `window + 1` headers give `window` solve times; a younger
+/// chain averages over what it has. Returns `None` with fewer than two
+/// headers, when there is no solve time to go by.
+pub fn lwma(recent: &[&BlockHeader], block_time: u64, window: usize) -> Option<u64> {
+    let n = window.min(recent.len().checked_sub(1)?);
+    if n == 0 {
+        return None;
+    }
+    let block_time = block_time.max(1) as u128;
+    let oldest_first = recent[..=n].iter().rev();
+
+    let mut previous = recent[n].timestamp;
+    let (mut weighted_solve_times, mut total_difficulty) = (0u128, 0u128);
+    for (weight, header) in (1..).zip(oldest_first.skip(1)) {
+        let timestamp = header.timestamp.max(previous + 1);
+        let solve_time = ((timestamp - previous) as u128).min(MAX_SOLVE_TIME_FACTOR as u128 * block_time);
+        previous = timestamp;
+        weighted_solve_times += weight * solve_time;
+        total_difficulty += header.difficulty.max(1) as u128;
+    }
+
+    // Weighted solve times if every block had taken exactly `block_time`
+    let n = n as u128;
+    let expected = n * (n + 1) / 2 * block_time;
+    let weighted_solve_times = weighted_solve_times.max(expected / MAX_RISE);
+    // Average difficulty times expected over actual weighted solve time
+    let next = total_difficulty * expected / (n * weighted_solve_times);
+    Some(next.clamp(1, u64::MAX as u128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_TIME: u64 = 120;
+    const WINDOW: usize = 60;
+
+    /// Mine `solve_times.len()` blocks after a genesis at difficulty
+    /// `start`, each at the difficulty `lwma` asks for, and return the
+    /// headers oldest first
+    fn simulate(start: u64, solve_times: impl IntoIterator<Item = i64>) -> Vec<BlockHeader> {
+        let mut headers = vec![header(0, 1_700_000_000, start)];
+        for solve_time in solve_times {
+            let difficulty = next(&headers);
+            let last = headers.last().unwrap();
+            headers.push(header(last.height + 1, last.timestamp.saturating_add_signed(solve_time), difficulty));
+        }
+        headers
+    }
+
+    fn header(height: u64, timestamp: u64, difficulty: u64) -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            prev_hash: [0; 32],
+            merkle_root: [0; 32],
+            timestamp,
+            height,
+            difficulty,
+            pow: primitives::Pow { nonce: 0, hash: [0; 32] },
+        }
+    }
+
+    fn next(headers: &[BlockHeader]) -> u64 {
+        let recent: Vec<&BlockHeader> = headers.iter().rev().take(WINDOW + 1).collect();
+        lwma(&recent, BLOCK_TIME, WINDOW).unwrap_or(headers[0].difficulty)
+    }
+
+    #[test]
+    fn test_on_target_blocks_keep_difficulty() {
+        let headers = simulate(1_000_000, std::iter::repeat_n(120, 200));
+        assert!(headers.iter().all(|h| h.difficulty == 1_000_000));
+        assert_eq!(next(&headers), 1_000_000);
+        assert_eq!(lwma(&[&headers[0]], BLOCK_TIME, WINDOW), None);
+    }
+
+    #[test]
+    fn test_difficulty_scales_with_solve_times() {
+        // A window of blocks at half the block time doubles the difficulty,
+        // one at twice the block time halves it
+        let steady = |solve_time: u64| -> Vec<BlockHeader> {
+            (0..=WINDOW as u64).map(|h| header(h, 1_700_000_000 + h * solve_time, 1_000_000)).collect()
+        };
+        assert_eq!(next(&steady(60)), 2_000_000);
+        assert_eq!(next(&steady(240)), 500_000);
+        // The newest solve times weigh most
+        let mut recent_fast = steady(120);
+        for (i, h) in recent_fast.iter_mut().enumerate().skip(WINDOW - 5) {
+            h.timestamp -= 60 * (i - (WINDOW - 5)) as u64;
+        }
+        let mut early_fast = steady(120);
+        for (i, h) in early_fast.iter_mut().enumerate() {
+            h.timestamp -= 60 * i.min(5) as u64;
+        }
+        assert!(next(&recent_fast) > next(&early_fast));
+    }
+
+    #[test]
+    fn test_hashrate_changes_converge_to_block_time() {
+        // Solve time scales with difficulty over the hashrate; the hashrate
+        // jumps tenfold at block 100 and drops back at block 300
+        let mut headers = simulate(1_000_000, [120]);
+        for height in 1..500u64 {
+            let hashrate = if (100..300).contains(&height) { 83_333 } else { 8_333 };
+            let difficulty = next(&headers);
+            let last = headers.last().unwrap();
+            // Each retarget is a step, not a jump at a window boundary
+            assert!(difficulty <= last.difficulty * 2 && difficulty >= last.difficulty / 2);
+            headers.push(header(height + 1, last.timestamp + (difficulty / hashrate).max(1), difficulty));
+        }
+        let average = |range: std::ops::Range<usize>| {
+            (headers[range.end].timestamp - headers[range.start].timestamp) / range.len() as u64
+        };
+        assert!((110..=130).contains(&average(200..300)), "{}", average(200..300));
+        assert!((110..=130).contains(&average(400..500)), "{}", average(400..500));
+        let settled = headers[299].difficulty;
+        assert!((9_000_000..=11_000_000).contains(&settled), "{}", settled);
+    }
+
+    #[test]
+    fn test_timestamp_manipulation_is_bounded() {
+        // Timestamps that go backwards count as one second solves
+        let mut solve_times = vec![120; 80];
+        solve_times.extend([-5_000, 5_000]);
+        let headers = simulate(1_000_000, solve_times);
+        let d = next(&headers);
+        assert!(d < 1_200_000, "{}", d);
+
+        // A stalled block counts as at most six block times
+        let stalled = simulate(1_000_000, std::iter::repeat_n(120, 80).chain([1_000_000]));
+        let capped = simulate(1_000_000, std::iter::repeat_n(120, 80).chain([6 * 120]));
+        assert_eq!(next(&stalled), next(&capped));
+
+        // A window of identical timestamps raises the difficulty tenfold
+        let instant: Vec<BlockHeader> = (0..=WINDOW as u64).map(|h| header(h, 1_700_000_000, 1_000_000)).collect();
+        assert_eq!(next(&instant), 10_000_000);
+    }
+
+    #[test]
+    fn test_retarget_is_deterministic() {
+        let solve_times = [90, 150, 30, 400, 120, 75, 200, 10, 120, 180];
+        let headers = simulate(500_000, solve_times.iter().copied().cycle().take(150));
+        let again = simulate(500_000, solve_times.iter().copied().cycle().take(150));
+        assert_eq!(headers.last().unwrap().difficulty, again.last().unwrap().difficulty);
+        // A young chain averages over the blocks it has: one block at 60s
+        let young = simulate(500_000, [60]);
+        assert_eq!(young[1].difficulty, 500_000);
+        assert_eq!(next(&young), 1_000_000);
+    }
+}
@@ -0,0 +1,266 @@
+//! Fee estimation from observed confirmation times
+//!
+//! Every transaction admitted to the mempool is filed under a fee rate bucket
+//! together with the height it arrived at. When a block confirms it, the
+//! number of blocks it waited is recorded for its bucket; when it leaves
+//! without confirming (evicted or expired) it counts as a miss. Old
+//! observations fade by `DECAY` per block so the estimate follows the
+//! current market.
+//!
+//! An estimate for a target of N blocks is the lowest fee rate whose
+//! transactions, together with every better paying one, confirmed within N
+//! blocks at least `SUCCESS_RATIO` of the time. Transactions still pending
+//! after N blocks count against their bucket.
+
+use std::collections::HashMap;
+
+use primitives::types::Hash;
+use serde::Serialize;
+
+use crate::mempool::FeeRate;
+
+/// Longest confirmation target tracked, in blocks
+pub const MAX_TARGET_BLOCKS: u64 = 48;
+
+/// Weight kept by each observation per block
+const DECAY: f64 = 0.998;
+
+/// Share of transactions that must confirm within the target
+const SUCCESS_RATIO: f64 = 0.85;
+
+/// Observations a group of buckets needs before it is trusted
+const MIN_SAMPLES: f64 = 8.0;
+
+/// Lowest and highest bucket boundary, per 1000 bytes
+const FIRST_BUCKET: u64 = 1_000;
+const LAST_BUCKET: u64 = 1_000_000_000;
+
+/// How soon a transaction should confirm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeePriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl FeePriority {
+    pub const ALL: [FeePriority; 3] = [FeePriority::Low, FeePriority::Normal, FeePriority::High];
+
+    /// Blocks within which a transaction of this priority should confirm
+    pub fn target_blocks(&self) -> u64 {
+        match self {
+            FeePriority::Low => 30,
+            FeePriority::Normal => 6,
+            FeePriority::High => 1,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "low" | "economy" => Some(FeePriority::Low),
+            "normal" | "medium" => Some(FeePriority::Normal),
+            "high" | "urgent" => Some(FeePriority::High),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tracked {
+    bucket: usize,
+    height: u64,
+}
+
+#[derive(Debug)]
+pub struct FeeEstimator {
+    /// Lower bound of each bucket, per 1000 bytes; the first is 0
+    bounds: Vec<u64>,
+    /// `confirmed[bucket][n - 1]`: transactions confirmed within n blocks
+    confirmed: Vec<Vec<f64>>,
+    /// Transactions that left the pool, confirmed or not
+    left: Vec<f64>,
+    pending: HashMap<Hash, Tracked>,
+    /// Height of the last block seen
+    height: u64,
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        FeeEstimator::new()
+    }
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        let mut bounds = vec![0, FIRST_BUCKET];
+        while let Some(&last) = bounds.last().filter(|&&b| b < LAST_BUCKET) {
+            bounds.push(last + (last / 5).max(1));
+        }
+        FeeEstimator {
+            confirmed: vec![vec![0.0; MAX_TARGET_BLOCKS as usize]; bounds.len()],
+            left: vec![0.0; bounds.len()],
+            bounds,
+            pending: HashMap::new(),
+            height: 0,
+        }
+    }
+
+    fn bucket(&self, rate: &FeeRate) -> usize {
+        self.bounds.partition_point(|&bound| bound <= rate.per_kb()) - 1
+    }
+
+    /// Height the next tracked transaction is counted from
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Start timing a transaction that entered the mempool. Until the first
+    /// block arrives there is no height to time it from.
+    pub fn track(&mut self, txid: Hash, rate: &FeeRate) {
+        if self.height == 0 {
+            return;
+        }
+        let tracked = Tracked { bucket: self.bucket(rate), height: self.height };
+        self.pending.insert(txid, tracked);
+    }
+
+    /// Stop timing a transaction without counting it, e.g. because a
+    /// conflicting spend replaced it
+    pub fn forget(&mut self, txid: &Hash) {
+        self.pending.remove(txid);
+    }
+
+    /// A transaction left the pool without being mined
+    pub fn record_miss(&mut self, txid: &Hash) {
+        if let Some(tracked) = self.pending.remove(txid) {
+            self.left[tracked.bucket] += 1.0;
+        }
+    }
+
+    /// Block `height` confirmed `txids`. Earlier observations decay, and
+    /// each tracked transaction in the block records how long it waited.
+    pub fn process_block(&mut self, height: u64, txids: &[Hash]) {
+        // Blocks from a reorg or a stale submission say nothing new
+        if height <= self.height {
+            for txid in txids {
+                self.forget(txid);
+            }
+            return;
+        }
+        for (confirmed, left) in self.confirmed.iter_mut().zip(self.left.iter_mut()) {
+            confirmed.iter_mut().for_each(|count| *count *= DECAY);
+            *left *= DECAY;
+        }
+        for txid in txids {
+            let Some(tracked) = self.pending.remove(txid) else { continue };
+            let waited = height.saturating_sub(tracked.height).max(1);
+            for within in waited..=MAX_TARGET_BLOCKS {
+                self.confirmed[tracked.bucket][within as usize - 1] += 1.0;
+            }
+            self.left[tracked.bucket] += 1.0;
+        }
+        self.height = height;
+    }
+
+    /// Lowest fee rate per 1000 bytes that confirmed within `target`
+    /// blocks, or `None` without enough data
+    pub fn estimate(&self, target: u64) -> Option<u64> {
+        let target = target.clamp(1, MAX_TARGET_BLOCKS);
+        // Pending transactions that already waited longer than the target
+        let mut overdue = vec![0.0; self.bounds.len()];
+        for tracked in self.pending.values() {
+            if self.height.saturating_sub(tracked.height) >= target {
+                overdue[tracked.bucket] += 1.0;
+            }
+        }
+
+        let mut best = None;
+        let (mut hits, mut samples) = (0.0, 0.0);
+        for bucket in (0..self.bounds.len()).rev() {
+            hits += self.confirmed[bucket][target as usize - 1];
+            samples += self.left[bucket] + overdue[bucket];
+            if samples < MIN_SAMPLES {
+                continue;
+            }
+            if hits / samples < SUCCESS_RATIO {
+                break;
+            }
+            best = Some(self.bounds[bucket]);
+            hits = 0.0;
+            samples = 0.0;
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(per_byte: u64) -> FeeRate {
+        FeeRate { fee: per_byte * 1_000, size: 1_000 }
+    }
+
+    fn txid(seed: u8, index: u8) -> Hash {
+        let mut txid = [0; 32];
+        txid[0] = seed;
+        txid[1] = index;
+        txid
+    }
+
+    /// Feed `count` transactions paying `per_byte` that each confirm
+    /// `waited` blocks after arriving
+    fn observe(estimator: &mut FeeEstimator, seed: u8, per_byte: u64, waited: u64, count: u8) {
+        for i in 0..count {
+            let txid = txid(seed, i);
+            estimator.track(txid, &rate(per_byte));
+            let height = estimator.height() + waited;
+            estimator.process_block(height, &[txid]);
+        }
+    }
+
+    #[test]
+    fn test_estimate_follows_confirmation_times() {
+        let mut estimator = FeeEstimator::new();
+        estimator.track([9; 32], &rate(1));
+        assert!(estimator.pending.is_empty());
+        estimator.process_block(1, &[]);
+        assert_eq!(estimator.estimate(1), None);
+
+        // Rich transactions make the next block, cheap ones take ten
+        observe(&mut estimator, 1, 50, 1, 20);
+        observe(&mut estimator, 2, 2, 10, 20);
+        let fast = estimator.estimate(1).unwrap();
+        assert!(fast > 2_000 && fast <= 50_000, "{}", fast);
+        assert!(estimator.estimate(10).unwrap() <= 2_000);
+        assert_eq!(FeePriority::parse("HIGH"), Some(FeePriority::High));
+        assert_eq!(FeePriority::parse("soon"), None);
+    }
+
+    #[test]
+    fn test_misses_and_waiting_transactions_raise_the_estimate() {
+        let mut estimator = FeeEstimator::new();
+        estimator.process_block(1, &[]);
+        observe(&mut estimator, 1, 50, 1, 20);
+        observe(&mut estimator, 2, 5, 1, 20);
+        assert!(estimator.estimate(1).unwrap() <= 5_000);
+
+        // Most cheap transactions are now stuck or dropped
+        for i in 0..20u8 {
+            let txid = txid(3, i);
+            estimator.track(txid, &rate(5));
+            if i % 2 == 0 {
+                estimator.record_miss(&txid);
+            }
+        }
+        let height = estimator.height() + 2;
+        estimator.process_block(height, &[]);
+        assert!(estimator.estimate(1).unwrap() > 5_000);
+
+        // A replaced transaction leaves no trace
+        estimator.track([9; 32], &rate(5));
+        estimator.forget(&[9; 32]);
+        assert!(!estimator.pending.contains_key(&[9; 32]));
+    }
+}
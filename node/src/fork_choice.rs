@@ -0,0 +1,273 @@
+//! Fork choice and chain reorganization
+//!
+//! The node follows the branch with the most cumulative work. Blocks that do
+//! not extend the current tip are kept in a side-chain store; once a side
+//! branch carries more work than the main chain above the fork point, the
+//! chain is rolled back to the common ancestor and the heavier branch is
+//! applied on top of it. Transactions from the disconnected blocks that did
+//! not make it into the new branch are handed back to the caller so they can
+//! be returned to the mempool.
+
+use std::collections::{HashMap, HashSet};
+
+use primitives::types::Hash;
+use primitives::{Block, BlockHeader, Transaction};
+
+use crate::Chain;
+
+/// Deepest reorganization the node will perform
+pub const MAX_REORG_DEPTH: u64 = 100;
+
+/// Maximum number of blocks kept in the side-chain store
+pub const MAX_SIDE_BLOCKS: usize = 1024;
+
+/// Work contributed by a single block
+pub fn block_work(header: &BlockHeader) -> u128 {
+    header.difficulty.max(1) as u128
+}
+
+/// Outcome of offering a block to the chain
+#[derive(Debug)]
+pub enum BlockAcceptance {
+    /// Block extended the current tip
+    Extended,
+    /// Block was stored on a side branch that is not (yet) heavier
+    SideChain,
+    /// A heavier side branch replaced part of the main chain
+    Reorganized {
+        disconnected: usize,
+        connected: usize,
+        orphaned_txs: Vec<Transaction>,
+    },
+    /// Parent is unknown; the caller should request the missing blocks
+    Orphan,
+    /// Block is already part of the main chain or a side branch
+    Duplicate,
+    /// Block failed validation
+    Rejected,
+}
+
+/// Blocks known to the node that are not part of the main chain
+#[derive(Debug, Clone, Default)]
+pub struct SideChainStore {
+    blocks: HashMap<Hash, Block>,
+}
+
+impl SideChainStore {
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&Block> {
+        self.blocks.get(hash)
+    }
+
+    pub fn insert(&mut self, block: Block) {
+        if self.blocks.len() >= MAX_SIDE_BLOCKS {
+            // Drop the lowest block to make room
+            if let Some(lowest) = self.blocks.values().min_by_key(|b| b.header.height).map(|b| b.hash()) {
+                self.blocks.remove(&lowest);
+            }
+        }
+        self.blocks.insert(block.hash(), block);
+    }
+
+    pub fn remove(&mut self, hash: &Hash) -> Option<Block> {
+        self.blocks.remove(hash)
+    }
+
+    /// Remove `hash` and every stored block built on top of it
+    pub fn remove_with_descendants(&mut self, hash: &Hash) {
+        let mut removed = HashSet::from([*hash]);
+        self.blocks.remove(hash);
+        loop {
+            let children: Vec<Hash> = self
+                .blocks
+                .iter()
+                .filter(|(_, b)| removed.contains(&b.header.prev_hash))
+                .map(|(hash, _)| *hash)
+                .collect();
+            if children.is_empty() {
+                break;
+            }
+            for child in children {
+                self.blocks.remove(&child);
+                removed.insert(child);
+            }
+        }
+    }
+
+    /// Forget side blocks that are too deep to ever trigger a reorg
+    pub fn prune_below(&mut self, height: u64) {
+        self.blocks.retain(|_, b| b.header.height >= height);
+    }
+}
+
+impl Chain {
+    /// Total work of the main chain
+    pub fn cumulative_work(&self) -> u128 {
+        // Work below the in-memory window comes from the block store
+        let below_window = match (&self.store, self.blocks.front()) {
+            (Some(store), Some(first)) if first.header.height > 0 => store.work_at(first.header.height - 1).unwrap_or(0),
+            _ => 0,
+        };
+        below_window + self.blocks.iter().map(|b| block_work(&b.header)).sum::<u128>()
+    }
+
+    /// Index of an in-memory main-chain block by its PoW hash
+    pub fn position_of(&self, hash: &Hash) -> Option<usize> {
+        self.blocks.iter().rposition(|b| b.hash() == *hash)
+    }
+
+    /// Offer a block received from the network and apply the fork-choice rule
+    pub fn process_block(&mut self, block: Block) -> BlockAcceptance {
+        let hash = block.hash();
+        if self.side_chains.contains(&hash) || (block.header.height > 0 && self.contains_block(&hash)) {
+            return BlockAcceptance::Duplicate;
+        }
+
+        // Fast path: block extends the current tip
+        if block.header.prev_hash == self.tip().hash() {
+            if self.add_block(block).is_err() {
+                return BlockAcceptance::Rejected;
+            }
+            let tip_height = self.tip().header.height;
+            self.side_chains.prune_below(tip_height.saturating_sub(MAX_REORG_DEPTH));
+            return BlockAcceptance::Extended;
+        }
+
+        let parent_known = self.side_chains.contains(&block.header.prev_hash)
+            || self.position_of(&block.header.prev_hash).is_some();
+        if !parent_known {
+            return BlockAcceptance::Orphan;
+        }
+        if block.header.height + MAX_REORG_DEPTH < self.tip().header.height {
+            println!("[Chain] Ignoring side block at height {} (deeper than max reorg depth)", block.header.height);
+            return BlockAcceptance::Rejected;
+        }
+        if let Err(e) = crate::validate_block_standalone(&block, !self.skip_pow_check) {
+            println!("[Chain] Side block at height {} rejected: {}", block.header.height, e);
+            return BlockAcceptance::Rejected;
+        }
+        self.side_chains.insert(block);
+
+        let (fork_index, branch) = match self.side_branch(&hash) {
+            Some(found) => found,
+            None => return BlockAcceptance::SideChain,
+        };
+        let branch_work: u128 = branch.iter().map(|b| block_work(&b.header)).sum();
+        let main_work: u128 = self.blocks.iter().skip(fork_index + 1).map(|b| block_work(&b.header)).sum();
+        if branch_work <= main_work {
+            println!("[Chain] Stored side block at height {} (branch work {} <= main work {})",
+                branch.last().map_or(0, |b| b.header.height), branch_work, main_work);
+            return BlockAcceptance::SideChain;
+        }
+
+        match self.reorganize(fork_index, branch) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                println!("[Chain] Reorg aborted: {}", e);
+                BlockAcceptance::Rejected
+            }
+        }
+    }
+
+    /// Walk back from a side block to the main chain. Returns the index of the
+    /// common ancestor in `blocks` and the branch ordered from oldest to newest.
+    fn side_branch(&self, tip_hash: &Hash) -> Option<(usize, Vec<Block>)> {
+        let mut branch = Vec::new();
+        let mut cursor = *tip_hash;
+        while let Some(block) = self.side_chains.get(&cursor) {
+            branch.push(block.clone());
+            cursor = block.header.prev_hash;
+            if branch.len() as u64 > MAX_REORG_DEPTH {
+                return None;
+            }
+        }
+        let fork_index = self.position_of(&cursor)?;
+        branch.reverse();
+        Some((fork_index, branch))
+    }
+
+    /// Roll back to `fork_index` and apply `branch` on top of it. On failure
+    /// the original main chain is restored; the block that failed validation
+    /// is discarded with its descendants, while the valid prefix stays on the
+    /// side and may still win with a later block.
+    pub fn reorganize(&mut self, fork_index: usize, branch: Vec<Block>) -> Result<BlockAcceptance, String> {
+        if fork_index >= self.blocks.len() {
+            return Err(format!("fork point {} is beyond the tip", fork_index));
+        }
+        let depth = (self.blocks.len() - fork_index - 1) as u64;
+        if depth > MAX_REORG_DEPTH {
+            return Err(format!("reorg depth {} exceeds limit {}", depth, MAX_REORG_DEPTH));
+        }
+        println!("[Chain] Reorganizing: rolling back {} block(s) to height {}, applying {} block(s)",
+            depth, self.blocks[fork_index].header.height, branch.len());
+
+        let fork_height = self.blocks[fork_index].header.height;
+        let mut disconnected = Vec::new();
+        while self.blocks.len() > fork_index + 1 {
+            disconnected.extend(self.disconnect_tip());
+        }
+        disconnected.reverse();
+        let mut connected = 0;
+        let mut failure = None;
+        let mut invalid = None;
+        for block in &branch {
+            if block.header.prev_hash != self.tip().hash() || self.connect_block(block.clone()).is_err() {
+                failure = Some(format!("branch block at height {} failed validation", block.header.height));
+                invalid = Some(block.hash());
+                break;
+            }
+            connected += 1;
+        }
+        // Rollback and the new branch reach the block store as one batch
+        if failure.is_none() {
+            if let Err(e) = self.persist(Some(fork_height), connected) {
+                failure = Some(e.to_string());
+            }
+        }
+        if let Some(reason) = failure {
+            // Restore the previous main chain and drop the invalid block
+            while self.blocks.len() > fork_index + 1 {
+                self.disconnect_tip();
+            }
+            self.reconnect_blocks(disconnected);
+            if let Some(hash) = invalid {
+                self.side_chains.remove_with_descendants(&hash);
+            }
+            return Err(reason);
+        }
+        self.trim_window();
+
+        // Move the blocks across: new branch leaves the side store, the old
+        // main-chain blocks become a side branch that may win back later.
+        for b in &branch {
+            self.side_chains.remove(&b.hash());
+        }
+        let confirmed: HashSet<Hash> = branch
+            .iter()
+            .flat_map(|b| b.transactions.iter())
+            .map(|tx| tx.id())
+            .collect();
+        let mut orphaned_txs = Vec::new();
+        let disconnected_count = disconnected.len();
+        for block in disconnected {
+            for tx in block.transactions.iter().filter(|tx| !tx.inputs.is_empty()) {
+                if !confirmed.contains(&tx.id()) {
+                    orphaned_txs.push(tx.clone());
+                }
+            }
+            self.side_chains.insert(block);
+        }
+        Ok(BlockAcceptance::Reorganized { disconnected: disconnected_count, connected, orphaned_txs })
+    }
+}
@@ -2,60 +2,42 @@
 //! Provides REST endpoints for wallets and external applications
 
 use std::collections::HashMap;
-use std::net::TcpStream;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use primitives::{Block, Transaction};
-use crate::{CHAIN, MEMPOOL, add_to_mempool, validate_transaction};
-use crate::randomx_verifier::RandomXVerifier;
+use crate::{CHAIN, MEMPOOL, validate_transaction, calculate_merkle_root};
+use crate::network::peer_manager::PeerManager;
+use crate::randomx_verifier::RANDOMX_VERIFIER;
 use crate::wasm_vm;
 
+/// Templates handed out to miners that have not been submitted yet
+const MAX_PENDING_TEMPLATES: usize = 64;
+
+/// Answer to the retired free marketplace data submission: every transaction
+/// must now spend an input and pay the relay fee, which only a wallet can do
+const MARKETPLACE_SUBMIT_GONE: &str =
+    "Marketplace data must be sent through /submit_tx in a wallet-funded transaction with MARKETPLACE:<base64> metadata";
+
 lazy_static::lazy_static! {
-    /// Global RandomX verifier with CPU-only enforcement
-    static ref RANDOMX_VERIFIER: RandomXVerifier = RandomXVerifier::new();
-}
-
-/// Calculate merkle root for a list of transactions
-fn calculate_merkle_root(transactions: &[Transaction]) -> [u8; 32] {
-    use crate::randomx::{randomx_hash, get_optimal_flags};
-    if transactions.is_empty() {
-        return [0; 32]; // Empty merkle root for blocks with no transactions
-    }
-    // Hash each transaction using SHA256, then concatenate
-    let mut tx_hashes = Vec::new();
-    for tx in transactions {
-        if let Ok(tx_bytes) = serde_json::to_vec(tx) {
-            let hash = sha2::Sha256::digest(&tx_bytes);
-            tx_hashes.extend_from_slice(&hash);
-        }
-    }
-    // Use RandomX to hash the concatenated transaction hashes
-    let key = b"blacksilk_merkle_root";
-    let flags = get_optimal_flags();
-    randomx_hash(key, &tx_hashes, flags)
-}
-
-/// Save chain to disk (persistence)
-pub fn save_chain_to_disk(chain: &crate::Chain, data_dir: &std::path::Path) {
-    use std::fs::{File, create_dir_all};
-    use std::io::Write;
-    use std::path::Path;
-    // Ensure data directory exists
-    if let Err(e) = create_dir_all(data_dir) {
-        println!("[Chain] Failed to create data dir {:?}: {}", data_dir, e);
-        return;
-    }
-    let chain_path = data_dir.join("chain.json");
-    if let Ok(chain_json) = serde_json::to_string_pretty(&chain.blocks) {
-        if let Ok(mut file) = File::create(&chain_path) {
-            let _ = file.write_all(chain_json.as_bytes());
-            println!("[Chain] Blockchain saved to disk at {:?}", chain_path);
-        } else {
-            println!("[Chain] Failed to create chain file at {:?}", chain_path);
-        }
-    } else {
-        println!("[Chain] Failed to serialize chain for saving");
-    }
+    /// Outstanding block templates keyed by the header bytes given to the miner
+    static ref BLOCK_TEMPLATES: std::sync::Mutex<HashMap<Vec<u8>, PendingBlockTemplate>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+/// Everything needed to rebuild the exact block a miner worked on
+#[derive(Clone)]
+struct PendingBlockTemplate {
+    height: u64,
+    prev_hash: [u8; 32],
+    merkle_root: [u8; 32],
+    timestamp: u64,
+    difficulty: u64,
+    coinbase_address: String,
+    transactions: Vec<Transaction>,
 }
 
 /// HTTP request/response types
@@ -66,19 +48,6 @@ pub struct GetBlocksResponse {
 }
 
 /// Marketplace data storage endpoints
-#[derive(Serialize, Deserialize)]
-pub struct MarketplaceDataRequest {
-    pub data: String, // Base64 encoded marketplace data
-    pub timestamp: i64,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct MarketplaceDataResponse {
-    pub tx_hash: String,
-    pub success: bool,
-    pub message: String,
-}
-
 #[derive(Serialize, Deserialize)]
 pub struct MarketplaceTransactionResponse {
     pub data: Option<String>, // Base64 encoded data
@@ -111,10 +80,25 @@ pub struct SubmitTransactionResponse {
     pub tx_hash: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize)]
 pub struct GetMempoolResponse {
     pub transactions: Vec<Transaction>,
     pub count: usize,
+    pub stats: crate::mempool::MempoolStats,
+}
+
+/// Fee for a transaction of `size` bytes at `priority`, with the rates
+/// for every priority level
+#[derive(Serialize)]
+pub struct EstimateFeeResponse {
+    pub size: usize,
+    pub priority: crate::fee_estimator::FeePriority,
+    pub target_blocks: u64,
+    pub fee_per_kb: u64,
+    pub fee: u64,
+    pub estimated: bool,
+    pub min_relay_fee_per_kb: u64,
+    pub estimates: Vec<crate::mempool::FeeEstimate>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -124,6 +108,19 @@ pub struct NodeInfoResponse {
     pub height: u64,
     pub peers: u32,
     pub difficulty: u64,
+    pub sync: crate::sync::SyncProgress,
+}
+
+/// Inclusion proof for a confirmed transaction; verify with
+/// `proof.verify(txid, merkle_root)` from `primitives::merkle`
+#[derive(Serialize, Deserialize)]
+pub struct TransactionProofResponse {
+    pub txid: String,
+    pub block_hash: String,
+    pub block_height: u64,
+    pub merkle_root: String,
+    pub confirmations: u64,
+    pub proof: primitives::merkle::MerkleProof,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -140,6 +137,8 @@ pub struct GetBlockTemplateResponse {
     pub height: u64,
     pub prev_hash: Vec<u8>,
     pub timestamp: u64,
+    pub merkle_root: Vec<u8>,
+    pub transactions: Vec<Transaction>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -157,18 +156,17 @@ pub struct SubmitBlockResponse {
 }
 
 /// Simple HTTP server implementation using std library
-pub async fn start_http_server(port: u16, data_dir: std::path::PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn start_http_server(port: u16) -> Result<(), Box<dyn std::error::Error>> {
     use std::net::TcpListener;
     use std::thread;
     let addr = format!("127.0.0.1:{}", port);
     let listener = TcpListener::bind(&addr)?;
     println!("[HTTP] Server listening on http://{}", addr);
     for stream in listener.incoming() {
-        let data_dir = data_dir.clone();
         match stream {
             Ok(stream) => {
                 thread::spawn(move || {
-                    if let Err(e) = handle_http_request(stream, &data_dir) {
+                    if let Err(e) = handle_http_request(HttpStream::Plain(stream), &HttpServerConfig::local(port)) {
                         eprintln!("[HTTP] Error handling request: {}", e);
                     }
                 });
@@ -181,21 +179,104 @@ pub async fn start_http_server(port: u16, data_dir: std::path::PathBuf) -> Resul
     Ok(())
 }
 
+/// How the API server accepts connections
+#[derive(Clone)]
+pub struct HttpServerConfig {
+    pub bind: SocketAddr,
+    /// User and password every request must present through HTTP basic auth
+    pub credentials: Option<(String, String)>,
+    /// Serve HTTPS instead of plain HTTP
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl HttpServerConfig {
+    /// Plain HTTP on localhost without authentication
+    pub fn local(port: u16) -> Self {
+        HttpServerConfig { bind: SocketAddr::from(([127, 0, 0, 1], port)), credentials: None, tls: None }
+    }
+}
+
+/// Server TLS settings from a PEM certificate chain and private key
+pub fn load_tls_config(cert: &Path, key: &Path) -> Result<Arc<rustls::ServerConfig>, Box<dyn std::error::Error>> {
+    let read = |path: &Path| std::fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e));
+    let certs = rustls_pemfile::certs(&mut read(cert)?.as_slice()).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", cert.display()).into());
+    }
+    let key = rustls_pemfile::private_key(&mut read(key)?.as_slice())?
+        .ok_or_else(|| format!("no private key in {}", key.display()))?;
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// A client connection, plain or TLS
+enum HttpStream {
+    Plain(TcpStream),
+    Tls(Box<rustls::StreamOwned<rustls::ServerConnection, TcpStream>>),
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            HttpStream::Plain(stream) => stream.read(buf),
+            HttpStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for HttpStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            HttpStream::Plain(stream) => stream.write(buf),
+            HttpStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            HttpStream::Plain(stream) => stream.flush(),
+            HttpStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
 /// Synchronous HTTP server startup (blocks current thread)
-pub fn start_http_server_sync(port: u16, data_dir: std::path::PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+pub fn start_http_server_sync(port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    start_http_server_with_config(HttpServerConfig::local(port))
+}
+
+/// Serve the API as configured (blocks current thread)
+pub fn start_http_server_with_config(config: HttpServerConfig) -> Result<(), Box<dyn std::error::Error>> {
     use std::net::TcpListener;
     use std::thread;
     
-    let addr = format!("127.0.0.1:{}", port);
-    let listener = TcpListener::bind(&addr)?;
-    println!("[HTTP] Server listening on http://{}", addr);
+    let listener = TcpListener::bind(config.bind)?;
+    let scheme = if config.tls.is_some() { "https" } else { "http" };
+    println!("[HTTP] Server listening on {}://{}", scheme, config.bind);
+    if config.credentials.is_some() {
+        println!("[HTTP] Requests require basic authentication");
+    }
+    let config = Arc::new(config);
     
     for stream in listener.incoming() {
-        let data_dir = data_dir.clone();
         match stream {
             Ok(stream) => {
+                let config = config.clone();
                 thread::spawn(move || {
-                    if let Err(e) = handle_http_request(stream, &data_dir) {
+                    let stream = match &config.tls {
+                        Some(tls) => match rustls::ServerConnection::new(tls.clone()) {
+                            Ok(conn) => HttpStream::Tls(Box::new(rustls::StreamOwned::new(conn, stream))),
+                            Err(e) => {
+                                eprintln!("[HTTP] TLS setup failed: {}", e);
+                                return;
+                            }
+                        },
+                        None => HttpStream::Plain(stream),
+                    };
+                    if let Err(e) = handle_http_request(stream, &config) {
                         eprintln!("[HTTP] Error handling request: {}", e);
                     }
                 });
@@ -209,10 +290,30 @@ pub fn start_http_server_sync(port: u16, data_dir: std::path::PathBuf) -> Result
     Ok(())
 }
 
-fn handle_http_request(mut stream: TcpStream, data_dir: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+/// Whether an `Authorization` header value carries `user`/`password`
+fn is_authorized(header: Option<&String>, user: &str, password: &str) -> bool {
+    use base64::Engine;
+    let Some(encoded) = header.and_then(|value| value.strip_prefix("Basic ")) else {
+        return false;
+    };
+    let expected = format!("{}:{}", user, password);
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .is_ok_and(|decoded| constant_time_eq(&decoded, expected.as_bytes()))
+}
+
+/// Compare digests of both sides byte by byte without stopping at the first
+/// difference, so response times reveal neither the length nor how much of
+/// a guess was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (primitives::encoding::sha256(a), primitives::encoding::sha256(b));
+    a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn handle_http_request(mut stream: HttpStream, config: &HttpServerConfig) -> Result<(), Box<dyn std::error::Error>> {
     use std::io::{BufRead, BufReader};
     
-    let mut reader = BufReader::new(&stream);
+    let mut reader = BufReader::new(&mut stream);
     let mut request_line = String::new();
     reader.read_line(&mut request_line)?;
     
@@ -269,6 +370,13 @@ fn handle_http_request(mut stream: TcpStream, data_dir: &std::path::Path) -> Res
         println!("[HTTP] POST request with no content-length or content-length = 0");
     }
     
+    if let Some((user, password)) = &config.credentials {
+        if !is_authorized(headers.get("authorization"), user, password) {
+            send_unauthorized(&mut stream)?;
+            return Ok(());
+        }
+    }
+    
     // Route the request
     println!("[HTTP] Routing: method='{}', path='{}', body_len={}", method, path, body.len());
     match (method, path) {
@@ -284,6 +392,10 @@ fn handle_http_request(mut stream: TcpStream, data_dir: &std::path::Path) -> Res
             println!("[HTTP] Matched: GET /mempool");
             handle_get_mempool(&mut stream)?;
         }
+        ("GET", path) if path.starts_with("/api/estimate_fee") => {
+            println!("[HTTP] Matched: GET /api/estimate_fee");
+            handle_estimate_fee(&mut stream, path)?;
+        }
         ("GET", "/info") => {
             println!("[HTTP] Matched: GET /info");
             handle_node_info(&mut stream)?;
@@ -298,11 +410,15 @@ fn handle_http_request(mut stream: TcpStream, data_dir: &std::path::Path) -> Res
         }
         ("POST", "/submit_block") => {
             println!("[HTTP] Matched: POST /submit_block");
-            handle_submit_block(&mut stream, &body, data_dir)?;
+            handle_submit_block(&mut stream, &body)?;
+        }
+        ("GET", path) if path.starts_with("/tx_proof/") => {
+            println!("[HTTP] Matched: GET /tx_proof");
+            handle_transaction_proof(&mut stream, path)?;
         }
         // Marketplace data storage endpoints
         ("POST", "/api/marketplace/data") => {
-            handle_marketplace_data_submit(&mut stream, &body)?;
+            send_error_response(&mut stream, 410, MARKETPLACE_SUBMIT_GONE)?;
         }
         ("GET", path) if path.starts_with("/api/marketplace/data/") => {
             handle_marketplace_data_get(&mut stream, path)?;
@@ -319,6 +435,27 @@ fn handle_http_request(mut stream: TcpStream, data_dir: &std::path::Path) -> Res
         ("GET", path) if path.starts_with("/api/contract/state/") => {
             handle_contract_state_query(&mut stream, path)?;
         }
+        ("POST", "/api/network/discover") => {
+            handle_network_discover(&mut stream)?;
+        }
+        ("POST", "/api/network/connect") => {
+            handle_network_connect(&mut stream, &body)?;
+        }
+        ("POST", "/api/network/disconnect") => {
+            handle_network_disconnect(&mut stream, &body)?;
+        }
+        ("POST", "/api/network/ping") => {
+            handle_network_ping(&mut stream, &body)?;
+        }
+        ("POST", "/api/network/ban") => {
+            handle_network_ban(&mut stream, &body)?;
+        }
+        ("POST", "/api/network/unban") => {
+            handle_network_unban(&mut stream, &body)?;
+        }
+        ("GET", "/api/network/bans") => {
+            handle_network_bans(&mut stream)?;
+        }
         _ => {
             println!("[HTTP] No route matched for: {} {}", method, path);
             send_error_response(&mut stream, 404, "Not Found")?;
@@ -328,7 +465,7 @@ fn handle_http_request(mut stream: TcpStream, data_dir: &std::path::Path) -> Res
     Ok(())
 }
 
-fn handle_get_blocks(stream: &mut TcpStream, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_get_blocks(stream: &mut HttpStream, path: &str) -> Result<(), Box<dyn std::error::Error>> {
     // Parse query parameters
     let from_height = if let Some(query_start) = path.find('?') {
         let query = &path[query_start + 1..];
@@ -338,11 +475,7 @@ fn handle_get_blocks(stream: &mut TcpStream, path: &str) -> Result<(), Box<dyn s
     };
     
     let chain = CHAIN.lock().unwrap();
-    let blocks: Vec<Block> = chain.blocks
-        .iter()
-        .filter(|block| block.header.height >= from_height)
-        .cloned()
-        .collect();
+    let blocks: Vec<Block> = chain.blocks_from(from_height);
     
     // Check if client expects simple format (for wallet compatibility)
     let use_simple_format = path.contains("simple=true") || path.contains("wallet=true");
@@ -353,7 +486,7 @@ fn handle_get_blocks(stream: &mut TcpStream, path: &str) -> Result<(), Box<dyn s
     } else {
         // Return full response with metadata
         let response = GetBlocksResponse {
-            total_height: chain.blocks.len() as u64,
+            total_height: chain.block_count(),
             blocks,
         };
         send_json_response(stream, 200, &response)?;
@@ -362,24 +495,52 @@ fn handle_get_blocks(stream: &mut TcpStream, path: &str) -> Result<(), Box<dyn s
     Ok(())
 }
 
-fn handle_submit_transaction(stream: &mut TcpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_transaction_proof(stream: &mut HttpStream, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Extract the transaction id from path: /tx_proof/{txid}
+    let txid_hex = path.strip_prefix("/tx_proof/").ok_or("Invalid path format")?;
+    let txid: primitives::types::Hash = match hex::decode(txid_hex).ok().and_then(|b| b.try_into().ok()) {
+        Some(id) => id,
+        None => return send_error_response(stream, 400, "Invalid transaction id"),
+    };
+    
+    let chain = CHAIN.lock().unwrap();
+    match chain.transaction_proof(&txid) {
+        Some((block, proof)) => {
+            let response = TransactionProofResponse {
+                txid: hex::encode(txid),
+                block_hash: hex::encode(block.hash()),
+                block_height: block.header.height,
+                merkle_root: hex::encode(block.header.merkle_root),
+                confirmations: chain.tip().header.height - block.header.height + 1,
+                proof,
+            };
+            send_json_response(stream, 200, &response)
+        }
+        None => send_error_response(stream, 404, "Transaction not found"),
+    }
+}
+
+fn handle_submit_transaction(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
     let body_str = std::str::from_utf8(body)?;
     
     match serde_json::from_str::<Transaction>(body_str) {
         Ok(transaction) => {
             if validate_transaction(&transaction) {
-                add_to_mempool(transaction.clone());
-                
-                // Broadcast to P2P network
-                use crate::{broadcast_message, P2PMessage};
-                broadcast_message(&P2PMessage::Transaction(transaction.clone()));
-                
-                let response = SubmitTransactionResponse {
-                    success: true,
-                    message: "Transaction accepted".to_string(),
-                    tx_hash: Some("0x".to_string() + &format!("{:x}", sha2::Sha256::digest(&serde_json::to_vec(&transaction).unwrap_or_default()))),
+                // Stem it through Dandelion++, or add and broadcast it
+                let response = match crate::submit_transaction(transaction.clone()) {
+                    Ok(()) => SubmitTransactionResponse {
+                        success: true,
+                        message: "Transaction accepted".to_string(),
+                        tx_hash: Some("0x".to_string() + &hex::encode(transaction.id())),
+                    },
+                    Err(e) => SubmitTransactionResponse {
+                        success: false,
+                        message: format!("Transaction not accepted: {}", e),
+                        tx_hash: None,
+                    },
                 };
-                send_json_response(stream, 200, &response)?;
+                let status = if response.success { 200 } else { 400 };
+                send_json_response(stream, status, &response)?;
             } else {
                 let response = SubmitTransactionResponse {
                     success: false,
@@ -402,31 +563,59 @@ fn handle_submit_transaction(stream: &mut TcpStream, body: &[u8]) -> Result<(),
     Ok(())
 }
 
-fn handle_get_mempool(stream: &mut TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_get_mempool(stream: &mut HttpStream) -> Result<(), Box<dyn std::error::Error>> {
     let mempool = MEMPOOL.lock().unwrap();
     let response = GetMempoolResponse {
         count: mempool.len(),
-        transactions: mempool.clone(),
+        transactions: mempool.transactions(),
+        stats: mempool.stats(),
     };
     
     send_json_response(stream, 200, &response)?;
     Ok(())
 }
 
-fn handle_node_info(stream: &mut TcpStream) -> Result<(), Box<dyn std::error::Error>> {
-    use crate::{current_network, PEER_COUNT};
+/// Size assumed when `/api/estimate_fee` is not given one
+const DEFAULT_ESTIMATE_SIZE: usize = 1_000;
+
+fn handle_estimate_fee(stream: &mut HttpStream, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::fee_estimator::FeePriority;
+
+    let query = path.split_once('?').map_or("", |(_, query)| query);
+    let size = parse_query_param(query, "size").map_or(DEFAULT_ESTIMATE_SIZE, |size| size as usize);
+    let priority = match query.split('&').find_map(|pair| pair.strip_prefix("priority=")) {
+        None => FeePriority::Normal,
+        Some(name) => match FeePriority::parse(name) {
+            Some(priority) => priority,
+            None => return send_error_response(stream, 400, "Unknown priority (use low, normal or high)"),
+        },
+    };
+
+    let mempool = MEMPOOL.lock().unwrap();
+    let estimate = mempool.estimate_fee(priority);
+    let response = EstimateFeeResponse {
+        size,
+        priority,
+        target_blocks: estimate.target_blocks,
+        fee_per_kb: estimate.fee_per_kb,
+        fee: estimate.fee_for_size(size),
+        estimated: estimate.estimated,
+        min_relay_fee_per_kb: mempool.min_fee_per_byte().saturating_mul(1000),
+        estimates: FeePriority::ALL.iter().map(|p| mempool.estimate_fee(*p)).collect(),
+    };
+    send_json_response(stream, 200, &response)
+}
+
+fn handle_node_info(stream: &mut HttpStream) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::current_network;
     
     let chain = CHAIN.lock().unwrap();
-    let current_height = chain.blocks.len() as u64;
+    let current_height = chain.block_count();
     let network = current_network();
-    let current_difficulty = if current_height > 0 {
-        chain.tip().header.difficulty
-    } else {
-        network.get_difficulty()
-    };
+    let current_difficulty = chain.next_difficulty();
     
-    // Get peer count from global atomic counter
-    let peer_count = PEER_COUNT.load(std::sync::atomic::Ordering::Relaxed);
+    let peer_count = crate::peer_count() as u32;
+    let sync = crate::sync::SYNC.lock().unwrap().progress(&chain);
     
     let response = NodeInfoResponse {
         version: env!("CARGO_PKG_VERSION").to_string(),
@@ -434,19 +623,161 @@ fn handle_node_info(stream: &mut TcpStream) -> Result<(), Box<dyn std::error::Er
         height: current_height,
         peers: peer_count,
         difficulty: current_difficulty,
+        sync,
     };
     
     send_json_response(stream, 200, &response)?;
     Ok(())
 }
 
+/// Resolve the seeds again and ask connected peers for addresses
+fn handle_network_discover(stream: &mut HttpStream) -> Result<(), Box<dyn std::error::Error>> {
+    match crate::network::peer_manager::peer_manager() {
+        Some(manager) => send_json_response(stream, 200, &manager.discover()),
+        None => send_error_response(stream, 503, "P2P networking is not running"),
+    }
+}
+
+/// Body of the peer admin endpoints
+#[derive(Deserialize)]
+struct PeerAdminRequest {
+    address: String,
+    /// Ban length, `/api/network/ban` only
+    #[serde(default)]
+    duration_secs: Option<u64>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PeerAdminResponse {
+    address: String,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rtt_ms: Option<f64>,
+}
+
+/// The running peer manager, answering 503 if there is none
+fn running_peer_manager(stream: &mut HttpStream) -> Result<Option<&'static Arc<PeerManager>>, Box<dyn std::error::Error>> {
+    let manager = crate::network::peer_manager::peer_manager();
+    if manager.is_none() {
+        send_error_response(stream, 503, "P2P networking is not running")?;
+    }
+    Ok(manager)
+}
+
+fn parse_peer_admin_request(stream: &mut HttpStream, body: &[u8]) -> Result<Option<PeerAdminRequest>, Box<dyn std::error::Error>> {
+    match serde_json::from_slice(body) {
+        Ok(request) => Ok(Some(request)),
+        Err(e) => {
+            send_error_response(stream, 400, &format!("Invalid request: {}", e))?;
+            Ok(None)
+        }
+    }
+}
+
+/// Parse `ip:port`, answering 400 if it is not one
+fn peer_socket_addr(stream: &mut HttpStream, address: &str) -> Result<Option<std::net::SocketAddr>, Box<dyn std::error::Error>> {
+    match crate::network::proxy::Endpoint::parse(address) {
+        Ok(endpoint) => Ok(Some(endpoint.peer_addr())),
+        Err(_) => {
+            send_error_response(stream, 400, &format!("Invalid peer address: {}", address))?;
+            Ok(None)
+        }
+    }
+}
+
+fn handle_network_connect(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::network::peer_manager::{connect_timeout, PeerError};
+
+    let Some(manager) = running_peer_manager(stream)? else { return Ok(()) };
+    let Some(request) = parse_peer_admin_request(stream, body)? else { return Ok(()) };
+    let Some(addr) = peer_socket_addr(stream, &request.address)? else { return Ok(()) };
+    match manager.connect(addr) {
+        Ok(()) | Err(PeerError::AlreadyConnected(_)) => {}
+        Err(e) => return send_error_response(stream, 409, &e.to_string()),
+    }
+    // Answer once the handshake is done so the caller knows the peer is usable
+    let deadline = std::time::Instant::now() + connect_timeout(&addr) + crate::network::wire::HANDSHAKE_TIMEOUT;
+    while !manager.ready_peers().contains(&addr) {
+        if std::time::Instant::now() > deadline {
+            return send_error_response(stream, 504, &format!("Could not connect to {}", addr));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    send_json_response(stream, 200, &PeerAdminResponse { address: request.address, success: true, rtt_ms: None })
+}
+
+fn handle_network_disconnect(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(manager) = running_peer_manager(stream)? else { return Ok(()) };
+    let Some(request) = parse_peer_admin_request(stream, body)? else { return Ok(()) };
+    let Some(addr) = peer_socket_addr(stream, &request.address)? else { return Ok(()) };
+    if !manager.disconnect(addr) {
+        return send_error_response(stream, 404, &format!("Not connected to {}", addr));
+    }
+    send_json_response(stream, 200, &PeerAdminResponse { address: addr.to_string(), success: true, rtt_ms: None })
+}
+
+fn handle_network_ping(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::network::peer_manager::PeerError;
+
+    let Some(manager) = running_peer_manager(stream)? else { return Ok(()) };
+    let Some(request) = parse_peer_admin_request(stream, body)? else { return Ok(()) };
+    let Some(addr) = peer_socket_addr(stream, &request.address)? else { return Ok(()) };
+    match manager.ping(addr, std::time::Duration::from_secs(10)) {
+        Ok(rtt) => send_json_response(stream, 200, &PeerAdminResponse {
+            address: addr.to_string(),
+            success: true,
+            rtt_ms: Some(rtt.as_secs_f64() * 1000.0),
+        }),
+        Err(e @ PeerError::NotConnected(_)) => send_error_response(stream, 404, &e.to_string()),
+        Err(e) => send_error_response(stream, 504, &e.to_string()),
+    }
+}
+
+fn handle_network_ban(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(manager) = running_peer_manager(stream)? else { return Ok(()) };
+    let Some(request) = parse_peer_admin_request(stream, body)? else { return Ok(()) };
+    // Bans are per IP; accept a peer's `ip:port` as well
+    let ip = match request.address.parse::<std::net::IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => match peer_socket_addr(stream, &request.address)? {
+            Some(addr) => addr.ip(),
+            None => return Ok(()),
+        },
+    };
+    let duration = request.duration_secs.map_or(manager.config().ban_duration, std::time::Duration::from_secs);
+    let reason = request.reason.as_deref().unwrap_or("banned by operator");
+    match manager.ban(ip, duration, reason) {
+        Ok(ban) => send_json_response(stream, 200, &ban),
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => send_error_response(stream, 400, &e.to_string()),
+        Err(e) => send_error_response(stream, 500, &format!("Banned {} but could not save the ban list: {}", ip, e)),
+    }
+}
+
+fn handle_network_unban(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(manager) = running_peer_manager(stream)? else { return Ok(()) };
+    let Some(request) = parse_peer_admin_request(stream, body)? else { return Ok(()) };
+    let Ok(ip) = request.address.parse::<std::net::IpAddr>() else {
+        return send_error_response(stream, 400, &format!("Invalid IP address: {}", request.address));
+    };
+    match manager.unban(&ip) {
+        Ok(true) => send_json_response(stream, 200, &PeerAdminResponse { address: ip.to_string(), success: true, rtt_ms: None }),
+        Ok(false) => send_error_response(stream, 404, &format!("{} is not banned", ip)),
+        Err(e) => send_error_response(stream, 500, &format!("Unbanned {} but could not save the ban list: {}", ip, e)),
+    }
+}
+
+fn handle_network_bans(stream: &mut HttpStream) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(manager) = running_peer_manager(stream)? else { return Ok(()) };
+    send_json_response(stream, 200, &manager.banned())
+}
+
 fn send_json_response<T: Serialize>(
-    stream: &mut TcpStream,
+    stream: &mut HttpStream,
     status: u16,
     data: &T,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    use std::io::Write;
-    
     let json = serde_json::to_string(data)?;
     let response = format!(
         "HTTP/1.1 {} OK\r\n\
@@ -467,13 +798,29 @@ fn send_json_response<T: Serialize>(
     Ok(())
 }
 
+fn send_unauthorized(stream: &mut HttpStream) -> Result<(), Box<dyn std::error::Error>> {
+    let message = "Unauthorized";
+    let response = format!(
+        "HTTP/1.1 401 {}\r\n\
+         WWW-Authenticate: Basic realm=\"blacksilk\"\r\n\
+         Content-Type: text/plain\r\n\
+         Content-Length: {}\r\n\
+         \r\n\
+         {}",
+        message,
+        message.len(),
+        message
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
 fn send_error_response(
-    stream: &mut TcpStream,
+    stream: &mut HttpStream,
     status: u16,
     message: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    use std::io::Write;
-    
     let response = format!(
         "HTTP/1.1 {} {}\r\n\
          Content-Type: text/plain\r\n\
@@ -505,48 +852,83 @@ fn parse_query_param(query: &str, param: &str) -> Option<u64> {
     None
 }
 
-fn handle_get_block_template(stream: &mut TcpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-    use crate::{CHAIN, MEMPOOL, current_network};
+fn handle_get_block_template(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::{CHAIN, MEMPOOL, coinbase_transaction, select_block_transactions, transaction_size, config};
     
     match serde_json::from_slice::<GetBlockTemplateRequest>(body) {
         Ok(req) => {
             let chain = CHAIN.lock().unwrap();
-            let _mempool = MEMPOOL.lock().unwrap();
+            let mempool = MEMPOOL.lock().unwrap();
             
             // Get the latest block
             let prev_block = chain.blocks.back().unwrap();
             let height = prev_block.header.height + 1;
+            // Never go backwards past the median of recent blocks
             let timestamp = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
-                .as_secs();
+                .as_secs()
+                .max(chain.median_time_past() + 1);
             
-            // Get current network and calculate difficulty
-            let network = current_network();
-            let difficulty = network.calculate_next_difficulty(&chain);
+            // Difficulty the chain will require for the next block
+            let difficulty = chain.next_difficulty();
             
-            // Create block template with mempool transactions
-            let header_data = format!("{}:{}:{}:{}", 
-                height, 
-                hex::encode(&prev_block.header.pow.hash), 
-                timestamp, 
-                req.address
-            );
+            // Coinbase first, then the best-paying mempool transactions that fit
+            let coinbase = coinbase_transaction(height, &req.address);
+            let budget = config::MAX_BLOCK_SIZE.saturating_sub(transaction_size(&coinbase));
+            let mut transactions = vec![coinbase];
+            transactions.extend(select_block_transactions(&chain, &mempool.transactions(), budget, mempool.min_fee_per_byte()));
+            let merkle_root = calculate_merkle_root(&transactions);
+            
+            // The miner hashes the canonical header encoding followed by the nonce
+            let candidate = primitives::BlockHeader {
+                version: 1,
+                prev_hash: prev_block.hash(),
+                merkle_root,
+                timestamp,
+                height,
+                difficulty,
+                pow: primitives::Pow { nonce: 0, hash: [0; 32] },
+            };
+            let header_data = candidate.signing_bytes();
             
             // Generate RandomX seed from previous block hash
-            let seed = prev_block.header.pow.hash.to_vec();
+            let seed = prev_block.hash().to_vec();
+            let prev_hash = prev_block.hash();
+            
+            {
+                let mut templates = BLOCK_TEMPLATES.lock().unwrap();
+                // Templates built on an older tip can no longer be submitted
+                templates.retain(|_, t| t.prev_hash == prev_hash);
+                if templates.len() >= MAX_PENDING_TEMPLATES {
+                    if let Some(oldest) = templates.iter().min_by_key(|(_, t)| t.timestamp).map(|(k, _)| k.clone()) {
+                        templates.remove(&oldest);
+                    }
+                }
+                templates.insert(header_data.clone(), PendingBlockTemplate {
+                    height,
+                    prev_hash,
+                    merkle_root,
+                    timestamp,
+                    difficulty,
+                    coinbase_address: req.address.clone(),
+                    transactions: transactions.clone(),
+                });
+            }
             
             let response = GetBlockTemplateResponse {
-                header: header_data.as_bytes().to_vec(),
+                header: header_data,
                 difficulty, // Use calculated difficulty instead of hardcoded value
                 seed,
                 coinbase_address: req.address,
                 height,
-                prev_hash: prev_block.header.pow.hash.to_vec(),
+                prev_hash: prev_hash.to_vec(),
                 timestamp,
+                merkle_root: merkle_root.to_vec(),
+                transactions,
             };
             
-            println!("[Mining] Block template generated - Height: {}, Difficulty: {}", height, difficulty);
+            println!("[Mining] Block template generated - Height: {}, Difficulty: {}, Transactions: {}", height, difficulty, response.transactions.len() - 1);
             send_json_response(stream, 200, &response)?;
         }
         Err(e) => {
@@ -561,10 +943,9 @@ fn handle_get_block_template(stream: &mut TcpStream, body: &[u8]) -> Result<(),
     Ok(())
 }
 
-fn handle_submit_block(stream: &mut TcpStream, body: &[u8], data_dir: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
-    use crate::{CHAIN, current_network, broadcast_message, P2PMessage};
+fn handle_submit_block(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    use crate::CHAIN;
     use primitives::{Block, BlockHeader, Coinbase, Pow};
-    use std::time::{SystemTime, UNIX_EPOCH};
     use std::sync::MutexGuard;
     use std::panic;
 
@@ -586,25 +967,41 @@ fn handle_submit_block(stream: &mut TcpStream, body: &[u8], data_dir: &std::path
         let peer_id = req.miner_address.as_deref().unwrap_or("unknown");
         println!("[HTTP] Parsed block submission from peer: {}", peer_id);
 
-        // Step 2: Build block header (need chain tip for prev_hash/height, so get tip under lock, then release)
-        let (prev_hash, prev_height, emission, current_difficulty) = {
+        // Step 2: Rebuild the block from the template the miner worked on
+        let template = match BLOCK_TEMPLATES.lock().unwrap().get(&req.header).cloned() {
+            Some(t) => t,
+            None => {
+                let response = SubmitBlockResponse {
+                    success: false,
+                    message: "Unknown or stale block template".to_string(),
+                };
+                send_json_response(stream, 400, &response)?;
+                return Ok::<(), Box<dyn std::error::Error>>(());
+            }
+        };
+        // Blocks from peers do not clear the templates; catch one that no
+        // longer extends the tip before paying for RandomX verification
+        let (emission, expected_difficulty) = {
             let chain = CHAIN.lock().unwrap();
-            let prev_block = chain.tip();
-            (
-                prev_block.header.pow.hash,
-                prev_block.header.height,
-                chain.emission.clone(),
-                current_network().get_difficulty(),
-            )
+            let extends_tip = chain.tip().hash() == template.prev_hash;
+            (chain.emission.clone(), extends_tip.then(|| chain.next_difficulty()))
         };
-        let new_height = prev_height + 1;
+        if expected_difficulty != Some(template.difficulty) {
+            let response = SubmitBlockResponse {
+                success: false,
+                message: "Block template is stale, request a new one".to_string(),
+            };
+            send_json_response(stream, 400, &response)?;
+            return Ok::<(), Box<dyn std::error::Error>>(());
+        }
+        let new_height = template.height;
         let block_header = BlockHeader {
             version: 1,
-            prev_hash,
-            merkle_root: calculate_merkle_root(&[]),
-            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            prev_hash: template.prev_hash,
+            merkle_root: template.merkle_root,
+            timestamp: template.timestamp,
             height: new_height,
-            difficulty: current_difficulty,
+            difficulty: template.difficulty,
             pow: Pow {
                 nonce: req.nonce,
                 hash: req.hash.clone().try_into().unwrap_or([0; 32]),
@@ -626,34 +1023,10 @@ fn handle_submit_block(stream: &mut TcpStream, body: &[u8], data_dir: &std::path
             send_json_response(stream, 400, &response)?;
             return Ok::<(), Box<dyn std::error::Error>>(());
         }
-        let hash_val = if req.hash.len() >= 8 {
-            u64::from_le_bytes([
-                req.hash[0], req.hash[1], req.hash[2], req.hash[3],
-                req.hash[4], req.hash[5], req.hash[6], req.hash[7]
-            ])
-        } else {
-            u64::MAX
-        };
-        if hash_val >= current_difficulty {
-            let response = SubmitBlockResponse {
-                success: false,
-                message: format!("Block does not meet difficulty target (hash: {}, difficulty: {})", hash_val, current_difficulty),
-            };
-            send_json_response(stream, 400, &response)?;
-            return Ok::<(), Box<dyn std::error::Error>>(());
-        }
         // Step 4: Build block (coinbase, etc.)
         let block_reward = emission.block_reward(new_height);
-        let miner_address = req.miner_address
-            .unwrap_or_else(|| {
-                let header_str = String::from_utf8_lossy(&req.header);
-                let parts: Vec<&str> = header_str.split(':').collect();
-                if parts.len() >= 4 {
-                    parts[3].to_string()
-                } else {
-                    "unknown_miner".to_string()
-                }
-            });
+        // The template's coinbase transaction already commits to this address
+        let miner_address = template.coinbase_address.clone();
         let coinbase = Coinbase {
             reward: block_reward,
             to: miner_address.clone(),
@@ -661,18 +1034,24 @@ fn handle_submit_block(stream: &mut TcpStream, body: &[u8], data_dir: &std::path
         let new_block = Block {
             header: block_header,
             coinbase,
-            transactions: vec![],
+            transactions: template.transactions,
         };
         // Step 5: Lock chain, add block, save, broadcast
         println!("[HTTP] Adding block to chain...");
         let mut chain = CHAIN.lock().unwrap();
-        if chain.add_block(new_block.clone()) {
+        if let Err(e) = chain.add_block(new_block.clone()) {
+            let response = SubmitBlockResponse {
+                success: false,
+                message: format!("Block validation failed during chain addition: {}", e),
+            };
+            send_json_response(stream, 400, &response)?;
+        } else {
             let hash_hex = hex::encode(&req.hash);
-            println!("[HTTP] Saving chain to disk...");
-            save_chain_to_disk(&chain, data_dir);
+            crate::remove_block_transactions_from_mempool(&new_block);
+            BLOCK_TEMPLATES.lock().unwrap().clear();
             drop(chain);
-            println!("[HTTP] Broadcasting new block to P2P network...");
-            broadcast_message(&P2PMessage::Block(new_block));
+            println!("[HTTP] Announcing new block to the P2P network...");
+            crate::relay_block(&new_block);
             println!("[Mining] ✅ Block {} created and added to chain! Hash: {}", new_height, hash_hex);
             println!("[Mining] Block reward: {} atomic units to {}", block_reward, miner_address);
             println!("[RandomX] CPU-only verification PASSED - Block accepted");
@@ -681,12 +1060,6 @@ fn handle_submit_block(stream: &mut TcpStream, body: &[u8], data_dir: &std::path
                 message: format!("Block {} accepted and added to chain with hash: {} (RandomX verified)", new_height, hash_hex),
             };
             send_json_response(stream, 200, &response)?;
-        } else {
-            let response = SubmitBlockResponse {
-                success: false,
-                message: "Block validation failed during chain addition".to_string(),
-            };
-            send_json_response(stream, 400, &response)?;
         }
         Ok(())
     }));
@@ -703,93 +1076,34 @@ fn handle_submit_block(stream: &mut TcpStream, body: &[u8], data_dir: &std::path
 }
 
 // Marketplace data storage functions
-fn handle_marketplace_data_submit(stream: &mut TcpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-    let request: MarketplaceDataRequest = serde_json::from_slice(body)?;
-    
-    // Decode the base64 data using the new API
-    use base64::{Engine as _, engine::general_purpose::STANDARD};
-    let marketplace_data = STANDARD.decode(&request.data)?;
-    
-    // Create a professional marketplace data transaction
-    use sha2::{Sha256, Digest};
-    let mut signature_hasher = Sha256::new();
-    Digest::update(&mut signature_hasher, &marketplace_data);
-    Digest::update(&mut signature_hasher, &request.timestamp.to_le_bytes());
-    Digest::update(&mut signature_hasher, b"MARKETPLACE_DATA");
-    let signature = hex::encode(signature_hasher.finalize());
-    
-    // Create a transaction with marketplace data in metadata
-    let tx = Transaction {
-        kind: primitives::TransactionKind::Payment, // Data-only tx, not a contract
-        inputs: vec![], // No financial inputs for data storage
-        outputs: vec![], // No financial outputs for data storage
-        fee: 0, // No fee for data storage transactions
-        extra: vec![], // Marketplace data stored in metadata field
-        metadata: Some(format!("MARKETPLACE:{}", request.data)), // Store the base64 marketplace data
-        signature, // Cryptographic signature of the data and timestamp
-        quantum_signature: None, // Add this field for quantum support
-    };
-    
-    // Add to mempool for blockchain inclusion
-    add_to_mempool(tx.clone());
-    
-    // Calculate transaction hash
-    let tx_bytes = serde_json::to_vec(&tx)?;
-    let mut hasher = sha2::Sha256::new();
-    hasher.update(&tx_bytes);
-    let tx_hash = hex::encode(hasher.finalize());
-    
-    println!("[Marketplace] 📦 Stored marketplace data with hash: {}", tx_hash);
-    
-    let response = MarketplaceDataResponse {
-        tx_hash,
-        success: true,
-        message: "Marketplace data stored successfully".to_string(),
-    };
-    
-    send_json_response(stream, 200, &response)
-}
-
-fn handle_marketplace_data_get(stream: &mut TcpStream, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_marketplace_data_get(stream: &mut HttpStream, path: &str) -> Result<(), Box<dyn std::error::Error>> {
     // Extract transaction hash from path: /api/marketplace/data/{hash}
     let hash = path.strip_prefix("/api/marketplace/data/")
         .ok_or("Invalid path format")?;
     
-    // Search for the transaction in the blockchain
+    // Look the transaction up in the chain's transaction index
     let chain = CHAIN.lock().unwrap();
+    let txid: Option<primitives::types::Hash> = hex::decode(hash).ok().and_then(|b| b.try_into().ok());
     
-    for block in &chain.blocks {
-        for tx in &block.transactions {
-            // Calculate this transaction's hash
-            let tx_bytes = serde_json::to_vec(tx).unwrap_or_default();
-            let mut hasher = sha2::Sha256::new();
-            hasher.update(&tx_bytes);
-            let tx_hash = hex::encode(hasher.finalize());
-            
-            if tx_hash == hash {
-                // Check if this is a marketplace transaction
-                if let Some(metadata) = &tx.metadata {
-                    if metadata.starts_with("MARKETPLACE:") {
-                        let data = metadata.strip_prefix("MARKETPLACE:").unwrap_or("");
-                        let response = MarketplaceTransactionResponse {
-                            data: Some(data.to_string()),
-                            timestamp: Some(block.header.timestamp as i64),
-                            block_height: Some(block.header.height),
-                        };
-                        return send_json_response(stream, 200, &response);
-                    }
-                }
+    if let Some((block, index)) = txid.and_then(|id| chain.find_transaction(&id)) {
+        // Check if this is a marketplace transaction
+        if let Some(metadata) = &block.transactions[index].metadata {
+            if metadata.starts_with("MARKETPLACE:") {
+                let data = metadata.strip_prefix("MARKETPLACE:").unwrap_or("");
+                let response = MarketplaceTransactionResponse {
+                    data: Some(data.to_string()),
+                    timestamp: Some(block.header.timestamp as i64),
+                    block_height: Some(block.header.height),
+                };
+                return send_json_response(stream, 200, &response);
             }
         }
     }
     
     // Also check mempool for pending transactions
     let mempool = MEMPOOL.lock().unwrap();
-    for tx in &*mempool {
-        let tx_bytes = serde_json::to_vec(tx).unwrap_or_default();
-        let mut hasher = sha2::Sha256::new();
-        hasher.update(&tx_bytes);
-        let tx_hash = hex::encode(hasher.finalize());
+    for tx in mempool.iter() {
+        let tx_hash = hex::encode(tx.id());
         
         if tx_hash == hash {
             if let Some(metadata) = &tx.metadata {
@@ -810,23 +1124,20 @@ fn handle_marketplace_data_get(stream: &mut TcpStream, path: &str) -> Result<(),
     send_error_response(stream, 404, "Transaction not found")
 }
 
-fn handle_marketplace_transactions_get(stream: &mut TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_marketplace_transactions_get(stream: &mut HttpStream) -> Result<(), Box<dyn std::error::Error>> {
     let mut transactions = Vec::new();
     
     // Get all marketplace transactions from the blockchain
     let chain = CHAIN.lock().unwrap();
     
-    for block in &chain.blocks {
+    for block in chain.blocks_from(0) {
         for tx in &block.transactions {
             if let Some(metadata) = &tx.metadata {
                 if metadata.starts_with("MARKETPLACE:") {
                     let data = metadata.strip_prefix("MARKETPLACE:").unwrap_or("");
                     
                     // Calculate transaction hash
-                    let tx_bytes = serde_json::to_vec(tx).unwrap_or_default();
-                    let mut hasher = sha2::Sha256::new();
-                    hasher.update(&tx_bytes);
-                    let tx_hash = hex::encode(hasher.finalize());
+                    let tx_hash = hex::encode(tx.id());
                     
                     transactions.push(MarketplaceTransaction {
                         tx_hash,
@@ -841,15 +1152,12 @@ fn handle_marketplace_transactions_get(stream: &mut TcpStream) -> Result<(), Box
     
     // Also include pending transactions from mempool
     let mempool = MEMPOOL.lock().unwrap();
-    for tx in &*mempool {
+    for tx in mempool.iter() {
         if let Some(metadata) = &tx.metadata {
             if metadata.starts_with("MARKETPLACE:") {
                 let data = metadata.strip_prefix("MARKETPLACE:").unwrap_or("");
                 
-                let tx_bytes = serde_json::to_vec(tx).unwrap_or_default();
-                let mut hasher = sha2::Sha256::new();
-                hasher.update(&tx_bytes);
-                let tx_hash = hex::encode(hasher.finalize());
+                let tx_hash = hex::encode(tx.id());
                 
                 transactions.push(MarketplaceTransaction {
                     tx_hash,
@@ -877,7 +1185,7 @@ struct ContractDeployResponse {
     success: bool,
     message: String,
 }
-fn handle_contract_deploy(stream: &mut TcpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_contract_deploy(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
     let req: ContractDeployRequest = serde_json::from_slice(body)?;
     match wasm_vm::deploy_contract(req.wasm_code, req.creator) {
         Ok(address) => send_json_response(stream, 200, &ContractDeployResponse {
@@ -905,7 +1213,7 @@ struct ContractInvokeResponse {
     success: bool,
     message: String,
 }
-fn handle_contract_invoke(stream: &mut TcpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_contract_invoke(stream: &mut HttpStream, body: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
     let req: ContractInvokeRequest = serde_json::from_slice(body)?;
     match wasm_vm::invoke_contract_json(&req.address, &req.function, &req.params) {
         Ok(result) => send_json_response(stream, 200, &ContractInvokeResponse {
@@ -921,10 +1229,41 @@ fn handle_contract_invoke(stream: &mut TcpStream, body: &[u8]) -> Result<(), Box
     }
 }
 
-fn handle_contract_state_query(stream: &mut TcpStream, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_contract_state_query(stream: &mut HttpStream, path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let address = path.trim_start_matches("/api/contract/state/");
     match wasm_vm::load_contract_state(address) {
         Ok(state) => send_json_response(stream, 200, &serde_json::json!({"address": address, "state": base64::encode(state)})),
         Err(_) => send_json_response(stream, 404, &serde_json::json!({"error": "State not found"})),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Serve one request on a local listener and return the raw response
+    fn request(method: &str, path: &str, body: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_http_request(HttpStream::Plain(stream), &HttpServerConfig::local(port)).unwrap();
+        });
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(client, "{} {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+        response
+    }
+
+    #[test]
+    fn test_free_marketplace_data_submission_is_gone() {
+        let pending = MEMPOOL.lock().unwrap().len();
+        let response = request("POST", "/api/marketplace/data", r#"{"data":"aGVsbG8=","timestamp":1}"#);
+        assert!(response.starts_with("HTTP/1.1 410 "), "{}", response);
+        assert!(response.ends_with(MARKETPLACE_SUBMIT_GONE));
+        assert_eq!(MEMPOOL.lock().unwrap().len(), pending);
+    }
+}
@@ -4,29 +4,37 @@
 //! - No premine, no ICO. All coins are mined.
 //! - Initial block reward: 5 BLK (atomic units)
 //! - Block time: 120 seconds
-//! - Halving every 1,051,200 blocks (~4 years)
+//! - Halving every 1,051,200 blocks (~4 years) on mainnet
 //! - Supply cap: 21,000,000 BLK
 //! - No tail emission: after cap, miners receive only transaction fees
-//! See README and docs/architecture.md for full details.
+//! Each network's parameters are read from `config/<network>/chain_spec.json`
+//! (see `chain_spec`). See README and docs/architecture.md for full details.
 
 #[macro_use]
 extern crate lazy_static;
 
+pub mod bootstrap;
+pub mod chain_spec;
+pub mod difficulty;
+pub mod fee_estimator;
+pub mod fork_choice;
 pub mod http_server;
+pub mod mempool;
+pub mod node_config;
 pub mod randomx_verifier;
 pub mod randomx;
+pub mod storage;
+pub mod sync;
 pub mod wasm_vm;
 
 use blake2::{Blake2b, Digest};
 use blake2::digest::Update;
 use digest::consts::U32;
-use std::sync::atomic::{AtomicU32, Ordering};
-use i2p::I2pClient;
 use primitives::{TransactionKind, ContractTx, StealthAddress, types::PublicKey};
 use serde::{Serialize, Deserialize};
 use pqsignatures::{Dilithium2, Falcon512, PQSignatureScheme};
-use crystals_dilithium::dilithium2::{PublicKey as D2PublicKey, Signature as D2Signature};
-use falcon_rust::falcon512::{PublicKey as F512PublicKey, Signature as F512Signature};
+// TMPFIX1
+// TMPFIX2
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
@@ -42,133 +50,122 @@ fn blake2b(data: &[u8]) -> Vec<u8> {
 pub mod config {
     pub const TESTNET_MAGIC: u32 = 0x1D670; // July 26, 1953
     pub const MAINNET_MAGIC: u32 = 0xB1A6C; // May 24, 2025
+    pub const REGTEST_MAGIC: u32 = 0x7E57C;
     
-    // Network Ports Configuration
-    pub const TESTNET_P2P_PORT: u16 = 8333;     // P2P port for testnet
-    pub const TESTNET_HTTP_PORT: u16 = 9333;    // HTTP API port for testnet
-    pub const TESTNET_TOR_PORT: u16 = 10333;    // Tor hidden service port for testnet
+    // Block time, difficulty window, emission, genesis and ports come from
+    // each network's chain spec (see `chain_spec`)
     
-    pub const MAINNET_P2P_PORT: u16 = 1776;     // P2P port for mainnet
-    pub const MAINNET_HTTP_PORT: u16 = 2776;    // HTTP API port for mainnet  
-    pub const MAINNET_TOR_PORT: u16 = 3776;     // Tor hidden service port for mainnet
+    /// Lowest difficulty the mainnet retarget goes down to
+    pub const MIN_MAINNET_DIFFICULTY: u64 = 1000;
     
-    /// Block time in seconds (2 minutes) - FIXED TARGET
-    pub const BLOCK_TIME_SEC: u64 = 120;
+    /// Maximum serialized size of a block's transactions in bytes
+    pub const MAX_BLOCK_SIZE: usize = 1_000_000;
     
-    /// Difficulty adjustment parameters
-    pub const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 60; // Adjust every 60 blocks (~2 hours)
-    pub const TESTNET_DIFFICULTY: u64 = 1;              // Minimal difficulty for testnet mining
-    pub const MAINNET_DIFFICULTY: u64 = 100_000_000;    // Starting mainnet difficulty
-    
-    /// Initial block reward in atomic units (5 BLK)
-    pub const GENESIS_REWARD: u64 = 5_000_000;
-    /// Halving interval in blocks (~4 years at 2 min/block)
-    pub const HALVING_INTERVAL: u64 = 1_051_200;
-    /// No tail emission after cap (miners receive only fees after cap)
-    pub const TAIL_EMISSION: u64 = 0;
-    /// Maximum supply: 21 million BLK (in atomic units)
-    pub const SUPPLY_CAP: u64 = 21_000_000 * 1_000_000;
-    
-    // Genesis timestamp for both networks - October 5, 1986
-    pub const MAINNET_GENESIS_TIMESTAMP: u64 = 528_854_400; // October 5, 1986
-    pub const TESTNET_GENESIS_TIMESTAMP: u64 = 528_854_400; // October 5, 1986
+    /// Timestamp rules
+    pub const MEDIAN_TIME_SPAN: usize = 11;             // Blocks used for median-time-past
+    pub const MAX_FUTURE_BLOCK_TIME_SEC: u64 = 2 * 60 * 60; // Max drift ahead of local clock
 }
 
 /// Network selection with proper port configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Network {
     Mainnet,
     Testnet,
+    /// Local chain for integration tests, usually with a custom chain spec
+    Regtest,
+}
+
+static MAINNET_SPEC: OnceCell<chain_spec::ChainSpec> = OnceCell::new();
+static TESTNET_SPEC: OnceCell<chain_spec::ChainSpec> = OnceCell::new();
+static REGTEST_SPEC: OnceCell<chain_spec::ChainSpec> = OnceCell::new();
+
+/// Run `network` on `spec` instead of its built-in chain spec. Must be
+/// called before anything reads the network's parameters; otherwise the
+/// spec is handed back.
+pub fn set_chain_spec(network: &Network, spec: chain_spec::ChainSpec) -> Result<(), chain_spec::ChainSpec> {
+    network.spec_cell().set(spec)
 }
 
 impl Network {
     fn from_env_or_default() -> Self {
         match std::env::var("BLACKSILK_NETWORK").as_deref() {
             Ok("mainnet") => Network::Mainnet,
+            Ok("regtest") => Network::Regtest,
             _ => Network::Testnet,
         }
     }
     
-    pub fn get_ports(&self) -> NetworkPorts {
+    /// Lower-case name, also the directory under `config/`
+    pub fn name(&self) -> &'static str {
         match self {
-            Network::Mainnet => NetworkPorts {
-                p2p: config::MAINNET_P2P_PORT,
-                http: config::MAINNET_HTTP_PORT,
-                tor: config::MAINNET_TOR_PORT,
-            },
-            Network::Testnet => NetworkPorts {
-                p2p: config::TESTNET_P2P_PORT,
-                http: config::TESTNET_HTTP_PORT,
-                tor: config::TESTNET_TOR_PORT,
-            },
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Regtest => "regtest",
         }
     }
     
-    pub fn get_difficulty(&self) -> u64 {
+    fn spec_cell(&self) -> &'static OnceCell<chain_spec::ChainSpec> {
         match self {
-            Network::Mainnet => config::MAINNET_DIFFICULTY,
-            Network::Testnet => config::TESTNET_DIFFICULTY,
+            Network::Mainnet => &MAINNET_SPEC,
+            Network::Testnet => &TESTNET_SPEC,
+            Network::Regtest => &REGTEST_SPEC,
         }
     }
     
-    /// Calculate next difficulty based on recent block times
-    pub fn calculate_next_difficulty(&self, chain: &Chain) -> u64 {
-        match self {
-            Network::Testnet => {
-                // Testnet: Keep fixed low difficulty for experiments
-                config::TESTNET_DIFFICULTY
-            },
-            Network::Mainnet => {
-                // Mainnet: Automatic difficulty adjustment every 60 blocks
-                let current_height = chain.blocks.len() as u64;
-                
-                if current_height < config::DIFFICULTY_ADJUSTMENT_INTERVAL {
-                    return config::MAINNET_DIFFICULTY; // Starting difficulty
-                }
-                
-                if current_height % config::DIFFICULTY_ADJUSTMENT_INTERVAL != 0 {
-                    // Not time for adjustment yet, return current difficulty
-                    return chain.tip().header.difficulty;
-                }
-                
-                // Calculate average block time over last 60 blocks
-                let recent_blocks: Vec<_> = chain.blocks
-                    .iter()
-                    .rev()
-                    .take(config::DIFFICULTY_ADJUSTMENT_INTERVAL as usize)
-                    .collect();
-                
-                if recent_blocks.len() < 2 {
-                    return chain.tip().header.difficulty;
-                }
-                
-                let time_span = recent_blocks.first().unwrap().header.timestamp 
-                    - recent_blocks.last().unwrap().header.timestamp;
-                
-                let expected_time = config::DIFFICULTY_ADJUSTMENT_INTERVAL * config::BLOCK_TIME_SEC;
-                let current_difficulty = chain.tip().header.difficulty;
-                
-                // Adjust difficulty to maintain 120-second block time
-                let new_difficulty = if time_span == 0 {
-                    current_difficulty
-                } else {
-                    (current_difficulty * expected_time) / time_span
-                };
-                
-                // Limit difficulty changes to prevent extreme swings
-                let max_change = current_difficulty / 4; // 25% max change
-                let min_difficulty = current_difficulty.saturating_sub(max_change);
-                let max_difficulty = current_difficulty.saturating_add(max_change);
-                
-                new_difficulty.clamp(min_difficulty.max(1000), max_difficulty)
-            }
+    /// Consensus parameters of this network
+    pub fn spec(&self) -> &'static chain_spec::ChainSpec {
+        self.spec_cell().get_or_init(|| {
+            let json = match self {
+                Network::Mainnet => chain_spec::MAINNET_SPEC_JSON,
+                Network::Testnet => chain_spec::TESTNET_SPEC_JSON,
+                Network::Regtest => chain_spec::REGTEST_SPEC_JSON,
+            };
+            chain_spec::ChainSpec::from_json(json).expect("built-in chain spec is valid")
+        })
+    }
+    
+    pub fn get_ports(&self) -> NetworkPorts {
+        let ports = &self.spec().network;
+        NetworkPorts {
+            p2p: ports.p2p_port,
+            http: ports.rpc_port,
+            tor: ports.tor_port,
         }
     }
     
+    /// Difficulty of the genesis block
+    pub fn get_difficulty(&self) -> u64 {
+        self.spec().genesis.difficulty
+    }
+    
+    /// Difficulty required for the block after `recent[0]`, given up to
+    /// `difficulty_adjustment_window + 1` of the latest headers, newest
+    /// first. This is the only retarget rule: block templates, connected
+    /// blocks and synced headers are all held to it.
+    pub fn next_difficulty(&self, recent: &[&BlockHeader]) -> u64 {
+        let spec = self.spec();
+        if spec.consensus.fixed_difficulty {
+            return spec.genesis.difficulty;
+        }
+        let floor = match self {
+            Network::Mainnet => config::MIN_MAINNET_DIFFICULTY,
+            _ => 1,
+        };
+        let window = spec.consensus.difficulty_adjustment_window as usize;
+        difficulty::lwma(recent, spec.consensus.block_time_seconds, window)
+            .map_or(spec.genesis.difficulty, |next| next.max(floor))
+    }
+    
+    /// Fee per byte a transaction must pay to be relayed and mined
+    pub fn min_tx_fee_per_byte(&self) -> u64 {
+        self.spec().consensus.min_tx_fee_per_byte
+    }
+    
     pub fn get_magic(&self) -> u32 {
         match self {
             Network::Mainnet => config::MAINNET_MAGIC,
             Network::Testnet => config::TESTNET_MAGIC,
+            Network::Regtest => config::REGTEST_MAGIC,
         }
     }
     
@@ -183,7 +180,7 @@ impl Network {
                 hidden_service_port: self.get_ports().tor,
                 ..Default::default()
             },
-            Network::Testnet => PrivacyConfig {
+            Network::Testnet | Network::Regtest => PrivacyConfig {
                 privacy_mode: PrivacyMode::Disabled, // Testnet allows all for development
                 tor_only: false,
                 hidden_service_port: self.get_ports().tor,
@@ -202,12 +199,11 @@ pub struct NetworkPorts {
 
 use primitives::{Block, BlockHeader, Coinbase};
 use primitives::{QuantumSignature};
-use std::collections::{VecDeque, HashSet};
-use std::io::{Write, BufRead};
-use std::net::{TcpListener, TcpStream, SocketAddr};
+use fork_choice::BlockAcceptance;
+use std::collections::{VecDeque, HashMap, HashSet};
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::thread;
 use once_cell::sync::OnceCell;
 
 /// Global network configuration
@@ -223,9 +219,12 @@ pub fn set_network(network: Network) -> Result<(), Network> {
     CURRENT_NETWORK.set(network)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum P2PMessage {
-    Version { version: u32, node: String },
+    /// First message on every connection; answered with `Verack`. `onion`
+    /// is the `<name>.onion:port` the node accepts peers on, if any.
+    Version { version: u32, best_height: u64, services: u64, node: String, onion: Option<String> },
+    Verack,
     Ping,
     Pong,
     Block(primitives::Block),
@@ -233,148 +232,434 @@ pub enum P2PMessage {
     PeerList(Vec<String>),
     GetBlocks { from_height: u64 },
     Blocks(Vec<primitives::Block>),
+    /// Headers after the first locator hash the peer recognises
+    GetHeaders { locator: Vec<primitives::types::Hash> },
+    Headers(Vec<BlockHeader>),
+    /// Block bodies by hash; answered with `Blocks`
+    GetBlockData { hashes: Vec<primitives::types::Hash> },
     GetMempool,
     Mempool(Vec<primitives::Transaction>),
+    /// Ask for known peer addresses; answered with `Addr`
+    GetAddr,
+    Addr(Vec<network::addrman::NetAddress>),
+    /// Announce blocks and transactions by hash
+    Inv(Vec<InvItem>),
+    /// Ask for announced objects; answered with `Block`/`Transaction` messages
+    /// and a `NotFound` for the rest
+    GetData(Vec<InvItem>),
+    NotFound(Vec<InvItem>),
+    /// New block as header, short transaction IDs and prefilled transactions
+    CompactBlock(network::compact::CompactBlock),
+    /// Transactions of a compact block that could not be filled from the mempool
+    GetBlockTxn { block_hash: primitives::types::Hash, indexes: Vec<u32> },
+    BlockTxn { block_hash: primitives::types::Hash, transactions: Vec<primitives::Transaction> },
+    /// Ephemeral key offering an encrypted link; only before `Version`
+    Hello { ephemeral: [u8; 32] },
+    /// Static identity key and its proof, first message on an encrypted link
+    Identity { key: Option<network::transport::IdentityKey>, signature: Vec<u8> },
+    /// Transaction in the Dandelion++ stem phase, for the receiver alone
+    StemTransaction(primitives::Transaction),
     // ... add more as needed
 }
 
-fn send_message(stream: &mut TcpStream, msg: &P2PMessage) -> std::io::Result<()> {
-    let json = serde_json::to_string(msg)?;
-    stream.write_all(json.as_bytes())?;
-    stream.write_all(b"\n")?;
-    Ok(())
-}
-
-fn read_message(stream: &mut TcpStream) -> Option<P2PMessage> {
-    let mut buf = String::new();
-    let mut reader = std::io::BufReader::new(stream);
-    match reader.read_line(&mut buf) {
-        Ok(0) => None, // EOF
-        Ok(_) => serde_json::from_str(&buf).ok(),
-        Err(_) => None,
+/// `Version` announcing our protocol version, best height and services
+pub(crate) fn version_message(node: &str, onion: Option<String>) -> P2PMessage {
+    use network::wire::{services, PROTOCOL_VERSION};
+    let chain = CHAIN.lock().unwrap();
+    let pruned = chain.store.as_ref().is_some_and(|store| store.pruned_height() > 0);
+    P2PMessage::Version {
+        version: PROTOCOL_VERSION,
+        best_height: chain.tip().header.height,
+        services: if pruned { services::PRUNED } else { services::NETWORK },
+        node: node.to_string(),
+        onion,
     }
 }
 
-lazy_static! {
-    pub static ref PEERS: Arc<Mutex<Vec<TcpStream>>> = Arc::new(Mutex::new(Vec::new()));
+/// Log line for a received message; bulk payloads are reduced to counts
+pub(crate) fn message_summary(msg: &P2PMessage) -> String {
+    match msg {
+        P2PMessage::Blocks(blocks) => format!("Blocks({} block(s))", blocks.len()),
+        P2PMessage::Headers(headers) => format!("Headers({} header(s))", headers.len()),
+        P2PMessage::GetHeaders { locator } => format!("GetHeaders({} locator hash(es))", locator.len()),
+        P2PMessage::GetBlockData { hashes } => format!("GetBlockData({} hash(es))", hashes.len()),
+        P2PMessage::Mempool(txs) => format!("Mempool({} tx(s))", txs.len()),
+        P2PMessage::Addr(addrs) => format!("Addr({} address(es))", addrs.len()),
+        P2PMessage::Inv(items) => format!("Inv({} item(s))", items.len()),
+        P2PMessage::GetData(items) => format!("GetData({} item(s))", items.len()),
+        P2PMessage::NotFound(items) => format!("NotFound({} item(s))", items.len()),
+        P2PMessage::CompactBlock(compact) => format!(
+            "CompactBlock(height {}, {} short id(s), {} prefilled)",
+            compact.header.height, compact.short_ids.len(), compact.prefilled.len()
+        ),
+        P2PMessage::GetBlockTxn { indexes, .. } => format!("GetBlockTxn({} index(es))", indexes.len()),
+        P2PMessage::BlockTxn { transactions, .. } => format!("BlockTxn({} tx(s))", transactions.len()),
+        other => format!("{:?}", other),
+    }
 }
 
 // Transaction pool (mempool)
 lazy_static::lazy_static! {
-    pub static ref MEMPOOL: Arc<Mutex<Vec<primitives::Transaction>>> = Arc::new(Mutex::new(Vec::new()));
+    pub static ref MEMPOOL: Arc<Mutex<mempool::Mempool>> = Arc::new(Mutex::new(mempool::Mempool::default()));
 }
 
 lazy_static! {
     pub static ref CHAIN: Arc<Mutex<Chain>> = Arc::new(Mutex::new(Chain::new()));
 }
 
-// Global peer counter for tracking active connections
-pub static PEER_COUNT: AtomicU32 = AtomicU32::new(0);
+/// Number of peers that completed the handshake
+pub fn peer_count() -> usize {
+    network::peer_manager::peer_manager().map_or(0, |manager| manager.peer_count())
+}
 
 pub fn broadcast_message(msg: &P2PMessage) {
-    let peers = PEERS.lock().unwrap();
-    for peer in peers.iter() {
-        let mut peer = peer.try_clone().unwrap();
-        let _ = send_message(&mut peer, msg);
+    if let Some(manager) = network::peer_manager::peer_manager() {
+        manager.broadcast(msg);
     }
 }
 
-fn handle_client(mut stream: TcpStream) {
-    println!("[P2P] New peer: {}", stream.peer_addr().unwrap());
-    
-    // Increment peer count
-    PEER_COUNT.fetch_add(1, Ordering::Relaxed);
-    
-    {
-        let mut peers = PEERS.lock().unwrap();
-        peers.push(stream.try_clone().unwrap());
-        // Send current peer list to the new peer
-        let peer_addrs: Vec<String> = peers.iter().filter_map(|s| s.peer_addr().ok().map(|a| a.to_string())).collect();
-        let _ = send_message(&mut stream, &P2PMessage::PeerList(peer_addrs));
-    }
-    let version = P2PMessage::Version { version: 1, node: "BlackSilkNode".to_string() };
-    let _ = send_message(&mut stream, &version);
-    loop {
-        match read_message(&mut stream) {
-            Some(msg) => {
-                println!("[P2P] Received: {:?}", msg);
-                match msg {
-                    P2PMessage::Ping => { let _ = send_message(&mut stream, &P2PMessage::Pong); },
-                    P2PMessage::Pong => {},
-                    P2PMessage::Version { .. } => {},
-                    P2PMessage::Block(block) => {
-                        let mut chain = CHAIN.lock().unwrap();
-                        if chain.blocks.back().map_or(true, |b| block.header.prev_hash == b.header.pow.hash) {
-                            if chain.add_block(block.clone()) {
-                                println!("[Chain] Block added");
-                                broadcast_message(&P2PMessage::Block(block));
-                            } else {
-                                println!("[Chain] Block rejected");
-                            }
-                        } else {
-                            println!("[Chain] Block does not extend current chain. Possible fork or reorg needed.");
-                            // TODO: Handle chain reorg (fork resolution)
-                        }
-                    },
-                    P2PMessage::Transaction(tx) => {
-                        if validate_transaction(&tx) {
-                            add_to_mempool(tx.clone());
-                            broadcast_message(&P2PMessage::Transaction(tx));
-                            println!("[Mempool] Transaction accepted");
-                        } else {
-                            println!("[Mempool] Invalid transaction rejected");
-                        }
-                    },
-                    P2PMessage::PeerList(peers) => {
-                        println!("[P2P] Received peer list: {:?}", peers);
-                    },
-                    P2PMessage::GetBlocks { from_height } => {
-                        let chain = CHAIN.lock().unwrap();
-                        let blocks: Vec<_> = chain.blocks.iter().filter(|b| b.header.height >= from_height).cloned().collect();
-                        let _ = send_message(&mut stream, &P2PMessage::Blocks(blocks));
-                    },
-                    P2PMessage::Blocks(blocks) => {
-                        let mut chain = CHAIN.lock().unwrap();
-                        if blocks.len() > chain.blocks.len() {
-                            drop(chain); // unlock before reorg
-                            maybe_reorg_chain(blocks);
-                        } else {
-                            for block in blocks {
-                                if chain.blocks.back().map_or(true, |b| block.header.prev_hash == b.header.pow.hash) {
-                                    chain.add_block(block);
-                                } else {
-                                    println!("[Chain] Received block does not extend current chain. Fork handling needed.");
-                                }
-                            }
-                        }
-                    },
-                    P2PMessage::GetMempool => {
-                        let mempool = get_mempool();
-                        let _ = send_message(&mut stream, &P2PMessage::Mempool(mempool));
-                    },
-                    P2PMessage::Mempool(txs) => {
-                        for tx in txs {
-                            if validate_transaction(&tx) {
-                                add_to_mempool(tx);
-                            }
-                        }
-                    },
-                }
+/// Announce a block that joined our chain to the network
+pub fn relay_block(block: &Block) {
+    if let Some(manager) = network::peer_manager::peer_manager() {
+        manager.announce_block(block);
+    }
+}
+
+/// Announce a transaction that entered our mempool to the network
+pub fn relay_transaction(tx: &primitives::Transaction) {
+    if let Some(manager) = network::peer_manager::peer_manager() {
+        manager.relay_transaction(tx.id());
+    }
+}
+
+/// Send a transaction that entered through this node (RPC, wallet) on its
+/// way: along the Dandelion++ stem when we can, otherwise into the mempool
+/// and out to every peer. Fails if our mempool would not take it.
+pub fn submit_transaction(tx: primitives::Transaction) -> Result<(), mempool::MempoolError> {
+    MEMPOOL.lock().unwrap().check(&tx)?;
+    let tx = match network::peer_manager::peer_manager() {
+        Some(manager) => match manager.stem_transaction(None, tx) {
+            Some(tx) => tx,
+            None => return Ok(()),
+        },
+        None => tx,
+    };
+    add_to_mempool(tx.clone())?;
+    relay_transaction(&tx);
+    Ok(())
+}
+
+/// End the stem phase of a transaction: add it to the mempool and announce
+/// it to every peer, unless it was mined or double-spent in the meantime
+pub(crate) fn fluff_transaction(manager: &PeerManager, tx: primitives::Transaction) {
+    if have_inventory(&InvItem::transaction(tx.id())) || !validate_transaction(&tx) {
+        return;
+    }
+    if add_to_mempool(tx.clone()).is_ok() {
+        manager.relay_transaction(tx.id());
+    }
+}
+
+/// Whether we already hold the announced object
+fn have_inventory(item: &InvItem) -> bool {
+    match item.kind {
+        InvKind::Block => {
+            let chain = CHAIN.lock().unwrap();
+            chain.contains_block(&item.hash) || chain.side_chains.contains(&item.hash)
+        }
+        InvKind::Transaction => {
+            if CHAIN.lock().unwrap().find_transaction(&item.hash).is_some() {
+                return true;
             }
+            MEMPOOL.lock().unwrap().contains(&item.hash)
+        }
+    }
+}
+
+/// Block by hash, from the main chain or a side branch
+fn find_block(hash: &primitives::types::Hash) -> Option<Block> {
+    let chain = CHAIN.lock().unwrap();
+    match chain.height_of(hash) {
+        Some(height) => chain.block_at(height),
+        None => chain.side_chains.get(hash).cloned(),
+    }
+}
+
+/// Run a full block from `peer` through fork choice and pass it on
+fn process_block(manager: &PeerManager, peer: SocketAddr, block: Block) {
+    use network::peer_manager::penalty;
+    // The hash is the one the sender put in the header, so the block only
+    // counts as seen once it validated; otherwise junk sent under a real
+    // block's hash would keep us from ever fetching the real one
+    let item = InvItem::block(block.hash());
+    match accept_network_block(block.clone()) {
+        BlockAcceptance::Extended | BlockAcceptance::Reorganized { .. } => {
+            manager.inventory_arrived(peer, item);
+            manager.announce_block(&block);
+        }
+        BlockAcceptance::SideChain | BlockAcceptance::Duplicate => {
+            manager.inventory_arrived(peer, item);
+        }
+        BlockAcceptance::Orphan => {
+            manager.inventory_rejected(peer, item);
+            // We are behind this peer: fetch its headers, then the bodies
+            sync::request_headers(manager, peer);
+        }
+        BlockAcceptance::Rejected => {
+            manager.inventory_rejected(peer, item);
+            manager.misbehaving(peer, penalty::INVALID_BLOCK, "invalid block");
+        }
+    }
+}
+
+/// Rebuild an announced compact block from the mempool, asking `peer` for
+/// the transactions we lack
+fn receive_compact_block(manager: &PeerManager, peer: SocketAddr, compact: network::compact::CompactBlock) {
+    use network::peer_manager::penalty;
+    let item = InvItem::block(compact.hash());
+    if manager.inventory_received(peer, vec![item], have_inventory).is_empty() {
+        return;
+    }
+    // The header is checked before any work goes into the body; compact
+    // blocks are only rebuilt on top of the main chain, anything else is
+    // fetched whole
+    let header_check = {
+        let chain = CHAIN.lock().unwrap();
+        match chain.height_of(&compact.header.prev_hash) {
+            Some(parent_height) => Some(sync::check_header(&chain, &compact.header, parent_height, |height| chain.header_at(height))),
+            None if chain.side_chains.contains(&compact.header.prev_hash) => None,
             None => {
-                println!("[P2P] Peer disconnected");
-                break;
+                drop(chain);
+                manager.inventory_not_found(peer, &[item]);
+                sync::request_headers(manager, peer);
+                return;
             }
         }
+    };
+    match header_check {
+        Some(Ok(())) => {}
+        Some(Err(e)) => {
+            manager.inventory_rejected(peer, item);
+            manager.misbehaving(peer, penalty::INVALID_BLOCK, &format!("invalid compact block header: {}", e));
+            return;
+        }
+        None => {
+            manager.send(peer, P2PMessage::GetData(vec![item]));
+            return;
+        }
     }
-    // On disconnect:
-    {
-        let mut peers = PEERS.lock().unwrap();
-        peers.retain(|s| s.peer_addr().unwrap() != stream.peer_addr().unwrap());
+    let reconstructed = compact.reconstruct(MEMPOOL.lock().unwrap().iter_with_ids());
+    let partial = match reconstructed {
+        Ok(partial) => partial,
+        Err(e) => {
+            manager.misbehaving(peer, penalty::INVALID_COMPACT_BLOCK, &e.to_string());
+            return;
+        }
+    };
+    let missing = partial.missing();
+    if missing.is_empty() {
+        complete_compact_block(manager, peer, partial);
+    } else if manager.await_block_transactions(peer, partial) {
+        manager.send(peer, P2PMessage::GetBlockTxn { block_hash: item.hash, indexes: missing });
+    } else {
+        manager.send(peer, P2PMessage::GetData(vec![item]));
+    }
+}
+
+/// Process a rebuilt compact block; on a merkle mismatch (a short ID
+/// collision) fetch the full block instead
+fn complete_compact_block(manager: &PeerManager, peer: SocketAddr, partial: network::compact::PartialBlock) {
+    let hash = partial.hash();
+    match partial.into_block() {
+        Some(block) if calculate_merkle_root(&block.transactions) == block.header.merkle_root => {
+            process_block(manager, peer, block);
+        }
+        _ => {
+            println!("[P2P] Could not rebuild compact block {} from {}, fetching it whole", hex::encode(hash), peer);
+            manager.send(peer, P2PMessage::GetData(vec![InvItem::block(hash)]));
+        }
+    }
+}
+
+/// Answer `GetData` with the blocks and transactions we have
+fn serve_inventory(manager: &PeerManager, peer: SocketAddr, items: Vec<InvItem>) {
+    let mut missing = Vec::new();
+    for item in items {
+        let found = match item.kind {
+            InvKind::Block => find_block(&item.hash).map(P2PMessage::Block),
+            InvKind::Transaction => MEMPOOL.lock().unwrap().get(&item.hash).cloned().map(P2PMessage::Transaction),
+        };
+        match found {
+            Some(msg) => { manager.send(peer, msg); },
+            None => missing.push(item),
+        }
+    }
+    if !missing.is_empty() {
+        manager.send(peer, P2PMessage::NotFound(missing));
+    }
+}
+
+/// Greet a peer that just completed the handshake and start syncing from it
+pub(crate) fn on_peer_ready(manager: &PeerManager, peer: SocketAddr) {
+    use network::peer_manager::Direction;
+    // Only outbound peers are known to listen on the address we see; our
+    // own onion service goes along
+    let peers = manager.peers();
+    let peer_addrs: Vec<String> = peers
+        .iter()
+        .filter(|p| p.direction == Direction::Outbound && p.addr != peer)
+        .map(|p| p.endpoint.clone().unwrap_or_else(|| p.addr.to_string()))
+        .chain(manager.onion_address().map(|endpoint| endpoint.to_string()))
+        .collect();
+    manager.send(peer, P2PMessage::PeerList(peer_addrs));
+    sync::request_headers(manager, peer);
+    if peers.iter().any(|p| p.addr == peer && p.direction == Direction::Outbound) {
+        manager.request_addresses(peer);
+    }
+}
+
+/// Handle one message from a peer that completed the handshake. Replies go
+/// through the peer's outbound queue.
+pub(crate) fn handle_peer_message(manager: &PeerManager, peer: SocketAddr, msg: P2PMessage) {
+    use network::peer_manager::penalty;
+    println!("[P2P] Received from {}: {}", peer, message_summary(&msg));
+    match msg {
+        P2PMessage::Ping => { manager.send(peer, P2PMessage::Pong); },
+        P2PMessage::Pong => manager.pong(peer),
+        P2PMessage::Version { .. } | P2PMessage::Verack | P2PMessage::Hello { .. } | P2PMessage::Identity { .. } => {
+            manager.misbehaving(peer, penalty::PROTOCOL_VIOLATION, "repeated the handshake");
+        },
+        P2PMessage::Block(block) => process_block(manager, peer, block),
+        P2PMessage::Transaction(tx) => {
+            let item = InvItem::transaction(tx.id());
+            if !manager.inventory_arrived(peer, item) || have_inventory(&item) {
+                return;
+            }
+            if validate_transaction(&tx) {
+                // Losing a conflict or a full mempool is no fault of the peer
+                match add_to_mempool(tx) {
+                    Ok(()) => {
+                        manager.relay_transaction(item.hash);
+                        println!("[Mempool] Transaction accepted");
+                    }
+                    Err(e) => println!("[Mempool] Transaction not accepted: {}", e),
+                }
+            } else {
+                println!("[Mempool] Invalid transaction rejected");
+                manager.misbehaving(peer, penalty::INVALID_TRANSACTION, "invalid transaction");
+            }
+        },
+        P2PMessage::StemTransaction(tx) => {
+            if have_inventory(&InvItem::transaction(tx.id())) {
+                return;
+            }
+            if !validate_transaction(&tx) {
+                println!("[Mempool] Invalid stem transaction rejected");
+                manager.misbehaving(peer, penalty::INVALID_TRANSACTION, "invalid transaction");
+                return;
+            }
+            if let Some(tx) = manager.stem_transaction(Some(peer), tx) {
+                fluff_transaction(manager, tx);
+            }
+        },
+        P2PMessage::Inv(items) => {
+            let wanted = manager.inventory_received(peer, items, have_inventory);
+            if !wanted.is_empty() {
+                manager.send(peer, P2PMessage::GetData(wanted));
+            }
+        },
+        P2PMessage::GetData(items) => {
+            if manager.inventory_size_ok(peer, items.len()) {
+                serve_inventory(manager, peer, items);
+            }
+        },
+        P2PMessage::NotFound(items) => {
+            manager.inventory_not_found(peer, &items);
+        },
+        P2PMessage::CompactBlock(compact) => receive_compact_block(manager, peer, compact),
+        P2PMessage::GetBlockTxn { block_hash, indexes } => {
+            if !manager.inventory_size_ok(peer, indexes.len()) {
+                return;
+            }
+            let Some(block) = find_block(&block_hash) else { return };
+            let transactions: Option<Vec<primitives::Transaction>> = indexes
+                .iter()
+                .map(|index| block.transactions.get(*index as usize).cloned())
+                .collect();
+            match transactions {
+                Some(transactions) => { manager.send(peer, P2PMessage::BlockTxn { block_hash, transactions }); },
+                None => manager.misbehaving(peer, penalty::INVALID_COMPACT_BLOCK, "transaction index outside the block"),
+            }
+        },
+        P2PMessage::BlockTxn { block_hash, transactions } => {
+            let Some(mut partial) = manager.take_partial_block(peer, &block_hash) else { return };
+            match partial.fill(transactions) {
+                Ok(()) => complete_compact_block(manager, peer, partial),
+                Err(e) => {
+                    manager.misbehaving(peer, penalty::INVALID_COMPACT_BLOCK, &e.to_string());
+                    manager.send(peer, P2PMessage::GetData(vec![InvItem::block(block_hash)]));
+                }
+            }
+        },
+        P2PMessage::PeerList(peers) => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let addresses = peers
+                .into_iter()
+                .map(|addr| network::addrman::NetAddress { addr, services: 0, last_seen: now })
+                .collect();
+            manager.learn_addresses(peer, addresses);
+        },
+        P2PMessage::GetAddr => {
+            if let Some(addresses) = manager.answer_get_addr(peer) {
+                manager.send(peer, P2PMessage::Addr(addresses));
+            }
+        },
+        P2PMessage::Addr(addresses) => {
+            manager.learn_addresses(peer, addresses);
+        },
+        P2PMessage::GetBlocks { from_height } => {
+            // Bounded like `GetBlockData` so the reply fits in one frame
+            let chain = CHAIN.lock().unwrap();
+            let blocks: Vec<Block> = (from_height..)
+                .take(sync::BLOCKS_PER_REQUEST)
+                .map_while(|height| chain.block_at(height))
+                .collect();
+            drop(chain);
+            manager.send(peer, P2PMessage::Blocks(blocks));
+        },
+        P2PMessage::Blocks(blocks) => {
+            sync::handle_blocks(manager, peer, blocks);
+        },
+        P2PMessage::GetHeaders { locator } => {
+            let headers = CHAIN.lock().unwrap().headers_after(&locator, sync::MAX_HEADERS_PER_MESSAGE);
+            manager.send(peer, P2PMessage::Headers(headers));
+        },
+        P2PMessage::Headers(headers) => {
+            sync::handle_headers(manager, peer, headers);
+        },
+        P2PMessage::GetBlockData { hashes } => {
+            let chain = CHAIN.lock().unwrap();
+            let blocks: Vec<Block> = hashes
+                .iter()
+                .take(sync::BLOCKS_PER_REQUEST)
+                .filter_map(|hash| chain.block_at(chain.height_of(hash)?))
+                .collect();
+            drop(chain);
+            manager.send(peer, P2PMessage::Blocks(blocks));
+        },
+        P2PMessage::GetMempool => {
+            manager.send(peer, P2PMessage::Mempool(get_mempool()));
+        },
+        P2PMessage::Mempool(txs) => {
+            for tx in txs {
+                if validate_transaction(&tx) {
+                    let _ = add_to_mempool(tx);
+                }
+            }
+        },
     }
-    
-    // Decrement peer count
-    PEER_COUNT.fetch_sub(1, Ordering::Relaxed);
 }
 
 // Minimal CryptoNote-style ring signature verification
@@ -478,43 +763,35 @@ lazy_static! {
     static ref KEY_IMAGES: Arc<Mutex<std::collections::HashSet<primitives::types::Hash>>> = Arc::new(Mutex::new(std::collections::HashSet::new()));
 }
 
-/// Collect all key images from the chain and mempool
-fn all_seen_key_images() -> HashSet<primitives::types::Hash> {
-    let chain = CHAIN.lock().unwrap();
-    let mempool = MEMPOOL.lock().unwrap();
-    let mut set = HashSet::new();
-    for block in &chain.blocks {
-        for tx in &block.transactions {
-            for input in &tx.inputs {
-                set.insert(input.key_image);
-            }
-        }
+/// Validate a transaction for relay or mempool admission, including the
+/// double-spend check against the chain. Spends that conflict with pending
+/// transactions are settled by the mempool's replacement rule.
+pub fn validate_transaction(tx: &primitives::Transaction) -> bool {
+    if !check_transaction(tx) {
+        return false;
     }
-    for tx in mempool.iter() {
-        for input in &tx.inputs {
-            set.insert(input.key_image);
-        }
+    let chain = CHAIN.lock().unwrap();
+    if tx.inputs.iter().any(|input| chain.is_key_image_spent(&input.key_image)) {
+        println!("[Validation] Double-spend detected (key image reused)");
+        return false;
     }
-    set
+    true
 }
 
-pub fn validate_transaction(tx: &primitives::Transaction) -> bool {
+/// Context-free transaction checks (signatures, range proofs, contract
+/// payloads). Does not touch `CHAIN` or `MEMPOOL`, so it is safe to call
+/// while holding either lock.
+pub fn check_transaction(tx: &primitives::Transaction) -> bool {
     if tx.outputs.is_empty() {
         println!("[Validation] Transaction missing outputs");
         return false;
     }
-    let seen_key_images = all_seen_key_images();
     for input in &tx.inputs {
         // Ring signature validation
         if !validate_ring_signature(&input.ring_sig.ring, &input.ring_sig.signature, &tx.extra) {
             println!("[Validation] Ring signature failed");
             return false;
         }
-        // Double-spend prevention
-        if seen_key_images.contains(&input.key_image) {
-            println!("[Validation] Double-spend detected (key image reused)");
-            return false;
-        }
         // Quantum signature validation (if present)
         if let Some(qsig) = &input.ring_sig.quantum {
             if !validate_quantum_signature(qsig, &tx.extra) {
@@ -603,57 +880,107 @@ pub struct EmissionSchedule {
     pub genesis_reward: u64,
     pub halving_interval: u64,
     pub supply_cap: u64,
+    /// Reward once halvings have taken it to zero
+    #[serde(default)]
+    pub tail_emission: u64,
 }
 
 impl EmissionSchedule {
     pub fn block_reward(&self, height: u64) -> u64 {
         let mut reward = self.genesis_reward;
         let halvings = height / self.halving_interval;
-        for _ in 0..halvings {
+        for _ in 0..halvings.min(64) {
             reward /= 2;
         }
-        // Enforce no tail emission and supply cap
         if reward == 0 {
-            return 0;
+            return self.tail_emission;
         }
         // Optionally, enforce supply cap logic here if needed
         reward
     }
 }
 
+/// Emission schedule of the network this node runs on
 pub fn default_emission() -> EmissionSchedule {
-    EmissionSchedule {
-        genesis_reward: config::GENESIS_REWARD,
-        halving_interval: config::HALVING_INTERVAL,
-        supply_cap: config::SUPPLY_CAP,
-    }
+    current_network().spec().emission()
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Chain {
+    /// Main chain blocks held in memory. With a block store attached this is a
+    /// window of the most recent `storage::MEMORY_WINDOW` blocks; older blocks
+    /// are read from disk.
     pub blocks: VecDeque<Block>,
     pub emission: EmissionSchedule,
     pub network: Network,
+    /// Known blocks that are not on the main chain (rebuilt from the network)
+    #[serde(skip)]
+    pub side_chains: fork_choice::SideChainStore,
+    /// Skip RandomX re-verification when connecting blocks (trusted imports, tests)
+    #[serde(skip)]
+    pub skip_pow_check: bool,
+    /// Skip ring signature and range proof checks (header-only bootstrap imports)
+    #[serde(skip)]
+    pub skip_tx_verification: bool,
+    /// Persistent block storage; `None` keeps the whole chain in memory
+    #[serde(skip)]
+    pub store: Option<storage::BlockStore>,
+    /// What the contract deployments of each in-memory block replaced in
+    /// the contract registry, so disconnecting the block can undo them
+    #[serde(skip)]
+    contract_journal: HashMap<primitives::types::Hash, Vec<(String, Option<wasm_vm::WasmContract>)>>,
 }
 
 impl Chain {
     pub fn new() -> Self {
         let network = Network::from_env_or_default();
-        let emission = default_emission();
+        let emission = network.spec().emission();
         let genesis = Self::genesis_block_with_params(&emission, &network);
         let mut blocks = VecDeque::new();
         blocks.push_back(genesis);
-        Self { blocks, emission, network }
+        Self { blocks, emission, network, side_chains: Default::default(), skip_pow_check: false, skip_tx_verification: false, store: None, contract_journal: HashMap::new() }
     }
     
     pub fn new_for_network(network: Network) -> Self {
-        let emission = default_emission();
+        let emission = network.spec().emission();
         let genesis = Self::genesis_block_with_params(&emission, &network);
         let mut blocks = VecDeque::new();
         blocks.push_back(genesis);
-        Self { blocks, emission, network }
+        Self { blocks, emission, network, side_chains: Default::default(), skip_pow_check: false, skip_tx_verification: false, store: None, contract_journal: HashMap::new() }
     }
     
+    /// Open the block store under `data_dir` and load the most recent blocks.
+    /// A fresh store is initialised with the genesis block.
+    pub fn open(network: Network, data_dir: &std::path::Path) -> std::io::Result<Self> {
+        let mut store = storage::BlockStore::open(&data_dir.join(storage::BLOCKS_DIR))?;
+        let mut chain = Self::new_for_network(network);
+        if store.is_empty() {
+            store.append_block(chain.tip())?;
+        } else {
+            let genesis = store.get_header(0)?.expect("non-empty store has a genesis block");
+            if genesis.timestamp != chain.tip().header.timestamp {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("block store in {} belongs to a different network", data_dir.display()),
+                ));
+            }
+            let tip_height = store.tip_height().unwrap_or(0);
+            let start = (tip_height + 1).saturating_sub(storage::MEMORY_WINDOW as u64);
+            chain.blocks.clear();
+            for height in start..=tip_height {
+                let block = store.get_block(height)?.ok_or_else(|| std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("block {} is pruned but inside the in-memory window", height),
+                ))?;
+                chain.blocks.push_back(block);
+            }
+        }
+        println!("[Chain] Loaded {} block(s) from {}, tip at height {}",
+            store.len(), store.dir().display(), chain.tip().header.height);
+        chain.store = Some(store);
+        Ok(chain)
+    }
+
     /// Generate a proper genesis address based on the network
     fn generate_genesis_address() -> String {
         // Generate a real BlackSilk address for genesis block
@@ -664,20 +991,17 @@ impl Chain {
     }
     
     fn genesis_block_with_params(emission: &EmissionSchedule, network: &Network) -> Block {
-        let timestamp = match network {
-            Network::Mainnet => config::MAINNET_GENESIS_TIMESTAMP,
-            Network::Testnet => config::TESTNET_GENESIS_TIMESTAMP,
-        };
+        let genesis = &network.spec().genesis;
         
         Block {
             header: BlockHeader {
                 version: 1,
-                prev_hash: [0u8; 32],
+                prev_hash: network.spec().genesis_parent_hash().expect("chain spec was validated"),
                 merkle_root: [0u8; 32],
-                timestamp,
+                timestamp: genesis.timestamp,
                 height: 0,
-                difficulty: network.get_difficulty(),
-                pow: primitives::Pow { nonce: 0, hash: [0u8; 32] },
+                difficulty: genesis.difficulty,
+                pow: primitives::Pow { nonce: genesis.nonce, hash: [0u8; 32] },
             },
             coinbase: Coinbase {
                 reward: emission.genesis_reward,
@@ -687,70 +1011,190 @@ impl Chain {
         }
     }
     
-    /// Calculate next difficulty using automatic adjustment algorithm
-    pub fn calculate_next_difficulty(&self) -> u64 {
-        let current_height = self.blocks.len() as u64;
-        
-        // Don't adjust difficulty for first few blocks
-        if current_height < config::DIFFICULTY_ADJUSTMENT_INTERVAL {
-            return self.network.get_difficulty();
+    /// Difficulty the next block on the main chain must have
+    pub fn next_difficulty(&self) -> u64 {
+        let window = self.network.spec().consensus.difficulty_adjustment_window as usize;
+        let recent: Vec<&BlockHeader> = self.blocks
+            .iter()
+            .rev()
+            .take(window + 1)
+            .map(|b| &b.header)
+            .collect();
+        self.network.next_difficulty(&recent)
+    }
+
+    pub fn tip(&self) -> &Block {
+        self.blocks.back().unwrap()
+    }
+
+    /// Number of blocks on the main chain, including genesis
+    pub fn block_count(&self) -> u64 {
+        self.tip().header.height + 1
+    }
+
+    /// Height of the oldest block held in memory
+    fn window_start(&self) -> u64 {
+        self.blocks.front().map_or(0, |b| b.header.height)
+    }
+
+    /// Main-chain block at `height`, from memory or the block store
+    pub fn block_at(&self, height: u64) -> Option<Block> {
+        let start = self.window_start();
+        if height >= start {
+            return self.blocks.get((height - start) as usize).cloned();
         }
-        
-        // For testnet, keep difficulty very low for experiments
-        if matches!(self.network, Network::Testnet) {
-            return config::TESTNET_DIFFICULTY;
+        self.store.as_ref()?.get_block(height).ok().flatten()
+    }
+
+    /// Main-chain blocks from `height` up to the tip; pruned blocks are skipped
+    pub fn blocks_from(&self, height: u64) -> Vec<Block> {
+        let start = self.window_start();
+        let mut blocks: Vec<Block> = (height..start.min(self.block_count()))
+            .filter_map(|h| self.block_at(h))
+            .collect();
+        blocks.extend(self.blocks.iter().filter(|b| b.header.height >= height).cloned());
+        blocks
+    }
+
+    /// Main-chain header at `height`; unlike `block_at` this also works for pruned blocks
+    pub fn header_at(&self, height: u64) -> Option<BlockHeader> {
+        let start = self.window_start();
+        if height >= start {
+            return self.blocks.get((height - start) as usize).map(|b| b.header.clone());
         }
-        
-        // Mainnet: Automatic difficulty adjustment every 60 blocks
-        if current_height % config::DIFFICULTY_ADJUSTMENT_INTERVAL == 0 {
-            let adjustment_start = current_height - config::DIFFICULTY_ADJUSTMENT_INTERVAL;
-            let start_block = &self.blocks[adjustment_start as usize];
-            let end_block = self.blocks.back().unwrap();
-            
-            let actual_time = end_block.header.timestamp - start_block.header.timestamp;
-            let expected_time = config::DIFFICULTY_ADJUSTMENT_INTERVAL * config::BLOCK_TIME_SEC;
-            
-            let current_difficulty = end_block.header.difficulty;
-            
-            // Adjust difficulty to maintain 120-second block time
-            let new_difficulty = if actual_time > 0 {
-                (current_difficulty * expected_time) / actual_time
-            } else {
-                current_difficulty * 2 // Double if time calculation fails
-            };
-            
-            // Limit adjustment to 4x change maximum (prevent huge swings)
-            let max_adjustment = current_difficulty * 4;
-            let min_adjustment = current_difficulty / 4;
-            
-            new_difficulty.max(min_adjustment).min(max_adjustment)
-        } else {
-            // Use previous block's difficulty
-            self.blocks.back().unwrap().header.difficulty
+        self.store.as_ref()?.get_header(height).ok().flatten()
+    }
+
+    /// Height of `hash` if it is a block on the main chain
+    pub fn height_of(&self, hash: &primitives::types::Hash) -> Option<u64> {
+        if let Some(index) = self.position_of(hash) {
+            return Some(self.window_start() + index as u64);
         }
+        self.store.as_ref()?.height_of(hash).filter(|height| *height < self.window_start())
     }
 
-    pub fn tip(&self) -> &Block {
-        self.blocks.back().unwrap()
+    /// Whether `hash` is a block on the main chain
+    pub fn contains_block(&self, hash: &primitives::types::Hash) -> bool {
+        self.height_of(hash).is_some()
     }
 
-    pub fn add_block(&mut self, block: Block) -> bool {
-        // Set coinbase reward for this height
-        let expected_reward = self.emission.block_reward(block.header.height);
-        if block.coinbase.reward != expected_reward {
-            println!("[Chain] Invalid coinbase reward: got {}, expected {}", block.coinbase.reward, expected_reward);
-            return false;
+    /// Locate a confirmed transaction: its block and position in the block
+    pub fn find_transaction(&self, txid: &primitives::types::Hash) -> Option<(Block, usize)> {
+        let in_window = self.blocks.iter().rev().find_map(|block| {
+            let index = block.transactions.iter().position(|tx| tx.id() == *txid)?;
+            Some((block.clone(), index))
+        });
+        in_window.or_else(|| {
+            let location = self.store.as_ref()?.transaction_location(txid)?;
+            if location.height >= self.window_start() {
+                return None;
+            }
+            Some((self.block_at(location.height)?, location.index))
+        })
+    }
+
+    /// Total number of transactions on the main chain
+    pub fn transaction_count(&self) -> usize {
+        match &self.store {
+            Some(store) => store.transaction_count(),
+            None => self.blocks.iter().map(|b| b.transactions.len()).sum(),
         }
-        if !validate_block(&block) {
-            println!("[Chain] Block validation failed at height {}", block.header.height);
-            return false;
+    }
+
+    /// Median timestamp of the last `MEDIAN_TIME_SPAN` blocks
+    pub fn median_time_past(&self) -> u64 {
+        let mut times: Vec<u64> = self.blocks
+            .iter()
+            .rev()
+            .take(config::MEDIAN_TIME_SPAN)
+            .map(|b| b.header.timestamp)
+            .collect();
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    /// Whether a transaction on the main chain spends `key_image`
+    pub fn is_key_image_spent(&self, key_image: &primitives::types::Hash) -> bool {
+        let in_window = self.blocks
+            .iter()
+            .flat_map(|b| b.transactions.iter())
+            .any(|tx| tx.inputs.iter().any(|input| input.key_image == *key_image));
+        // The store may still hold blocks that a reorg is replacing, so only
+        // trust it below the in-memory window
+        in_window || self.store
+            .as_ref()
+            .and_then(|store| store.key_image_height(key_image))
+            .is_some_and(|height| height < self.window_start())
+    }
+
+    /// Locate a confirmed transaction and build its inclusion proof against the block's merkle root
+    pub fn transaction_proof(&self, txid: &primitives::types::Hash) -> Option<(Block, primitives::merkle::MerkleProof)> {
+        let (block, index) = self.find_transaction(txid)?;
+        let ids: Vec<_> = block.transactions.iter().map(|tx| tx.id()).collect();
+        let proof = primitives::merkle::MerkleTree::new(&ids).proof(index)?;
+        Some((block, proof))
+    }
+
+    /// Validate and connect a block, then persist it
+    pub fn add_block(&mut self, block: Block) -> Result<(), BlockValidationError> {
+        self.connect_block(block)?;
+        if let Err(e) = self.persist(None, 1) {
+            self.disconnect_tip();
+            return Err(e);
+        }
+        self.trim_window();
+        Ok(())
+    }
+
+    /// Write the last `count` in-memory blocks to the store, optionally rolling
+    /// the store back to `rollback_to` first, as one atomic batch
+    fn persist(&mut self, rollback_to: Option<u64>, count: usize) -> Result<(), BlockValidationError> {
+        let Some(store) = self.store.as_mut() else {
+            return Ok(());
+        };
+        let batch: Vec<Block> = self.blocks.iter().skip(self.blocks.len() - count).cloned().collect();
+        store.write_batch(rollback_to, &batch).map_err(|e| {
+            println!("[Store] Failed to write block(s): {}", e);
+            BlockValidationError::StorageFailure(e.to_string())
+        })
+    }
+
+    /// Drop blocks that fall out of the in-memory window (store-backed chains only)
+    fn trim_window(&mut self) {
+        if self.store.is_some() {
+            while self.blocks.len() > storage::MEMORY_WINDOW {
+                if let Some(block) = self.blocks.pop_front() {
+                    self.contract_journal.remove(&block.hash());
+                }
+            }
+        }
+    }
+
+    /// Validate a block against the chain and append it in memory
+    fn connect_block(&mut self, block: Block) -> Result<(), BlockValidationError> {
+        if let Err(e) = validate_block_with_chain(&block, Some(self)) {
+            println!("[Chain] Block validation failed at height {}: {}", block.header.height, e);
+            return Err(e);
         }
-        // Process contract transactions
+        self.apply_contracts(&block);
+        self.blocks.push_back(block);
+        Ok(())
+    }
+
+    /// Run a block's contract transactions, journaling what each deployment
+    /// replaces. Invocations run on a fresh instance and leave no state
+    /// behind, so only deployments need undoing.
+    fn apply_contracts(&mut self, block: &Block) {
+        let mut journal = Vec::new();
         for tx in &block.transactions {
             if let TransactionKind::Contract(contract_tx) = &tx.kind {
                 match contract_tx {
                     ContractTx::Deploy { wasm_code, creator, .. } => {
-                        let _ = wasm_vm::deploy_contract(wasm_code.clone(), creator.clone());
+                        let address = wasm_vm::contract_address(wasm_code);
+                        let previous = wasm_vm::contract(&address);
+                        if wasm_vm::deploy_contract(wasm_code.clone(), creator.clone()).is_ok() {
+                            journal.push((address, previous));
+                        }
                     }
                     ContractTx::Invoke { contract_address, function, params, .. } => {
                         // Deserialize params as Vec<serde_json::Value>
@@ -763,80 +1207,316 @@ impl Chain {
                 }
             }
         }
-        self.blocks.push_back(block);
-        true
+        if !journal.is_empty() {
+            self.contract_journal.insert(block.hash(), journal);
+        }
+    }
+
+    /// Take the tip off the main chain in memory, undoing its contract
+    /// deployments
+    fn disconnect_tip(&mut self) -> Option<Block> {
+        let block = self.blocks.pop_back()?;
+        for (address, previous) in self.contract_journal.remove(&block.hash()).into_iter().flatten().rev() {
+            wasm_vm::restore_contract(&address, previous);
+        }
+        Some(block)
+    }
+
+    /// Put blocks taken off by `disconnect_tip` back on the main chain,
+    /// redoing their contract deployments
+    fn reconnect_blocks(&mut self, blocks: impl IntoIterator<Item = Block>) {
+        for block in blocks {
+            self.apply_contracts(&block);
+            self.blocks.push_back(block);
+        }
     }
 }
 
-pub fn validate_block(block: &Block) -> bool {
+/// Reasons a block can be rejected by consensus validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockValidationError {
+    MissingCoinbase,
+    CoinbaseHasInputs,
+    BlockTooLarge { size: usize, max: usize },
+    MalformedTransaction { index: usize },
+    InvalidTransaction { index: usize },
+    DuplicateKeyImage { index: usize },
+    KeyImageAlreadySpent { index: usize },
+    MerkleRootMismatch,
+    InvalidPow(String),
+    InvalidHeight { expected: u64, got: u64 },
+    InvalidPrevHash,
+    InvalidCoinbaseReward { expected: u64, got: u64 },
+    InvalidDifficulty { expected: u64, got: u64 },
+    TimestampTooOld { timestamp: u64, median_time_past: u64 },
+    TimestampTooFarInFuture { timestamp: u64, max_allowed: u64 },
+    /// Block was valid but could not be written to the block store
+    StorageFailure(String),
+}
+
+impl std::fmt::Display for BlockValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockValidationError::MissingCoinbase => write!(f, "block missing coinbase transaction"),
+            BlockValidationError::CoinbaseHasInputs => write!(f, "first transaction is not coinbase"),
+            BlockValidationError::BlockTooLarge { size, max } => write!(f, "block transactions are {} bytes (max {})", size, max),
+            BlockValidationError::MalformedTransaction { index } => write!(f, "tx {} missing inputs or outputs", index),
+            BlockValidationError::InvalidTransaction { index } => write!(f, "tx {} failed validation", index),
+            BlockValidationError::DuplicateKeyImage { index } => write!(f, "tx {} reuses a key image spent earlier in the block", index),
+            BlockValidationError::KeyImageAlreadySpent { index } => write!(f, "tx {} spends a key image already on chain", index),
+            BlockValidationError::MerkleRootMismatch => write!(f, "merkle root does not match transactions"),
+            BlockValidationError::InvalidPow(reason) => write!(f, "invalid proof of work: {}", reason),
+            BlockValidationError::InvalidHeight { expected, got } => write!(f, "invalid block height: expected {}, got {}", expected, got),
+            BlockValidationError::InvalidPrevHash => write!(f, "previous hash does not match chain tip"),
+            BlockValidationError::InvalidCoinbaseReward { expected, got } => write!(f, "invalid coinbase reward: expected {}, got {}", expected, got),
+            BlockValidationError::InvalidDifficulty { expected, got } => write!(f, "invalid difficulty: expected {}, got {}", expected, got),
+            BlockValidationError::TimestampTooOld { timestamp, median_time_past } => write!(f, "timestamp {} not after median time past {}", timestamp, median_time_past),
+            BlockValidationError::TimestampTooFarInFuture { timestamp, max_allowed } => write!(f, "timestamp {} too far in the future (max {})", timestamp, max_allowed),
+            BlockValidationError::StorageFailure(e) => write!(f, "failed to store block: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlockValidationError {}
+
+/// Merkle root committing to a block's transactions
+pub fn calculate_merkle_root(transactions: &[primitives::Transaction]) -> primitives::types::Hash {
+    let ids: Vec<_> = transactions.iter().map(|tx| tx.id()).collect();
+    primitives::merkle::merkle_root(&ids)
+}
+
+/// Encoded size of a transaction, used for fee rates and block limits
+pub fn transaction_size(tx: &primitives::Transaction) -> usize {
+    tx.encode().len()
+}
+
+fn block_transactions_size(transactions: &[primitives::Transaction]) -> usize {
+    transactions.iter().map(transaction_size).fold(0, usize::saturating_add)
+}
+
+/// Input-less transaction placed first in every mined block
+pub fn coinbase_transaction(height: u64, address: &str) -> primitives::Transaction {
+    primitives::Transaction {
+        kind: TransactionKind::Payment,
+        inputs: vec![],
+        outputs: vec![],
+        fee: 0,
+        extra: format!("{}:{}", height, address).into_bytes(),
+        metadata: None,
+        signature: String::new(),
+        quantum_signature: None,
+    }
+}
+
+/// Pick mempool transactions for a new block: highest fee rate first,
+/// skipping anything that pays less than `min_fee_per_byte` or conflicts
+/// with the chain or an already selected transaction, until `max_size`
+/// bytes are used
+pub fn select_block_transactions(chain: &Chain, mempool: &[primitives::Transaction], max_size: usize, min_fee_per_byte: u64) -> Vec<primitives::Transaction> {
+    let mut candidates: Vec<(&primitives::Transaction, usize)> = mempool
+        .iter()
+        .map(|tx| (tx, transaction_size(tx)))
+        .collect();
+    // Compare fee/size by cross-multiplying to avoid floating point
+    candidates.sort_by(|(a, a_size), (b, b_size)| {
+        (b.fee as u128 * *a_size as u128).cmp(&(a.fee as u128 * *b_size as u128))
+    });
+    let mut spent = HashSet::new();
+    let mut selected = Vec::new();
+    let mut used = 0usize;
+    for (tx, size) in candidates {
+        if tx.inputs.is_empty() || used.saturating_add(size) > max_size {
+            continue;
+        }
+        if (tx.fee as u128) < min_fee_per_byte as u128 * size as u128 {
+            continue;
+        }
+        if tx.inputs.iter().any(|input| spent.contains(&input.key_image) || chain.is_key_image_spent(&input.key_image)) {
+            continue;
+        }
+        spent.extend(tx.inputs.iter().map(|input| input.key_image));
+        used += size;
+        selected.push(tx.clone());
+    }
+    selected
+}
+
+/// Drop mempool transactions that are confirmed by `block` or spend a key
+/// image it spends, and those that waited too long
+pub fn remove_block_transactions_from_mempool(block: &Block) {
+    let mut mempool = MEMPOOL.lock().unwrap();
+    let removed = mempool.remove_for_block(block);
+    if removed > 0 {
+        println!("[Mempool] Evicted {} transaction(s) after block {}", removed, block.header.height);
+    }
+    let expired = mempool.expire(mempool::unix_now());
+    if expired > 0 {
+        println!("[Mempool] Expired {} transaction(s)", expired);
+    }
+}
+
+pub fn validate_block(block: &Block) -> Result<(), BlockValidationError> {
     // Basic block validation without chain context
     validate_block_with_chain(block, None)
 }
 
-pub fn validate_block_with_chain(block: &Block, chain: Option<&Chain>) -> bool {
+/// Checks that do not depend on the chain: coinbase placement, transaction
+/// validity, key image uniqueness within the block, merkle root and PoW
+pub fn validate_block_standalone(block: &Block, verify_pow: bool) -> Result<(), BlockValidationError> {
+    validate_block_contents(block, verify_pow, true)
+}
+
+fn validate_block_contents(block: &Block, verify_pow: bool, verify_txs: bool) -> Result<(), BlockValidationError> {
     // Check block has at least one transaction (coinbase)
     if block.transactions.is_empty() {
-        println!("[Validation] Block missing coinbase transaction");
-        return false;
+        return Err(BlockValidationError::MissingCoinbase);
     }
     // Check coinbase is first and has no inputs
     if !block.transactions[0].inputs.is_empty() {
-        println!("[Validation] First transaction is not coinbase");
-        return false;
+        return Err(BlockValidationError::CoinbaseHasInputs);
+    }
+    let size = block_transactions_size(&block.transactions);
+    if size > config::MAX_BLOCK_SIZE {
+        return Err(BlockValidationError::BlockTooLarge { size, max: config::MAX_BLOCK_SIZE });
     }
-    // Check all transactions (except coinbase) have at least one input and output
+    let mut block_key_images = HashSet::new();
     for (i, tx) in block.transactions.iter().enumerate().skip(1) {
+        // Every non-coinbase transaction needs at least one input and output
         if tx.inputs.is_empty() || tx.outputs.is_empty() {
-            println!("[Validation] Tx {} missing inputs or outputs", i);
-            return false;
+            return Err(BlockValidationError::MalformedTransaction { index: i });
+        }
+        for input in &tx.inputs {
+            if !block_key_images.insert(input.key_image) {
+                return Err(BlockValidationError::DuplicateKeyImage { index: i });
+            }
         }
     }
-    
+    if verify_txs {
+        if let Some(index) = first_invalid_transaction(&block.transactions[1..]) {
+            return Err(BlockValidationError::InvalidTransaction { index: index + 1 });
+        }
+    }
+    if calculate_merkle_root(&block.transactions) != block.header.merkle_root {
+        return Err(BlockValidationError::MerkleRootMismatch);
+    }
+    if verify_pow {
+        randomx_verifier::RANDOMX_VERIFIER
+            .verify_consensus_pow(&block.header)
+            .map_err(BlockValidationError::InvalidPow)?;
+    }
+    Ok(())
+}
+
+/// Threads that verify the signatures and proofs of block transactions
+static VERIFY_POOL: OnceCell<rayon::ThreadPool> = OnceCell::new();
+
+/// Verify block transactions on `threads` threads instead of rayon's
+/// default pool. Only the first call takes effect.
+pub fn set_verify_threads(threads: usize) -> Result<(), rayon::ThreadPoolBuildError> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("verify-{}", i))
+        .build()?;
+    let _ = VERIFY_POOL.set(pool);
+    Ok(())
+}
+
+/// Index of the first transaction failing `check_transaction`, checked in parallel
+fn first_invalid_transaction(txs: &[primitives::Transaction]) -> Option<usize> {
+    use rayon::prelude::*;
+    let find = || txs.par_iter().position_first(|tx| !check_transaction(tx));
+    match VERIFY_POOL.get() {
+        Some(pool) => pool.install(find),
+        None => find(),
+    }
+}
+
+pub fn validate_block_with_chain(block: &Block, chain: Option<&Chain>) -> Result<(), BlockValidationError> {
+    let verify_pow = chain.is_none_or(|c| !c.skip_pow_check);
+    let verify_txs = chain.is_none_or(|c| !c.skip_tx_verification);
+    validate_block_contents(block, verify_pow, verify_txs)?;
+
     // Enhanced validation with chain context
     if let Some(chain) = chain {
+        let prev_block = chain.tip();
         // Validate block height sequence
-        if block.header.height > 0 {
-            let prev_block = chain.tip();
-            if block.header.height != prev_block.header.height + 1 {
-                println!("[Validation] Invalid block height: expected {}, got {}", 
-                    prev_block.header.height + 1, block.header.height);
-                return false;
-            }
-            // Validate previous hash
-            if block.header.prev_hash != prev_block.header.pow.hash {
-                println!("[Validation] Invalid previous hash");
-                return false;
-            }
+        if block.header.height != prev_block.header.height + 1 {
+            return Err(BlockValidationError::InvalidHeight {
+                expected: prev_block.header.height + 1,
+                got: block.header.height,
+            });
+        }
+        // Validate previous hash
+        if block.header.prev_hash != prev_block.hash() {
+            return Err(BlockValidationError::InvalidPrevHash);
         }
         
         // Validate coinbase reward
         let expected_reward = chain.emission.block_reward(block.header.height);
         if block.coinbase.reward != expected_reward {
-            println!("[Validation] Invalid coinbase reward: got {}, expected {}", 
-                block.coinbase.reward, expected_reward);
-            return false;
+            return Err(BlockValidationError::InvalidCoinbaseReward {
+                expected: expected_reward,
+                got: block.coinbase.reward,
+            });
         }
         
-        // Validate difficulty (simplified check)
-        if block.header.height > 0 {
-            let expected_difficulty = chain.network.calculate_next_difficulty(chain);
-            if block.header.difficulty != expected_difficulty {
-                println!("[Validation] Invalid difficulty: got {}, expected {}", 
-                    block.header.difficulty, expected_difficulty);
-                // For now, just warn instead of rejecting
-                // return false;
+        // Validate difficulty
+        let expected_difficulty = chain.next_difficulty();
+        if block.header.difficulty != expected_difficulty {
+            return Err(BlockValidationError::InvalidDifficulty {
+                expected: expected_difficulty,
+                got: block.header.difficulty,
+            });
+        }
+
+        // Timestamp must move past the median of recent blocks...
+        let median_time_past = chain.median_time_past();
+        if block.header.timestamp <= median_time_past {
+            return Err(BlockValidationError::TimestampTooOld {
+                timestamp: block.header.timestamp,
+                median_time_past,
+            });
+        }
+        // ...and must not run too far ahead of our clock
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let max_allowed = now + config::MAX_FUTURE_BLOCK_TIME_SEC;
+        if block.header.timestamp > max_allowed {
+            return Err(BlockValidationError::TimestampTooFarInFuture {
+                timestamp: block.header.timestamp,
+                max_allowed,
+            });
+        }
+
+        // No key image may already be spent on the main chain
+        for (i, tx) in block.transactions.iter().enumerate().skip(1) {
+            if tx.inputs.iter().any(|input| chain.is_key_image_spent(&input.key_image)) {
+                return Err(BlockValidationError::KeyImageAlreadySpent { index: i });
             }
         }
     }
     
-    // TODO: Check merkle root, signatures, and block hash
-    true
+    Ok(())
 }
 
 pub mod network {
+    pub mod addrman;
+    pub mod banlist;
+    pub mod compact;
+    pub mod dandelion;
+    pub mod onion;
+    pub mod peer_manager;
     pub mod privacy;
+    pub mod proxy;
+    pub mod relay;
+    pub mod transport;
+    pub mod wire;
 }
-use network::privacy::{PrivacyConfig, is_onion_address, is_i2p_address};
+use network::peer_manager::{PeerConfig, PeerManager};
+use network::relay::{InvItem, InvKind};
+use network::privacy::{PrivacyConfig, PrivacyManager, PrivacyMode};
 // use once_cell::sync::OnceCell; (already imported above)
 
 static PRIVACY_CONFIG: OnceCell<PrivacyConfig> = OnceCell::new();
@@ -848,114 +1528,50 @@ fn get_privacy_config() -> &'static PrivacyConfig {
     })
 }
 
+/// Dial `addr` (`ip:port`, onion or I2P) through the peer manager; the
+/// privacy policy decides whether the connection is allowed
 pub fn connect_to_peer(addr: &str) {
-    let privacy = get_privacy_config();
-    let mode = &privacy.privacy_mode;
-    match mode {
-        crate::network::privacy::PrivacyMode::Auto => {
-            // Try Tor first
-            if is_onion_address(addr) {
-                println!("[Privacy] Auto mode: trying Tor for {}", addr);
-                // In a real implementation, use Tor SOCKS5 here
-                // For now, fall through to clearnet if not available
-            } else if is_i2p_address(addr) && privacy.i2p_enabled {
-                println!("[Privacy] Auto mode: trying I2P for {}", addr);
-                let sam_addr = privacy.i2p_proxy.as_deref().unwrap_or(i2p::DEFAULT_SAM_ADDR);
-                match I2pClient::new(sam_addr, "blacksilk", "STREAM") {
-                    Ok(mut client) => {
-                        println!("[I2P] Connected to I2P peer {} via SAM {}", addr, sam_addr);
-                        let version = P2PMessage::Version { version: 1, node: "BlackSilkNode-I2P".to_string() };
-                        let data = serde_json::to_vec(&version).unwrap();
-                        if let Err(e) = client.send_to(addr, &data) {
-                            eprintln!("[I2P] Failed to send to I2P peer {}: {}", addr, e);
-                        }
-                        return;
-                    }
-                    Err(e) => {
-                        eprintln!("[I2P] Failed to connect to I2P peer {}: {}", addr, e);
-                    }
-                }
-            } else {
-                println!("[Privacy] Auto mode: falling back to clearnet for {}", addr);
-            }
+    let endpoint = match network::proxy::Endpoint::parse(addr) {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            eprintln!("[P2P] {}", e);
+            return;
         }
-        _ => {
-            if privacy.tor_only && !(is_onion_address(addr) || is_i2p_address(addr)) {
-                println!("[Privacy] Connection to non-Tor/I2P address blocked: {}", addr);
-                return;
-            }
-            if is_i2p_address(addr) && privacy.i2p_enabled {
-                let sam_addr = privacy.i2p_proxy.as_deref().unwrap_or(i2p::DEFAULT_SAM_ADDR);
-                match I2pClient::new(sam_addr, "blacksilk", "STREAM") {
-                    Ok(mut client) => {
-                        println!("[I2P] Connected to I2P peer {} via SAM {}", addr, sam_addr);
-                        let version = P2PMessage::Version { version: 1, node: "BlackSilkNode-I2P".to_string() };
-                        let data = serde_json::to_vec(&version).unwrap();
-                        if let Err(e) = client.send_to(addr, &data) {
-                            eprintln!("[I2P] Failed to send to I2P peer {}: {}", addr, e);
-                        }
-                        return;
-                    }
-                    Err(e) => {
-                        eprintln!("[I2P] Failed to connect to I2P peer {}: {}", addr, e);
-                    }
-                }
-                return;
+    };
+    match network::peer_manager::start(PeerConfig::default(), Some(legacy_privacy_manager())) {
+        Ok(manager) => {
+            if let Err(e) = manager.connect_endpoint(&endpoint) {
+                eprintln!("[P2P] Not connecting to {}: {}", endpoint, e);
             }
         }
+        Err(e) => eprintln!("[P2P] Failed to start peer manager: {}", e),
     }
-    // Default: clearnet connection
-    let mut retries = 3;
-    while retries > 0 {
-        match TcpStream::connect(addr) {
-            Ok(mut stream) => {
-                println!("[P2P] Connected to peer {}", addr);
-                let version = P2PMessage::Version { version: 1, node: "BlackSilkNode".to_string() };
-                if let Err(e) = send_message(&mut stream, &version) {
-                    eprintln!("[P2P] Failed to send version message: {}", e);
-                }
-                return;
-            }
-            Err(e) => {
-                eprintln!("[P2P] Failed to connect to {}: {}", addr, e);
-            }
-        }
-        retries -= 1;
-        if retries > 0 {
-            println!("[P2P] Retrying connection... ({} attempts left)", retries);
-            std::thread::sleep(std::time::Duration::from_secs(2));
-        }
+}
+
+/// Privacy policy for the nodes started without `PrivacyManager`: with
+/// `tor_only` set, clearnet peers are refused in both directions
+fn legacy_privacy_manager() -> Arc<PrivacyManager> {
+    let mut config = get_privacy_config().clone();
+    if config.tor_only {
+        config.privacy_mode = PrivacyMode::MaxPrivacy;
     }
-    println!("[P2P] All attempts to connect to peer {} failed.", addr);
+    Arc::new(PrivacyManager::new(config))
 }
 
 pub fn start_p2p_server(port: u16) {
-    let privacy = get_privacy_config();
-    let addr = format!("0.0.0.0:{}", port);
-    let listener = TcpListener::bind(&addr).expect("Failed to bind P2P port");
-    println!("[P2P] Listening for peers on {}", addr);
-    for stream in listener.incoming() {
-        match stream {
-            Ok(s) => {
-                // Check remote address for Tor/I2P enforcement
-                let remote = s.peer_addr().map(|a| a.to_string()).unwrap_or_default();
-                if privacy.tor_only && !(is_onion_address(&remote) || is_i2p_address(&remote)) {
-                    println!("[Privacy] Rejected clearnet connection from {}", remote);
-                    continue;
-                }
-                thread::spawn(|| handle_client(s));
-            }
-            Err(e) => println!("[P2P] Connection failed: {}", e),
-        }
+    let manager = network::peer_manager::start(PeerConfig::default(), Some(legacy_privacy_manager()))
+        .expect("Failed to start peer manager");
+    manager
+        .listen(SocketAddr::from(([0, 0, 0, 0], port)))
+        .expect("Failed to bind P2P port");
+    loop {
+        std::thread::park();
     }
 }
 
 pub fn start_node_with_port_and_connect(port: u16, connect_addr: Option<String>) {
     let network = Network::from_env_or_default();
-    let magic = match network {
-        Network::Mainnet => config::MAINNET_MAGIC,
-        Network::Testnet => config::TESTNET_MAGIC,
-    };
+    let magic = network.get_magic();
     println!("[BlackSilk Node] Starting {:?} node on port {} (magic: 0x{:X})", network, port, magic);
     let chain = Chain::new_for_network(network);
     println!("[BlackSilk Node] Genesis block height: {}", chain.tip().header.height);
@@ -966,20 +1582,24 @@ pub fn start_node_with_port_and_connect(port: u16, connect_addr: Option<String>)
     // TODO: Networking, consensus, mining, إلخ
 }
 
+/// Replace the global chain with the one persisted under `data_dir`
+pub fn load_chain(network: Network, data_dir: &std::path::Path) -> std::io::Result<()> {
+    let chain = Chain::open(network, data_dir)?;
+    *CHAIN.lock().unwrap() = chain;
+    Ok(())
+}
+
 pub fn start_node_with_args(port: u16, connect_addr: Option<String>, data_dir: Option<PathBuf>) {
     let network = Network::from_env_or_default();
-    let magic = match network {
-        Network::Mainnet => config::MAINNET_MAGIC,
-        Network::Testnet => config::TESTNET_MAGIC,
-    };
-    if let Some(ref dir) = data_dir {
-        println!("[BlackSilk Node] Using data directory: {}", dir.display());
-        // هنا يمكنك تفعيل منطق تغيير مسار التخزين إذا أردت
-        // مثال: std::env::set_current_dir(dir).unwrap();
-    }
+    let magic = network.get_magic();
+    let data_dir = data_dir.unwrap_or_else(|| PathBuf::from("./data"));
+    println!("[BlackSilk Node] Using data directory: {}", data_dir.display());
     println!("[BlackSilk Node] Starting {:?} node on port {} (magic: 0x{:X})", network, port, magic);
-    let chain = Chain::new_for_network(network.clone());
-    println!("[BlackSilk Node] Genesis block height: {}", chain.tip().header.height);
+    if let Err(e) = load_chain(network.clone(), &data_dir) {
+        eprintln!("[BlackSilk Node] Failed to open block store: {}", e);
+        std::process::exit(1);
+    }
+    println!("[BlackSilk Node] Chain height: {}", CHAIN.lock().unwrap().tip().header.height);
     if let Some(addr) = connect_addr {
         connect_to_peer(&addr);
     }
@@ -1004,8 +1624,7 @@ pub fn start_node_with_args(port: u16, connect_addr: Option<String>, data_dir: O
     let _http_handle = std::thread::spawn(move || {
         println!("[HTTP] HTTP thread starting...");
         io::stdout().flush().unwrap();
-        let data_dir = data_dir.clone().unwrap_or_else(|| PathBuf::from("./data"));
-        match http_server::start_http_server_sync(http_port, data_dir) {
+        match http_server::start_http_server_sync(http_port) {
             Ok(_) => {
                 println!("[HTTP] HTTP server stopped normally");
             }
@@ -1026,10 +1645,7 @@ pub fn start_node_with_args(port: u16, connect_addr: Option<String>, data_dir: O
 /// Placeholder for node startup
 pub fn start_node() {
     let network = Network::from_env_or_default();
-    let (port, magic) = match network {
-        Network::Mainnet => (config::MAINNET_P2P_PORT, config::MAINNET_MAGIC),
-        Network::Testnet => (config::TESTNET_P2P_PORT, config::TESTNET_MAGIC),
-    };
+    let (port, magic) = (network.get_ports().p2p, network.get_magic());
     println!("[BlackSilk Node] Starting {:?} node on port {} (magic: 0x{:X})", network, port, magic);
     let chain = Chain::new_for_network(network);
     println!("[BlackSilk Node] Genesis block height: {}", chain.tip().header.height);
@@ -1037,121 +1653,139 @@ pub fn start_node() {
     // TODO: Networking, consensus, mining, إلخ
 }
 
+/// Address the local Tor daemon reaches a listener bound to `listen` on
+fn local_target(listen: SocketAddr) -> SocketAddr {
+    if listen.ip().is_unspecified() {
+        SocketAddr::from(([127, 0, 0, 1], listen.port()))
+    } else {
+        listen
+    }
+}
+
 pub fn start_p2p_server_with_privacy(
-    port: u16, 
+    listen: SocketAddr,
     privacy_manager: Arc<crate::network::privacy::PrivacyManager>,
-    peers: Vec<String>
+    peers: Vec<String>,
+    peer_config: PeerConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let addr = format!("0.0.0.0:{}", port);
-    let listener = TcpListener::bind(&addr)?;
-    println!("[P2P] Enhanced privacy-aware server listening on {}", addr);
-    
     // Display privacy manager stats
     let stats = privacy_manager.get_stats();
     println!("[Privacy] Starting with {} total connections", stats.total_connections);
-    
-    // Connect to initial peers with privacy validation
-    for peer_addr in peers {
-        let peer_socket: SocketAddr = peer_addr.parse()
-            .map_err(|e| format!("Invalid peer address {}: {}", peer_addr, e))?;
-        
-        if privacy_manager.allow_connection(&peer_socket, true) {
-            println!("[P2P] Connecting to validated peer: {}", peer_addr);
-            let privacy_clone = privacy_manager.clone();
-            std::thread::spawn(move || {
-                connect_to_peer_with_privacy(&peer_addr, privacy_clone);
-            });
-        } else {
-            println!("[Privacy] Peer {} rejected by privacy policy", peer_addr);
+    println!("[P2P] Peer limits: {} inbound, {} outbound (keeping at least {})",
+        peer_config.max_inbound, peer_config.max_outbound, peer_config.min_outbound);
+
+    let data_dir = peer_config.data_dir.clone();
+    let privacy_config = privacy_manager.config().clone();
+    let manager = network::peer_manager::start(peer_config, Some(privacy_manager))?;
+    manager.listen(listen)?;
+    let port = listen.port();
+
+    // The onion service forwards to the listener; a node that cannot
+    // publish it still runs, just without inbound onion peers
+    if privacy_config.hidden_service {
+        let control = privacy_config.tor_control.as_deref().unwrap_or(network::onion::DEFAULT_CONTROL_ADDR);
+        let onion_port = if privacy_config.hidden_service_port == 0 { port } else { privacy_config.hidden_service_port };
+        let published = match (control.parse::<SocketAddr>(), data_dir) {
+            (Ok(control), Some(data_dir)) => manager
+                .publish_onion_service(&data_dir, control, onion_port, local_target(listen))
+                .map_err(|e| e.to_string()),
+            (Err(_), _) => Err(format!("invalid Tor control address {}", control)),
+            (_, None) => Err("no data directory for the onion key".to_string()),
+        };
+        if let Err(e) = published {
+            println!("[Tor] Onion service not published: {}", e);
         }
     }
-    
-    // Main server loop with privacy filtering
-    for stream in listener.incoming() {
-        match stream {
-            Ok(s) => {
-                if let Ok(peer_addr) = s.peer_addr() {
-                    // Check privacy policy before accepting connection
-                    if privacy_manager.allow_connection(&peer_addr, false) {
-                        // Register the connection
-                        privacy_manager.register_connection(peer_addr, false);
-                        
-                        let privacy_clone = privacy_manager.clone();
-                        std::thread::spawn(move || {
-                            handle_client_with_privacy(s, privacy_clone);
-                        });
-                    } else {
-                        println!("[Privacy] Incoming connection from {} rejected by privacy policy", peer_addr);
-                        // Connection is automatically dropped
-                    }
-                } else {
-                    println!("[P2P] Failed to get peer address, rejecting connection");
-                }
-            }
-            Err(e) => {
-                println!("[P2P] Connection error: {}", e);
-            }
+
+    // Initial peers seed the address book and are dialed right away
+    for peer_addr in peers {
+        let endpoint = network::proxy::Endpoint::parse(&peer_addr)?;
+        if let Err(e) = manager.connect_endpoint(&endpoint) {
+            println!("[P2P] Not connecting to {}: {}", peer_addr, e);
         }
     }
-    
-    Ok(())
-}
 
-fn connect_to_peer_with_privacy(addr: &str, privacy_manager: Arc<crate::network::privacy::PrivacyManager>) {
-    println!("[P2P] Attempting privacy-aware connection to {}", addr);
-    
-    let mut retries = 3;
-    while retries > 0 {
-        match TcpStream::connect(addr) {
-            Ok(mut stream) => {
-                if let Ok(peer_addr) = stream.peer_addr() {
-                    privacy_manager.register_connection(peer_addr, true);
-                    
-                    println!("[P2P] Privacy-validated connection established to {}", addr);
-                    let version = P2PMessage::Version { 
-                        version: 1, 
-                        node: "BlackSilkNode-Privacy".to_string() 
-                    };
-                    
-                    if let Err(e) = send_message(&mut stream, &version) {
-                        eprintln!("[P2P] Failed to send version message: {}", e);
-                        privacy_manager.unregister_connection(&stream.peer_addr().unwrap());
-                    } else {
-                        // Connection established, wait for messages
-                        let _ = handle_client_with_privacy(stream, privacy_manager);
-                        return;
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("[P2P] Failed to connect to {}: {}", addr, e);
-            }
-        }
-        retries -= 1;
-        if retries > 0 {
-            println!("[P2P] Retrying connection... ({} attempts left)", retries);
-            std::thread::sleep(std::time::Duration::from_secs(2));
-        }
+    loop {
+        std::thread::park();
     }
-    println!("[P2P] All attempts to connect to peer {} failed.", addr);
 }
 
-/// Add a transaction to the mempool
-pub fn add_to_mempool(tx: primitives::Transaction) {
+/// Add a transaction to the mempool, replacing conflicting spends it
+/// outbids and evicting cheaper transactions if the mempool is full
+pub fn add_to_mempool(tx: primitives::Transaction) -> Result<(), mempool::MempoolError> {
     let mut mempool = MEMPOOL.lock().unwrap();
-    mempool.push(tx);
+    mempool.insert(tx, mempool::unix_now())
 }
 
-/// Get a copy of the current mempool
+/// Get a copy of the current mempool, best fee rate first
 pub fn get_mempool() -> Vec<primitives::Transaction> {
     let mempool = MEMPOOL.lock().unwrap();
-    mempool.clone()
+    mempool.transactions()
+}
+
+/// Cap the mempool at `max_size` bytes (`--mempool-size`)
+pub fn set_mempool_max_size(max_size: usize) {
+    MEMPOOL.lock().unwrap().set_max_size(max_size);
+}
+
+/// Keep up to `bytes` of blocks read back from the block store in memory
+pub fn set_db_cache(bytes: usize) {
+    if let Some(store) = CHAIN.lock().unwrap().store.as_mut() {
+        store.set_cache_limit(bytes);
+    }
+}
+
+/// Set the fee per byte spends must pay to enter the mempool and blocks
+pub fn set_min_relay_fee(min_fee_per_byte: u64) {
+    MEMPOOL.lock().unwrap().set_min_fee_per_byte(min_fee_per_byte);
 }
 
-/// Stub for chain reorg logic
-pub fn maybe_reorg_chain(_blocks: Vec<primitives::Block>) {
-    // TODO: Implement chain reorganization logic
-    println!("[Chain] Reorg logic not yet implemented");
+/// Feed a batch of blocks (e.g. a `Blocks` response) through fork choice,
+/// reorganizing onto a heavier branch if one is found
+pub fn maybe_reorg_chain(mut blocks: Vec<primitives::Block>) {
+    blocks.sort_by_key(|b| b.header.height);
+    for block in blocks {
+        accept_network_block(block);
+    }
+}
+
+/// Apply a block received from a peer and return orphaned transactions to
+/// the mempool if it caused a reorganization
+pub fn accept_network_block(block: primitives::Block) -> BlockAcceptance {
+    let height = block.header.height;
+    let mut chain = CHAIN.lock().unwrap();
+    let outcome = chain.process_block(block.clone());
+    match &outcome {
+        BlockAcceptance::Extended => {
+            println!("[Chain] Block {} added", height);
+            remove_block_transactions_from_mempool(&block);
+        }
+        BlockAcceptance::SideChain => println!("[Chain] Block {} stored on side chain", height),
+        BlockAcceptance::Reorganized { disconnected, connected, orphaned_txs } => {
+            println!("[Chain] Reorg complete: -{} +{} blocks, new tip {}, {} tx(s) returned to mempool",
+                disconnected, connected, chain.tip().header.height, orphaned_txs.len());
+            let spent: HashSet<primitives::types::Hash> = chain.blocks
+                .iter()
+                .rev()
+                .take(*connected)
+                .flat_map(|b| b.transactions.iter())
+                .flat_map(|tx| tx.inputs.iter().map(|input| input.key_image))
+                .collect();
+            let mut mempool = MEMPOOL.lock().unwrap();
+            // Anything the new branch already spends is no longer valid
+            mempool.remove_spent(&spent);
+            let now = mempool::unix_now();
+            for tx in orphaned_txs {
+                if !tx.inputs.iter().any(|input| chain.is_key_image_spent(&input.key_image)) {
+                    let _ = mempool.insert(tx.clone(), now);
+                }
+            }
+        }
+        BlockAcceptance::Orphan => println!("[Chain] Block {} has unknown parent", height),
+        BlockAcceptance::Duplicate => {}
+        BlockAcceptance::Rejected => println!("[Chain] Block {} rejected", height),
+    }
+    outcome
 }
 
 /// Stub for range proof validation
@@ -1160,12 +1794,6 @@ pub fn validate_range_proof(_proof: &[u8], _commitment: &[u8]) -> bool {
     true
 }
 
-/// Stub for privacy-aware client handler
-pub fn handle_client_with_privacy(_stream: std::net::TcpStream, _privacy_manager: std::sync::Arc<crate::network::privacy::PrivacyManager>) -> std::io::Result<()> {
-    // TODO: Implement privacy-aware client handling
-    Ok(())
-}
-
 /// Validate an Address (classical or quantum)
 fn validate_address(addr: &primitives::Address) -> bool {
     // Example: check encoding, scheme, and key lengths
@@ -1190,6 +1818,7 @@ mod pqsignatures_integration;
 #[cfg(test)]
 mod tests {
     use super::pqsignatures_integration;
+    use super::{Chain, Network, BlockAcceptance, BlockValidationError};
     #[test]
     fn test_dilithium2_integration() {
         pqsignatures_integration::dilithium2_demo();
@@ -1198,4 +1827,493 @@ mod tests {
     fn test_falcon512_integration() {
         pqsignatures_integration::falcon512_demo();
     }
+
+    fn coinbase_tx() -> primitives::Transaction {
+        primitives::Transaction {
+            kind: primitives::TransactionKind::Payment,
+            inputs: vec![],
+            outputs: vec![],
+            fee: 0,
+            extra: vec![],
+            metadata: None,
+            signature: String::new(),
+            quantum_signature: None,
+        }
+    }
+
+    fn spend_tx(key_image: u8) -> primitives::Transaction {
+        use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+        use curve25519_dalek::scalar::Scalar;
+        let mut tx = coinbase_tx();
+        tx.extra = vec![key_image];
+        let secrets = [[key_image; 32], [key_image.wrapping_add(1); 32]];
+        let ring: Vec<[u8; 32]> = secrets
+            .iter()
+            .map(|sk| (ED25519_BASEPOINT_POINT * Scalar::from_bytes_mod_order(*sk)).compress().to_bytes())
+            .collect();
+        let signature = primitives::ring_sig::generate_ring_signature(&tx.extra, &ring, &secrets[0], 0);
+        tx.inputs.push(primitives::TransactionInput {
+            key_image: [key_image; 32],
+            ring_sig: primitives::RingSignature { ring, signature, quantum: None },
+        });
+        tx.outputs.push(primitives::TransactionOutput {
+            amount_commitment: [0u8; 32],
+            stealth_address: primitives::StealthAddress {
+                view_key: primitives::types::PublicKey::Ed25519([0u8; 32]),
+                spend_key: primitives::types::PublicKey::Ed25519([0u8; 32]),
+            },
+            range_proof: vec![1],
+        });
+        tx
+    }
+
+    fn test_chain() -> Chain {
+        let mut chain = Chain::new_for_network(Network::Testnet);
+        chain.skip_pow_check = true;
+        chain
+    }
+
+    fn test_block(chain: &Chain, prev: &primitives::Block, tag: u8, difficulty: u64, txs: Vec<primitives::Transaction>) -> primitives::Block {
+        let height = prev.header.height + 1;
+        let mut transactions = vec![coinbase_tx()];
+        transactions.extend(txs);
+        primitives::Block {
+            header: primitives::BlockHeader {
+                version: 1,
+                prev_hash: prev.hash(),
+                merkle_root: super::calculate_merkle_root(&transactions),
+                timestamp: prev.header.timestamp + 120,
+                height,
+                difficulty,
+                pow: primitives::Pow { nonce: 0, hash: [tag, height as u8, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1] },
+            },
+            coinbase: primitives::Coinbase { reward: chain.emission.block_reward(height), to: "miner".to_string() },
+            transactions,
+        }
+    }
+
+    #[test]
+    fn test_heavier_side_branch_triggers_reorg() {
+        let mut chain = test_chain();
+        let genesis = chain.tip().clone();
+        let a1 = test_block(&chain, &genesis, 0xA, 1, vec![spend_tx(7)]);
+        assert!(matches!(chain.process_block(a1.clone()), BlockAcceptance::Extended));
+
+        // Competing block at the same height with equal work stays on the side
+        let b1 = test_block(&chain, &genesis, 0xB, 1, vec![]);
+        assert!(matches!(chain.process_block(b1.clone()), BlockAcceptance::SideChain));
+        assert_eq!(chain.tip().hash(), a1.hash());
+
+        // Extending the side branch makes it heavier
+        let b2 = test_block(&chain, &b1, 0xB, 1, vec![]);
+        match chain.process_block(b2.clone()) {
+            BlockAcceptance::Reorganized { disconnected, connected, orphaned_txs } => {
+                assert_eq!(disconnected, 1);
+                assert_eq!(connected, 2);
+                assert_eq!(orphaned_txs.len(), 1);
+                assert_eq!(orphaned_txs[0].inputs[0].key_image, [7u8; 32]);
+            }
+            other => panic!("expected reorg, got {:?}", other),
+        }
+        assert_eq!(chain.tip().hash(), b2.hash());
+        assert_eq!(chain.blocks.len(), 3);
+        assert!(chain.side_chains.contains(&a1.hash()));
+    }
+
+    #[test]
+    fn test_reorg_undoes_contract_deployments() {
+        use primitives::{ContractTx, TransactionKind};
+        // `(module (func (export "<name>")))`
+        let wasm = |export: &str| {
+            let mut code = b"\0asm\x01\0\0\0\x01\x04\x01\x60\0\0\x03\x02\x01\0".to_vec();
+            code.extend([0x07, export.len() as u8 + 4, 0x01, export.len() as u8]);
+            code.extend(export.as_bytes());
+            code.extend([0x00, 0x00, 0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b]);
+            code
+        };
+        let deploy_tx = |key_image: u8, export: &str| {
+            let mut tx = spend_tx(key_image);
+            tx.kind = TransactionKind::Contract(ContractTx::Deploy {
+                wasm_code: wasm(export),
+                creator: "creator".to_string(),
+                metadata: None,
+            });
+            tx
+        };
+        let address = |tx: &primitives::Transaction| match &tx.kind {
+            TransactionKind::Contract(ContractTx::Deploy { wasm_code, .. }) => crate::wasm_vm::contract_address(wasm_code),
+            _ => unreachable!(),
+        };
+        let mut chain = test_chain();
+        let genesis = chain.tip().clone();
+        let a1 = test_block(&chain, &genesis, 0xA, 1, vec![deploy_tx(31, "reorg_a")]);
+        let contract_a = address(&a1.transactions[1]);
+        assert!(matches!(chain.process_block(a1.clone()), BlockAcceptance::Extended));
+        assert!(crate::wasm_vm::contract(&contract_a).is_some());
+
+        // The deployment goes away with its block...
+        let b1 = test_block(&chain, &genesis, 0xB, 1, vec![]);
+        let b2 = test_block(&chain, &b1, 0xB, 1, vec![]);
+        chain.process_block(b1);
+        assert!(matches!(chain.process_block(b2.clone()), BlockAcceptance::Reorganized { .. }));
+        assert!(crate::wasm_vm::contract(&contract_a).is_none());
+
+        // ...and comes back when the branch wins again
+        let a2 = test_block(&chain, &a1, 0xA, 1, vec![]);
+        let a3 = test_block(&chain, &a2, 0xA, 1, vec![]);
+        chain.process_block(a2);
+        assert!(matches!(chain.process_block(a3.clone()), BlockAcceptance::Reorganized { .. }));
+        assert!(crate::wasm_vm::contract(&contract_a).is_some());
+
+        // A branch that fails halfway leaves no deployment behind and
+        // restores those of the old chain
+        let c1 = test_block(&chain, &genesis, 0xC, 1, vec![deploy_tx(32, "reorg_c")]);
+        let contract_c = address(&c1.transactions[1]);
+        let c2 = test_block(&chain, &c1, 0xC, 1, vec![]);
+        let c3 = test_block(&chain, &c2, 0xC, 10, vec![]);
+        chain.process_block(c1);
+        chain.process_block(c2);
+        assert!(matches!(chain.process_block(c3), BlockAcceptance::Rejected));
+        assert_eq!(chain.tip().hash(), a3.hash());
+        assert!(crate::wasm_vm::contract(&contract_c).is_none());
+        assert!(crate::wasm_vm::contract(&contract_a).is_some());
+    }
+
+    #[test]
+    fn test_invalid_heavier_branch_is_discarded() {
+        let mut chain = test_chain();
+        let genesis = chain.tip().clone();
+        let a1 = test_block(&chain, &genesis, 0xA, 1, vec![]);
+        let a2 = test_block(&chain, &a1, 0xA, 1, vec![]);
+        chain.process_block(a1);
+        chain.process_block(a2.clone());
+
+        // A single block claiming more work than the main branch triggers a
+        // reorg attempt, but its difficulty is wrong so the old chain stays
+        let b1 = test_block(&chain, &genesis, 0xB, 5, vec![]);
+        assert!(matches!(chain.process_block(b1.clone()), BlockAcceptance::Rejected));
+        assert_eq!(chain.tip().hash(), a2.hash());
+        assert_eq!(chain.cumulative_work(), 3);
+        assert!(!chain.side_chains.contains(&b1.hash()));
+
+        // When a longer branch fails partway, its valid prefix is kept and
+        // can still win with a later block
+        let c1 = test_block(&chain, &genesis, 0xC, 1, vec![]);
+        let c2 = test_block(&chain, &c1, 0xC, 1, vec![]);
+        let bad_c3 = test_block(&chain, &c2, 0xC, 10, vec![]);
+        let bad_c4 = test_block(&chain, &bad_c3, 0xC, 1, vec![]);
+        chain.process_block(c1.clone());
+        chain.side_chains.insert(bad_c4.clone());
+        assert!(matches!(chain.process_block(c2.clone()), BlockAcceptance::SideChain));
+        assert!(matches!(chain.process_block(bad_c3.clone()), BlockAcceptance::Rejected));
+        assert_eq!(chain.tip().hash(), a2.hash());
+        assert!(chain.side_chains.contains(&c1.hash()) && chain.side_chains.contains(&c2.hash()));
+        assert!(!chain.side_chains.contains(&bad_c3.hash()) && !chain.side_chains.contains(&bad_c4.hash()));
+        let c3 = test_block(&chain, &c2, 0xD, 1, vec![]);
+        assert!(matches!(chain.process_block(c3.clone()), BlockAcceptance::Reorganized { .. }));
+        assert_eq!(chain.tip().hash(), c3.hash());
+
+        // Unknown parent is reported as an orphan and not stored
+        let stray = test_block(&chain, &a2, 0xC, 1, vec![]);
+        let stray_child = test_block(&chain, &stray, 0xC, 1, vec![]);
+        assert!(matches!(chain.process_block(stray_child), BlockAcceptance::Orphan));
+    }
+
+    #[test]
+    fn test_block_validation_errors() {
+        let mut chain = test_chain();
+        let genesis = chain.tip().clone();
+
+        let mut bad_merkle = test_block(&chain, &genesis, 0xA, 1, vec![spend_tx(1)]);
+        bad_merkle.header.merkle_root = [9u8; 32];
+        assert_eq!(chain.add_block(bad_merkle), Err(BlockValidationError::MerkleRootMismatch));
+
+        let dup = test_block(&chain, &genesis, 0xA, 1, vec![spend_tx(1), spend_tx(1)]);
+        assert_eq!(chain.add_block(dup), Err(BlockValidationError::DuplicateKeyImage { index: 2 }));
+
+        let mut bad_sig = spend_tx(2);
+        bad_sig.extra = vec![0xFF];
+        let bad_sig = test_block(&chain, &genesis, 0xA, 1, vec![bad_sig]);
+        assert_eq!(chain.add_block(bad_sig), Err(BlockValidationError::InvalidTransaction { index: 1 }));
+
+        let wrong_difficulty = test_block(&chain, &genesis, 0xA, 2, vec![]);
+        assert_eq!(chain.add_block(wrong_difficulty), Err(BlockValidationError::InvalidDifficulty { expected: 1, got: 2 }));
+
+        let mut stale = test_block(&chain, &genesis, 0xA, 1, vec![]);
+        stale.header.timestamp = genesis.header.timestamp;
+        assert!(matches!(chain.add_block(stale), Err(BlockValidationError::TimestampTooOld { .. })));
+
+        let mut future = test_block(&chain, &genesis, 0xA, 1, vec![]);
+        future.header.timestamp = u64::MAX / 2;
+        assert!(matches!(chain.add_block(future), Err(BlockValidationError::TimestampTooFarInFuture { .. })));
+
+        let a1 = test_block(&chain, &genesis, 0xA, 1, vec![spend_tx(3)]);
+        assert_eq!(chain.add_block(a1.clone()), Ok(()));
+        let double_spend = test_block(&chain, &a1, 0xA, 1, vec![spend_tx(3)]);
+        assert_eq!(chain.add_block(double_spend), Err(BlockValidationError::KeyImageAlreadySpent { index: 1 }));
+    }
+
+    #[test]
+    fn test_blocks_are_held_to_the_retarget() {
+        let mut chain = Chain::new_for_network(Network::Mainnet);
+        chain.skip_pow_check = true;
+        let start = chain.tip().header.difficulty;
+        for tag in 0..20u8 {
+            let prev = chain.tip().clone();
+            let mut block = test_block(&chain, &prev, tag, chain.next_difficulty(), vec![]);
+            block.header.timestamp = prev.header.timestamp + 60;
+            chain.add_block(block).unwrap();
+        }
+        // Blocks at half the block time raised the difficulty
+        let expected = chain.next_difficulty();
+        assert!(expected > start);
+        let prev = chain.tip().clone();
+        let stale = test_block(&chain, &prev, 0xFF, start, vec![]);
+        assert_eq!(chain.add_block(stale), Err(BlockValidationError::InvalidDifficulty { expected, got: start }));
+
+        // Test networks stay at the genesis difficulty
+        assert_eq!(test_chain().next_difficulty(), Network::Testnet.get_difficulty());
+    }
+
+    #[test]
+    fn test_block_template_selects_by_fee_rate() {
+        let mut chain = test_chain();
+        let genesis = chain.tip().clone();
+        let confirmed = spend_tx(1);
+        chain.add_block(test_block(&chain, &genesis, 0xA, 1, vec![confirmed.clone()])).unwrap();
+
+        let mut cheap = spend_tx(2);
+        cheap.fee = 1;
+        let mut rich = spend_tx(3);
+        rich.fee = 1_000;
+        let mut conflicting = spend_tx(3);
+        conflicting.fee = 10;
+        let mempool = vec![cheap.clone(), confirmed, conflicting, rich.clone()];
+
+        let selected = super::select_block_transactions(&chain, &mempool, usize::MAX, 0);
+        let fees: Vec<u64> = selected.iter().map(|tx| tx.fee).collect();
+        assert_eq!(fees, vec![1_000, 1]);
+
+        // Only the best transaction fits in a tight size budget
+        let budget = super::transaction_size(&rich);
+        let selected = super::select_block_transactions(&chain, &mempool, budget, 0);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].fee, 1_000);
+
+        // Below the minimum relay fee nothing is picked
+        let selected = super::select_block_transactions(&chain, &mempool, usize::MAX, 1);
+        assert_eq!(selected.iter().map(|tx| tx.fee).collect::<Vec<_>>(), vec![1_000]);
+    }
+
+    #[test]
+    fn test_transaction_proof_matches_header() {
+        let mut chain = test_chain();
+        let genesis = chain.tip().clone();
+        let txs = vec![spend_tx(1), spend_tx(2), spend_tx(3)];
+        let block = test_block(&chain, &genesis, 0xA, 1, txs.clone());
+        chain.add_block(block.clone()).unwrap();
+
+        let txid = txs[1].id();
+        let (found, proof) = chain.transaction_proof(&txid).unwrap();
+        assert_eq!(found.hash(), block.hash());
+        assert!(proof.verify(&txid, &block.header.merkle_root));
+        assert!(!proof.verify(&txs[2].id(), &block.header.merkle_root));
+        assert!(chain.transaction_proof(&[7u8; 32]).is_none());
+    }
+
+    #[test]
+    fn test_chain_reloads_from_block_store() {
+        let dir = std::env::temp_dir().join(format!("blacksilk-chain-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (a1, b2) = {
+            let mut chain = Chain::open(Network::Testnet, &dir).unwrap();
+            chain.skip_pow_check = true;
+            let genesis = chain.tip().clone();
+            let a1 = test_block(&chain, &genesis, 0xA, 1, vec![spend_tx(7)]);
+            assert!(matches!(chain.process_block(a1.clone()), BlockAcceptance::Extended));
+            let b1 = test_block(&chain, &genesis, 0xB, 1, vec![]);
+            chain.process_block(b1.clone());
+            let b2 = test_block(&chain, &b1, 0xB, 1, vec![]);
+            assert!(matches!(chain.process_block(b2.clone()), BlockAcceptance::Reorganized { .. }));
+            (a1, b2)
+        };
+
+        let chain = Chain::open(Network::Testnet, &dir).unwrap();
+        assert_eq!(chain.tip().hash(), b2.hash());
+        assert_eq!(chain.block_count(), 3);
+        assert!(!chain.contains_block(&a1.hash()));
+        assert!(chain.find_transaction(&a1.transactions[1].id()).is_none());
+        assert!(!chain.is_key_image_spent(&a1.transactions[1].inputs[0].key_image));
+        assert_eq!(chain.cumulative_work(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bootstrap_export_import_round_trip() {
+        use crate::bootstrap::{self, BootstrapError, ImportMode};
+
+        // Second spend carries a broken ring signature that only full validation catches
+        let mut forged = spend_tx(9);
+        forged.inputs[0].ring_sig.signature[0] ^= 1;
+        let mut source = test_chain();
+        let genesis = source.tip().clone();
+        let b1 = test_block(&source, &genesis, 0xA, 1, vec![spend_tx(7)]);
+        source.add_block(b1.clone()).unwrap();
+        let b2 = test_block(&source, &b1, 0xA, 1, vec![forged]);
+        source.skip_tx_verification = true;
+        source.add_block(b2.clone()).unwrap();
+
+        let path = std::env::temp_dir().join(format!("blacksilk-bootstrap-{}.bin", std::process::id()));
+        let header = bootstrap::export(&source, &path, 0, None).unwrap();
+        assert_eq!(header.block_count(), 3);
+
+        let mut full = test_chain();
+        match bootstrap::import(&mut full, &path, ImportMode::Full, |_, _| {}) {
+            Err(BootstrapError::InvalidBlock { height: 2, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(full.tip().hash(), b1.hash());
+        assert!(!full.skip_tx_verification);
+
+        let mut target = test_chain();
+        let mut seen = Vec::new();
+        let report = bootstrap::import(&mut target, &path, ImportMode::HeadersOnly, |done, total| seen.push((done, total))).unwrap();
+        assert_eq!((report.imported, report.skipped, report.tip_height), (2, 1, 2));
+        assert_eq!(seen, vec![(1, 3), (2, 3), (3, 3)]);
+        assert_eq!(target.tip().hash(), b2.hash());
+        assert!(b1.transactions[1..].iter().chain(&b2.transactions[1..])
+            .all(|tx| tx.inputs.iter().all(|input| target.is_key_image_spent(&input.key_image))));
+
+        // Importing the same file again is a no-op
+        let report = bootstrap::import(&mut target, &path, ImportMode::HeadersOnly, |_, _| {}).unwrap();
+        assert_eq!((report.imported, report.skipped), (0, 3));
+
+        // Any corruption is caught before a single block is applied
+        let mut bytes = std::fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            bootstrap::import(&mut test_chain(), &path, ImportMode::HeadersOnly, |_, _| {}),
+            Err(BootstrapError::ChecksumMismatch)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_handshake_completes_and_wrong_network_is_dropped() {
+        use crate::network::peer_manager::{PeerConfig, PeerManager};
+        use crate::network::wire::{read_frame, write_frame, PROTOCOL_VERSION};
+        use crate::{current_network, P2PMessage};
+        use std::io::Read;
+        use std::net::TcpStream;
+
+        let manager = PeerManager::spawn(PeerConfig { min_outbound: 0, ..PeerConfig::default() }, None).unwrap();
+        let addr = manager.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+        let magic = current_network().get_magic();
+        let version = P2PMessage::Version { version: PROTOCOL_VERSION, best_height: 0, services: 0, node: "test".to_string(), onion: None };
+
+        // A plaintext dialer speaks first
+        let mut peer = TcpStream::connect(addr).unwrap();
+        write_frame(&mut peer, magic, &version).unwrap();
+        assert!(matches!(read_frame(&mut peer, magic).unwrap(), P2PMessage::Version { version: PROTOCOL_VERSION, .. }));
+        assert!(matches!(read_frame(&mut peer, magic).unwrap(), P2PMessage::Verack));
+        write_frame(&mut peer, magic, &P2PMessage::Verack).unwrap();
+        assert!(matches!(read_frame(&mut peer, magic).unwrap(), P2PMessage::PeerList(_)));
+        assert!(matches!(read_frame(&mut peer, magic).unwrap(), P2PMessage::GetHeaders { .. }));
+
+        // A peer speaking another network's magic is disconnected
+        let mut stranger = TcpStream::connect(addr).unwrap();
+        write_frame(&mut stranger, magic ^ 1, &version).unwrap();
+        let mut rest = Vec::new();
+        stranger.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        assert_eq!(stranger.read_to_end(&mut rest).unwrap(), 0);
+    }
+
+    fn extend_chain(chain: &mut Chain, count: usize) {
+        for _ in 0..count {
+            let block = test_block(chain, chain.tip(), 0xA, 1, vec![]);
+            chain.add_block(block).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_headers_first_sync_downloads_in_parallel_batches() {
+        use crate::sync::{SyncManager, BLOCKS_PER_REQUEST, MAX_HEADERS_PER_MESSAGE};
+
+        let mut source = test_chain();
+        extend_chain(&mut source, 40);
+        let fetch = |hashes: &[primitives::types::Hash]| -> Vec<primitives::Block> {
+            hashes.iter().map(|h| source.block_at(source.height_of(h).unwrap()).unwrap()).collect()
+        };
+
+        let mut target = test_chain();
+        let mut sync = SyncManager::new();
+        let headers = source.headers_after(&sync.locator(&target), MAX_HEADERS_PER_MESSAGE);
+        assert_eq!(headers.len(), 40);
+        assert_eq!(sync.on_headers(&target, "a", &headers).unwrap(), 40);
+        assert_eq!(sync.on_headers(&target, "b", &headers).unwrap(), 0);
+        assert_eq!(sync.progress(&target).header_height, 40);
+
+        // Each peer gets its own batch and no second one while busy
+        let batch_a = sync.next_request("a").unwrap();
+        let batch_b = sync.next_request("b").unwrap();
+        assert_eq!(batch_a.len(), BLOCKS_PER_REQUEST);
+        assert_eq!(batch_b[0], headers[BLOCKS_PER_REQUEST].pow.hash);
+        assert!(sync.next_request("a").is_none());
+
+        // Later bodies wait for the earlier ones
+        assert!(sync.on_blocks("b", fetch(&batch_b)).is_empty());
+        assert!(sync.take_ready().is_empty());
+
+        // A partial answer releases the rest of the batch to other peers
+        sync.on_blocks("a", fetch(&batch_a[..8]));
+        for block in sync.take_ready() {
+            target.add_block(block).unwrap();
+        }
+        assert_eq!(target.tip().header.height, 8);
+        assert_eq!(sync.next_request("b").unwrap()[0], batch_a[8]);
+        assert!(!sync.progress(&target).synced);
+
+        sync.on_blocks("b", fetch(&batch_a[8..]));
+        while let Some(batch) = sync.next_request("a") {
+            sync.on_blocks("a", fetch(&batch));
+        }
+        for block in sync.take_ready() {
+            target.add_block(block).unwrap();
+        }
+        assert_eq!(target.tip().hash(), source.tip().hash());
+        let progress = sync.progress(&target);
+        assert!(progress.synced);
+        assert_eq!((progress.local_height, progress.target_height, progress.blocks_in_flight), (40, 40, 0));
+    }
+
+    #[test]
+    fn test_sync_rejects_bad_headers_and_reassigns_stalled_requests() {
+        use crate::sync::{SyncError, SyncManager, BLOCK_REQUEST_TIMEOUT};
+
+        let mut source = test_chain();
+        extend_chain(&mut source, 3);
+        let target = test_chain();
+        let headers = source.headers_after(&[target.tip().hash()], 10);
+
+        let mut sync = SyncManager::new();
+        let mut bad = headers.clone();
+        bad[1].timestamp = 0;
+        match sync.on_headers(&target, "a", &bad) {
+            Err(SyncError::InvalidHeader { height: 2, error: BlockValidationError::TimestampTooOld { .. } }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(sync.progress(&target).header_height, 1);
+
+        let mut sync = SyncManager::new();
+        assert!(matches!(sync.on_headers(&target, "a", &headers[1..]), Err(SyncError::Unconnected { height: 2 })));
+
+        sync.on_headers(&target, "a", &headers).unwrap();
+        let batch = sync.next_request("a").unwrap();
+        assert!(sync.expire_requests(std::time::Instant::now()).is_empty());
+        let expired = sync.expire_requests(std::time::Instant::now() + BLOCK_REQUEST_TIMEOUT);
+        assert_eq!(expired, vec!["a".to_string()]);
+        assert_eq!(sync.next_request("a").unwrap(), batch);
+    }
 }
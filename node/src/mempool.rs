@@ -0,0 +1,570 @@
+//! Pool of transactions waiting to be mined
+//!
+//! Transactions are indexed by txid and by the key images they spend, and
+//! ordered by fee rate: fee per byte of canonical encoding, the same size
+//! that counts against the block limit. The pool is capped in bytes. A
+//! transaction that does not fit pushes out the lowest-paying ones, or is
+//! turned away if they pay as much as it does. Transactions still waiting
+//! after `EXPIRY_SECS` are dropped.
+//!
+//! Only one spend of a key image can be pending. A later spend is rejected
+//! unless it outbids every transaction it conflicts with: its fee rate must be
+//! `REPLACEMENT_BUMP_PERCENT` higher than each of theirs, and its fee must be
+//! higher than all of theirs together. If it is, it replaces them.
+//!
+//! Every transaction must pay the network's minimum relay fee per byte,
+//! input-less data transactions included, so nothing is relayed for free.
+//!
+//! The pool reports arrivals, confirmations and drops to a `FeeEstimator`.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use primitives::types::Hash;
+use primitives::{Block, Transaction};
+use serde::Serialize;
+
+use crate::fee_estimator::{FeeEstimator, FeePriority};
+
+/// Size cap of a pool built without `--mempool-size`
+pub const DEFAULT_MAX_SIZE: usize = 100 * 1024 * 1024;
+
+/// How long a transaction may wait to be mined
+pub const EXPIRY_SECS: u64 = 72 * 60 * 60;
+
+/// How much a replacement must raise the fee rate of what it replaces
+pub const REPLACEMENT_BUMP_PERCENT: u64 = 10;
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Fee per byte. Rates are compared by cross-multiplying, so no precision
+/// is lost to division.
+#[derive(Debug, Clone, Copy)]
+pub struct FeeRate {
+    pub fee: u64,
+    pub size: usize,
+}
+
+impl FeeRate {
+    pub fn of(tx: &Transaction) -> Self {
+        FeeRate { fee: tx.fee, size: crate::transaction_size(tx) }
+    }
+
+    /// Atomic units per 1000 bytes, rounded down
+    pub fn per_kb(&self) -> u64 {
+        (self.fee as u128 * 1000 / self.size.max(1) as u128) as u64
+    }
+
+    /// Whether this rate is at least `percent` percent above `other`
+    fn bumps(&self, other: &FeeRate, percent: u64) -> bool {
+        self.fee as u128 * 100 * other.size as u128 >= other.fee as u128 * (100 + percent) as u128 * self.size as u128
+    }
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.fee as u128 * other.size as u128).cmp(&(other.fee as u128 * self.size as u128))
+    }
+}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    AlreadyKnown,
+    /// Spends nothing; such transactions can never be mined
+    NoInputs,
+    /// Spends one key image twice
+    DuplicateKeyImage,
+    /// Spends a key image of pending transaction `txid` without paying
+    /// enough to replace it
+    Conflict { txid: Hash },
+    /// Larger than the whole pool
+    TooLarge { size: usize, max: usize },
+    /// The pool is full of transactions paying at least as much
+    Full,
+    /// Pays less than the minimum relay fee for its size
+    FeeTooLow { fee: u64, required: u64 },
+}
+
+impl std::fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MempoolError::AlreadyKnown => write!(f, "transaction already in mempool"),
+            MempoolError::NoInputs => write!(f, "transaction has no inputs"),
+            MempoolError::DuplicateKeyImage => write!(f, "transaction spends a key image twice"),
+            MempoolError::Conflict { txid } => write!(
+                f,
+                "conflicts with pending transaction {} and does not raise its fee rate by {}%",
+                hex::encode(txid),
+                REPLACEMENT_BUMP_PERCENT
+            ),
+            MempoolError::TooLarge { size, max } => write!(f, "transaction of {} bytes exceeds mempool size {}", size, max),
+            MempoolError::Full => write!(f, "mempool full and fee rate too low"),
+            MempoolError::FeeTooLow { fee, required } => write!(f, "fee {} below minimum relay fee {}", fee, required),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+/// What `/mempool` reports
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MempoolStats {
+    pub count: usize,
+    /// Encoded size of all pending transactions, in bytes
+    pub size: usize,
+    pub max_size: usize,
+    /// Lowest and highest fee rate pending, per 1000 bytes
+    pub min_fee_per_kb: u64,
+    pub max_fee_per_kb: u64,
+    /// Transactions pushed out to make room since startup
+    pub evicted: u64,
+    pub expired: u64,
+    /// Transactions outbid by a conflicting spend
+    pub replaced: u64,
+    /// Minimum fee rate a spend must pay to be admitted, per 1000 bytes
+    pub min_relay_fee_per_kb: u64,
+}
+
+/// Suggested fee rate for a priority level
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeEstimate {
+    pub priority: FeePriority,
+    pub target_blocks: u64,
+    /// Atomic units per 1000 bytes
+    pub fee_per_kb: u64,
+    /// Whether the rate comes from observed confirmations rather than the
+    /// minimum relay fee
+    pub estimated: bool,
+}
+
+impl FeeEstimate {
+    /// Fee for a transaction of `size` bytes, rounded up
+    pub fn fee_for_size(&self, size: usize) -> u64 {
+        (self.fee_per_kb as u128 * size as u128).div_ceil(1000) as u64
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    tx: Transaction,
+    rate: FeeRate,
+    added: u64,
+    seq: u64,
+}
+
+#[derive(Debug)]
+pub struct Mempool {
+    entries: HashMap<Hash, Entry>,
+    /// Pending transaction spending each key image
+    spends: HashMap<Hash, Hash>,
+    /// Cheapest first; ties go by arrival
+    by_fee_rate: BTreeSet<(FeeRate, u64, Hash)>,
+    size: usize,
+    max_size: usize,
+    /// Minimum relay fee, atomic units per byte
+    min_fee_per_byte: u64,
+    estimator: FeeEstimator,
+    next_seq: u64,
+    evicted: u64,
+    expired: u64,
+    replaced: u64,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::new(DEFAULT_MAX_SIZE)
+    }
+}
+
+impl Mempool {
+    pub fn new(max_size: usize) -> Self {
+        Mempool {
+            entries: HashMap::new(),
+            spends: HashMap::new(),
+            by_fee_rate: BTreeSet::new(),
+            size: 0,
+            max_size,
+            min_fee_per_byte: 0,
+            estimator: FeeEstimator::new(),
+            next_seq: 0,
+            evicted: 0,
+            expired: 0,
+            replaced: 0,
+        }
+    }
+
+    /// Change the size cap, evicting the cheapest transactions if the pool
+    /// no longer fits
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        while self.size > self.max_size {
+            let Some(&(_, _, txid)) = self.by_fee_rate.iter().next() else { break };
+            self.remove(&txid);
+            self.estimator.record_miss(&txid);
+            self.evicted += 1;
+        }
+    }
+
+    /// Change the minimum relay fee. Pending transactions are kept.
+    pub fn set_min_fee_per_byte(&mut self, min_fee_per_byte: u64) {
+        self.min_fee_per_byte = min_fee_per_byte;
+    }
+
+    pub fn min_fee_per_byte(&self) -> u64 {
+        self.min_fee_per_byte
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, txid: &Hash) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    pub fn get(&self, txid: &Hash) -> Option<&Transaction> {
+        self.entries.get(txid).map(|entry| &entry.tx)
+    }
+
+    /// Pending transactions, best fee rate first
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.by_fee_rate.iter().rev().map(move |(_, _, txid)| &self.entries[txid].tx)
+    }
+
+    /// Pending transactions with their IDs, in no particular order
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (Hash, &Transaction)> {
+        self.entries.iter().map(|(txid, entry)| (*txid, &entry.tx))
+    }
+
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.iter().cloned().collect()
+    }
+
+    /// Whether `tx` would be admitted; `Ok` holds what it would replace and
+    /// what would be evicted to make room
+    pub fn check(&self, tx: &Transaction) -> Result<(Vec<Hash>, Vec<Hash>), MempoolError> {
+        if self.contains(&tx.id()) {
+            return Err(MempoolError::AlreadyKnown);
+        }
+        if tx.inputs.is_empty() {
+            return Err(MempoolError::NoInputs);
+        }
+        let key_images: HashSet<Hash> = tx.inputs.iter().map(|input| input.key_image).collect();
+        if key_images.len() != tx.inputs.len() {
+            return Err(MempoolError::DuplicateKeyImage);
+        }
+        let rate = FeeRate::of(tx);
+        let required = (self.min_fee_per_byte as u128 * rate.size as u128).min(u64::MAX as u128) as u64;
+        if tx.fee < required {
+            return Err(MempoolError::FeeTooLow { fee: tx.fee, required });
+        }
+        if rate.size > self.max_size {
+            return Err(MempoolError::TooLarge { size: rate.size, max: self.max_size });
+        }
+
+        let conflicts: HashSet<Hash> = key_images.iter().filter_map(|image| self.spends.get(image).copied()).collect();
+        let mut conflicts_fee = 0u64;
+        for txid in &conflicts {
+            let conflict = &self.entries[txid].rate;
+            if !rate.bumps(conflict, REPLACEMENT_BUMP_PERCENT) {
+                return Err(MempoolError::Conflict { txid: *txid });
+            }
+            conflicts_fee = conflicts_fee.saturating_add(conflict.fee);
+        }
+        if let Some(txid) = conflicts.iter().next() {
+            if tx.fee <= conflicts_fee {
+                return Err(MempoolError::Conflict { txid: *txid });
+            }
+        }
+
+        let conflicts_size: usize = conflicts.iter().map(|txid| self.entries[txid].rate.size).sum();
+        let mut excess = (self.size - conflicts_size + rate.size).saturating_sub(self.max_size);
+        let mut evicted = Vec::new();
+        for (victim_rate, _, txid) in &self.by_fee_rate {
+            if excess == 0 {
+                break;
+            }
+            if conflicts.contains(txid) {
+                continue;
+            }
+            if *victim_rate >= rate {
+                return Err(MempoolError::Full);
+            }
+            excess = excess.saturating_sub(victim_rate.size);
+            evicted.push(*txid);
+        }
+        Ok((conflicts.into_iter().collect(), evicted))
+    }
+
+    /// Admit `tx`, received at `now`, replacing the spends it outbids and
+    /// evicting cheaper transactions if the pool is full
+    pub fn insert(&mut self, tx: Transaction, now: u64) -> Result<(), MempoolError> {
+        let (replaced, evicted) = self.check(&tx)?;
+        for txid in &replaced {
+            self.remove(txid);
+            self.estimator.forget(txid);
+        }
+        for txid in &evicted {
+            self.remove(txid);
+            self.estimator.record_miss(txid);
+        }
+        self.replaced += replaced.len() as u64;
+        self.evicted += evicted.len() as u64;
+
+        let txid = tx.id();
+        let rate = FeeRate::of(&tx);
+        for input in &tx.inputs {
+            self.spends.insert(input.key_image, txid);
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.by_fee_rate.insert((rate, seq, txid));
+        self.estimator.track(txid, &rate);
+        self.size += rate.size;
+        self.entries.insert(txid, Entry { tx, rate, added: now, seq });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, txid: &Hash) -> Option<Transaction> {
+        let entry = self.entries.remove(txid)?;
+        for input in &entry.tx.inputs {
+            self.spends.remove(&input.key_image);
+        }
+        self.by_fee_rate.remove(&(entry.rate, entry.seq, *txid));
+        self.size -= entry.rate.size;
+        Some(entry.tx)
+    }
+
+    /// Drop transactions that spend any of `key_images`; returns how many
+    pub fn remove_spent(&mut self, key_images: &HashSet<Hash>) -> usize {
+        let spenders: HashSet<Hash> = key_images.iter().filter_map(|image| self.spends.get(image).copied()).collect();
+        for txid in &spenders {
+            self.remove(txid);
+            self.estimator.forget(txid);
+        }
+        spenders.len()
+    }
+
+    /// Drop transactions confirmed by `block` or spending a key image it
+    /// spends, and time the confirmed ones; returns how many
+    pub fn remove_for_block(&mut self, block: &Block) -> usize {
+        let spent: HashSet<Hash> = block
+            .transactions
+            .iter()
+            .flat_map(|tx| tx.inputs.iter().map(|input| input.key_image))
+            .collect();
+        let txids: Vec<Hash> = block.transactions.iter().map(|tx| tx.id()).collect();
+        let confirmed = txids.iter().filter(|txid| self.remove(txid).is_some()).count();
+        self.estimator.process_block(block.header.height, &txids);
+        confirmed + self.remove_spent(&spent)
+    }
+
+    /// Drop transactions that have waited `EXPIRY_SECS` by `now`; returns
+    /// how many
+    pub fn expire(&mut self, now: u64) -> usize {
+        let stale: Vec<Hash> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| now.saturating_sub(entry.added) >= EXPIRY_SECS)
+            .map(|(txid, _)| *txid)
+            .collect();
+        for txid in &stale {
+            self.remove(txid);
+            self.estimator.record_miss(txid);
+        }
+        self.expired += stale.len() as u64;
+        stale.len()
+    }
+
+    pub fn stats(&self) -> MempoolStats {
+        MempoolStats {
+            count: self.len(),
+            size: self.size,
+            max_size: self.max_size,
+            min_fee_per_kb: self.by_fee_rate.iter().next().map_or(0, |(rate, _, _)| rate.per_kb()),
+            max_fee_per_kb: self.by_fee_rate.iter().next_back().map_or(0, |(rate, _, _)| rate.per_kb()),
+            evicted: self.evicted,
+            expired: self.expired,
+            replaced: self.replaced,
+            min_relay_fee_per_kb: self.min_fee_per_byte.saturating_mul(1000),
+        }
+    }
+
+    /// Fee rate to pay for `priority`: what confirmed in time recently, but
+    /// never below the minimum relay fee
+    pub fn estimate_fee(&self, priority: FeePriority) -> FeeEstimate {
+        let target_blocks = priority.target_blocks();
+        let observed = self.estimator.estimate(target_blocks);
+        let min_fee_per_kb = self.min_fee_per_byte.saturating_mul(1000);
+        FeeEstimate {
+            priority,
+            target_blocks,
+            fee_per_kb: observed.unwrap_or(0).max(min_fee_per_kb),
+            estimated: observed.is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Transaction spending `key_images`, padded with `padding` bytes
+    fn tx(key_images: &[u8], fee: u64, padding: usize) -> Transaction {
+        Transaction {
+            kind: primitives::TransactionKind::Payment,
+            inputs: key_images
+                .iter()
+                .map(|image| primitives::TransactionInput {
+                    key_image: [*image; 32],
+                    ring_sig: primitives::RingSignature { ring: vec![], signature: vec![], quantum: None },
+                })
+                .collect(),
+            outputs: vec![],
+            fee,
+            extra: vec![0; padding],
+            metadata: None,
+            signature: String::new(),
+            quantum_signature: None,
+        }
+    }
+
+    #[test]
+    fn test_transactions_are_ordered_and_evicted_by_fee_rate() {
+        let size = |tx: &Transaction| crate::transaction_size(tx);
+        let cheap = tx(&[1], 200, 0);
+        let rich = tx(&[2], 1_000, 0);
+        // Same fee, far larger: a lower rate
+        let bloated = tx(&[3], 200, 200);
+        let mut pool = Mempool::new(size(&cheap) + size(&rich) + size(&bloated));
+        pool.insert(cheap.clone(), 0).unwrap();
+        pool.insert(rich.clone(), 0).unwrap();
+        assert_eq!(pool.insert(rich.clone(), 0), Err(MempoolError::AlreadyKnown));
+        assert!(matches!(pool.insert(tx(&[4], 1, 1_000), 0), Err(MempoolError::TooLarge { .. })));
+        pool.insert(bloated.clone(), 0).unwrap();
+        assert_eq!(pool.iter().map(|tx| tx.id()).collect::<Vec<_>>(), vec![rich.id(), cheap.id(), bloated.id()]);
+
+        // Over the cap: the lowest fee rate makes room
+        let newcomer = tx(&[5], 500, 0);
+        pool.insert(newcomer.clone(), 0).unwrap();
+        assert!(!pool.contains(&bloated.id()));
+        assert_eq!(pool.stats().size, size(&cheap) + size(&rich) + size(&newcomer));
+        assert_eq!(pool.stats().evicted, 1);
+        // Everything pending pays more than this one
+        assert_eq!(pool.insert(tx(&[6], 200, 300), 0), Err(MempoolError::Full));
+        assert_eq!(pool.len(), 3);
+
+        pool.set_max_size(size(&rich));
+        assert_eq!(pool.transactions().iter().map(|tx| tx.id()).collect::<Vec<_>>(), vec![rich.id()]);
+        let stats = pool.stats();
+        assert_eq!((stats.count, stats.evicted), (1, 3));
+        assert_eq!(stats.min_fee_per_kb, FeeRate::of(&rich).per_kb());
+    }
+
+    #[test]
+    fn test_conflicting_spends_need_a_fee_bump() {
+        let mut pool = Mempool::default();
+        let first = tx(&[1, 2], 1_000, 0);
+        pool.insert(first.clone(), 0).unwrap();
+        assert_eq!(pool.insert(tx(&[3, 3], 1_000, 0), 0), Err(MempoolError::DuplicateKeyImage));
+        // Nothing to spend: it could never be mined, so it is not admitted
+        assert_eq!(pool.insert(tx(&[], 1_000, 0), 0), Err(MempoolError::NoInputs));
+
+        // Less than a 10% bump is not enough
+        let timid = tx(&[2, 6], 1_050, 0);
+        assert_eq!(pool.insert(timid, 0), Err(MempoolError::Conflict { txid: first.id() }));
+        let bump = tx(&[2, 4], 1_200, 0);
+        pool.insert(bump.clone(), 0).unwrap();
+        assert!(!pool.contains(&first.id()));
+        assert_eq!(pool.stats().replaced, 1);
+        // The replaced transaction's other key image is free again
+        pool.insert(tx(&[1], 10, 0), 0).unwrap();
+
+        // Replacing two transactions takes more than their combined fee
+        let second = tx(&[5], 1_000, 0);
+        pool.insert(second.clone(), 0).unwrap();
+        let greedy = tx(&[4, 5], 2_000, 0);
+        assert!(matches!(pool.insert(greedy, 0), Err(MempoolError::Conflict { .. })));
+        pool.insert(tx(&[4, 5], 2_201, 0), 0).unwrap();
+        assert!(!pool.contains(&bump.id()) && !pool.contains(&second.id()));
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_minimum_relay_fee_and_estimates() {
+        let mut pool = Mempool::default();
+        pool.set_min_fee_per_byte(2);
+        let cheap = tx(&[1], 0, 0);
+        let required = 2 * crate::transaction_size(&cheap) as u64;
+        assert_eq!(pool.insert(cheap, 0), Err(MempoolError::FeeTooLow { fee: 0, required }));
+        // Extra data is paid for by the byte like everything else
+        let data = tx(&[2], 0, 10);
+        let required = 2 * crate::transaction_size(&data) as u64;
+        assert_eq!(pool.insert(data, 0), Err(MempoolError::FeeTooLow { fee: 0, required }));
+        pool.insert(tx(&[2], 1_000, 10), 0).unwrap();
+
+        // Without confirmations to learn from, every priority pays the minimum
+        let estimate = pool.estimate_fee(FeePriority::High);
+        assert_eq!((estimate.fee_per_kb, estimate.estimated), (2_000, false));
+        assert_eq!(estimate.fee_for_size(250), 500);
+        assert_eq!(pool.stats().min_relay_fee_per_kb, 2_000);
+
+        // Transactions that make the next block teach the estimator their rate
+        let mut block = crate::Chain::new_for_network(crate::Network::Testnet).tip().clone();
+        block.header.height = 1;
+        pool.remove_for_block(&block);
+        for image in 0..10u8 {
+            let rich = tx(&[100 + image], 5_000, 0);
+            pool.insert(rich.clone(), 0).unwrap();
+            block.header.height += 1;
+            block.transactions = vec![rich];
+            pool.remove_for_block(&block);
+        }
+        let estimate = pool.estimate_fee(FeePriority::High);
+        assert!(estimate.estimated);
+        assert!(estimate.fee_per_kb > 2_000 && estimate.fee_per_kb <= FeeRate::of(&tx(&[1], 5_000, 0)).per_kb());
+    }
+
+    #[test]
+    fn test_old_and_mined_transactions_leave() {
+        let mut pool = Mempool::default();
+        let old = tx(&[1], 10, 0);
+        let mined = tx(&[2], 10, 0);
+        let double_spent = tx(&[3], 10, 0);
+        let fresh = tx(&[4], 10, 0);
+        pool.insert(old.clone(), 1_000).unwrap();
+        pool.insert(mined.clone(), 2_000).unwrap();
+        pool.insert(double_spent, 2_000).unwrap();
+        pool.insert(fresh.clone(), 2_000).unwrap();
+
+        assert_eq!(pool.expire(1_000 + EXPIRY_SECS), 1);
+        assert!(!pool.contains(&old.id()));
+        assert_eq!(pool.stats().expired, 1);
+
+        let mut block = crate::Chain::new_for_network(crate::Network::Testnet).tip().clone();
+        block.transactions = vec![mined, tx(&[3], 99, 0)];
+        assert_eq!(pool.remove_for_block(&block), 2);
+        assert_eq!(pool.transactions().iter().map(|tx| tx.id()).collect::<Vec<_>>(), vec![fresh.id()]);
+        assert_eq!(pool.stats().size, crate::transaction_size(&fresh));
+    }
+}
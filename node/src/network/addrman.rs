@@ -0,0 +1,491 @@
+//! Peer address manager
+//!
+//! Known peer addresses live in two tables. Addresses heard about from seeds
+//! or gossip go into the *new* table; once an outbound connection to one
+//! completes the handshake it moves to the *tried* table. Both tables are
+//! split into buckets chosen by a keyed hash of the address's network group
+//! (and, for new addresses, the group of the peer that told us about it), so
+//! a single source or subnet can only fill a small part of the book. When a
+//! bucket is full the least useful entry is evicted.
+//!
+//! The book is saved as JSON in the data directory and reloaded on startup.
+//! Onion and I2P peers are kept under their stand-in addresses (see `proxy`)
+//! together with their names, which are registered again on reload and
+//! are what gets passed on in gossip.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::proxy::{self, Endpoint};
+
+pub const ADDRESS_BOOK_FILE: &str = "peers.json";
+
+pub const NEW_BUCKET_COUNT: usize = 256;
+pub const TRIED_BUCKET_COUNT: usize = 64;
+pub const BUCKET_SIZE: usize = 64;
+
+/// Buckets a single source group can spread its new addresses over
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 32;
+/// Buckets a single address group can occupy in the tried table
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+
+/// Delay before redialing an address after a failed attempt; doubles with
+/// every further failure up to `MAX_RETRY_DELAY`
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Addresses not seen for this long are forgotten first
+const HORIZON: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Gossiped timestamps this far ahead of our clock are not believed
+const MAX_FUTURE_DRIFT: Duration = Duration::from_secs(10 * 60);
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// An address as exchanged in `Addr` messages
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetAddress {
+    pub addr: String,
+    pub services: u64,
+    /// Unix time the address was last known to be reachable
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddrInfo {
+    pub addr: SocketAddr,
+    pub services: u64,
+    /// Peer that told us about this address
+    pub source: IpAddr,
+    pub last_seen: u64,
+    pub last_try: u64,
+    pub last_success: u64,
+    /// Failed attempts since the last success
+    pub attempts: u32,
+    pub tried: bool,
+    /// Onion or I2P name behind a stand-in `addr`
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+impl AddrInfo {
+    /// Not worth keeping or handing out
+    fn is_terrible(&self, now: u64) -> bool {
+        if self.last_try + 60 >= now {
+            // Just tried it, give it a chance
+            return false;
+        }
+        if self.last_seen > now + MAX_FUTURE_DRIFT.as_secs() {
+            return true;
+        }
+        if self.last_seen == 0 || now - self.last_seen.min(now) > HORIZON.as_secs() {
+            return true;
+        }
+        if self.last_success == 0 && self.attempts >= 3 {
+            return true;
+        }
+        now - self.last_success.min(now) > 7 * 24 * 60 * 60 && self.attempts >= 10
+    }
+
+    fn retry_at(&self) -> u64 {
+        if self.attempts == 0 {
+            return self.last_try;
+        }
+        let delay = RETRY_DELAY.saturating_mul(1 << (self.attempts - 1).min(7)).min(MAX_RETRY_DELAY);
+        self.last_try + delay.as_secs()
+    }
+
+    /// Relative weight when choosing what to dial next
+    fn chance(&self, now: u64) -> f64 {
+        let mut chance = 1.0;
+        if now.saturating_sub(self.last_try) < 10 * 60 {
+            chance *= 0.01;
+        }
+        chance * 0.66f64.powi(self.attempts.min(8) as i32)
+    }
+}
+
+/// Network group used to spread addresses over buckets: /16 for IPv4, /32
+/// for IPv6
+fn group(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(v4) => {
+            let o = v4.octets();
+            vec![4, o[0], o[1]]
+        }
+        IpAddr::V6(v6) => {
+            let o = v6.octets();
+            vec![6, o[0], o[1], o[2], o[3]]
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AddressBookFile {
+    key: [u8; 32],
+    entries: Vec<AddrInfo>,
+}
+
+pub struct AddrMan {
+    /// Secret salt for bucket placement so others cannot predict it
+    key: [u8; 32],
+    entries: HashMap<SocketAddr, AddrInfo>,
+    new: Vec<Vec<SocketAddr>>,
+    tried: Vec<Vec<SocketAddr>>,
+    path: Option<PathBuf>,
+    dirty: bool,
+}
+
+impl Default for AddrMan {
+    fn default() -> Self {
+        AddrMan::new()
+    }
+}
+
+impl AddrMan {
+    /// Empty in-memory book
+    pub fn new() -> Self {
+        AddrMan {
+            key: rand::thread_rng().gen(),
+            entries: HashMap::new(),
+            new: vec![Vec::new(); NEW_BUCKET_COUNT],
+            tried: vec![Vec::new(); TRIED_BUCKET_COUNT],
+            path: None,
+            dirty: false,
+        }
+    }
+
+    /// Load the book kept in `data_dir`, or start an empty one there
+    pub fn open(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(ADDRESS_BOOK_FILE);
+        let mut book = AddrMan::new();
+        if path.exists() {
+            let file: AddressBookFile = serde_json::from_slice(&std::fs::read(&path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            book.key = file.key;
+            for info in file.entries {
+                let registered = match &info.endpoint {
+                    Some(name) => Endpoint::parse(name).is_ok_and(|endpoint| endpoint.peer_addr() == info.addr),
+                    None => proxy::overlay_network(&info.addr).is_none(),
+                };
+                if registered {
+                    book.insert(info);
+                }
+            }
+        }
+        book.path = Some(path);
+        book.dirty = false;
+        Ok(book)
+    }
+
+    /// Write the book back if anything changed since the last save
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        if !self.dirty {
+            return Ok(());
+        }
+        let file = AddressBookFile { key: self.key, entries: self.entries.values().cloned().collect() };
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&file)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn tried_count(&self) -> usize {
+        self.entries.values().filter(|info| info.tried).count()
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&AddrInfo> {
+        self.entries.get(addr)
+    }
+
+    fn hash(&self, parts: &[&[u8]]) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(self.key);
+        for part in parts {
+            hasher.update(part);
+        }
+        u64::from_le_bytes(hasher.finalize()[..8].try_into().unwrap())
+    }
+
+    fn new_bucket(&self, addr: &SocketAddr, source: &IpAddr) -> usize {
+        let source_group = group(source);
+        let slot = self.hash(&[&group(&addr.ip()), &source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        (self.hash(&[&source_group, &slot.to_le_bytes()]) % NEW_BUCKET_COUNT as u64) as usize
+    }
+
+    fn tried_bucket(&self, addr: &SocketAddr) -> usize {
+        let slot = self.hash(&[addr.to_string().as_bytes()]) % TRIED_BUCKETS_PER_GROUP;
+        (self.hash(&[&group(&addr.ip()), &slot.to_le_bytes()]) % TRIED_BUCKET_COUNT as u64) as usize
+    }
+
+    /// Place `info` in its bucket, evicting the least useful entry if the
+    /// bucket is full
+    fn insert(&mut self, info: AddrInfo) {
+        let now = unix_now();
+        let addr = info.addr;
+        let (bucket, tried) = if info.tried {
+            (self.tried_bucket(&addr), true)
+        } else {
+            (self.new_bucket(&addr, &info.source), false)
+        };
+        let slots = if tried { &self.tried[bucket] } else { &self.new[bucket] };
+        if slots.len() >= BUCKET_SIZE {
+            let victim = *slots
+                .iter()
+                .max_by_key(|a| {
+                    let e = &self.entries[*a];
+                    (e.is_terrible(now), u64::MAX - if tried { e.last_success } else { e.last_seen })
+                })
+                .unwrap();
+            let evicted = self.remove(&victim);
+            if let Some(mut demoted) = evicted.filter(|_| tried) {
+                // Demote rather than forget an address that used to work
+                demoted.tried = false;
+                self.insert(demoted);
+            }
+        }
+        let slots = if tried { &mut self.tried[bucket] } else { &mut self.new[bucket] };
+        slots.push(addr);
+        self.entries.insert(addr, info);
+        self.dirty = true;
+    }
+
+    fn remove(&mut self, addr: &SocketAddr) -> Option<AddrInfo> {
+        let info = self.entries.remove(addr)?;
+        let bucket = if info.tried { self.tried_bucket(addr) } else { self.new_bucket(addr, &info.source) };
+        let slots = if info.tried { &mut self.tried[bucket] } else { &mut self.new[bucket] };
+        slots.retain(|a| a != addr);
+        self.dirty = true;
+        Some(info)
+    }
+
+    /// Learn about `addr` from `source`. Returns whether it was new to us.
+    /// A stand-in address must be registered with `Endpoint::peer_addr`.
+    pub fn add(&mut self, addr: SocketAddr, services: u64, last_seen: u64, source: IpAddr) -> bool {
+        if addr.ip().is_unspecified() || addr.ip().is_multicast() {
+            return false;
+        }
+        let endpoint = proxy::overlay_endpoint(&addr);
+        match &endpoint {
+            // I2P streams have no port
+            Some(Endpoint::I2p { .. }) => {}
+            _ if addr.port() == 0 => return false,
+            None if proxy::overlay_network(&addr).is_some() => return false,
+            _ => {}
+        }
+        let endpoint = endpoint.map(|endpoint| endpoint.to_string());
+        if let Some(info) = self.entries.get_mut(&addr) {
+            if last_seen > info.last_seen || services & !info.services != 0 {
+                info.last_seen = info.last_seen.max(last_seen);
+                info.services |= services;
+                self.dirty = true;
+            }
+            return false;
+        }
+        self.insert(AddrInfo {
+            addr,
+            services,
+            source,
+            last_seen,
+            last_try: 0,
+            last_success: 0,
+            attempts: 0,
+            tried: false,
+            endpoint,
+        });
+        true
+    }
+
+    /// We are about to dial `addr`
+    pub fn attempt(&mut self, addr: &SocketAddr) {
+        if let Some(info) = self.entries.get_mut(addr) {
+            info.last_try = unix_now();
+            info.attempts += 1;
+            self.dirty = true;
+        }
+    }
+
+    /// An outbound connection to `addr` completed the handshake; move it to
+    /// the tried table
+    pub fn good(&mut self, addr: &SocketAddr, services: u64) {
+        let now = unix_now();
+        let Some(mut info) = self.remove(addr) else { return };
+        info.last_success = now;
+        info.last_try = now;
+        info.last_seen = now;
+        info.attempts = 0;
+        info.services = services;
+        info.tried = true;
+        self.insert(info);
+    }
+
+    /// A connection to `addr` that worked has closed; remember when we last
+    /// saw it alive
+    pub fn connected(&mut self, addr: &SocketAddr) {
+        if let Some(info) = self.entries.get_mut(addr) {
+            info.last_seen = unix_now();
+            self.dirty = true;
+        }
+    }
+
+    /// Choose an address to dial: tried and new tables with equal odds,
+    /// weighted against recently failed entries. Terrible entries are
+    /// never picked.
+    pub fn select(&self, exclude: &HashSet<SocketAddr>) -> Option<SocketAddr> {
+        let now = unix_now();
+        let usable = |info: &&AddrInfo| {
+            !exclude.contains(&info.addr) && info.retry_at() <= now && !info.is_terrible(now)
+        };
+        let (tried, new): (Vec<&AddrInfo>, Vec<&AddrInfo>) =
+            self.entries.values().filter(usable).partition(|info| info.tried);
+        let mut rng = rand::thread_rng();
+        let table = match (tried.is_empty(), new.is_empty()) {
+            (true, true) => return None,
+            (false, true) => tried,
+            (true, false) => new,
+            (false, false) => if rng.gen_bool(0.5) { tried } else { new },
+        };
+        table.choose_weighted(&mut rng, |info| info.chance(now)).ok().map(|info| info.addr)
+    }
+
+    /// Random selection of useful addresses for an `Addr` reply
+    pub fn sample(&self, max: usize) -> Vec<NetAddress> {
+        let now = unix_now();
+        let mut good: Vec<&AddrInfo> = self.entries.values().filter(|info| !info.is_terrible(now)).collect();
+        good.shuffle(&mut rand::thread_rng());
+        good.into_iter()
+            .take(max)
+            .map(|info| NetAddress {
+                addr: info.endpoint.clone().unwrap_or_else(|| info.addr.to_string()),
+                services: info.services,
+                last_seen: info.last_seen,
+            })
+            .collect()
+    }
+}
+
+/// Bootnode addresses from a `bootnodes.txt` file. Lines look like
+/// `<peer_id>@<host>:<port>`; comments and blank lines are skipped.
+pub fn read_bootnodes(path: &Path) -> io::Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.rsplit('@').next().unwrap_or(line).to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_addresses_move_to_tried_and_survive_reload() {
+        let dir = std::env::temp_dir().join(format!("addrman_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = unix_now();
+        let source: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut book = AddrMan::open(&dir).unwrap();
+        assert!(book.add(addr("1.2.3.4:1776"), 1, now, source));
+        assert!(!book.add(addr("1.2.3.4:1776"), 2, now, source));
+        assert_eq!(book.get(&addr("1.2.3.4:1776")).unwrap().services, 3);
+        assert!(book.add(addr("5.6.7.8:1776"), 1, now, source));
+        assert!(!book.add(addr("0.0.0.0:1776"), 1, now, source));
+
+        book.attempt(&addr("1.2.3.4:1776"));
+        book.good(&addr("1.2.3.4:1776"), 1);
+        book.attempt(&addr("5.6.7.8:1776"));
+        assert_eq!(book.tried_count(), 1);
+        // The failed address waits out its retry delay, the good one does not
+        assert_eq!(book.select(&HashSet::new()), Some(addr("1.2.3.4:1776")));
+        assert_eq!(book.select(&[addr("1.2.3.4:1776")].into_iter().collect()), None);
+        book.save().unwrap();
+
+        let reloaded = AddrMan::open(&dir).unwrap();
+        assert_eq!(reloaded.len(), 2);
+        assert_eq!(reloaded.tried_count(), 1);
+        assert_eq!(reloaded.get(&addr("5.6.7.8:1776")).unwrap().attempts, 1);
+        assert_eq!(reloaded.sample(10).len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_onion_addresses_keep_their_names() {
+        let dir = std::env::temp_dir().join(format!("addrman_onion_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let name = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion:9334";
+        let onion = Endpoint::parse(name).unwrap().peer_addr();
+        let source: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut book = AddrMan::open(&dir).unwrap();
+        assert!(book.add(onion, 1, unix_now(), source));
+        // A stand-in nobody registered cannot be dialed
+        assert!(!book.add(addr("[fd87:d87e:eb43::1]:9334"), 1, unix_now(), source));
+        book.save().unwrap();
+
+        let reloaded = AddrMan::open(&dir).unwrap();
+        assert_eq!(reloaded.get(&onion).unwrap().endpoint.as_deref(), Some(name));
+        assert_eq!(reloaded.sample(10)[0].addr, name);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_terrible_addresses_are_not_selected() {
+        let mut book = AddrMan::new();
+        let now = unix_now();
+        let source: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(book.add(addr("1.2.3.4:1776"), 1, now - HORIZON.as_secs() - 60, source));
+        assert_eq!(book.select(&HashSet::new()), None);
+        assert!(book.add(addr("5.6.7.8:1776"), 1, now, source));
+        for _ in 0..20 {
+            assert_eq!(book.select(&HashSet::new()), Some(addr("5.6.7.8:1776")));
+        }
+    }
+
+    #[test]
+    fn test_one_source_cannot_flood_the_book() {
+        let mut book = AddrMan::new();
+        let now = unix_now();
+        let source: IpAddr = "10.0.0.1".parse().unwrap();
+        for i in 0..20_000u32 {
+            let b = i.to_be_bytes();
+            book.add(SocketAddr::from(([b[1], b[2], b[3], 1], 1776)), 0, now, source);
+        }
+        let limit = NEW_BUCKETS_PER_SOURCE_GROUP as usize * BUCKET_SIZE;
+        assert!(book.len() <= limit, "{} entries from one source", book.len());
+        assert!(book.new.iter().all(|bucket| bucket.len() <= BUCKET_SIZE));
+    }
+
+    #[test]
+    fn test_bootnode_lines_are_parsed() {
+        let path = std::env::temp_dir().join(format!("bootnodes_test_{}.txt", std::process::id()));
+        std::fs::write(&path, "# seeds\n12D3KooWNode1@seed1.example.com:9334\n\n  10.0.0.2:1776\n# 12D3KooWOff@off:1\n").unwrap();
+        assert_eq!(read_bootnodes(&path).unwrap(), vec!["seed1.example.com:9334", "10.0.0.2:1776"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
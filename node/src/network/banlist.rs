@@ -0,0 +1,127 @@
+//! Banned peer IPs
+//!
+//! Bans come from misbehavior scoring or from an operator through the admin
+//! API. Each one carries an expiry time and a reason, and the list is saved as
+//! JSON in the data directory so bans survive a restart.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+pub const BAN_LIST_FILE: &str = "banlist.json";
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanEntry {
+    pub ip: IpAddr,
+    /// Unix time the ban ends
+    pub until: u64,
+    pub reason: String,
+}
+
+#[derive(Default)]
+pub struct BanList {
+    entries: HashMap<IpAddr, BanEntry>,
+    path: Option<PathBuf>,
+}
+
+impl BanList {
+    /// Load the list kept in `data_dir`, or start an empty one there
+    pub fn open(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(BAN_LIST_FILE);
+        let mut list = BanList::default();
+        if path.exists() {
+            let entries: Vec<BanEntry> = serde_json::from_slice(&std::fs::read(&path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            list.entries = entries.into_iter().map(|ban| (ban.ip, ban)).collect();
+        }
+        list.path = Some(path);
+        list.expire();
+        Ok(list)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(&self.list())?)?;
+        std::fs::rename(&tmp_path, path)
+    }
+
+    /// Ban `ip` for `duration`, replacing any earlier ban
+    pub fn ban(&mut self, ip: IpAddr, duration: Duration, reason: &str) -> io::Result<BanEntry> {
+        let ban = BanEntry { ip, until: unix_now().saturating_add(duration.as_secs()), reason: reason.to_string() };
+        self.entries.insert(ip, ban.clone());
+        self.save()?;
+        Ok(ban)
+    }
+
+    pub fn unban(&mut self, ip: &IpAddr) -> io::Result<bool> {
+        if self.entries.remove(ip).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.entries.get(ip).is_some_and(|ban| ban.until > unix_now())
+    }
+
+    /// Active bans, soonest to expire first
+    pub fn list(&self) -> Vec<BanEntry> {
+        let now = unix_now();
+        let mut bans: Vec<BanEntry> = self.entries.values().filter(|ban| ban.until > now).cloned().collect();
+        bans.sort_by_key(|ban| (ban.until, ban.ip));
+        bans
+    }
+
+    /// Drop bans that have run out; returns the IPs that were released
+    pub fn expire(&mut self) -> Vec<IpAddr> {
+        let now = unix_now();
+        let expired: Vec<IpAddr> = self.entries.values().filter(|ban| ban.until <= now).map(|ban| ban.ip).collect();
+        if !expired.is_empty() {
+            for ip in &expired {
+                self.entries.remove(ip);
+            }
+            if let Err(e) = self.save() {
+                println!("[P2P] Failed to save the ban list: {}", e);
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bans_persist_and_expire() {
+        let dir = std::env::temp_dir().join(format!("banlist_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let mut list = BanList::open(&dir).unwrap();
+        list.ban(ip, Duration::from_secs(3600), "spam").unwrap();
+        list.ban(other, Duration::ZERO, "already over").unwrap();
+        assert!(list.is_banned(&ip));
+        assert!(!list.is_banned(&other));
+
+        let mut reloaded = BanList::open(&dir).unwrap();
+        assert_eq!(reloaded.list().len(), 1);
+        assert_eq!(reloaded.list()[0].reason, "spam");
+        assert!(reloaded.is_banned(&ip));
+        assert!(reloaded.unban(&ip).unwrap());
+        assert!(!reloaded.unban(&ip).unwrap());
+        assert!(BanList::open(&dir).unwrap().list().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+//! Compact block relay
+//!
+//! A new block is announced as its header and coinbase, a 6-byte short ID per
+//! transaction and, whole, the transactions the peer is unlikely to have. The
+//! receiver fills the other slots from its mempool and fetches whatever is
+//! still missing with `GetBlockTxn`, so a block whose transactions were
+//! already relayed crosses the network in a few kilobytes and at most one
+//! extra round trip. Short IDs are keyed by the block hash and a random nonce,
+//! so colliding transactions cannot be prepared in advance; a collision that
+//! happens anyway shows up as a merkle root mismatch and the receiver falls
+//! back to fetching the full block.
+
+use std::collections::HashMap;
+
+use primitives::encoding::sha256;
+use primitives::types::Hash;
+use primitives::{Block, BlockHeader, Coinbase, Transaction};
+use serde::{Deserialize, Serialize};
+
+pub const SHORT_ID_LEN: usize = 6;
+
+/// Encoded size of the smallest possible transaction: no inputs, outputs,
+/// extra data or signatures
+const MIN_TRANSACTION_SIZE: usize = 16;
+
+/// Most transactions a block within `MAX_BLOCK_SIZE` can hold
+pub const MAX_BLOCK_TRANSACTIONS: usize = crate::config::MAX_BLOCK_SIZE / MIN_TRANSACTION_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ShortId(pub [u8; SHORT_ID_LEN]);
+
+/// Transaction sent whole, at its position in the block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefilledTransaction {
+    pub index: u32,
+    pub tx: Transaction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    pub coinbase: Coinbase,
+    pub nonce: u64,
+    /// Short IDs of the transactions that are not prefilled, in block order
+    pub short_ids: Vec<ShortId>,
+    pub prefilled: Vec<PrefilledTransaction>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CompactError {
+    /// Prefilled index out of range or used twice
+    BadPrefilledIndex(u32),
+    /// More transactions announced than a block can hold
+    TooManyTransactions(usize),
+    /// `BlockTxn` answer does not match the requested slots
+    WrongTransactionCount { expected: usize, got: usize },
+}
+
+impl std::fmt::Display for CompactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompactError::BadPrefilledIndex(index) => write!(f, "bad prefilled transaction index {}", index),
+            CompactError::TooManyTransactions(count) => {
+                write!(f, "{} transactions announced, a block holds at most {}", count, MAX_BLOCK_TRANSACTIONS)
+            }
+            CompactError::WrongTransactionCount { expected, got } => {
+                write!(f, "expected {} transaction(s), got {}", expected, got)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompactError {}
+
+/// Key for the short IDs of one compact block
+fn short_id_key(block_hash: &Hash, nonce: u64) -> Hash {
+    let mut data = block_hash.to_vec();
+    data.extend_from_slice(&nonce.to_le_bytes());
+    sha256(&data)
+}
+
+pub fn short_id(key: &Hash, txid: &Hash) -> ShortId {
+    let mut data = key.to_vec();
+    data.extend_from_slice(txid);
+    let digest = sha256(&data);
+    let mut id = [0u8; SHORT_ID_LEN];
+    id.copy_from_slice(&digest[..SHORT_ID_LEN]);
+    ShortId(id)
+}
+
+impl CompactBlock {
+    /// Compact form of `block`; `prefill(index, tx)` picks the transactions
+    /// sent whole
+    pub fn new(block: &Block, nonce: u64, prefill: impl Fn(usize, &Transaction) -> bool) -> Self {
+        let key = short_id_key(&block.hash(), nonce);
+        let mut short_ids = Vec::new();
+        let mut prefilled = Vec::new();
+        for (index, tx) in block.transactions.iter().enumerate() {
+            if prefill(index, tx) {
+                prefilled.push(PrefilledTransaction { index: index as u32, tx: tx.clone() });
+            } else {
+                short_ids.push(short_id(&key, &tx.id()));
+            }
+        }
+        CompactBlock { header: block.header.clone(), coinbase: block.coinbase.clone(), nonce, short_ids, prefilled }
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.header.pow.hash
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+
+    /// Fill in what `mempool` has, given as transactions with their IDs.
+    /// Only the transactions the block uses are cloned; mempool
+    /// transactions whose short IDs collide are left out and fetched
+    /// instead.
+    pub fn reconstruct<'a>(
+        &self,
+        mempool: impl IntoIterator<Item = (Hash, &'a Transaction)>,
+    ) -> Result<PartialBlock, CompactError> {
+        let count = self.transaction_count();
+        // The count comes from the peer: check it before allocating slots
+        if count > MAX_BLOCK_TRANSACTIONS {
+            return Err(CompactError::TooManyTransactions(count));
+        }
+        let mut slots: Vec<Option<Transaction>> = vec![None; count];
+        let mut prefilled = vec![false; count];
+        for PrefilledTransaction { index, tx } in &self.prefilled {
+            match prefilled.get_mut(*index as usize) {
+                Some(taken @ false) => *taken = true,
+                _ => return Err(CompactError::BadPrefilledIndex(*index)),
+            }
+            slots[*index as usize] = Some(tx.clone());
+        }
+
+        let key = short_id_key(&self.hash(), self.nonce);
+        let mut candidates: HashMap<ShortId, Option<&Transaction>> = HashMap::new();
+        for (txid, tx) in mempool {
+            candidates
+                .entry(short_id(&key, &txid))
+                .and_modify(|found| *found = None)
+                .or_insert(Some(tx));
+        }
+        let open = (0..count).filter(|index| !prefilled[*index]);
+        for (index, id) in open.zip(&self.short_ids) {
+            slots[index] = candidates.get(id).copied().flatten().cloned();
+        }
+        Ok(PartialBlock { header: self.header.clone(), coinbase: self.coinbase.clone(), slots })
+    }
+}
+
+/// Block being rebuilt from a compact block
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    coinbase: Coinbase,
+    slots: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    pub fn hash(&self) -> Hash {
+        self.header.pow.hash
+    }
+
+    /// Indexes of the transactions still missing, in block order
+    pub fn missing(&self) -> Vec<u32> {
+        (0..self.slots.len()).filter(|index| self.slots[*index].is_none()).map(|index| index as u32).collect()
+    }
+
+    /// Fill the missing slots, in order, with a `BlockTxn` answer
+    pub fn fill(&mut self, transactions: Vec<Transaction>) -> Result<(), CompactError> {
+        let missing = self.missing();
+        if missing.len() != transactions.len() {
+            return Err(CompactError::WrongTransactionCount { expected: missing.len(), got: transactions.len() });
+        }
+        for (index, tx) in missing.into_iter().zip(transactions) {
+            self.slots[index as usize] = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// The full block once nothing is missing
+    pub fn into_block(self) -> Option<Block> {
+        let transactions = self.slots.into_iter().collect::<Option<Vec<_>>>()?;
+        Some(Block { header: self.header, coinbase: self.coinbase, transactions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(seed: u8) -> Transaction {
+        crate::coinbase_transaction(seed as u64, "compact")
+    }
+
+    fn pool(txs: &[Transaction]) -> Vec<(Hash, &Transaction)> {
+        txs.iter().map(|tx| (tx.id(), tx)).collect()
+    }
+
+    #[test]
+    fn test_block_is_rebuilt_from_mempool_and_missing_transactions() {
+        let mut block = crate::Chain::new_for_network(crate::Network::Testnet).tip().clone();
+        block.transactions = vec![tx(0), tx(1), tx(2), tx(3)];
+        // The coinbase-like first transaction goes whole
+        let compact = CompactBlock::new(&block, 7, |index, _| index == 0);
+        assert_eq!((compact.prefilled.len(), compact.short_ids.len()), (1, 3));
+
+        let mut partial = compact.reconstruct(pool(&[tx(3), tx(1), tx(9)])).unwrap();
+        assert_eq!(partial.missing(), vec![2]);
+        assert!(partial.clone().into_block().is_none());
+        assert_eq!(
+            partial.fill(vec![]),
+            Err(CompactError::WrongTransactionCount { expected: 1, got: 0 })
+        );
+        partial.fill(vec![tx(2)]).unwrap();
+        let rebuilt = partial.into_block().unwrap();
+        let ids = |b: &Block| b.transactions.iter().map(Transaction::id).collect::<Vec<_>>();
+        assert_eq!(ids(&rebuilt), ids(&block));
+
+        let mut bad = compact.clone();
+        bad.prefilled[0].index = 4;
+        assert_eq!(bad.reconstruct(pool(&[])).unwrap_err(), CompactError::BadPrefilledIndex(4));
+    }
+
+    #[test]
+    fn test_oversized_transaction_count_is_rejected() {
+        let empty = Transaction {
+            kind: primitives::TransactionKind::Payment,
+            inputs: vec![],
+            outputs: vec![],
+            fee: 0,
+            extra: vec![],
+            metadata: None,
+            signature: String::new(),
+            quantum_signature: None,
+        };
+        assert!(crate::transaction_size(&empty) >= MIN_TRANSACTION_SIZE);
+
+        let block = crate::Chain::new_for_network(crate::Network::Testnet).tip().clone();
+        let mut compact = CompactBlock::new(&block, 7, |_, _| false);
+        compact.short_ids = vec![ShortId([0; SHORT_ID_LEN]); MAX_BLOCK_TRANSACTIONS];
+        assert!(compact.reconstruct(pool(&[])).is_ok());
+        compact.short_ids.push(ShortId([0; SHORT_ID_LEN]));
+        assert_eq!(
+            compact.reconstruct(pool(&[])).unwrap_err(),
+            CompactError::TooManyTransactions(MAX_BLOCK_TRANSACTIONS + 1)
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Dandelion++ transaction propagation
+//!
+//! A new transaction is not announced to every peer at once. It first travels
+//! the stem: each node passes it on to a single relay peer, until a node that
+//! is fluffing this epoch adds it to its mempool and announces it as usual,
+//! with the random batch delays of `relay`. A spy connected to many nodes then
+//! sees the transaction appear around the fluff node rather than its origin.
+//!
+//! Routes only change once per epoch. Each node picks whether it fluffs and up
+//! to two outbound relays; the stem from each inbound peer goes to one of them
+//! and our own transactions always take the same one, so sending many
+//! transactions does not reveal more of the graph. Stem transactions wait in
+//! the stem pool, which is never announced or served. One that has not come
+//! back fluffed when its embargo timer runs out is fluffed by the node holding
+//! it, so a relay that drops transactions cannot make them disappear.
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use primitives::types::Hash;
+use primitives::Transaction;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use super::relay;
+
+/// How long stem routes are kept
+pub const EPOCH: Duration = Duration::from_secs(600);
+
+/// Chance that a node fluffs, rather than relays, stem transactions for an epoch
+pub const FLUFF_PROBABILITY: f64 = 0.1;
+
+/// Outbound peers stem transactions are passed to each epoch
+pub const STEM_RELAYS: usize = 2;
+
+/// Shortest time a stem transaction waits before being fluffed anyway
+pub const EMBARGO_MIN: Duration = Duration::from_secs(10);
+
+/// Mean of the random time added to `EMBARGO_MIN`
+pub const EMBARGO_MEAN: Duration = Duration::from_secs(30);
+
+/// Most transactions held in the stem pool
+pub const MAX_STEMPOOL: usize = 5000;
+
+#[derive(Debug)]
+pub struct Dandelion {
+    epoch_ends: Option<Instant>,
+    fluff: bool,
+    relays: Vec<SocketAddr>,
+    /// Relay chosen for the stem from each inbound peer; `None` is ours
+    routes: HashMap<Option<SocketAddr>, SocketAddr>,
+    /// Stem transactions and when their embargo ends
+    stempool: HashMap<Hash, (Transaction, Instant)>,
+}
+
+impl Default for Dandelion {
+    fn default() -> Self {
+        Dandelion { epoch_ends: None, fluff: true, relays: Vec::new(), routes: HashMap::new(), stempool: HashMap::new() }
+    }
+}
+
+impl Dandelion {
+    /// Whether a new epoch should start: the current one is over, or there
+    /// are no relays (yet)
+    pub fn epoch_due(&self, now: Instant) -> bool {
+        match self.epoch_ends {
+            Some(ends) => now >= ends || self.relays.is_empty(),
+            None => true,
+        }
+    }
+
+    /// Start an epoch, picking the relays among `candidates` (outbound peers
+    /// that take stem transactions). Without candidates the node fluffs.
+    pub fn new_epoch(&mut self, now: Instant, mut candidates: Vec<SocketAddr>) {
+        let mut rng = rand::thread_rng();
+        candidates.shuffle(&mut rng);
+        candidates.truncate(STEM_RELAYS);
+        self.fluff = candidates.is_empty() || rng.gen_bool(FLUFF_PROBABILITY);
+        self.relays = candidates;
+        self.routes.clear();
+        self.epoch_ends = Some(now + EPOCH);
+    }
+
+    pub fn is_fluffing(&self) -> bool {
+        self.fluff
+    }
+
+    pub fn relays(&self) -> &[SocketAddr] {
+        &self.relays
+    }
+
+    /// Relay for a stem transaction from `from`, `None` for our own; `None`
+    /// if the transaction should be fluffed instead. Our own transactions are
+    /// always stemmed while there is a relay, even in a fluff epoch
+    pub fn route(&mut self, from: Option<SocketAddr>) -> Option<SocketAddr> {
+        if self.fluff && from.is_some() {
+            return None;
+        }
+        if let Some(relay) = self.routes.get(&from) {
+            return Some(*relay);
+        }
+        let choices: Vec<SocketAddr> = self.relays.iter().copied().filter(|relay| Some(*relay) != from).collect();
+        let relay = *choices.choose(&mut rand::thread_rng())?;
+        self.routes.insert(from, relay);
+        Some(relay)
+    }
+
+    /// Hold `tx` until it comes back fluffed or its embargo runs out; returns
+    /// `false` if it is already held, spends the same key image as a held
+    /// transaction, or the pool is full
+    pub fn stem(&mut self, tx: Transaction, now: Instant) -> bool {
+        if self.stempool.len() >= MAX_STEMPOOL || self.stempool.contains_key(&tx.id()) || self.conflicts(&tx) {
+            return false;
+        }
+        let embargo = now + EMBARGO_MIN + relay::trickle_delay(EMBARGO_MEAN);
+        self.stempool.insert(tx.id(), (tx, embargo));
+        true
+    }
+
+    fn conflicts(&self, tx: &Transaction) -> bool {
+        let held: HashSet<_> = self
+            .stempool
+            .values()
+            .flat_map(|(held, _)| held.inputs.iter().map(|input| input.key_image))
+            .collect();
+        tx.inputs.iter().any(|input| held.contains(&input.key_image))
+    }
+
+    pub fn contains(&self, txid: &Hash) -> bool {
+        self.stempool.contains_key(txid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stempool.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stempool.is_empty()
+    }
+
+    /// `txid` was fluffed; it leaves the stem pool
+    pub fn fluffed(&mut self, txid: &Hash) -> bool {
+        self.stempool.remove(txid).is_some()
+    }
+
+    /// Take the transactions whose embargo ended by `now`
+    pub fn expired(&mut self, now: Instant) -> Vec<Transaction> {
+        let ids: Vec<Hash> = self.stempool.iter().filter(|(_, (_, ends))| *ends <= now).map(|(id, _)| *id).collect();
+        ids.iter().filter_map(|id| self.stempool.remove(id)).map(|(tx, _)| tx).collect()
+    }
+
+    /// Stop routing to or for a peer that went away
+    pub fn forget_peer(&mut self, peer: SocketAddr) {
+        self.relays.retain(|relay| *relay != peer);
+        self.routes.retain(|from, relay| *relay != peer && *from != Some(peer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes_hold_for_the_epoch_and_avoid_the_sender() {
+        let relays: Vec<SocketAddr> = vec!["10.0.0.1:1776".parse().unwrap(), "10.0.0.2:1776".parse().unwrap()];
+        let now = Instant::now();
+        let mut dandelion = Dandelion::default();
+        assert!(dandelion.epoch_due(now));
+        // Roughly one epoch in ten fluffs; find one that stems
+        while {
+            dandelion.new_epoch(now, relays.clone());
+            dandelion.is_fluffing()
+        } {}
+        assert!(!dandelion.epoch_due(now));
+
+        let own = dandelion.route(None).unwrap();
+        assert!((0..20).all(|_| dandelion.route(None) == Some(own)));
+        // A stem never goes straight back to the relay it came from
+        assert!((0..20).all(|_| dandelion.route(Some(relays[0])) == Some(relays[1])));
+
+        dandelion.forget_peer(relays[1]);
+        assert_eq!(dandelion.route(Some(relays[0])), None);
+        dandelion.forget_peer(relays[0]);
+        assert!(dandelion.epoch_due(now));
+        // Without outbound peers everything is fluffed until some connect
+        dandelion.new_epoch(now, Vec::new());
+        assert!(dandelion.is_fluffing());
+        assert!(dandelion.epoch_due(now));
+    }
+
+    #[test]
+    fn test_own_transactions_are_stemmed_in_fluff_epochs() {
+        let relays: Vec<SocketAddr> = vec!["10.0.0.1:1776".parse().unwrap(), "10.0.0.2:1776".parse().unwrap()];
+        let mut dandelion = Dandelion::default();
+        dandelion.new_epoch(Instant::now(), relays.clone());
+        dandelion.fluff = true;
+
+        let own = dandelion.route(None).unwrap();
+        assert!(relays.contains(&own));
+        assert_eq!(dandelion.route(Some(relays[0])), None);
+    }
+
+    #[test]
+    fn test_stem_pool_embargo() {
+        let tx = crate::coinbase_transaction(1, "stem");
+        let now = Instant::now();
+        let mut dandelion = Dandelion::default();
+        assert!(dandelion.stem(tx.clone(), now));
+        assert!(!dandelion.stem(tx.clone(), now));
+        assert!(dandelion.contains(&tx.id()));
+
+        assert!(dandelion.expired(now + EMBARGO_MIN / 2).is_empty());
+        let expired = dandelion.expired(now + EMBARGO_MIN + EMBARGO_MEAN * 10);
+        assert_eq!(expired.iter().map(Transaction::id).collect::<Vec<_>>(), vec![tx.id()]);
+        assert!(dandelion.is_empty());
+
+        assert!(dandelion.stem(tx.clone(), now));
+        assert!(dandelion.fluffed(&tx.id()));
+        assert!(!dandelion.fluffed(&tx.id()));
+    }
+}
@@ -0,0 +1,255 @@
+//! Persistent Tor onion service
+//!
+//! The node's v3 onion key is kept in the data directory, so its `.onion`
+//! address stays the same across restarts. On startup the service is
+//! registered with the Tor daemon through its control port as a detached
+//! ephemeral service forwarding to the local P2P listener: Tor keeps it up
+//! without the control connection being held open, and a restarted node
+//! replaces the service it left behind rather than colliding with it. The
+//! control port is also where `privacy tor-status` gets the real state.
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::net::TcpStream;
+use torut::control::{AsyncEvent, AuthenticatedConn, ConnError, UnauthenticatedConn};
+use torut::onion::TorSecretKeyV3;
+
+pub const ONION_KEY_FILE: &str = "onion_v3.key";
+
+/// Tor control port of a default Tor install
+pub const DEFAULT_CONTROL_ADDR: &str = "127.0.0.1:9051";
+
+/// Time a control port conversation may take
+pub const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum OnionError {
+    Io(io::Error),
+    Control(ConnError),
+    /// Tor only offers authentication methods we cannot use, e.g. a password
+    NoAuthMethod,
+    TimedOut,
+}
+
+impl std::fmt::Display for OnionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnionError::Io(e) => write!(f, "I/O error: {}", e),
+            OnionError::Control(e) => write!(f, "Tor control port: {}", e),
+            OnionError::NoAuthMethod => write!(f, "Tor control port needs a password; enable cookie authentication"),
+            OnionError::TimedOut => write!(f, "Tor control port did not answer"),
+        }
+    }
+}
+
+impl std::error::Error for OnionError {}
+
+impl From<io::Error> for OnionError {
+    fn from(e: io::Error) -> Self {
+        OnionError::Io(e)
+    }
+}
+
+impl From<ConnError> for OnionError {
+    fn from(e: ConnError) -> Self {
+        OnionError::Control(e)
+    }
+}
+
+/// Load the onion key kept in `data_dir`, creating it on first use
+pub fn load_or_create_key(data_dir: &Path) -> io::Result<TorSecretKeyV3> {
+    let path = data_dir.join(ONION_KEY_FILE);
+    if path.exists() {
+        let secret: [u8; 64] = hex::decode(std::fs::read_to_string(&path)?.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a valid key", path.display())))?;
+        return Ok(TorSecretKeyV3::from(secret));
+    }
+    let key = TorSecretKeyV3::generate();
+    let tmp_path = path.with_extension("key.tmp");
+    super::transport::write_secret(&tmp_path, hex::encode(key.as_bytes()).as_bytes())?;
+    std::fs::rename(&tmp_path, &path)?;
+    Ok(key)
+}
+
+/// `<name>.onion` address of the service run with `key`
+pub fn onion_address(key: &TorSecretKeyV3) -> String {
+    format!("{}.onion", service_id(key))
+}
+
+/// Service ID Tor knows the service by: the address without `.onion`
+fn service_id(key: &TorSecretKeyV3) -> String {
+    key.public().get_onion_address().get_address_without_dot_onion()
+}
+
+/// What `privacy tor-status` shows
+#[derive(Debug, Clone, Serialize)]
+pub struct TorStatus {
+    pub version: String,
+    /// Whether Tor has built a circuit, i.e. can reach the Tor network
+    pub circuit_established: bool,
+    /// Detached onion services Tor is running, with `.onion`
+    pub services: Vec<String>,
+}
+
+type NoEvents = fn(AsyncEvent<'static>) -> std::future::Ready<Result<(), ConnError>>;
+type ControlConn = AuthenticatedConn<TcpStream, NoEvents>;
+
+/// Connect to the control port and authenticate with whatever method Tor
+/// offers that needs no password
+async fn authenticate(control: SocketAddr) -> Result<ControlConn, OnionError> {
+    let mut conn = UnauthenticatedConn::new(TcpStream::connect(control).await?);
+    let auth = conn.load_protocol_info().await?.make_auth_data()?.ok_or(OnionError::NoAuthMethod)?;
+    conn.authenticate(&auth).await?;
+    Ok(conn.into_authenticated().await)
+}
+
+async fn detached_services(conn: &mut ControlConn) -> Result<Vec<String>, OnionError> {
+    match conn.get_info("onions/detached").await {
+        Ok(list) => Ok(list.split_whitespace().map(str::to_string).collect()),
+        // Tor answers 551 when it runs none
+        Err(ConnError::InvalidResponseCode(551)) => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn with_timeout<T>(conversation: impl std::future::Future<Output = Result<T, OnionError>>) -> Result<T, OnionError> {
+    tokio::time::timeout(CONTROL_TIMEOUT, conversation).await.map_err(|_| OnionError::TimedOut)?
+}
+
+/// Run the service for `key` on `port`, forwarding to `target`; replaces the
+/// service a previous run left registered. Returns the `.onion` address.
+pub async fn publish(control: SocketAddr, key: &TorSecretKeyV3, port: u16, target: SocketAddr) -> Result<String, OnionError> {
+    with_timeout(async {
+        let mut conn = authenticate(control).await?;
+        let id = service_id(key);
+        if detached_services(&mut conn).await?.contains(&id) {
+            conn.del_onion(&id).await?;
+        }
+        conn.add_onion_v3(key, true, false, false, None, &mut [(port, target)].iter()).await?;
+        Ok(onion_address(key))
+    })
+    .await
+}
+
+/// Ask the Tor daemon at `control` how it is doing
+pub async fn status(control: SocketAddr) -> Result<TorStatus, OnionError> {
+    with_timeout(async {
+        let mut conn = authenticate(control).await?;
+        let version = conn.get_info("version").await?;
+        let circuit_established = conn.get_info("status/circuit-established").await? == "1";
+        let services = detached_services(&mut conn).await?.into_iter().map(|id| format!("{}.onion", id)).collect();
+        Ok(TorStatus { version, circuit_established, services })
+    })
+    .await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use base64::Engine;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// Control port that accepts null authentication and keeps track of
+    /// detached onion services; returns its address and the services
+    pub(crate) fn mock_control_port() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let services = Arc::new(Mutex::new(Vec::new()));
+        let running = services.clone();
+        std::thread::spawn(move || {
+            for client in listener.incoming() {
+                let mut client = client.unwrap();
+                let services = running.clone();
+                std::thread::spawn(move || {
+                    let reader = BufReader::new(client.try_clone().unwrap());
+                    for line in reader.lines() {
+                        let Ok(line) = line else { break };
+                        let reply = control_reply(&line, &mut services.lock().unwrap());
+                        client.write_all(reply.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+        (addr, services)
+    }
+
+    fn control_reply(command: &str, services: &mut Vec<String>) -> String {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["PROTOCOLINFO", "1"] => {
+                "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250-VERSION Tor=\"0.4.8.9\"\r\n250 OK\r\n".to_string()
+            }
+            ["AUTHENTICATE"] => "250 OK\r\n".to_string(),
+            ["GETINFO", "version"] => "250-version=0.4.8.9\r\n250 OK\r\n".to_string(),
+            ["GETINFO", "status/circuit-established"] => "250-status/circuit-established=1\r\n250 OK\r\n".to_string(),
+            ["GETINFO", "onions/detached"] => match services.as_slice() {
+                [] => "551 No onion services of the specified type.\r\n".to_string(),
+                [id] => format!("250-onions/detached={}\r\n250 OK\r\n", id),
+                ids => format!("250+onions/detached=\r\n{}\r\n.\r\n250 OK\r\n", ids.join("\r\n")),
+            },
+            ["ADD_ONION", key, args @ ..] => {
+                assert!(args.contains(&"Flags=DiscardPK,Detach"), "{}", command);
+                assert!(args.iter().any(|arg| arg.starts_with("Port=")), "{}", command);
+                let blob = key.strip_prefix("ED25519-V3:").unwrap();
+                let secret: [u8; 64] = base64::engine::general_purpose::STANDARD.decode(blob).unwrap().try_into().unwrap();
+                let id = service_id(&TorSecretKeyV3::from(secret));
+                if services.contains(&id) {
+                    return "550 Onion address collision\r\n".to_string();
+                }
+                services.push(id.clone());
+                format!("250-ServiceID={}\r\n250 OK\r\n", id)
+            }
+            ["DEL_ONION", id] if services.iter().any(|s| s == id) => {
+                services.retain(|s| s != id);
+                "250 OK\r\n".to_string()
+            }
+            ["DEL_ONION", _] => "552 Unknown Onion Service id\r\n".to_string(),
+            _ => "510 Unrecognized command\r\n".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_onion_key_survives_restarts() {
+        let dir = std::env::temp_dir().join(format!("onion_key_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = load_or_create_key(&dir).unwrap();
+        let address = onion_address(&key);
+        assert_eq!(address.len(), 56 + ".onion".len());
+        assert_eq!(onion_address(&load_or_create_key(&dir).unwrap()), address);
+
+        std::fs::write(dir.join(ONION_KEY_FILE), "not a key").unwrap();
+        assert!(load_or_create_key(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_service_is_published_again_after_a_restart() {
+        let (control, services) = mock_control_port();
+        let key = TorSecretKeyV3::generate();
+        let target: SocketAddr = "127.0.0.1:9334".parse().unwrap();
+
+        let status_before = status(control).await.unwrap();
+        assert_eq!(status_before.version, "0.4.8.9");
+        assert!(status_before.circuit_established);
+        assert!(status_before.services.is_empty());
+
+        let address = publish(control, &key, 9334, target).await.unwrap();
+        assert_eq!(address, onion_address(&key));
+        // A restarted node finds its old service still registered
+        assert_eq!(publish(control, &key, 9334, target).await.unwrap(), address);
+        assert_eq!(services.lock().unwrap().len(), 1);
+
+        let other = TorSecretKeyV3::generate();
+        publish(control, &other, 9334, target).await.unwrap();
+        let services = status(control).await.unwrap().services;
+        assert_eq!(services, vec![address, onion_address(&other)]);
+    }
+}
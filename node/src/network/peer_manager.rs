@@ -0,0 +1,1669 @@
+//! Async peer manager
+//!
+//! Every connection is served by two tokio tasks: a reader that decodes frames
+//! and hands each message to the protocol handler, and a writer that drains
+//! the peer's bounded outbound queue. The manager enforces separate inbound
+//! and outbound limits, keeps `min_outbound` outbound connections open by
+//! dialing addresses from the address book, and scores misbehavior: a peer
+//! whose score reaches the ban threshold is disconnected and its IP banned for
+//! a while. Block and transaction relay bookkeeping lives here too; see
+//! `relay` and `dandelion`.
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tokio::io::{AsyncRead, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Notify};
+
+use super::addrman::{AddrMan, NetAddress};
+use super::banlist::{BanEntry, BanList};
+use super::compact::{CompactBlock, PartialBlock};
+use super::dandelion::{self, Dandelion};
+use super::onion::OnionError;
+use super::privacy::{self, ConnectionType, PrivacyManager};
+use super::proxy::{self, Endpoint};
+use super::relay::{self, InvItem, Relay, RollingSet};
+use super::transport::{self, EncryptionPolicy, FrameReader, FrameWriter, Identity, IdentityKey, Link};
+use super::wire::{WireError, COMPACT_BLOCKS_VERSION, DANDELION_VERSION, HANDSHAKE_TIMEOUT, MIN_PROTOCOL_VERSION};
+use crate::P2PMessage;
+
+/// Messages a peer may have queued before it is considered stalled
+pub const OUTBOUND_QUEUE_LEN: usize = 1024;
+
+/// Time allowed for an outbound TCP connection to be established
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time allowed for a connection through the Tor or I2P proxy, which may
+/// have to build a circuit or tunnel first
+pub const PROXY_CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Misbehavior score at which a peer is banned
+pub const BAN_THRESHOLD: u32 = 100;
+
+pub const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Seconds between attempts to top up outbound connections
+const DIAL_INTERVAL_SECS: u64 = 5;
+
+/// Seconds between saves of the address book
+const SAVE_INTERVAL_SECS: u64 = 60;
+
+/// Largest `Addr` message accepted, and the most addresses sent in one
+pub const MAX_ADDR_PER_MESSAGE: usize = 1000;
+
+/// Rate at which a peer may feed us addresses we did not ask for
+const ADDR_TOKENS_PER_SEC: f64 = 0.1;
+
+/// Minimum time between seed lookups the manager starts on its own
+const SEED_QUERY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Compact blocks kept waiting for their missing transactions
+const MAX_PARTIAL_BLOCKS: usize = 16;
+
+/// Misbehavior points for each kind of offence
+pub mod penalty {
+    /// Frame that fails to decode or checksum
+    pub const MALFORMED_MESSAGE: u32 = 50;
+    /// Handshake messages after the handshake
+    pub const PROTOCOL_VIOLATION: u32 = 100;
+    pub const INVALID_HEADERS: u32 = 50;
+    pub const INVALID_BLOCK: u32 = 20;
+    pub const INVALID_TRANSACTION: u32 = 10;
+    /// `Addr` message over `MAX_ADDR_PER_MESSAGE`
+    pub const OVERSIZED_ADDR: u32 = 20;
+    /// `Inv`, `GetData` or `NotFound` over `relay::MAX_INV_PER_MESSAGE`
+    pub const OVERSIZED_INV: u32 = 20;
+    /// Compact block or `GetBlockTxn` with indexes outside the block
+    pub const INVALID_COMPACT_BLOCK: u32 = 20;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerConfig {
+    pub max_inbound: usize,
+    pub max_outbound: usize,
+    /// Outbound connections the manager keeps open by dialing known addresses
+    pub min_outbound: usize,
+    pub ban_threshold: u32,
+    pub ban_duration: Duration,
+    /// Where the address book and ban list are kept; in memory only if `None`
+    pub data_dir: Option<PathBuf>,
+    /// `host:port` seeds resolved into the address book at startup and
+    /// whenever the book runs dry
+    pub seeds: Vec<String>,
+    pub encryption: EncryptionPolicy,
+    /// Static key proven to peers on encrypted links
+    pub identity: Option<Identity>,
+}
+
+impl PeerConfig {
+    /// Limits from `--max-peers`/`--min-peers`: `min_peers` outbound slots,
+    /// the rest of `max_peers` for inbound connections
+    pub fn from_limits(max_peers: usize, min_peers: usize) -> Self {
+        let outbound = min_peers.min(max_peers);
+        PeerConfig {
+            max_inbound: max_peers - outbound,
+            max_outbound: outbound,
+            min_outbound: outbound,
+            ..PeerConfig::default()
+        }
+    }
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        PeerConfig {
+            max_inbound: 42,
+            max_outbound: 8,
+            min_outbound: 8,
+            ban_threshold: BAN_THRESHOLD,
+            ban_duration: DEFAULT_BAN_DURATION,
+            data_dir: None,
+            seeds: Vec::new(),
+            encryption: EncryptionPolicy::default(),
+            identity: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PeerError {
+    Banned(IpAddr),
+    AlreadyConnected(SocketAddr),
+    InboundLimit,
+    OutboundLimit,
+    RejectedByPrivacyPolicy(SocketAddr),
+    NotConnected(SocketAddr),
+    PingTimeout(SocketAddr),
+}
+
+impl std::fmt::Display for PeerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerError::Banned(ip) => write!(f, "{} is banned", ip),
+            PeerError::AlreadyConnected(addr) => write!(f, "already connected to {}", addr),
+            PeerError::InboundLimit => write!(f, "inbound connection limit reached"),
+            PeerError::OutboundLimit => write!(f, "outbound connection limit reached"),
+            PeerError::RejectedByPrivacyPolicy(addr) => write!(f, "{} rejected by privacy policy", addr),
+            PeerError::NotConnected(addr) => write!(f, "not connected to {}", addr),
+            PeerError::PingTimeout(addr) => write!(f, "{} did not answer the ping", addr),
+        }
+    }
+}
+
+impl std::error::Error for PeerError {}
+
+/// What a peer announced in its `Version`
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerVersion {
+    pub version: u32,
+    pub best_height: u64,
+    pub services: u64,
+    pub node: String,
+    /// Onion address it accepts peers on
+    pub onion: Option<String>,
+}
+
+/// Snapshot of a connected peer for status output
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerInfo {
+    pub addr: SocketAddr,
+    pub direction: Direction,
+    pub version: PeerVersion,
+    pub score: u32,
+    pub connected_secs: u64,
+    /// Round-trip time of the last answered ping
+    pub ping_ms: Option<u64>,
+    pub encrypted: bool,
+    /// Hex identity key the peer proved, if any
+    pub identity: Option<String>,
+    /// Onion or I2P address behind a stand-in `addr`
+    pub endpoint: Option<String>,
+}
+
+/// Outcome of `PeerManager::discover`
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveryReport {
+    /// Addresses from the seeds that were new to the address book
+    pub from_seeds: usize,
+    /// Connected peers asked for their addresses
+    pub peers_queried: usize,
+    pub known_addresses: usize,
+}
+
+struct Peer {
+    direction: Direction,
+    queue: mpsc::Sender<P2PMessage>,
+    shutdown: Arc<Notify>,
+    connected_at: Instant,
+    /// `None` until the handshake completes
+    version: Option<PeerVersion>,
+    score: u32,
+    /// Addresses this peer may still send us; see `learn_addresses`
+    addr_tokens: f64,
+    addr_tokens_at: Instant,
+    /// Whether we already answered its `GetAddr`
+    sent_addr: bool,
+    /// Outstanding `Ping` and who is waiting for the answer
+    ping: Option<(Instant, std::sync::mpsc::Sender<Duration>)>,
+    last_rtt: Option<Duration>,
+    encrypted: bool,
+    identity: Option<IdentityKey>,
+    /// Inventory the peer has or was announced
+    known: RollingSet,
+    /// Transactions waiting for the next batch announcement
+    tx_queue: Vec<primitives::types::Hash>,
+    next_tx_batch: Instant,
+}
+
+pub struct PeerManager {
+    config: PeerConfig,
+    privacy: Option<Arc<PrivacyManager>>,
+    runtime: tokio::runtime::Handle,
+    peers: Mutex<HashMap<SocketAddr, Peer>>,
+    bans: Mutex<BanList>,
+    addrman: Mutex<AddrMan>,
+    last_seed_query: Mutex<Option<Instant>>,
+    relay: Mutex<Relay>,
+    /// Compact blocks waiting for a `BlockTxn`, by block hash
+    partial_blocks: Mutex<HashMap<primitives::types::Hash, (SocketAddr, Instant, PartialBlock)>>,
+    dandelion: Mutex<Dandelion>,
+    /// SAM session I2P streams are opened through, created on first use
+    sam: Mutex<Option<Arc<i2p::SamSession>>>,
+    /// Our onion service, advertised in `Version` and address gossip
+    onion: Mutex<Option<Endpoint>>,
+}
+
+static PEER_MANAGER: OnceCell<Arc<PeerManager>> = OnceCell::new();
+
+/// Start the node's peer manager; later calls return the running instance
+pub fn start(config: PeerConfig, privacy: Option<Arc<PrivacyManager>>) -> std::io::Result<&'static Arc<PeerManager>> {
+    PEER_MANAGER.get_or_try_init(|| PeerManager::spawn(config, privacy))
+}
+
+/// The node's peer manager, if networking has been started
+pub fn peer_manager() -> Option<&'static Arc<PeerManager>> {
+    PEER_MANAGER.get()
+}
+
+impl PeerManager {
+    /// Create a manager with its own runtime and maintenance loop
+    pub fn spawn(config: PeerConfig, privacy: Option<Arc<PrivacyManager>>) -> std::io::Result<Arc<Self>> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .thread_name("p2p")
+            .enable_all()
+            .build()?;
+        let addrman = match &config.data_dir {
+            Some(dir) => AddrMan::open(dir)?,
+            None => AddrMan::new(),
+        };
+        if !addrman.is_empty() {
+            println!("[P2P] Loaded {} known address(es), {} tried", addrman.len(), addrman.tried_count());
+        }
+        let bans = match &config.data_dir {
+            Some(dir) => BanList::open(dir)?,
+            None => BanList::default(),
+        };
+        if let Some(privacy) = &privacy {
+            for ban in bans.list().into_iter().filter(|ban| !privacy.is_tor_loopback(&ban.ip)) {
+                privacy.ban_ip(ban.ip);
+            }
+        }
+        let manager = Arc::new(PeerManager {
+            config,
+            privacy,
+            runtime: runtime.handle().clone(),
+            peers: Mutex::new(HashMap::new()),
+            bans: Mutex::new(bans),
+            addrman: Mutex::new(addrman),
+            last_seed_query: Mutex::new(None),
+            relay: Mutex::new(Relay::default()),
+            partial_blocks: Mutex::new(HashMap::new()),
+            dandelion: Mutex::new(Dandelion::default()),
+            sam: Mutex::new(None),
+            onion: Mutex::new(None),
+        });
+        if !manager.config.seeds.is_empty() {
+            runtime.spawn(manager.clone().query_seeds());
+        }
+        let maintained = manager.clone();
+        std::thread::Builder::new()
+            .name("p2p-maintenance".to_string())
+            .spawn(move || runtime.block_on(maintained.maintain()))?;
+        Ok(manager)
+    }
+
+    pub fn config(&self) -> &PeerConfig {
+        &self.config
+    }
+
+    /// Accept peers on `addr` in the background; returns the bound address
+    pub fn listen(self: &Arc<Self>, addr: SocketAddr) -> std::io::Result<SocketAddr> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local = listener.local_addr()?;
+        let _runtime = self.runtime.enter();
+        let listener = TcpListener::from_std(listener)?;
+        println!("[P2P] Listening for peers on {}", local);
+        self.runtime.spawn(self.clone().accept_loop(listener));
+        Ok(local)
+    }
+
+    async fn accept_loop(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => match self.register(addr, Direction::Inbound, false) {
+                    Ok((queue, shutdown)) => {
+                        tokio::spawn(self.clone().run_peer(stream, addr, Direction::Inbound, queue, shutdown));
+                    }
+                    Err(e) => println!("[P2P] Refused inbound {}: {}", addr, e),
+                },
+                Err(e) => {
+                    println!("[P2P] Accept failed: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    }
+
+    /// Dial `addr` on request (`--connect`, `network connect`). Manual
+    /// connections may exceed the outbound limit but not the total one.
+    pub fn connect(self: &Arc<Self>, addr: SocketAddr) -> Result<(), PeerError> {
+        self.add_address(addr);
+        self.dial(addr, true)
+    }
+
+    /// Dial a clearnet, onion or I2P endpoint on request; returns the
+    /// address the peer is known by
+    pub fn connect_endpoint(self: &Arc<Self>, endpoint: &Endpoint) -> Result<SocketAddr, PeerError> {
+        let addr = endpoint.peer_addr();
+        self.connect(addr)?;
+        Ok(addr)
+    }
+
+    fn dial(self: &Arc<Self>, addr: SocketAddr, manual: bool) -> Result<(), PeerError> {
+        let (queue, shutdown) = self.register(addr, Direction::Outbound, manual)?;
+        self.note_attempt(addr);
+        let manager = self.clone();
+        self.runtime.spawn(async move {
+            match tokio::time::timeout(connect_timeout(&addr), manager.open_stream(addr)).await {
+                Ok(Ok(stream)) => {
+                    println!("[P2P] Connected to peer {}", addr);
+                    manager.run_peer(stream, addr, Direction::Outbound, queue, shutdown).await;
+                }
+                Ok(Err(e)) => {
+                    println!("[P2P] Failed to connect to {}: {}", addr, e);
+                    manager.remove(addr);
+                }
+                Err(_) => {
+                    println!("[P2P] Connection to {} timed out", addr);
+                    manager.remove(addr);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Open the connection to `addr`: directly, or through the Tor SOCKS5
+    /// proxy or the I2P SAM bridge for a stand-in address
+    async fn open_stream(&self, addr: SocketAddr) -> std::io::Result<TcpStream> {
+        let unavailable = |msg: String| std::io::Error::new(std::io::ErrorKind::Unsupported, msg);
+        let config = self.privacy.as_ref().map(|privacy| privacy.config());
+        match proxy::overlay_endpoint(&addr) {
+            Some(Endpoint::Onion { host, port }) => {
+                let tor_proxy = config
+                    .and_then(|config| config.tor_proxy.as_deref())
+                    .ok_or_else(|| unavailable("no Tor proxy configured".to_string()))?;
+                let tor_proxy: SocketAddr = tor_proxy
+                    .parse()
+                    .map_err(|_| unavailable(format!("invalid Tor proxy address {}", tor_proxy)))?;
+                proxy::socks5_connect(tor_proxy, &host, port).await
+            }
+            Some(Endpoint::I2p { destination, .. }) => {
+                let config = config
+                    .filter(|config| config.i2p_enabled)
+                    .ok_or_else(|| unavailable("I2P is not enabled".to_string()))?;
+                let sam_addr = config.i2p_proxy.clone().unwrap_or_else(|| i2p::DEFAULT_SAM_ADDR.to_string());
+                let session = self.sam_session(sam_addr).await?;
+                let result = proxy::sam_connect(session, destination).await;
+                if result.as_ref().is_err_and(|e| e.kind() != std::io::ErrorKind::ConnectionRefused) {
+                    // The bridge itself failed; start over with a new session
+                    self.sam.lock().unwrap().take();
+                }
+                result
+            }
+            Some(Endpoint::Clearnet(_)) | None if proxy::overlay_network(&addr).is_some() => {
+                Err(unavailable(format!("no onion or I2P address known for {}", addr)))
+            }
+            Some(Endpoint::Clearnet(_)) | None => TcpStream::connect(addr).await,
+        }
+    }
+
+    async fn sam_session(&self, sam_addr: String) -> std::io::Result<Arc<i2p::SamSession>> {
+        if let Some(session) = self.sam.lock().unwrap().clone() {
+            return Ok(session);
+        }
+        let session = tokio::task::spawn_blocking(move || i2p::SamSession::connect(sam_addr.as_str(), "", i2p::SessionStyle::Stream))
+            .await
+            .map_err(std::io::Error::other)??;
+        println!("[I2P] Created SAM session {}", session.nickname);
+        Ok(self.sam.lock().unwrap().get_or_insert(Arc::new(session)).clone())
+    }
+
+    /// Reserve a connection slot for `addr`
+    fn register(
+        &self,
+        addr: SocketAddr,
+        direction: Direction,
+        manual: bool,
+    ) -> Result<(mpsc::Receiver<P2PMessage>, Arc<Notify>), PeerError> {
+        if self.is_banned(&addr.ip()) {
+            return Err(PeerError::Banned(addr.ip()));
+        }
+        let outbound = direction == Direction::Outbound;
+        if let Some(privacy) = &self.privacy {
+            if !privacy.allow_connection(&addr, outbound) {
+                return Err(PeerError::RejectedByPrivacyPolicy(addr));
+            }
+        }
+        let mut peers = self.peers.lock().unwrap();
+        if peers.contains_key(&addr) {
+            return Err(PeerError::AlreadyConnected(addr));
+        }
+        let count = peers.values().filter(|p| p.direction == direction).count();
+        match direction {
+            Direction::Inbound if count >= self.config.max_inbound => return Err(PeerError::InboundLimit),
+            Direction::Outbound if !manual && count >= self.config.max_outbound => return Err(PeerError::OutboundLimit),
+            Direction::Outbound if peers.len() >= self.config.max_inbound + self.config.max_outbound => {
+                return Err(PeerError::OutboundLimit)
+            }
+            _ => {}
+        }
+        let (queue, receiver) = mpsc::channel(OUTBOUND_QUEUE_LEN);
+        let shutdown = Arc::new(Notify::new());
+        peers.insert(addr, Peer {
+            direction,
+            queue,
+            shutdown: shutdown.clone(),
+            connected_at: Instant::now(),
+            version: None,
+            score: 0,
+            addr_tokens: 1.0,
+            addr_tokens_at: Instant::now(),
+            sent_addr: false,
+            ping: None,
+            last_rtt: None,
+            encrypted: false,
+            identity: None,
+            known: RollingSet::new(relay::KNOWN_INVENTORY_PER_PEER),
+            tx_queue: Vec::new(),
+            next_tx_batch: Instant::now() + relay::trickle_delay(Self::tx_delay(direction)),
+        });
+        drop(peers);
+        if let Some(privacy) = &self.privacy {
+            privacy.register_connection(addr, outbound);
+        }
+        Ok((receiver, shutdown))
+    }
+
+    async fn run_peer(
+        self: Arc<Self>,
+        stream: TcpStream,
+        addr: SocketAddr,
+        direction: Direction,
+        queue: mpsc::Receiver<P2PMessage>,
+        shutdown: Arc<Notify>,
+    ) {
+        let _ = stream.set_nodelay(true);
+        let magic = crate::current_network().get_magic();
+        let (reader, writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let result = tokio::select! {
+            result = self.clone().serve(&mut reader, writer, queue, addr, direction, magic) => result,
+            _ = shutdown.notified() => Ok(()),
+        };
+        match result {
+            Ok(()) => println!("[P2P] Peer {} disconnected", addr),
+            Err(e) => println!("[P2P] Dropping {}: {}", addr, e),
+        }
+        self.remove(addr);
+        let manager = self.clone();
+        let _ = tokio::task::spawn_blocking(move || crate::sync::peer_disconnected(&manager, addr)).await;
+    }
+
+    /// Set up the link, handshake, then feed every message to the protocol
+    /// handler in order
+    async fn serve<R: AsyncRead + Unpin>(
+        self: Arc<Self>,
+        reader: &mut R,
+        mut writer: OwnedWriteHalf,
+        queue: mpsc::Receiver<P2PMessage>,
+        addr: SocketAddr,
+        direction: Direction,
+        magic: u32,
+    ) -> Result<(), String> {
+        let outbound = direction == Direction::Outbound;
+        let negotiation = transport::negotiate(reader, &mut writer, magic, outbound, self.config.encryption, self.config.identity.as_ref());
+        let Link { reader: mut frames, writer: sealer, peer_identity, first_message } = tokio::time::timeout(HANDSHAKE_TIMEOUT, negotiation)
+            .await
+            .map_err(|_| "link setup timed out".to_string())??;
+        if let Some(privacy) = &self.privacy {
+            if !privacy.identity_matches(&addr, peer_identity.as_ref()) {
+                return Err("did not prove the identity key pinned for it".to_string());
+            }
+        }
+        if let Some(peer) = self.peers.lock().unwrap().get_mut(&addr) {
+            peer.encrypted = frames.is_encrypted();
+            peer.identity = peer_identity;
+        }
+        tokio::spawn(write_loop(writer, sealer, queue, magic));
+
+        let handshake = self.handshake(reader, &mut frames, first_message, addr, magic);
+        let version = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .map_err(|_| "handshake timed out".to_string())??;
+        let services = version.services;
+        if let Some(peer) = self.peers.lock().unwrap().get_mut(&addr) {
+            peer.version = Some(version);
+        }
+        if outbound {
+            self.addrman.lock().unwrap().good(&addr, services);
+        }
+        println!("[P2P] New peer: {} ({} connected{})", addr, self.peer_count(),
+            if frames.is_encrypted() { ", encrypted" } else { "" });
+        self.dispatch(move |manager| crate::on_peer_ready(manager, addr)).await?;
+
+        loop {
+            let msg = match frames.read(reader, magic).await {
+                Ok(msg) => msg,
+                Err(WireError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e @ (WireError::Io(_) | WireError::WrongMagic { .. })) => return Err(e.to_string()),
+                Err(e) => {
+                    self.misbehaving(addr, penalty::MALFORMED_MESSAGE, &e.to_string());
+                    return Err(e.to_string());
+                }
+            };
+            self.dispatch(move |manager| crate::handle_peer_message(manager, addr, msg)).await?;
+        }
+    }
+
+    /// Run a protocol handler off the async workers; it takes the chain lock
+    async fn dispatch(self: &Arc<Self>, handler: impl FnOnce(&PeerManager) + Send + 'static) -> Result<(), String> {
+        let manager = self.clone();
+        tokio::task::spawn_blocking(move || handler(&manager))
+            .await
+            .map_err(|e| format!("message handler failed: {}", e))
+    }
+
+    /// Exchange `Version`/`Verack`. The peer must complete the handshake
+    /// before sending anything else; `pending` is a message link setup
+    /// already read.
+    async fn handshake<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+        frames: &mut FrameReader,
+        mut pending: Option<P2PMessage>,
+        addr: SocketAddr,
+        magic: u32,
+    ) -> Result<PeerVersion, String> {
+        let onion = self.onion_address().map(|endpoint| endpoint.to_string());
+        let ours = tokio::task::spawn_blocking(|| crate::version_message("BlackSilkNode", onion))
+            .await
+            .map_err(|e| e.to_string())?;
+        self.send(addr, ours);
+        let mut announced = None;
+        let mut got_verack = false;
+        while announced.is_none() || !got_verack {
+            let msg = match pending.take() {
+                Some(msg) => msg,
+                None => frames.read(reader, magic).await.map_err(|e| e.to_string())?,
+            };
+            match msg {
+                P2PMessage::Version { version, best_height, services, node, onion } => {
+                    if announced.is_some() {
+                        return Err("duplicate version message".to_string());
+                    }
+                    if version < MIN_PROTOCOL_VERSION {
+                        return Err(format!("protocol version {} is older than {}", version, MIN_PROTOCOL_VERSION));
+                    }
+                    println!("[P2P] {} runs {} (protocol {}, height {}, services 0x{:x})",
+                        addr, node, version, best_height, services);
+                    // Anything but a valid onion address is ignored
+                    let onion = onion.and_then(|onion| match Endpoint::parse(&onion) {
+                        Ok(endpoint @ Endpoint::Onion { .. }) => Some(endpoint),
+                        _ => None,
+                    });
+                    if let Some(endpoint) = &onion {
+                        println!("[P2P] {} has onion service {}", addr, endpoint);
+                        self.addrman.lock().unwrap().add(endpoint.peer_addr(), services, unix_now(), addr.ip());
+                    }
+                    self.send(addr, P2PMessage::Verack);
+                    let onion = onion.map(|endpoint| endpoint.to_string());
+                    announced = Some(PeerVersion { version, best_height, services, node, onion });
+                }
+                P2PMessage::Verack => got_verack = true,
+                other => return Err(format!("sent {} before completing the handshake", crate::message_summary(&other))),
+            }
+        }
+        Ok(announced.unwrap())
+    }
+
+    /// Queue `msg` for `addr`. A peer whose queue is full is not keeping up
+    /// and gets disconnected.
+    pub fn send(&self, addr: SocketAddr, msg: P2PMessage) -> bool {
+        let result = match self.peers.lock().unwrap().get(&addr) {
+            Some(peer) => peer.queue.try_send(msg),
+            None => return false,
+        };
+        match result {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                println!("[P2P] Outbound queue to {} is full, disconnecting", addr);
+                self.disconnect(addr);
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Queue `msg` for every peer that completed the handshake
+    pub fn broadcast(&self, msg: &P2PMessage) {
+        for addr in self.ready_peers() {
+            self.send(addr, msg.clone());
+        }
+    }
+
+    /// Addresses of peers that completed the handshake
+    pub fn ready_peers(&self) -> Vec<SocketAddr> {
+        let peers = self.peers.lock().unwrap();
+        peers.iter().filter(|(_, p)| p.version.is_some()).map(|(addr, _)| *addr).collect()
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.peers.lock().unwrap().values().filter(|p| p.version.is_some()).count()
+    }
+
+    pub fn outbound_count(&self) -> usize {
+        self.peers.lock().unwrap().values().filter(|p| p.direction == Direction::Outbound).count()
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let peers = self.peers.lock().unwrap();
+        let mut infos: Vec<PeerInfo> = peers
+            .iter()
+            .filter_map(|(addr, peer)| {
+                Some(PeerInfo {
+                    addr: *addr,
+                    direction: peer.direction,
+                    version: peer.version.clone()?,
+                    score: peer.score,
+                    connected_secs: peer.connected_at.elapsed().as_secs(),
+                    ping_ms: peer.last_rtt.map(|rtt| rtt.as_millis() as u64),
+                    encrypted: peer.encrypted,
+                    identity: peer.identity.map(hex::encode),
+                    endpoint: proxy::overlay_endpoint(addr).map(|endpoint| endpoint.to_string()),
+                })
+            })
+            .collect();
+        infos.sort_by_key(|info| info.addr);
+        infos
+    }
+
+    /// Close the connection to `addr`
+    pub fn disconnect(&self, addr: SocketAddr) -> bool {
+        self.remove(addr)
+    }
+
+    fn remove(&self, addr: SocketAddr) -> bool {
+        let Some(peer) = self.peers.lock().unwrap().remove(&addr) else { return false };
+        // Dropping the queue ends the writer; the notification ends the reader
+        peer.shutdown.notify_one();
+        let mut book = self.addrman.lock().unwrap();
+        if peer.direction == Direction::Outbound && peer.version.is_some() {
+            book.connected(&addr);
+        }
+        // Stand-in addresses stay registered only while the book can dial them
+        if book.get(&addr).is_none() {
+            proxy::forget_endpoint(&addr);
+        }
+        drop(book);
+        if let Some(privacy) = &self.privacy {
+            privacy.unregister_connection(&addr);
+        }
+        self.relay.lock().unwrap().forget_peer(addr);
+        self.partial_blocks.lock().unwrap().retain(|_, (from, _, _)| *from != addr);
+        self.dandelion.lock().unwrap().forget_peer(addr);
+        true
+    }
+
+    /// Add `points` to the misbehavior score of `addr`, banning it once the
+    /// score reaches the threshold
+    pub fn misbehaving(&self, addr: SocketAddr, points: u32, reason: &str) {
+        let score = match self.peers.lock().unwrap().get_mut(&addr) {
+            Some(peer) => {
+                peer.score = peer.score.saturating_add(points);
+                peer.score
+            }
+            None => return,
+        };
+        println!("[P2P] {} misbehaving (+{}: {}), score {}", addr, points, reason, score);
+        if score < self.config.ban_threshold {
+            return;
+        }
+        if !self.bans_by_ip(&addr.ip()) {
+            println!("[P2P] Disconnecting {}: {}", addr, reason);
+            self.remove(addr);
+        } else if let Err(e) = self.ban(addr.ip(), self.config.ban_duration, reason) {
+            println!("[P2P] Failed to save the ban list: {}", e);
+        }
+    }
+
+    /// Whether `ip` stands for one peer. Onion peers handed over by the
+    /// local Tor daemon all come from loopback, so they are only ever
+    /// disconnected one connection at a time.
+    fn bans_by_ip(&self, ip: &IpAddr) -> bool {
+        !self.privacy.as_ref().is_some_and(|privacy| privacy.is_tor_loopback(ip))
+    }
+
+    /// Ban `ip` for `duration` and disconnect every peer using it. The ban
+    /// applies even if saving the ban list fails.
+    pub fn ban(&self, ip: IpAddr, duration: Duration, reason: &str) -> std::io::Result<BanEntry> {
+        if !self.bans_by_ip(&ip) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is shared by every onion peer and cannot be banned", ip),
+            ));
+        }
+        let result = self.bans.lock().unwrap().ban(ip, duration, reason);
+        println!("[P2P] Banned {} for {}s: {}", ip, duration.as_secs(), reason);
+        if let Some(privacy) = &self.privacy {
+            privacy.ban_ip(ip);
+        }
+        let victims: Vec<SocketAddr> = self.peers.lock().unwrap().keys().filter(|a| a.ip() == ip).copied().collect();
+        for addr in victims {
+            self.remove(addr);
+        }
+        result
+    }
+
+    pub fn unban(&self, ip: &IpAddr) -> std::io::Result<bool> {
+        if let Some(privacy) = &self.privacy {
+            privacy.unban_ip(ip);
+        }
+        self.bans.lock().unwrap().unban(ip)
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.bans_by_ip(ip) && self.bans.lock().unwrap().is_banned(ip)
+    }
+
+    pub fn banned(&self) -> Vec<BanEntry> {
+        self.bans.lock().unwrap().list()
+    }
+
+    /// Measure the round-trip time to `addr` with a `Ping`
+    pub fn ping(&self, addr: SocketAddr, timeout: Duration) -> Result<Duration, PeerError> {
+        let (answer, answered) = std::sync::mpsc::channel();
+        match self.peers.lock().unwrap().get_mut(&addr) {
+            Some(peer) if peer.version.is_some() => peer.ping = Some((Instant::now(), answer)),
+            _ => return Err(PeerError::NotConnected(addr)),
+        }
+        if !self.send(addr, P2PMessage::Ping) {
+            return Err(PeerError::NotConnected(addr));
+        }
+        answered.recv_timeout(timeout).map_err(|_| PeerError::PingTimeout(addr))
+    }
+
+    /// `Pong` from `addr`: completes an outstanding ping
+    pub fn pong(&self, addr: SocketAddr) {
+        if let Some(peer) = self.peers.lock().unwrap().get_mut(&addr) {
+            if let Some((sent, answer)) = peer.ping.take() {
+                let rtt = sent.elapsed();
+                peer.last_rtt = Some(rtt);
+                let _ = answer.send(rtt);
+            }
+        }
+    }
+
+    fn tx_delay(direction: Direction) -> Duration {
+        match direction {
+            Direction::Inbound => relay::INBOUND_TX_DELAY,
+            Direction::Outbound => relay::OUTBOUND_TX_DELAY,
+        }
+    }
+
+    /// Announce a block we accepted to every peer that does not know it yet.
+    /// Blocks are announced at once; propagation delay matters for mining.
+    /// Peers that take compact blocks get one with the transactions they are
+    /// not known to have prefilled, together with the first transaction,
+    /// which no mempool holds.
+    pub fn announce_block(&self, block: &primitives::Block) {
+        let item = InvItem::block(block.hash());
+        self.relay.lock().unwrap().arrived(item);
+        let nonce: u64 = rand::random();
+        let mut announcements = Vec::new();
+        for (addr, peer) in self.peers.lock().unwrap().iter_mut() {
+            let Some(version) = &peer.version else { continue };
+            if !peer.known.insert(item) {
+                continue;
+            }
+            let msg = if version.version >= COMPACT_BLOCKS_VERSION {
+                let known = &peer.known;
+                P2PMessage::CompactBlock(CompactBlock::new(block, nonce, |index, tx| {
+                    index == 0 || !known.contains(&InvItem::transaction(tx.id()))
+                }))
+            } else {
+                P2PMessage::Inv(vec![item])
+            };
+            announcements.push((*addr, msg));
+        }
+        for (addr, msg) in announcements {
+            self.send(addr, msg);
+        }
+    }
+
+    /// Keep a compact block from `peer` until its missing transactions
+    /// arrive; returns `false` if too many are already waiting
+    pub fn await_block_transactions(&self, peer: SocketAddr, partial: PartialBlock) -> bool {
+        let mut partial_blocks = self.partial_blocks.lock().unwrap();
+        if partial_blocks.len() >= MAX_PARTIAL_BLOCKS {
+            return false;
+        }
+        partial_blocks.insert(partial.hash(), (peer, Instant::now(), partial));
+        true
+    }
+
+    /// The compact block `hash` that was waiting on a `BlockTxn` from `peer`
+    pub fn take_partial_block(&self, peer: SocketAddr, hash: &primitives::types::Hash) -> Option<PartialBlock> {
+        let mut partial_blocks = self.partial_blocks.lock().unwrap();
+        match partial_blocks.get(hash) {
+            Some((from, _, _)) if *from == peer => partial_blocks.remove(hash).map(|(_, _, partial)| partial),
+            _ => None,
+        }
+    }
+
+    /// Queue a transaction we accepted for the next batch announcement to
+    /// every peer that does not know it yet
+    pub fn relay_transaction(&self, txid: primitives::types::Hash) {
+        let item = InvItem::transaction(txid);
+        self.relay.lock().unwrap().arrived(item);
+        self.dandelion.lock().unwrap().fluffed(&txid);
+        for peer in self.peers.lock().unwrap().values_mut() {
+            if peer.version.is_some() && peer.known.insert(item) {
+                peer.tx_queue.push(txid);
+            }
+        }
+    }
+
+    fn dandelion_enabled(&self) -> bool {
+        self.privacy.as_ref().is_none_or(|privacy| privacy.dandelion_enabled())
+    }
+
+    /// Pass a transaction on along the Dandelion++ stem: our own when `from`
+    /// is `None`, otherwise one `from` stemmed to us. Returns it back if it
+    /// should be fluffed instead, `None` if it went on or is already held.
+    pub fn stem_transaction(&self, from: Option<SocketAddr>, tx: primitives::Transaction) -> Option<primitives::Transaction> {
+        if !self.dandelion_enabled() {
+            return Some(tx);
+        }
+        let txid = tx.id();
+        if let Some(from) = from {
+            // Once fluffed, it is not announced back to the peer it came from
+            if let Some(peer) = self.peers.lock().unwrap().get_mut(&from) {
+                peer.known.insert(InvItem::transaction(txid));
+            }
+        }
+        let relay = {
+            let mut dandelion = self.dandelion.lock().unwrap();
+            if dandelion.contains(&txid) {
+                return None;
+            }
+            if dandelion.len() >= dandelion::MAX_STEMPOOL {
+                return Some(tx);
+            }
+            let Some(relay) = dandelion.route(from) else { return Some(tx) };
+            if !dandelion.stem(tx.clone(), Instant::now()) {
+                // Spends the same key image as a transaction on the stem
+                return None;
+            }
+            relay
+        };
+        println!("[Dandelion] Stemming transaction {} to {}", hex::encode(txid), relay);
+        self.send(relay, P2PMessage::StemTransaction(tx));
+        None
+    }
+
+    /// Start a new Dandelion++ epoch when one is due and take the stem
+    /// transactions whose embargo ran out
+    fn dandelion_tick(&self, now: Instant) -> Vec<primitives::Transaction> {
+        if self.dandelion.lock().unwrap().epoch_due(now) {
+            let candidates: Vec<SocketAddr> = self
+                .peers
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, p)| p.direction == Direction::Outbound)
+                .filter(|(_, p)| p.version.as_ref().is_some_and(|v| v.version >= DANDELION_VERSION))
+                .map(|(addr, _)| *addr)
+                .collect();
+            let mut dandelion = self.dandelion.lock().unwrap();
+            dandelion.new_epoch(now, candidates);
+            if dandelion.is_fluffing() && !dandelion.relays().is_empty() {
+                println!("[Dandelion] New epoch: fluffing stem transactions");
+            } else if !dandelion.is_fluffing() {
+                println!("[Dandelion] New epoch: stem relays {:?}", dandelion.relays());
+            }
+        }
+        self.dandelion.lock().unwrap().expired(now)
+    }
+
+    /// Send the queued transaction announcements of every peer whose batch
+    /// delay has run out at `now`
+    pub fn flush_transactions(&self, now: Instant) {
+        let mut batches = Vec::new();
+        for (addr, peer) in self.peers.lock().unwrap().iter_mut() {
+            if peer.tx_queue.is_empty() || peer.next_tx_batch > now {
+                continue;
+            }
+            let count = peer.tx_queue.len().min(relay::MAX_TX_PER_INV);
+            let items: Vec<InvItem> = peer.tx_queue.drain(..count).map(InvItem::transaction).collect();
+            peer.next_tx_batch = now + relay::trickle_delay(Self::tx_delay(peer.direction));
+            batches.push((*addr, items));
+        }
+        for (addr, items) in batches {
+            self.send(addr, P2PMessage::Inv(items));
+        }
+    }
+
+    /// `Inv` from `peer`: returns the items to fetch with `GetData`, leaving
+    /// out what `have` reports as present and what is seen or already asked for
+    pub fn inventory_received(&self, peer: SocketAddr, items: Vec<InvItem>, have: impl Fn(&InvItem) -> bool) -> Vec<InvItem> {
+        if !self.inventory_size_ok(peer, items.len()) {
+            return Vec::new();
+        }
+        if let Some(known) = self.peers.lock().unwrap().get_mut(&peer).map(|p| &mut p.known) {
+            for item in &items {
+                known.insert(*item);
+            }
+        }
+        let unknown: Vec<InvItem> = items.into_iter().filter(|item| !have(item)).collect();
+        let now = Instant::now();
+        let mut relay = self.relay.lock().unwrap();
+        unknown.into_iter().filter(|item| relay.want(*item, peer, now)).collect()
+    }
+
+    /// A block or transaction arrived from `peer`; returns `false` if it was
+    /// seen before and needs no processing
+    pub fn inventory_arrived(&self, peer: SocketAddr, item: InvItem) -> bool {
+        if let Some(p) = self.peers.lock().unwrap().get_mut(&peer) {
+            p.known.insert(item);
+        }
+        self.relay.lock().unwrap().arrived(item)
+    }
+
+    /// `item` from `peer` could not be used (invalid, or its parent is
+    /// unknown); it is not marked as seen and another announcer may be asked
+    pub fn inventory_rejected(&self, peer: SocketAddr, item: InvItem) {
+        self.relay.lock().unwrap().not_found(&item, peer);
+    }
+
+    /// `NotFound` from `peer`: another announcer may be asked instead
+    pub fn inventory_not_found(&self, peer: SocketAddr, items: &[InvItem]) {
+        if !self.inventory_size_ok(peer, items.len()) {
+            return;
+        }
+        let mut relay = self.relay.lock().unwrap();
+        for item in items {
+            relay.not_found(item, peer);
+        }
+    }
+
+    /// Whether an inventory list of `len` items is within limits; penalizes
+    /// `peer` if not
+    pub fn inventory_size_ok(&self, peer: SocketAddr, len: usize) -> bool {
+        if len > relay::MAX_INV_PER_MESSAGE {
+            self.misbehaving(peer, penalty::OVERSIZED_INV, &format!("{} inventory items", len));
+            return false;
+        }
+        true
+    }
+
+    /// Remember `addr` as a candidate for outbound connections
+    pub fn add_address(&self, addr: SocketAddr) {
+        self.addrman.lock().unwrap().add(addr, 0, unix_now(), addr.ip());
+    }
+
+    pub fn address_count(&self) -> usize {
+        self.addrman.lock().unwrap().len()
+    }
+
+    fn note_attempt(&self, addr: SocketAddr) {
+        self.addrman.lock().unwrap().attempt(&addr);
+    }
+
+    /// Ask `peer` for addresses; its reply is let past the gossip rate limit
+    pub fn request_addresses(&self, peer: SocketAddr) {
+        if let Some(p) = self.peers.lock().unwrap().get_mut(&peer) {
+            p.addr_tokens += MAX_ADDR_PER_MESSAGE as f64;
+        }
+        self.send(peer, P2PMessage::GetAddr);
+    }
+
+    /// Addresses for a `GetAddr` from `peer`, our onion service first; each
+    /// connection gets one answer
+    pub fn answer_get_addr(&self, peer: SocketAddr) -> Option<Vec<NetAddress>> {
+        {
+            let mut peers = self.peers.lock().unwrap();
+            let p = peers.get_mut(&peer)?;
+            if p.sent_addr {
+                return None;
+            }
+            p.sent_addr = true;
+        }
+        let mut addresses: Vec<NetAddress> = self
+            .onion_address()
+            .map(|endpoint| NetAddress { addr: endpoint.to_string(), services: 0, last_seen: unix_now() })
+            .into_iter()
+            .collect();
+        addresses.extend(self.addrman.lock().unwrap().sample(MAX_ADDR_PER_MESSAGE - addresses.len()));
+        Some(addresses)
+    }
+
+    /// Advertise `endpoint` as our onion service from now on
+    pub fn set_onion_address(&self, endpoint: Option<Endpoint>) {
+        *self.onion.lock().unwrap() = endpoint;
+    }
+
+    pub fn onion_address(&self) -> Option<Endpoint> {
+        self.onion.lock().unwrap().clone()
+    }
+
+    /// Publish our onion service, keyed by the onion key in `data_dir`,
+    /// through the Tor control port at `control` and advertise it to peers.
+    /// Must not be called from the manager's runtime.
+    pub fn publish_onion_service(
+        &self,
+        data_dir: &Path,
+        control: SocketAddr,
+        port: u16,
+        target: SocketAddr,
+    ) -> Result<Endpoint, OnionError> {
+        let endpoint = self.runtime.block_on(privacy::setup_tor_hidden_service(data_dir, control, port, target))?;
+        self.set_onion_address(Some(endpoint.clone()));
+        Ok(endpoint)
+    }
+
+    /// Add gossiped addresses from `peer` to the address book. Unsolicited
+    /// addresses are rate limited per peer; the excess is dropped. Returns
+    /// the number of addresses that were new.
+    pub fn learn_addresses(&self, peer: SocketAddr, addresses: Vec<NetAddress>) -> usize {
+        if addresses.len() > MAX_ADDR_PER_MESSAGE {
+            self.misbehaving(peer, penalty::OVERSIZED_ADDR, "oversized addr message");
+            return 0;
+        }
+        let allowed = match self.peers.lock().unwrap().get_mut(&peer) {
+            Some(p) => {
+                let now = Instant::now();
+                let refill = now.duration_since(p.addr_tokens_at).as_secs_f64() * ADDR_TOKENS_PER_SEC;
+                p.addr_tokens_at = now;
+                // Refilling never goes past one full message, but keeps a larger grant
+                p.addr_tokens = (p.addr_tokens + refill).min(MAX_ADDR_PER_MESSAGE as f64).max(p.addr_tokens);
+                let allowed = (p.addr_tokens as usize).min(addresses.len());
+                p.addr_tokens -= allowed as f64;
+                allowed
+            }
+            None => return 0,
+        };
+        let now = unix_now();
+        let mut added = 0;
+        let mut book = self.addrman.lock().unwrap();
+        for entry in addresses.iter().take(allowed) {
+            let Ok(endpoint) = Endpoint::parse(&entry.addr) else { continue };
+            let addr = endpoint.peer_addr();
+            // Implausible timestamps are replaced with an old one, like Bitcoin does
+            let last_seen = if entry.last_seen == 0 || entry.last_seen > now + 10 * 60 {
+                now.saturating_sub(5 * 24 * 60 * 60)
+            } else {
+                entry.last_seen
+            };
+            if book.add(addr, entry.services, last_seen, peer.ip()) {
+                added += 1;
+            }
+        }
+        drop(book);
+        if added > 0 || allowed < addresses.len() {
+            println!("[P2P] Learned {} new address(es) from {} ({} over the rate limit)",
+                added, peer, addresses.len() - allowed);
+        }
+        added
+    }
+
+    /// Resolve the seeds into the address book; returns how many were new
+    async fn query_seeds(self: Arc<Self>) -> usize {
+        *self.last_seed_query.lock().unwrap() = Some(Instant::now());
+        let mut added = 0;
+        for seed in &self.config.seeds {
+            match tokio::net::lookup_host(seed.as_str()).await {
+                Ok(resolved) => {
+                    let mut book = self.addrman.lock().unwrap();
+                    for addr in resolved {
+                        if book.add(addr, 0, unix_now(), addr.ip()) {
+                            added += 1;
+                        }
+                    }
+                }
+                Err(e) => println!("[P2P] Could not resolve seed {}: {}", seed, e),
+            }
+        }
+        if added > 0 {
+            println!("[P2P] {} new address(es) from {} seed(s)", added, self.config.seeds.len());
+        }
+        added
+    }
+
+    /// Active discovery: look up the seeds again and ask every connected
+    /// peer for its addresses. Must not be called from the manager's runtime.
+    pub fn discover(self: &Arc<Self>) -> DiscoveryReport {
+        let from_seeds = self.runtime.block_on(self.clone().query_seeds());
+        let peers = self.ready_peers();
+        for peer in &peers {
+            self.request_addresses(*peer);
+        }
+        DiscoveryReport { from_seeds, peers_queried: peers.len(), known_addresses: self.address_count() }
+    }
+
+    /// Addresses worth dialing now, chosen by the address book
+    fn dial_candidates(&self, max: usize) -> Vec<SocketAddr> {
+        let mut exclude: HashSet<SocketAddr> = self.peers.lock().unwrap().keys().copied().collect();
+        exclude.extend(self.onion_address().map(|endpoint| endpoint.peer_addr()));
+        let book = self.addrman.lock().unwrap();
+        let mut picked = Vec::new();
+        while picked.len() < max {
+            let Some(addr) = book.select(&exclude) else { break };
+            exclude.insert(addr);
+            if !self.is_banned(&addr.ip()) && self.can_reach(&addr) {
+                picked.push(addr);
+            }
+        }
+        picked
+    }
+
+    /// Whether `addr` is on a network we have a way to dial: onion peers
+    /// need the Tor proxy, I2P peers I2P support
+    fn can_reach(&self, addr: &SocketAddr) -> bool {
+        let config = self.privacy.as_ref().map(|privacy| privacy.config());
+        match proxy::overlay_network(addr) {
+            Some(ConnectionType::Tor) => config.is_some_and(|config| config.tor_proxy.is_some()),
+            Some(ConnectionType::I2P) => config.is_some_and(|config| config.i2p_enabled),
+            _ => true,
+        }
+    }
+
+    /// Dial known addresses until `min_outbound` connections are open or pending
+    fn fill_outbound(self: &Arc<Self>) {
+        let missing = self.config.min_outbound.saturating_sub(self.outbound_count());
+        if missing == 0 {
+            return;
+        }
+        let candidates = self.dial_candidates(missing);
+        if candidates.is_empty() && !self.config.seeds.is_empty() {
+            let due = self.last_seed_query.lock().unwrap().is_none_or(|at| at.elapsed() >= SEED_QUERY_INTERVAL);
+            if due {
+                self.runtime.spawn(self.clone().query_seeds());
+            }
+        }
+        for addr in candidates {
+            if let Err(e) = self.dial(addr, false) {
+                println!("[P2P] Not dialing {}: {}", addr, e);
+            }
+        }
+    }
+
+    fn expire_bans(&self) {
+        let expired = self.bans.lock().unwrap().expire();
+        for ip in expired {
+            println!("[P2P] Ban on {} expired", ip);
+            if let Some(privacy) = &self.privacy {
+                privacy.unban_ip(&ip);
+            }
+        }
+    }
+
+    async fn maintain(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        let mut ticks: u64 = 0;
+        loop {
+            interval.tick().await;
+            // Sync housekeeping takes the chain lock
+            let _ = self.dispatch(crate::sync::tick).await;
+            self.flush_transactions(Instant::now());
+            for tx in self.dandelion_tick(Instant::now()) {
+                println!("[Dandelion] Embargo on {} ran out, fluffing it", hex::encode(tx.id()));
+                let _ = self.dispatch(move |manager| crate::fluff_transaction(manager, tx)).await;
+            }
+            self.relay.lock().unwrap().expire(Instant::now());
+            self.partial_blocks
+                .lock()
+                .unwrap()
+                .retain(|_, (_, since, _)| since.elapsed() < relay::GETDATA_TIMEOUT);
+            if ticks.is_multiple_of(DIAL_INTERVAL_SECS) {
+                self.expire_bans();
+                self.fill_outbound();
+            }
+            if ticks.is_multiple_of(SAVE_INTERVAL_SECS) {
+                if let Err(e) = self.addrman.lock().unwrap().save() {
+                    println!("[P2P] Failed to save the address book: {}", e);
+                }
+            }
+            ticks += 1;
+        }
+    }
+}
+
+/// Time allowed for the connection to `addr` to be established
+pub fn connect_timeout(addr: &SocketAddr) -> Duration {
+    match proxy::overlay_network(addr) {
+        Some(_) => PROXY_CONNECT_TIMEOUT,
+        None => CONNECT_TIMEOUT,
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Drain the outbound queue of one peer onto its socket
+async fn write_loop(mut writer: OwnedWriteHalf, mut frames: FrameWriter, mut queue: mpsc::Receiver<P2PMessage>, magic: u32) {
+    while let Some(msg) = queue.recv().await {
+        if frames.write(&mut writer, magic, &msg).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::wire::{read_frame, write_frame, PROTOCOL_VERSION};
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    fn local_manager(config: PeerConfig) -> (Arc<PeerManager>, SocketAddr) {
+        let manager = PeerManager::spawn(config, None).unwrap();
+        let addr = manager.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+        (manager, addr)
+    }
+
+    /// Connect and complete the handshake as a remote plaintext peer would;
+    /// the dialing side speaks first
+    fn handshake(addr: SocketAddr) -> TcpStream {
+        let magic = crate::current_network().get_magic();
+        let mut peer = TcpStream::connect(addr).unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let version = P2PMessage::Version { version: PROTOCOL_VERSION, best_height: 0, services: 0, node: "test".to_string(), onion: None };
+        write_frame(&mut peer, magic, &version).unwrap();
+        assert!(matches!(read_frame(&mut peer, magic).unwrap(), P2PMessage::Version { .. }));
+        assert!(matches!(read_frame(&mut peer, magic).unwrap(), P2PMessage::Verack));
+        write_frame(&mut peer, magic, &P2PMessage::Verack).unwrap();
+        peer
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition not reached in time");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn assert_closed(stream: &mut TcpStream) {
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut rest = Vec::new();
+        // Queued frames may still arrive before the close; a reset is fine too
+        if let Err(e) = stream.read_to_end(&mut rest) {
+            assert!(!matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut), "connection left open");
+        }
+    }
+
+    #[test]
+    fn test_inbound_limit_is_enforced() {
+        let (manager, addr) = local_manager(PeerConfig { max_inbound: 1, min_outbound: 0, ..PeerConfig::default() });
+        let _first = handshake(addr);
+        wait_for(|| manager.peer_count() == 1);
+
+        let mut second = TcpStream::connect(addr).unwrap();
+        let mut buf = Vec::new();
+        second.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(second.read_to_end(&mut buf).unwrap(), 0);
+        assert_eq!(manager.peer_count(), 1);
+    }
+
+    #[test]
+    fn test_misbehaving_peer_is_banned_and_disconnected() {
+        let (manager, addr) = local_manager(PeerConfig { min_outbound: 0, ..PeerConfig::default() });
+        let mut peer = handshake(addr);
+        wait_for(|| manager.peer_count() == 1);
+        let remote = manager.ready_peers()[0];
+
+        manager.misbehaving(remote, penalty::INVALID_BLOCK, "invalid block");
+        assert_eq!(manager.peers()[0].score, penalty::INVALID_BLOCK);
+        assert!(!manager.is_banned(&remote.ip()));
+
+        manager.misbehaving(remote, penalty::PROTOCOL_VIOLATION, "repeated handshake");
+        assert!(manager.is_banned(&remote.ip()));
+        assert_closed(&mut peer);
+        assert_eq!(manager.peer_count(), 0);
+
+        // Banned addresses cannot come back until unbanned
+        let mut again = TcpStream::connect(addr).unwrap();
+        let mut buf = Vec::new();
+        again.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(again.read_to_end(&mut buf).unwrap(), 0);
+        assert!(manager.unban(&remote.ip()).unwrap());
+        let _peer = handshake(addr);
+        wait_for(|| manager.peer_count() == 1);
+    }
+
+    #[test]
+    fn test_ping_measures_round_trip() {
+        let (manager, addr) = local_manager(PeerConfig { min_outbound: 0, ..PeerConfig::default() });
+        let mut peer = handshake(addr);
+        wait_for(|| manager.peer_count() == 1);
+        let remote = manager.ready_peers()[0];
+
+        let pinger = {
+            let manager = manager.clone();
+            std::thread::spawn(move || manager.ping(remote, Duration::from_secs(5)))
+        };
+        let magic = crate::current_network().get_magic();
+        while !matches!(read_frame(&mut peer, magic).unwrap(), P2PMessage::Ping) {}
+        write_frame(&mut peer, magic, &P2PMessage::Pong).unwrap();
+        let rtt = pinger.join().unwrap().unwrap();
+        assert_eq!(manager.peers()[0].ping_ms, Some(rtt.as_millis() as u64));
+
+        let unknown: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        assert!(matches!(manager.ping(unknown, Duration::from_secs(1)), Err(PeerError::NotConnected(_))));
+    }
+
+    #[test]
+    fn test_transactions_are_announced_in_batches_but_not_to_the_sender() {
+        let (manager, addr) = local_manager(PeerConfig { min_outbound: 0, ..PeerConfig::default() });
+        let mut sender = handshake(addr);
+        wait_for(|| manager.peer_count() == 1);
+        let sender_addr = manager.ready_peers()[0];
+        let mut other = handshake(addr);
+        wait_for(|| manager.peer_count() == 2);
+        let magic = crate::current_network().get_magic();
+
+        // The sender announces a transaction: it is fetched from it once
+        let tx = InvItem::transaction([9; 32]);
+        assert_eq!(manager.inventory_received(sender_addr, vec![tx], |_| false), vec![tx]);
+        assert!(manager.inventory_arrived(sender_addr, tx));
+        assert!(!manager.inventory_arrived(sender_addr, tx));
+
+        // A block that failed validation is not remembered as seen, so the
+        // next announcer is asked for it
+        let block = InvItem::block([11; 32]);
+        let other_addr = *manager.ready_peers().iter().find(|p| **p != sender_addr).unwrap();
+        assert_eq!(manager.inventory_received(sender_addr, vec![block], |_| false), vec![block]);
+        assert!(manager.inventory_received(other_addr, vec![block], |_| false).is_empty());
+        manager.inventory_rejected(sender_addr, block);
+        assert_eq!(manager.inventory_received(other_addr, vec![block], |_| false), vec![block]);
+
+        manager.relay_transaction(tx.hash);
+        manager.relay_transaction([10; 32]);
+        manager.flush_transactions(Instant::now() + Duration::from_secs(3600));
+        // The maintenance loop may flush in between; collect until both arrive
+        let mut announced = Vec::new();
+        while announced.len() < 2 {
+            if let P2PMessage::Inv(items) = read_frame(&mut other, magic).unwrap() {
+                announced.extend(items);
+            }
+        }
+        assert_eq!(announced, vec![tx, InvItem::transaction([10; 32])]);
+
+        // The sender only hears about the transaction it did not send
+        manager.flush_transactions(Instant::now() + Duration::from_secs(3600));
+        let pinger = {
+            let manager = manager.clone();
+            std::thread::spawn(move || manager.ping(sender_addr, Duration::from_secs(5)))
+        };
+        let mut invs = Vec::new();
+        loop {
+            match read_frame(&mut sender, magic).unwrap() {
+                P2PMessage::Inv(items) => invs.extend(items),
+                P2PMessage::Ping => break,
+                _ => {}
+            }
+        }
+        write_frame(&mut sender, magic, &P2PMessage::Pong).unwrap();
+        pinger.join().unwrap().unwrap();
+        assert_eq!(invs, vec![InvItem::transaction([10; 32])]);
+    }
+
+    #[test]
+    fn test_blocks_are_announced_compact_with_unknown_transactions_prefilled() {
+        let (manager, addr) = local_manager(PeerConfig { min_outbound: 0, ..PeerConfig::default() });
+        let mut peer = handshake(addr);
+        wait_for(|| manager.peer_count() == 1);
+        let remote = manager.ready_peers()[0];
+        let magic = crate::current_network().get_magic();
+
+        let mut block = crate::Chain::new_for_network(crate::Network::Testnet).tip().clone();
+        block.transactions = (0..3).map(|i| crate::coinbase_transaction(i, "relay")).collect();
+        manager.inventory_arrived(remote, InvItem::transaction(block.transactions[1].id()));
+        manager.announce_block(&block);
+        let compact = loop {
+            if let P2PMessage::CompactBlock(compact) = read_frame(&mut peer, magic).unwrap() {
+                break compact;
+            }
+        };
+        assert_eq!(compact.hash(), block.hash());
+        assert_eq!(compact.prefilled.iter().map(|p| p.index).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(compact.short_ids.len(), 1);
+        let rebuilt = compact.reconstruct(block.transactions[1..2].iter().map(|tx| (tx.id(), tx))).unwrap();
+        assert!(rebuilt.missing().is_empty());
+    }
+
+    #[test]
+    fn test_compact_block_with_invalid_header_is_not_rebuilt() {
+        let (manager, addr) = local_manager(PeerConfig { min_outbound: 0, ..PeerConfig::default() });
+        let mut peer = handshake(addr);
+        wait_for(|| manager.peer_count() == 1);
+        let magic = crate::current_network().get_magic();
+
+        // A block on the current tip that claims the wrong difficulty
+        let mut block = crate::CHAIN.lock().unwrap().tip().clone();
+        block.header.prev_hash = block.hash();
+        block.header.height += 1;
+        block.header.difficulty += 1;
+        block.header.pow.hash = [0xBD; 32];
+        block.transactions = (0..2).map(|i| crate::coinbase_transaction(i, "bad header")).collect();
+        let compact = CompactBlock::new(&block, 7, |index, _| index == 0);
+        write_frame(&mut peer, magic, &P2PMessage::CompactBlock(compact)).unwrap();
+
+        wait_for(|| manager.peers().first().map_or(false, |p| p.score == penalty::INVALID_BLOCK));
+        let pinger = {
+            let manager = manager.clone();
+            let remote = manager.ready_peers()[0];
+            std::thread::spawn(move || manager.ping(remote, Duration::from_secs(5)))
+        };
+        loop {
+            match read_frame(&mut peer, magic).unwrap() {
+                P2PMessage::GetBlockTxn { .. } => panic!("transactions requested for an invalid header"),
+                P2PMessage::Ping => break,
+                _ => {}
+            }
+        }
+        write_frame(&mut peer, magic, &P2PMessage::Pong).unwrap();
+        pinger.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_unsolicited_addresses_are_rate_limited() {
+        let (manager, addr) = local_manager(PeerConfig { min_outbound: 0, ..PeerConfig::default() });
+        let _peer = handshake(addr);
+        wait_for(|| manager.peer_count() == 1);
+        let remote = manager.ready_peers()[0];
+        let gossip = |count: usize| -> Vec<NetAddress> {
+            (0..count)
+                .map(|i| NetAddress { addr: format!("10.1.{}.1:1776", i), services: 1, last_seen: unix_now() })
+                .collect()
+        };
+
+        // Unasked, only a trickle gets through
+        assert_eq!(manager.learn_addresses(remote, gossip(20)), 1);
+        assert_eq!(manager.address_count(), 1);
+        // The answer to our own `GetAddr` is taken in full
+        manager.request_addresses(remote);
+        assert_eq!(manager.learn_addresses(remote, gossip(20)), 19);
+        assert_eq!(manager.address_count(), 20);
+
+        manager.learn_addresses(remote, gossip(MAX_ADDR_PER_MESSAGE + 1));
+        assert_eq!(manager.peers()[0].score, penalty::OVERSIZED_ADDR);
+        assert_eq!(manager.address_count(), 20);
+    }
+
+    #[test]
+    fn test_outbound_connections_are_kept_at_minimum() {
+        let (_remote, remote_addr) = local_manager(PeerConfig { min_outbound: 0, ..PeerConfig::default() });
+        let manager = PeerManager::spawn(PeerConfig { max_outbound: 1, min_outbound: 1, ..PeerConfig::default() }, None).unwrap();
+        manager.add_address(remote_addr);
+        wait_for(|| manager.peer_count() == 1);
+        assert_eq!(manager.peers()[0].direction, Direction::Outbound);
+        assert!(manager.peers()[0].encrypted);
+
+        // The outbound slot is taken, only manual connections may exceed it
+        assert!(matches!(manager.dial(remote_addr, false), Err(PeerError::AlreadyConnected(_))));
+        let other: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        assert!(matches!(manager.dial(other, false), Err(PeerError::OutboundLimit)));
+        assert!(manager.connect(other).is_ok());
+    }
+
+    #[test]
+    fn test_pinned_peer_must_prove_its_identity() {
+        let identity = Identity::generate();
+        let key = identity.public_key();
+        let (_remote, remote_addr) = local_manager(PeerConfig { min_outbound: 0, identity: Some(identity), ..PeerConfig::default() });
+        let privacy = Arc::new(PrivacyManager::new(Default::default()));
+        let manager = PeerManager::spawn(PeerConfig { min_outbound: 0, ..PeerConfig::default() }, Some(privacy.clone())).unwrap();
+
+        privacy.pin_identity(remote_addr.ip(), [0xAB; 32]);
+        manager.connect(remote_addr).unwrap();
+        // The link is dropped before the handshake
+        wait_for(|| manager.outbound_count() == 0);
+        assert_eq!(manager.peer_count(), 0);
+
+        privacy.pin_identity(remote_addr.ip(), key);
+        manager.connect(remote_addr).unwrap();
+        wait_for(|| manager.peer_count() == 1);
+        assert_eq!(manager.peers()[0].identity, Some(hex::encode(key)));
+    }
+
+    #[test]
+    fn test_transactions_take_the_stem_before_being_fluffed() {
+        let magic = crate::current_network().get_magic();
+        let manager = PeerManager::spawn(
+            PeerConfig { min_outbound: 0, encryption: EncryptionPolicy::Disabled, ..PeerConfig::default() },
+            None,
+        )
+        .unwrap();
+        // An outbound peer to serve as the stem relay
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let relay = listener.local_addr().unwrap();
+        manager.connect(relay).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert!(matches!(read_frame(&mut peer, magic).unwrap(), P2PMessage::Version { .. }));
+        let version = P2PMessage::Version { version: PROTOCOL_VERSION, best_height: 0, services: 0, node: "test".to_string(), onion: None };
+        write_frame(&mut peer, magic, &version).unwrap();
+        write_frame(&mut peer, magic, &P2PMessage::Verack).unwrap();
+        wait_for(|| manager.peer_count() == 1);
+        while {
+            manager.dandelion.lock().unwrap().new_epoch(Instant::now(), vec![relay]);
+            manager.dandelion.lock().unwrap().is_fluffing()
+        } {}
+
+        let ours = crate::coinbase_transaction(1, "dandelion");
+        let forwarded = crate::coinbase_transaction(2, "dandelion");
+        assert!(manager.stem_transaction(None, ours.clone()).is_none());
+        let stranger: SocketAddr = "10.0.0.9:1776".parse().unwrap();
+        assert!(manager.stem_transaction(Some(stranger), forwarded.clone()).is_none());
+        // Held ones are not passed on twice, and nothing goes back to the relay
+        assert!(manager.stem_transaction(Some(stranger), forwarded.clone()).is_none());
+        let back = crate::coinbase_transaction(3, "dandelion");
+        assert_eq!(manager.stem_transaction(Some(relay), back.clone()).map(|tx| tx.id()), Some(back.id()));
+
+        let mut stemmed = Vec::new();
+        while stemmed.len() < 2 {
+            match read_frame(&mut peer, magic).unwrap() {
+                P2PMessage::StemTransaction(tx) => stemmed.push(tx.id()),
+                P2PMessage::Inv(items) => panic!("stem transaction announced: {:?}", items),
+                _ => {}
+            }
+        }
+        assert_eq!(stemmed, vec![ours.id(), forwarded.id()]);
+        assert_eq!(manager.dandelion.lock().unwrap().len(), 2);
+        // Fluffing, by us or anyone, takes it out of the stem pool
+        manager.relay_transaction(ours.id());
+        assert_eq!(manager.dandelion.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_onion_and_i2p_peers_are_dialed_through_their_proxies() {
+        use crate::network::privacy::PrivacyConfig;
+        use crate::network::proxy::tests::{mock_sam, mock_socks5};
+
+        let (_remote, remote_addr) = local_manager(PeerConfig { min_outbound: 0, ..PeerConfig::default() });
+        let (socks, requests) = mock_socks5(remote_addr);
+        let sam = mock_sam(remote_addr);
+        let privacy = Arc::new(PrivacyManager::new(PrivacyConfig {
+            tor_proxy: Some(socks.to_string()),
+            i2p_proxy: Some(sam.to_string()),
+            i2p_enabled: true,
+            ..PrivacyConfig::default()
+        }));
+        let manager = PeerManager::spawn(PeerConfig { min_outbound: 0, ..PeerConfig::default() }, Some(privacy.clone())).unwrap();
+
+        let onion = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+        let addr = manager.connect_endpoint(&Endpoint::parse(&format!("{}:1776", onion)).unwrap()).unwrap();
+        wait_for(|| manager.peer_count() == 1);
+        assert_eq!(requests.recv().unwrap(), (onion.to_string(), 1776));
+        assert_eq!(manager.peers()[0].endpoint, Some(format!("{}:1776", onion)));
+        assert!(manager.peers()[0].encrypted);
+
+        let i2p = Endpoint::parse("ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkdq.b32.i2p").unwrap();
+        manager.connect_endpoint(&i2p).unwrap();
+        wait_for(|| manager.peer_count() == 2);
+        let stats = privacy.get_stats();
+        assert_eq!((stats.tor_connections, stats.i2p_connections, stats.clearnet_connections), (1, 1, 0));
+
+        // The address book keeps them under their names
+        assert!(matches!(manager.connect(addr), Err(PeerError::AlreadyConnected(_))));
+        assert_eq!(manager.address_count(), 2);
+        assert_eq!(manager.addrman.lock().unwrap().get(&addr).unwrap().endpoint, Some(format!("{}:1776", onion)));
+
+        // Closed connections keep their names only while the book has them
+        manager.disconnect(addr);
+        assert!(proxy::overlay_endpoint(&addr).is_some());
+        let i2p_addr = i2p.peer_addr();
+        *manager.addrman.lock().unwrap() = AddrMan::new();
+        manager.disconnect(i2p_addr);
+        assert_eq!(proxy::overlay_endpoint(&i2p_addr), None);
+    }
+
+    #[test]
+    fn test_onion_peers_behind_loopback_are_disconnected_not_banned() {
+        let privacy = Arc::new(PrivacyManager::new(privacy::PrivacyConfig::default()));
+        let manager = PeerManager::spawn(PeerConfig { min_outbound: 0, ..PeerConfig::default() }, Some(privacy)).unwrap();
+        let addr = manager.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut bad = handshake(addr);
+        wait_for(|| manager.ready_peers().len() == 1);
+        let bad_addr = manager.ready_peers()[0];
+        let _good = handshake(addr);
+        wait_for(|| manager.ready_peers().len() == 2);
+
+        manager.misbehaving(bad_addr, BAN_THRESHOLD, "invalid block");
+        assert_closed(&mut bad);
+        assert_eq!(manager.peer_count(), 1);
+        assert!(!manager.is_banned(&bad_addr.ip()));
+        assert!(manager.ban(bad_addr.ip(), DEFAULT_BAN_DURATION, "test").is_err());
+        let _again = handshake(addr);
+        wait_for(|| manager.ready_peers().len() == 2);
+    }
+
+    #[test]
+    fn test_onion_service_is_advertised_to_peers() {
+        use crate::network::onion::tests::mock_control_port;
+
+        let dir = std::env::temp_dir().join(format!("onion_advert_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (control, _services) = mock_control_port();
+        let (remote, remote_addr) = local_manager(PeerConfig { min_outbound: 0, ..PeerConfig::default() });
+        let manager = PeerManager::spawn(PeerConfig { min_outbound: 0, ..PeerConfig::default() }, None).unwrap();
+        let target = "127.0.0.1:9334".parse().unwrap();
+        let endpoint = manager.publish_onion_service(&dir, control, 9334, target).unwrap();
+        // Same key, same address
+        assert_eq!(manager.publish_onion_service(&dir, control, 9334, target).unwrap(), endpoint);
+
+        manager.connect(remote_addr).unwrap();
+        wait_for(|| remote.peer_count() == 1 && manager.peer_count() == 1);
+        assert_eq!(remote.peers()[0].version.onion, Some(endpoint.to_string()));
+        assert!(remote.addrman.lock().unwrap().get(&endpoint.peer_addr()).is_some());
+        let answer = manager.answer_get_addr(remote_addr).unwrap();
+        assert_eq!(answer[0].addr, endpoint.to_string());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -4,13 +4,10 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::path::Path;
 use serde::{Serialize, Deserialize};
-use torut::control::{UnauthenticatedConn, TorAuthData};
-use torut::control::AsyncEvent;
-use torut::onion::{TorSecretKeyV3, TorPublicKeyV3};
-use tokio::net::TcpStream;
-use torut::control::ConnError;
-use std::future::Future;
+use super::onion::{self, OnionError};
+use super::proxy::Endpoint;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PrivacyConfig {
@@ -18,9 +15,12 @@ pub struct PrivacyConfig {
     pub i2p_enabled: bool,       // Enable I2P support
     pub clearnet_banned: bool,   // Ban all clearnet connections
     pub tor_proxy: Option<String>, // Tor SOCKS5 proxy address
+    pub tor_control: Option<String>, // Tor control port address
+    pub hidden_service: bool,      // Publish an onion service for inbound peers
     pub i2p_proxy: Option<String>, // I2P proxy address
     pub hidden_service_port: u16,  // Port for Tor hidden service
     pub privacy_mode: PrivacyMode, // Privacy enforcement level
+    pub dandelion: bool,           // Stem new transactions through Dandelion++
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -39,9 +39,12 @@ impl Default for PrivacyConfig {
             i2p_enabled: false,
             clearnet_banned: false,
             tor_proxy: Some("127.0.0.1:9050".to_string()),
+            tor_control: Some(onion::DEFAULT_CONTROL_ADDR.to_string()),
+            hidden_service: false,
             i2p_proxy: Some("127.0.0.1:4444".to_string()),
             hidden_service_port: 0, // Will be set by network config
             privacy_mode: PrivacyMode::Tor,
+            dandelion: true,
         }
     }
 }
@@ -68,6 +71,8 @@ pub struct PrivacyManager {
     config: PrivacyConfig,
     connections: Arc<Mutex<HashMap<SocketAddr, ConnectionInfo>>>,
     banned_ips: Arc<Mutex<HashSet<IpAddr>>>,
+    /// Identity keys trusted peers must prove on encrypted links
+    pinned_identities: Arc<Mutex<HashMap<IpAddr, [u8; 32]>>>,
 }
 
 impl PrivacyManager {
@@ -76,6 +81,7 @@ impl PrivacyManager {
             config,
             connections: Arc::new(Mutex::new(HashMap::new())),
             banned_ips: Arc::new(Mutex::new(HashSet::new())),
+            pinned_identities: Arc::new(Mutex::new(HashMap::new())),
         }
     }
     
@@ -136,27 +142,25 @@ impl PrivacyManager {
         }
     }
     
-    /// Detect connection type based on address patterns
+    /// Connection type of a peer address. Onion and I2P peers carry the
+    /// stand-in addresses they were dialed under (see `proxy`); loopback
+    /// connections are the local Tor daemon handing over hidden service
+    /// connections, and never leave the host anyway.
     pub fn detect_connection_type(&self, addr: &SocketAddr) -> ConnectionType {
-        let addr_str = addr.to_string();
-        
-        // Tor detection patterns
-        if self.is_tor_connection(&addr_str) {
-            return ConnectionType::Tor;
-        }
-        
-        // I2P detection patterns  
-        if self.is_i2p_connection(&addr_str) {
-            return ConnectionType::I2P;
+        if let Some(network) = super::proxy::overlay_network(addr) {
+            return network;
         }
-        
-        // Check if coming through known Tor exit nodes (simplified)
-        if self.is_likely_tor_exit(&addr.ip()) {
+        if self.is_tor_loopback(&addr.ip()) {
             return ConnectionType::Tor;
         }
-        
         ConnectionType::Clearnet
     }
+
+    /// Whether connections from `ip` are the local Tor daemon's, which all
+    /// onion peers share
+    pub fn is_tor_loopback(&self, ip: &IpAddr) -> bool {
+        ip.is_loopback() && self.config.tor_proxy.is_some()
+    }
     
     /// Register a new connection
     pub fn register_connection(&self, addr: SocketAddr, is_outbound: bool) {
@@ -173,6 +177,58 @@ impl PrivacyManager {
         }
     }
     
+    /// Refuse connections from `ip` until `unban_ip`
+    pub fn ban_ip(&self, ip: IpAddr) {
+        if let Ok(mut banned) = self.banned_ips.lock() {
+            banned.insert(ip);
+        }
+    }
+
+    pub fn unban_ip(&self, ip: &IpAddr) {
+        if let Ok(mut banned) = self.banned_ips.lock() {
+            banned.remove(ip);
+        }
+    }
+
+    pub fn config(&self) -> &PrivacyConfig {
+        &self.config
+    }
+
+    /// Whether new transactions take the Dandelion++ stem before being announced
+    pub fn dandelion_enabled(&self) -> bool {
+        self.config.dandelion
+    }
+
+    /// Only accept `ip` as a peer if it proves the identity `key`
+    pub fn pin_identity(&self, ip: IpAddr, key: [u8; 32]) {
+        if let Ok(mut pinned) = self.pinned_identities.lock() {
+            pinned.insert(ip, key);
+        }
+    }
+
+    pub fn unpin_identity(&self, ip: &IpAddr) {
+        if let Ok(mut pinned) = self.pinned_identities.lock() {
+            pinned.remove(ip);
+        }
+    }
+
+    /// Whether a peer that proved `identity` (if any) may stay connected;
+    /// peers without a pinned key are always accepted
+    pub fn identity_matches(&self, addr: &SocketAddr, identity: Option<&[u8; 32]>) -> bool {
+        let pinned = match self.pinned_identities.lock() {
+            Ok(pinned) => pinned.get(&addr.ip()).copied(),
+            Err(_) => return true,
+        };
+        match pinned {
+            None => true,
+            Some(key) if identity == Some(&key) => true,
+            Some(_) => {
+                println!("[Privacy] Rejected {}: identity key does not match the pinned one", addr);
+                false
+            }
+        }
+    }
+
     /// Remove connection
     pub fn unregister_connection(&self, addr: &SocketAddr) {
         if let Ok(mut connections) = self.connections.lock() {
@@ -205,30 +261,6 @@ impl PrivacyManager {
         stats.total_connections = connections.len();
         stats
     }
-    
-    fn is_tor_connection(&self, addr: &str) -> bool {
-        // Check for Tor hidden service addresses
-        addr.contains(".onion") || 
-        // Check for localhost connections through Tor proxy
-        (addr.starts_with("127.0.0.1") && self.config.tor_proxy.as_ref().map_or(false, |proxy| addr.contains(&proxy.split(':').nth(1).unwrap_or(""))))
-    }
-    
-    fn is_i2p_connection(&self, addr: &str) -> bool {
-        addr.contains(".i2p") ||
-        (addr.starts_with("127.0.0.1") && self.config.i2p_proxy.as_ref().map_or(false, |proxy| addr.contains(&proxy.split(':').nth(1).unwrap_or(""))))
-    }
-    
-    fn is_likely_tor_exit(&self, ip: &IpAddr) -> bool {
-        // Simplified Tor exit detection - in production this would query Tor consensus
-        match ip {
-            IpAddr::V4(ipv4) => {
-                let octets = ipv4.octets();
-                // Some known Tor exit ranges (simplified)
-                matches!(octets[0], 185 | 176 | 198) // Example Tor exit ranges
-            },
-            _ => false,
-        }
-    }
 }
 
 #[derive(Debug, Default)]
@@ -252,38 +284,22 @@ pub fn is_i2p_address(addr: &str) -> bool {
     addr.ends_with(".i2p")
 }
 
-/// Initialize Tor hidden service (stub - would use arti-client in production)
-pub async fn setup_tor_hidden_service(port: u16) -> Result<String, Box<dyn std::error::Error>> {
+/// Publish the node's Tor hidden service through the control port at
+/// `control`, forwarding onion port `port` to the P2P listener at `target`.
+/// The key is kept in `data_dir`, so the address stays the same across runs.
+pub async fn setup_tor_hidden_service(
+    data_dir: &Path,
+    control: SocketAddr,
+    port: u16,
+    target: SocketAddr,
+) -> Result<Endpoint, OnionError> {
     println!("[Privacy] Setting up Tor hidden service on port {}", port);
 
-    // Connect to the Tor control port
-    let stream = TcpStream::connect("127.0.0.1:9051").await?;
-    let mut unauth_conn = UnauthenticatedConn::new(stream);
-
-    // Authenticate using Null authentication method
-    unauth_conn.authenticate(&TorAuthData::Null).await?;
-
-    // Convert to an authenticated connection with a no-op handler
-    let mut auth_conn = unauth_conn.into_authenticated::<fn(AsyncEvent<'static>) -> std::pin::Pin<Box<dyn Future<Output = Result<(), ConnError>> + Send>>>().await;
-
-    // Generate a new Tor secret key
-    let secret_key = TorSecretKeyV3::generate();
-
-    // Create an ephemeral hidden service
-    auth_conn.add_onion_v3(
-        &secret_key,
-        false, // detach
-        false, // non-anonymous
-        false, // max_streams_close_circuit
-        None,  // max_num_streams
-        &mut [(port, "127.0.0.1:9050".parse()?)].iter(),
-    ).await?;
-
-    let public_key: TorPublicKeyV3 = secret_key.public();
-    let onion_addr = public_key.get_onion_address().to_string();
-    println!("[Privacy] Tor hidden service available at: {}", onion_addr);
+    let secret_key = onion::load_or_create_key(data_dir)?;
+    let host = onion::publish(control, &secret_key, port, target).await?;
+    println!("[Privacy] Tor hidden service available at: {}:{}", host, port);
 
-    Ok(onion_addr)
+    Ok(Endpoint::Onion { host, port })
 }
 
 /// Network status display with privacy info
@@ -0,0 +1,363 @@
+//! Dialing onion and I2P peers
+//!
+//! Tor hidden services are reached through the Tor daemon's SOCKS5 proxy and
+//! I2P destinations through a SAM bridge `STREAM CONNECT`; either way the
+//! result is a socket that carries the peer protocol like a direct TCP
+//! connection. The peer manager keys connections by `SocketAddr`, so each
+//! overlay endpoint gets a stand-in IPv6 address in the OnionCat (Tor) or
+//! GarliCat (I2P) range, derived from a hash of its name. Those ranges never
+//! route on the internet, which also tells `PrivacyManager` reliably how a
+//! peer is connected.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use primitives::encoding::sha256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::privacy::ConnectionType;
+
+/// Stand-in address prefix of onion peers (OnionCat)
+const ONION_PREFIX: [u8; 6] = [0xfd, 0x87, 0xd8, 0x7e, 0xeb, 0x43];
+
+/// Stand-in address prefix of I2P peers (GarliCat)
+const I2P_PREFIX: [u8; 6] = [0xfd, 0x60, 0xdb, 0x4d, 0xdd, 0xb5];
+
+/// Where a peer can be reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Clearnet(SocketAddr),
+    /// Tor hidden service
+    Onion { host: String, port: u16 },
+    /// I2P destination: a `.b32.i2p` or `.i2p` name; streams have no port
+    I2p { destination: String, port: u16 },
+}
+
+/// Overlay endpoints by their stand-in address
+static OVERLAY_ENDPOINTS: Lazy<Mutex<HashMap<SocketAddr, Endpoint>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn split_port(address: &str) -> Result<(&str, Option<u16>), String> {
+    match address.split_once(':') {
+        Some((host, port)) => {
+            let port = port.parse().map_err(|_| format!("invalid port in {}", address))?;
+            Ok((host, Some(port)))
+        }
+        None => Ok((address, None)),
+    }
+}
+
+/// Whether `name` (without `.i2p`) is a base32 destination hash or an
+/// address book hostname. The name goes verbatim into SAM commands, so
+/// nothing outside letters, digits, dots and inner hyphens gets through.
+fn valid_i2p_name(name: &str) -> bool {
+    if let Some(hash) = name.strip_suffix(".b32") {
+        // 52 characters for a plain hash, more for encrypted lease sets
+        return hash.len() >= 52 && hash.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7'));
+    }
+    // I2P hostnames are at most 67 characters including `.i2p`
+    name.len() <= 63
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| matches!(c, 'a'..='z' | '0'..='9' | '-'))
+        })
+}
+
+impl Endpoint {
+    /// Parse `ip:port`, `<name>.onion:port` or `<name>.i2p[:port]`
+    pub fn parse(address: &str) -> Result<Endpoint, String> {
+        if let Ok(addr) = address.parse() {
+            return Ok(Endpoint::Clearnet(addr));
+        }
+        let (host, port) = split_port(address)?;
+        let host = host.to_ascii_lowercase();
+        if let Some(name) = host.strip_suffix(".onion") {
+            // v3 names are 56 base32 characters, the retired v2 ones 16
+            let base32 = name.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7'));
+            if !base32 || !matches!(name.len(), 16 | 56) {
+                return Err(format!("invalid onion address {}", address));
+            }
+            let port = port.ok_or_else(|| format!("onion address {} needs a port", address))?;
+            return Ok(Endpoint::Onion { host, port });
+        }
+        if let Some(name) = host.strip_suffix(".i2p") {
+            if !valid_i2p_name(name) {
+                return Err(format!("invalid I2P address {}", address));
+            }
+            return Ok(Endpoint::I2p { destination: host, port: port.unwrap_or(0) });
+        }
+        Err(format!("invalid peer address {}", address))
+    }
+
+    /// Address the peer is known by in the peer manager; overlay endpoints
+    /// are registered so they can be dialed by it
+    pub fn peer_addr(&self) -> SocketAddr {
+        let (prefix, name, port) = match self {
+            Endpoint::Clearnet(addr) => return *addr,
+            Endpoint::Onion { host, port } => (ONION_PREFIX, host, *port),
+            Endpoint::I2p { destination, port } => (I2P_PREFIX, destination, *port),
+        };
+        let mut octets = [0u8; 16];
+        octets[..6].copy_from_slice(&prefix);
+        octets[6..].copy_from_slice(&sha256(name.as_bytes())[..10]);
+        let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port);
+        OVERLAY_ENDPOINTS.lock().unwrap().insert(addr, self.clone());
+        addr
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Clearnet(addr) => write!(f, "{}", addr),
+            Endpoint::Onion { host, port } => write!(f, "{}:{}", host, port),
+            Endpoint::I2p { destination, port: 0 } => write!(f, "{}", destination),
+            Endpoint::I2p { destination, port } => write!(f, "{}:{}", destination, port),
+        }
+    }
+}
+
+/// The overlay endpoint behind a stand-in address, if it was registered
+pub fn overlay_endpoint(addr: &SocketAddr) -> Option<Endpoint> {
+    OVERLAY_ENDPOINTS.lock().unwrap().get(addr).cloned()
+}
+
+/// Drop the registration of a stand-in address nothing dials any more
+pub fn forget_endpoint(addr: &SocketAddr) {
+    OVERLAY_ENDPOINTS.lock().unwrap().remove(addr);
+}
+
+/// Network a stand-in address belongs to; `None` for real addresses
+pub fn overlay_network(addr: &SocketAddr) -> Option<ConnectionType> {
+    let IpAddr::V6(ip) = addr.ip() else { return None };
+    let octets = ip.octets();
+    if octets[..6] == ONION_PREFIX {
+        Some(ConnectionType::Tor)
+    } else if octets[..6] == I2P_PREFIX {
+        Some(ConnectionType::I2P)
+    } else {
+        None
+    }
+}
+
+fn socks_error(reply: u8) -> io::Error {
+    let reason = match reply {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    };
+    io::Error::new(io::ErrorKind::ConnectionRefused, format!("SOCKS5 proxy: {}", reason))
+}
+
+/// Connect to `host:port` through the SOCKS5 proxy at `proxy`, letting the
+/// proxy resolve the name (RFC 1928, no authentication)
+pub async fn socks5_connect(proxy: SocketAddr, host: &str, port: u16) -> io::Result<TcpStream> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("SOCKS5 proxy: {}", msg));
+    let name_len = u8::try_from(host.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "host name too long"))?;
+    let mut stream = TcpStream::connect(proxy).await?;
+
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice != [5, 0] {
+        return Err(invalid("no acceptable authentication method"));
+    }
+
+    let mut request = vec![5, 1, 0, 3, name_len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 {
+        return Err(invalid("bad reply version"));
+    }
+    if reply[1] != 0 {
+        return Err(socks_error(reply[1]));
+    }
+    // Skip the bound address
+    let bound_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        _ => return Err(invalid("bad bound address type")),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(stream)
+}
+
+/// Open an I2P stream to `destination` through `session`
+pub async fn sam_connect(session: std::sync::Arc<i2p::SamSession>, destination: String) -> io::Result<TcpStream> {
+    let stream = tokio::task::spawn_blocking(move || session.stream_connect(&destination))
+        .await
+        .map_err(io::Error::other)??
+        .into_inner();
+    stream.set_nonblocking(true)?;
+    TcpStream::from_std(stream)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    const ONION: &str = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+
+    /// Copy bytes both ways between two sockets until either side closes
+    fn pipe(a: std::net::TcpStream, b: std::net::TcpStream) {
+        let (mut a2, mut b2) = (a.try_clone().unwrap(), b.try_clone().unwrap());
+        let (mut a, mut b) = (a, b);
+        std::thread::spawn(move || {
+            let _ = std::io::copy(&mut a2, &mut b2);
+            let _ = b2.shutdown(std::net::Shutdown::Both);
+        });
+        std::thread::spawn(move || {
+            let _ = std::io::copy(&mut b, &mut a);
+            let _ = a.shutdown(std::net::Shutdown::Both);
+        });
+    }
+
+    /// SOCKS5 proxy that forwards every `CONNECT` to `target`; returns its
+    /// address and the names it was asked for
+    pub(crate) fn mock_socks5(target: SocketAddr) -> (SocketAddr, std::sync::mpsc::Receiver<(String, u16)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (requests, received) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for client in listener.incoming() {
+                let mut client = client.unwrap();
+                let mut greeting = [0u8; 3];
+                client.read_exact(&mut greeting).unwrap();
+                assert_eq!(greeting, [5, 1, 0]);
+                client.write_all(&[5, 0]).unwrap();
+                let mut head = [0u8; 5];
+                client.read_exact(&mut head).unwrap();
+                assert_eq!(head[..4], [5, 1, 0, 3]);
+                let mut name = vec![0u8; head[4] as usize + 2];
+                client.read_exact(&mut name).unwrap();
+                let port = u16::from_be_bytes([name[name.len() - 2], name[name.len() - 1]]);
+                name.truncate(name.len() - 2);
+                let _ = requests.send((String::from_utf8(name).unwrap(), port));
+                match std::net::TcpStream::connect(target) {
+                    Ok(upstream) => {
+                        client.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+                        pipe(client, upstream);
+                    }
+                    Err(_) => client.write_all(&[5, 4, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap(),
+                }
+            }
+        });
+        (addr, received)
+    }
+
+    /// SAM bridge that opens sessions and forwards every `STREAM CONNECT`
+    /// to `target`
+    pub(crate) fn mock_sam(target: SocketAddr) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for client in listener.incoming() {
+                let mut client = client.unwrap();
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(client.try_clone().unwrap());
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    assert!(line.starts_with("HELLO VERSION"));
+                    client.write_all(b"HELLO REPLY RESULT=OK VERSION=3.1\n").unwrap();
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    if line.starts_with("SESSION CREATE") {
+                        client.write_all(b"SESSION STATUS RESULT=OK DESTINATION=bWVzaGVk\n").unwrap();
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        assert!(line.starts_with("NAMING LOOKUP NAME=ME"));
+                        client.write_all(b"NAMING REPLY RESULT=OK NAME=ME VALUE=bWVzaA\n").unwrap();
+                        // The session lives as long as this connection
+                        let _ = reader.read_line(&mut line);
+                    } else if line.starts_with("STREAM CONNECT") && line.contains("DESTINATION=") {
+                        client.write_all(b"STREAM STATUS RESULT=OK\n").unwrap();
+                        pipe(client, std::net::TcpStream::connect(target).unwrap());
+                    } else {
+                        client.write_all(b"STREAM STATUS RESULT=I2P_ERROR\n").unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_endpoints_parse_and_map_to_overlay_addresses() {
+        let onion = Endpoint::parse(&format!("{}:1776", ONION)).unwrap();
+        assert!(matches!(&onion, Endpoint::Onion { port: 1776, .. }));
+        let addr = onion.peer_addr();
+        assert_eq!(overlay_network(&addr), Some(ConnectionType::Tor));
+        assert_eq!(overlay_endpoint(&addr), Some(onion.clone()));
+        assert_eq!(onion.to_string(), format!("{}:1776", ONION));
+
+        let i2p = Endpoint::parse("ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkdq.b32.i2p").unwrap();
+        assert_eq!(overlay_network(&i2p.peer_addr()), Some(ConnectionType::I2P));
+        let clearnet = Endpoint::parse("10.0.0.1:1776").unwrap();
+        assert_eq!(overlay_network(&clearnet.peer_addr()), None);
+
+        assert!(Endpoint::parse("tooshort.onion:1776").is_err());
+        assert!(Endpoint::parse(ONION).is_err());
+        assert!(Endpoint::parse("example.com:1776").is_err());
+    }
+
+    #[test]
+    fn test_malformed_i2p_names_are_rejected() {
+        assert!(Endpoint::parse("forum.i2p").is_ok());
+        assert!(Endpoint::parse("my-site.i2p:80").is_ok());
+        // A peer-supplied name that would smuggle a SAM command
+        let injected = "x.i2p SILENT=false\nSESSION CREATE STYLE=STREAM ID=evil DESTINATION=TRANSIENT\nNAMING LOOKUP NAME=a.i2p";
+        assert!(Endpoint::parse(injected).is_err());
+        for bad in [
+            "a b.i2p",
+            "a.i2p\n",
+            "a=b.i2p",
+            ".i2p",
+            "a..i2p",
+            "-a.i2p",
+            "short.b32.i2p",
+            "ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkd1.b32.i2p",
+            "a.i2p:80:80",
+            "a:80.i2p",
+        ] {
+            assert!(Endpoint::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect_reaches_the_target() {
+        let target = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (proxy, requests) = mock_socks5(target.local_addr().unwrap());
+        let mut stream = socks5_connect(proxy, ONION, 1776).await.unwrap();
+        assert_eq!(requests.recv().unwrap(), (ONION.to_string(), 1776));
+        let (mut accepted, _) = target.accept().unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        accepted.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // A refused target is reported as such
+        drop(target);
+        let (proxy, _requests) = mock_socks5("127.0.0.1:1".parse().unwrap());
+        let err = socks5_connect(proxy, ONION, 1776).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
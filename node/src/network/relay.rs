@@ -0,0 +1,199 @@
+//! Inventory relay
+//!
+//! New blocks and transactions are announced by hash with `Inv` and only sent
+//! whole to peers that ask for them with `GetData`. Each peer has a rolling
+//! filter of the inventory it is known to have, so nothing is announced back
+//! to the peer it came from, and the node keeps a cache of recently seen
+//! hashes so an object making the rounds is processed and relayed once.
+//! Transaction announcements are queued per peer and flushed in batches after
+//! a random delay, which saves messages and makes the origin of a transaction
+//! harder to tell from announcement timing.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use primitives::types::Hash;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Largest `Inv`, `GetData` or `NotFound` accepted
+pub const MAX_INV_PER_MESSAGE: usize = 5000;
+
+/// Most transactions announced to a peer in one batch
+pub const MAX_TX_PER_INV: usize = 1000;
+
+/// Inventory remembered per peer
+pub const KNOWN_INVENTORY_PER_PEER: usize = 5000;
+
+/// Hashes remembered as already processed
+pub const RECENTLY_SEEN_SIZE: usize = 50_000;
+
+/// Time a peer gets to deliver requested inventory before another peer may be asked
+pub const GETDATA_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Mean delay between transaction batches to outbound peers
+pub const OUTBOUND_TX_DELAY: Duration = Duration::from_secs(2);
+
+/// Mean delay between transaction batches to inbound peers; longer, since
+/// anyone can connect inbound to watch announcements
+pub const INBOUND_TX_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InvKind {
+    Block,
+    Transaction,
+}
+
+/// One announced object
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InvItem {
+    pub kind: InvKind,
+    pub hash: Hash,
+}
+
+impl InvItem {
+    pub fn block(hash: Hash) -> Self {
+        InvItem { kind: InvKind::Block, hash }
+    }
+
+    pub fn transaction(hash: Hash) -> Self {
+        InvItem { kind: InvKind::Transaction, hash }
+    }
+}
+
+/// Set of at most `capacity` items that forgets the oldest first
+#[derive(Debug)]
+pub struct RollingSet {
+    items: HashSet<InvItem>,
+    order: VecDeque<InvItem>,
+    capacity: usize,
+}
+
+impl RollingSet {
+    pub fn new(capacity: usize) -> Self {
+        RollingSet { items: HashSet::new(), order: VecDeque::new(), capacity }
+    }
+
+    /// Add `item`; returns whether it was new
+    pub fn insert(&mut self, item: InvItem) -> bool {
+        if !self.items.insert(item) {
+            return false;
+        }
+        self.order.push_back(item);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.items.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, item: &InvItem) -> bool {
+        self.items.contains(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+/// Node-wide relay state: what was processed and what is being fetched
+#[derive(Debug)]
+pub struct Relay {
+    seen: RollingSet,
+    in_flight: HashMap<InvItem, (SocketAddr, Instant)>,
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        Relay { seen: RollingSet::new(RECENTLY_SEEN_SIZE), in_flight: HashMap::new() }
+    }
+}
+
+impl Relay {
+    /// Whether `item` should be requested from `peer`; if so it is recorded
+    /// as in flight so other announcers are not asked as well
+    pub fn want(&mut self, item: InvItem, peer: SocketAddr, now: Instant) -> bool {
+        if self.seen.contains(&item) {
+            return false;
+        }
+        if let Some((_, asked_at)) = self.in_flight.get(&item) {
+            if now.duration_since(*asked_at) < GETDATA_TIMEOUT {
+                return false;
+            }
+        }
+        self.in_flight.insert(item, (peer, now));
+        true
+    }
+
+    /// `item` arrived; returns whether it is the first time
+    pub fn arrived(&mut self, item: InvItem) -> bool {
+        self.in_flight.remove(&item);
+        self.seen.insert(item)
+    }
+
+    /// `peer` does not have `item` after all; the next announcer may be asked
+    pub fn not_found(&mut self, item: &InvItem, peer: SocketAddr) {
+        if self.in_flight.get(item).is_some_and(|(asked, _)| *asked == peer) {
+            self.in_flight.remove(item);
+        }
+    }
+
+    /// Release everything requested from a peer that went away
+    pub fn forget_peer(&mut self, peer: SocketAddr) {
+        self.in_flight.retain(|_, (asked, _)| *asked != peer);
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        self.in_flight.retain(|_, (_, asked_at)| now.duration_since(*asked_at) < GETDATA_TIMEOUT);
+    }
+}
+
+/// Random delay until the next transaction batch, exponentially distributed
+/// around `mean` so batches to different peers are not synchronised
+pub fn trickle_delay(mean: Duration) -> Duration {
+    let uniform: f64 = rand::thread_rng().gen_range(f64::EPSILON..1.0);
+    mean.mul_f64(-uniform.ln()).min(mean * 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling_set_forgets_oldest() {
+        let mut set = RollingSet::new(2);
+        assert!(set.insert(InvItem::block([1; 32])));
+        assert!(!set.insert(InvItem::block([1; 32])));
+        assert!(set.insert(InvItem::transaction([1; 32])));
+        assert!(set.insert(InvItem::block([2; 32])));
+        assert_eq!(set.len(), 2);
+        assert!(!set.contains(&InvItem::block([1; 32])));
+        assert!(set.contains(&InvItem::transaction([1; 32])));
+    }
+
+    #[test]
+    fn test_inventory_is_requested_once() {
+        let mut relay = Relay::default();
+        let first: SocketAddr = "10.0.0.1:1776".parse().unwrap();
+        let second: SocketAddr = "10.0.0.2:1776".parse().unwrap();
+        let tx = InvItem::transaction([7; 32]);
+        let now = Instant::now();
+
+        assert!(relay.want(tx, first, now));
+        assert!(!relay.want(tx, second, now));
+        // A peer that stalls or lacks it frees the request for others
+        assert!(relay.want(tx, second, now + GETDATA_TIMEOUT));
+        relay.not_found(&tx, second);
+        assert!(relay.want(tx, first, now));
+
+        assert!(relay.arrived(tx));
+        assert!(!relay.arrived(tx));
+        assert!(!relay.want(tx, second, now));
+    }
+}
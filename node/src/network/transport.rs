@@ -0,0 +1,379 @@
+//! Encrypted peer transport
+//!
+//! Before the `Version` handshake the dialing side sends a plaintext `Hello`
+//! carrying an ephemeral X25519 key. If the other side answers with its own
+//! `Hello`, both derive per-direction AES-256-GCM keys from the shared secret
+//! and every later frame travels sealed in a record:
+//!
+//! ```text
+//! record length u32 | AES-256-GCM(frame)
+//! ```
+//!
+//! Nonces are per-direction message counters, so a replayed, dropped or
+//! reordered record fails authentication. The first sealed message each way
+//! is an `Identity`: an optional static Ed25519 key with a signature over the
+//! handshake transcript, which lets `PrivacyManager` pin trusted peers to their
+//! keys. A node whose policy allows plaintext falls back to it when the other
+//! side does not answer the `Hello` in kind.
+
+use std::io;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use primitives::encoding::sha256;
+use primitives::types::Hash;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::wire::{self, WireError, FRAME_HEADER_LEN, MAX_PAYLOAD_SIZE};
+use crate::P2PMessage;
+
+/// File in the data directory holding the node's identity key
+pub const IDENTITY_KEY_FILE: &str = "p2p_identity.key";
+
+/// Largest sealed record: a full frame plus the GCM tag
+const MAX_RECORD_LEN: u32 = FRAME_HEADER_LEN as u32 + MAX_PAYLOAD_SIZE + 16;
+
+/// Public half of a node's static identity key
+pub type IdentityKey = [u8; 32];
+
+/// Whether peer links are encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EncryptionPolicy {
+    /// Plaintext only; a `Hello` is ignored
+    Disabled,
+    /// Encrypt when the peer supports it, plaintext otherwise
+    #[default]
+    Preferred,
+    /// Drop peers that do not encrypt
+    Required,
+}
+
+/// Static Ed25519 key a node proves on every encrypted link
+#[derive(Clone)]
+pub struct Identity {
+    signing: SigningKey,
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Identity({})", hex::encode(self.public_key()))
+    }
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Identity { signing: SigningKey::from_bytes(&rand::random()) }
+    }
+
+    /// Load the key kept in `data_dir`, creating it on first use
+    pub fn load_or_create(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(IDENTITY_KEY_FILE);
+        if path.exists() {
+            let secret: [u8; 32] = hex::decode(std::fs::read_to_string(&path)?.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a valid key", path.display())))?;
+            return Ok(Identity { signing: SigningKey::from_bytes(&secret) });
+        }
+        let identity = Identity::generate();
+        let tmp_path = path.with_extension("key.tmp");
+        write_secret(&tmp_path, hex::encode(identity.signing.to_bytes()).as_bytes())?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(identity)
+    }
+
+    pub fn public_key(&self) -> IdentityKey {
+        self.signing.verifying_key().to_bytes()
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn write_secret(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.write_all(contents)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn write_secret(path: &Path, contents: &[u8]) -> io::Result<()> {
+    std::fs::write(path, contents)
+}
+
+/// Parse a hex-encoded identity key, e.g. from `--pin-peer`
+pub fn parse_identity_key(hex_key: &str) -> Option<IdentityKey> {
+    hex::decode(hex_key).ok()?.try_into().ok()
+}
+
+/// One direction of an encrypted link
+struct CipherState {
+    cipher: Aes256Gcm,
+    counter: u64,
+}
+
+impl CipherState {
+    fn new(key: &Hash) -> Self {
+        CipherState { cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)), counter: 0 }
+    }
+
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter += 1;
+        nonce
+    }
+
+    fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self.next_nonce();
+        self.cipher.encrypt(Nonce::from_slice(&nonce), plaintext).expect("AES-GCM encryption cannot fail")
+    }
+
+    fn open(&mut self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.next_nonce();
+        self.cipher.decrypt(Nonce::from_slice(&nonce), ciphertext).ok()
+    }
+}
+
+/// Reads frames off a connection, opening records once the link is encrypted
+pub struct FrameReader {
+    cipher: Option<CipherState>,
+}
+
+impl FrameReader {
+    pub fn plaintext() -> Self {
+        FrameReader { cipher: None }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    pub async fn read<R: AsyncRead + Unpin>(&mut self, reader: &mut R, magic: u32) -> Result<P2PMessage, WireError> {
+        let Some(cipher) = &mut self.cipher else {
+            return wire::read_frame_async(reader, magic).await;
+        };
+        let len = reader.read_u32_le().await?;
+        if len > MAX_RECORD_LEN {
+            return Err(WireError::PayloadTooLarge(len));
+        }
+        let mut record = vec![0u8; len as usize];
+        reader.read_exact(&mut record).await?;
+        let frame = cipher.open(&record).ok_or(WireError::Unauthenticated)?;
+        let mut rest = frame.as_slice();
+        match wire::read_frame(&mut rest, magic) {
+            // A truncated frame inside a record is corrupt, not a closed link
+            Err(WireError::Io(_)) => Err(WireError::Malformed(primitives::encoding::DecodeError::UnexpectedEof)),
+            Ok(_) if !rest.is_empty() => Err(WireError::Malformed(primitives::encoding::DecodeError::TrailingBytes(rest.len()))),
+            result => result,
+        }
+    }
+}
+
+/// Writes frames onto a connection, sealing them once the link is encrypted
+pub struct FrameWriter {
+    cipher: Option<CipherState>,
+}
+
+impl FrameWriter {
+    pub fn plaintext() -> Self {
+        FrameWriter { cipher: None }
+    }
+
+    pub async fn write<W: AsyncWrite + Unpin>(&mut self, writer: &mut W, magic: u32, msg: &P2PMessage) -> io::Result<()> {
+        let Some(cipher) = &mut self.cipher else {
+            return wire::write_frame_async(writer, magic, msg).await;
+        };
+        let sealed = cipher.seal(&wire::checked_frame(magic, msg)?);
+        let mut record = Vec::with_capacity(4 + sealed.len());
+        record.extend_from_slice(&(sealed.len() as u32).to_le_bytes());
+        record.extend_from_slice(&sealed);
+        writer.write_all(&record).await
+    }
+}
+
+/// Outcome of `negotiate`
+pub struct Link {
+    pub reader: FrameReader,
+    pub writer: FrameWriter,
+    /// Identity key the peer proved, on encrypted links only
+    pub peer_identity: Option<IdentityKey>,
+    /// Message already read while finding out the peer talks plaintext
+    pub first_message: Option<P2PMessage>,
+}
+
+impl Link {
+    fn plaintext(first_message: Option<P2PMessage>) -> Self {
+        Link { reader: FrameReader::plaintext(), writer: FrameWriter::plaintext(), peer_identity: None, first_message }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.reader.is_encrypted()
+    }
+}
+
+fn derive_key(label: &[u8], shared: &[u8; 32], transcript: &Hash) -> Hash {
+    let mut data = b"BlackSilk p2p ".to_vec();
+    data.extend_from_slice(label);
+    data.extend_from_slice(shared);
+    data.extend_from_slice(transcript);
+    sha256(&data)
+}
+
+/// What a node signs to prove its identity on one link; the role keeps a
+/// signature from being reflected back to its owner
+fn identity_statement(transcript: &Hash, initiator: bool) -> Vec<u8> {
+    let mut statement = b"BlackSilk p2p identity ".to_vec();
+    statement.extend_from_slice(transcript);
+    statement.push(initiator as u8);
+    statement
+}
+
+/// Set up the link to a freshly connected peer: the `Hello` exchange, then
+/// the identity proof. The dialing side speaks first.
+pub async fn negotiate<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    magic: u32,
+    outbound: bool,
+    policy: EncryptionPolicy,
+    identity: Option<&Identity>,
+) -> Result<Link, String>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let secret: [u8; 32] = rand::random();
+    let ours = MontgomeryPoint::mul_base_clamped(secret).to_bytes();
+    let hello = P2PMessage::Hello { ephemeral: ours };
+    let theirs = if outbound {
+        if policy == EncryptionPolicy::Disabled {
+            return Ok(Link::plaintext(None));
+        }
+        wire::write_frame_async(writer, magic, &hello).await.map_err(|e| e.to_string())?;
+        match wire::read_frame_async(reader, magic).await.map_err(|e| e.to_string())? {
+            P2PMessage::Hello { ephemeral } => ephemeral,
+            other if policy == EncryptionPolicy::Required => {
+                return Err(format!("answered {} instead of an encrypted link", crate::message_summary(&other)));
+            }
+            other => return Ok(Link::plaintext(Some(other))),
+        }
+    } else {
+        match wire::read_frame_async(reader, magic).await.map_err(|e| e.to_string())? {
+            P2PMessage::Hello { .. } if policy == EncryptionPolicy::Disabled => return Ok(Link::plaintext(None)),
+            P2PMessage::Hello { ephemeral } => {
+                wire::write_frame_async(writer, magic, &hello).await.map_err(|e| e.to_string())?;
+                ephemeral
+            }
+            _ if policy == EncryptionPolicy::Required => return Err("did not offer an encrypted link".to_string()),
+            other => return Ok(Link::plaintext(Some(other))),
+        }
+    };
+
+    let shared = MontgomeryPoint(theirs).mul_clamped(secret).to_bytes();
+    if shared == [0u8; 32] {
+        return Err("sent a low-order key".to_string());
+    }
+    let (initiator, responder) = if outbound { (ours, theirs) } else { (theirs, ours) };
+    let mut transcript_data = magic.to_le_bytes().to_vec();
+    transcript_data.extend_from_slice(&initiator);
+    transcript_data.extend_from_slice(&responder);
+    let transcript = sha256(&transcript_data);
+    let initiator_key = derive_key(b"initiator", &shared, &transcript);
+    let responder_key = derive_key(b"responder", &shared, &transcript);
+    let (send_key, receive_key) = if outbound { (initiator_key, responder_key) } else { (responder_key, initiator_key) };
+    let mut link = Link {
+        reader: FrameReader { cipher: Some(CipherState::new(&receive_key)) },
+        writer: FrameWriter { cipher: Some(CipherState::new(&send_key)) },
+        peer_identity: None,
+        first_message: None,
+    };
+
+    let proof = P2PMessage::Identity {
+        key: identity.map(Identity::public_key),
+        signature: identity
+            .map(|id| id.signing.sign(&identity_statement(&transcript, outbound)).to_bytes().to_vec())
+            .unwrap_or_default(),
+    };
+    link.writer.write(writer, magic, &proof).await.map_err(|e| e.to_string())?;
+    link.peer_identity = match link.reader.read(reader, magic).await.map_err(|e| e.to_string())? {
+        P2PMessage::Identity { key: None, .. } => None,
+        P2PMessage::Identity { key: Some(key), signature } => {
+            let verifying = VerifyingKey::from_bytes(&key).map_err(|_| "sent an invalid identity key".to_string())?;
+            let signature: [u8; 64] = signature.try_into().map_err(|_| "sent a malformed identity signature".to_string())?;
+            verifying
+                .verify(&identity_statement(&transcript, !outbound), &Signature::from_bytes(&signature))
+                .map_err(|_| "failed to prove its identity key".to_string())?;
+            Some(key)
+        }
+        other => return Err(format!("sent {} instead of its identity", crate::message_summary(&other))),
+    };
+    Ok(link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: u32 = crate::config::TESTNET_MAGIC;
+
+    /// Negotiate one end; a side left talking plaintext sends the first
+    /// message of the handshake that would follow
+    async fn end<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        outbound: bool,
+        (policy, identity): (EncryptionPolicy, Option<&Identity>),
+    ) -> Result<Link, String> {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let link = negotiate(&mut reader, &mut writer, MAGIC, outbound, policy, identity).await;
+        if link.as_ref().is_ok_and(|link| link.first_message.is_none() && !link.is_encrypted()) {
+            wire::write_frame_async(&mut writer, MAGIC, &P2PMessage::Verack).await.unwrap();
+        }
+        link
+    }
+
+    /// Negotiate both ends of an in-memory connection
+    async fn connect(
+        dialer: (EncryptionPolicy, Option<&Identity>),
+        listener: (EncryptionPolicy, Option<&Identity>),
+    ) -> (Result<Link, String>, Result<Link, String>, tokio::io::DuplexStream, tokio::io::DuplexStream) {
+        let (mut a, mut b) = tokio::io::duplex(64 * 1024);
+        let (out, inb) = tokio::join!(end(&mut a, true, dialer), end(&mut b, false, listener));
+        (out, inb, a, b)
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_link_carries_frames_and_proves_identity() {
+        let identity = Identity::generate();
+        let (out, inb, mut a, mut b) = connect((EncryptionPolicy::Preferred, Some(&identity)), (EncryptionPolicy::Required, None)).await;
+        let (mut out, mut inb) = (out.unwrap(), inb.unwrap());
+        assert!(out.is_encrypted() && inb.is_encrypted());
+        assert_eq!(inb.peer_identity, Some(identity.public_key()));
+        assert_eq!(out.peer_identity, None);
+
+        out.writer.write(&mut a, MAGIC, &P2PMessage::GetBlocks { from_height: 9 }).await.unwrap();
+        out.writer.write(&mut a, MAGIC, &P2PMessage::Ping).await.unwrap();
+        assert!(matches!(inb.reader.read(&mut b, MAGIC).await.unwrap(), P2PMessage::GetBlocks { from_height: 9 }));
+        assert!(matches!(inb.reader.read(&mut b, MAGIC).await.unwrap(), P2PMessage::Ping));
+
+        // Tampered records fail authentication
+        let mut sealed = Vec::new();
+        out.writer.write(&mut sealed, MAGIC, &P2PMessage::Pong).await.unwrap();
+        *sealed.last_mut().unwrap() ^= 1;
+        assert!(matches!(inb.reader.read(&mut sealed.as_slice(), MAGIC).await, Err(WireError::Unauthenticated)));
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_fallback_follows_policy() {
+        let (out, inb, _a, _b) = connect((EncryptionPolicy::Preferred, None), (EncryptionPolicy::Disabled, None)).await;
+        let (out, inb) = (out.unwrap(), inb.unwrap());
+        assert!(!out.is_encrypted() && !inb.is_encrypted());
+
+        let (out, inb, _a, _b) = connect((EncryptionPolicy::Disabled, None), (EncryptionPolicy::Required, None)).await;
+        assert!(out.is_ok());
+        assert!(inb.is_err());
+    }
+}
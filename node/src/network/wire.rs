@@ -0,0 +1,511 @@
+//! P2P wire format
+//!
+//! Every message travels in a frame (integers little-endian):
+//!
+//! ```text
+//! network magic u32 | command u8 | payload length u32 | checksum [u8; 4] | payload
+//! ```
+//!
+//! The checksum is the first four bytes of SHA-256 over the payload. Payloads
+//! use the canonical binary encoding from `primitives::encoding`. A frame with
+//! another network's magic, an oversized payload or a bad checksum is a
+//! protocol violation and the connection is dropped.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use primitives::encoding::{decode_list, encode_list, sha256, Decode, DecodeError, Encode, Reader};
+use primitives::{Block, BlockHeader, Transaction};
+
+use super::addrman::NetAddress;
+use super::compact::{CompactBlock, PrefilledTransaction, ShortId, SHORT_ID_LEN};
+use super::relay::{InvItem, InvKind};
+use crate::P2PMessage;
+
+/// Protocol version announced in `Version`; version 6 may end `Version`
+/// with an onion address, which only nodes running an onion service send
+pub const PROTOCOL_VERSION: u32 = 6;
+
+/// Oldest protocol version we talk to; version 3 relays through `Inv`/`GetData`
+pub const MIN_PROTOCOL_VERSION: u32 = 3;
+
+/// First protocol version that takes compact blocks; older peers get `Inv`
+pub const COMPACT_BLOCKS_VERSION: u32 = 4;
+
+/// First protocol version that takes Dandelion++ stem transactions
+pub const DANDELION_VERSION: u32 = 5;
+
+/// Largest accepted payload; fits a full `GetBlockData` batch of maximum-size blocks
+pub const MAX_PAYLOAD_SIZE: u32 = 32 * 1024 * 1024;
+
+pub const FRAME_HEADER_LEN: usize = 4 + 1 + 4 + 4;
+
+/// Time a new peer gets to complete the `Version`/`Verack` exchange
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Service bits announced in `Version`
+pub mod services {
+    /// Serves full blocks for the whole chain
+    pub const NETWORK: u64 = 1 << 0;
+    /// Serves headers for the whole chain but only recent block bodies
+    pub const PRUNED: u64 = 1 << 1;
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    Io(io::Error),
+    WrongMagic { expected: u32, got: u32 },
+    PayloadTooLarge(u32),
+    ChecksumMismatch,
+    UnknownCommand(u8),
+    Malformed(DecodeError),
+    /// Encrypted record that fails authentication
+    Unauthenticated,
+}
+
+impl std::fmt::Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireError::Io(e) => write!(f, "I/O error: {}", e),
+            WireError::WrongMagic { expected, got } => write!(f, "network magic 0x{:X}, expected 0x{:X}", got, expected),
+            WireError::PayloadTooLarge(len) => write!(f, "payload of {} bytes exceeds {}", len, MAX_PAYLOAD_SIZE),
+            WireError::ChecksumMismatch => write!(f, "payload checksum mismatch"),
+            WireError::UnknownCommand(command) => write!(f, "unknown command {}", command),
+            WireError::Malformed(e) => write!(f, "malformed payload: {}", e),
+            WireError::Unauthenticated => write!(f, "record failed authentication"),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+impl From<io::Error> for WireError {
+    fn from(e: io::Error) -> Self {
+        WireError::Io(e)
+    }
+}
+
+impl From<DecodeError> for WireError {
+    fn from(e: DecodeError) -> Self {
+        WireError::Malformed(e)
+    }
+}
+
+mod command {
+    pub const VERSION: u8 = 0;
+    pub const VERACK: u8 = 1;
+    pub const PING: u8 = 2;
+    pub const PONG: u8 = 3;
+    pub const BLOCK: u8 = 4;
+    pub const TRANSACTION: u8 = 5;
+    pub const PEER_LIST: u8 = 6;
+    pub const GET_BLOCKS: u8 = 7;
+    pub const BLOCKS: u8 = 8;
+    pub const GET_HEADERS: u8 = 9;
+    pub const HEADERS: u8 = 10;
+    pub const GET_BLOCK_DATA: u8 = 11;
+    pub const GET_MEMPOOL: u8 = 12;
+    pub const MEMPOOL: u8 = 13;
+    pub const GET_ADDR: u8 = 14;
+    pub const ADDR: u8 = 15;
+    pub const INV: u8 = 16;
+    pub const GET_DATA: u8 = 17;
+    pub const NOT_FOUND: u8 = 18;
+    pub const COMPACT_BLOCK: u8 = 19;
+    pub const GET_BLOCK_TXN: u8 = 20;
+    pub const BLOCK_TXN: u8 = 21;
+    pub const HELLO: u8 = 22;
+    pub const IDENTITY: u8 = 23;
+    pub const STEM_TRANSACTION: u8 = 24;
+}
+
+impl Encode for NetAddress {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.addr.encode_to(out);
+        self.services.encode_to(out);
+        self.last_seen.encode_to(out);
+    }
+}
+
+impl Decode for NetAddress {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(NetAddress {
+            addr: Decode::decode_from(reader)?,
+            services: Decode::decode_from(reader)?,
+            last_seen: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for InvItem {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let kind: u8 = match self.kind {
+            InvKind::Block => 1,
+            InvKind::Transaction => 2,
+        };
+        kind.encode_to(out);
+        self.hash.encode_to(out);
+    }
+}
+
+impl Decode for InvItem {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let kind = match u8::decode_from(reader)? {
+            1 => InvKind::Block,
+            2 => InvKind::Transaction,
+            tag => return Err(DecodeError::InvalidTag { kind: "inventory", tag }),
+        };
+        Ok(InvItem { kind, hash: Decode::decode_from(reader)? })
+    }
+}
+
+impl Encode for ShortId {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+}
+
+impl Decode for ShortId {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(ShortId(reader.read_bytes(SHORT_ID_LEN)?.try_into().unwrap()))
+    }
+}
+
+impl Encode for PrefilledTransaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.index.encode_to(out);
+        self.tx.encode_to(out);
+    }
+}
+
+impl Decode for PrefilledTransaction {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(PrefilledTransaction { index: Decode::decode_from(reader)?, tx: Decode::decode_from(reader)? })
+    }
+}
+
+impl Encode for CompactBlock {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        self.coinbase.encode_to(out);
+        self.nonce.encode_to(out);
+        encode_list(&self.short_ids, out);
+        encode_list(&self.prefilled, out);
+    }
+}
+
+impl Decode for CompactBlock {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(CompactBlock {
+            header: Decode::decode_from(reader)?,
+            coinbase: Decode::decode_from(reader)?,
+            nonce: Decode::decode_from(reader)?,
+            short_ids: decode_list(reader)?,
+            prefilled: decode_list(reader)?,
+        })
+    }
+}
+
+impl P2PMessage {
+    /// Command id carried in the frame header
+    pub fn command(&self) -> u8 {
+        match self {
+            P2PMessage::Version { .. } => command::VERSION,
+            P2PMessage::Verack => command::VERACK,
+            P2PMessage::Ping => command::PING,
+            P2PMessage::Pong => command::PONG,
+            P2PMessage::Block(_) => command::BLOCK,
+            P2PMessage::Transaction(_) => command::TRANSACTION,
+            P2PMessage::PeerList(_) => command::PEER_LIST,
+            P2PMessage::GetBlocks { .. } => command::GET_BLOCKS,
+            P2PMessage::Blocks(_) => command::BLOCKS,
+            P2PMessage::GetHeaders { .. } => command::GET_HEADERS,
+            P2PMessage::Headers(_) => command::HEADERS,
+            P2PMessage::GetBlockData { .. } => command::GET_BLOCK_DATA,
+            P2PMessage::GetMempool => command::GET_MEMPOOL,
+            P2PMessage::Mempool(_) => command::MEMPOOL,
+            P2PMessage::GetAddr => command::GET_ADDR,
+            P2PMessage::Addr(_) => command::ADDR,
+            P2PMessage::Inv(_) => command::INV,
+            P2PMessage::GetData(_) => command::GET_DATA,
+            P2PMessage::NotFound(_) => command::NOT_FOUND,
+            P2PMessage::CompactBlock(_) => command::COMPACT_BLOCK,
+            P2PMessage::GetBlockTxn { .. } => command::GET_BLOCK_TXN,
+            P2PMessage::BlockTxn { .. } => command::BLOCK_TXN,
+            P2PMessage::Hello { .. } => command::HELLO,
+            P2PMessage::Identity { .. } => command::IDENTITY,
+            P2PMessage::StemTransaction(_) => command::STEM_TRANSACTION,
+        }
+    }
+
+    pub fn encode_payload(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            P2PMessage::Version { version, best_height, services, node, onion } => {
+                version.encode_to(&mut out);
+                best_height.encode_to(&mut out);
+                services.encode_to(&mut out);
+                node.encode_to(&mut out);
+                if let Some(onion) = onion {
+                    onion.encode_to(&mut out);
+                }
+            }
+            P2PMessage::Verack | P2PMessage::Ping | P2PMessage::Pong | P2PMessage::GetMempool | P2PMessage::GetAddr => {}
+            P2PMessage::Block(block) => block.encode_to(&mut out),
+            P2PMessage::Transaction(tx) | P2PMessage::StemTransaction(tx) => tx.encode_to(&mut out),
+            P2PMessage::PeerList(peers) => encode_list(peers, &mut out),
+            P2PMessage::GetBlocks { from_height } => from_height.encode_to(&mut out),
+            P2PMessage::Blocks(blocks) => encode_list(blocks, &mut out),
+            P2PMessage::GetHeaders { locator } => encode_list(locator, &mut out),
+            P2PMessage::Headers(headers) => encode_list(headers, &mut out),
+            P2PMessage::GetBlockData { hashes } => encode_list(hashes, &mut out),
+            P2PMessage::Mempool(txs) => encode_list(txs, &mut out),
+            P2PMessage::Addr(addrs) => encode_list(addrs, &mut out),
+            P2PMessage::Inv(items) | P2PMessage::GetData(items) | P2PMessage::NotFound(items) => encode_list(items, &mut out),
+            P2PMessage::CompactBlock(compact) => compact.encode_to(&mut out),
+            P2PMessage::GetBlockTxn { block_hash, indexes } => {
+                block_hash.encode_to(&mut out);
+                encode_list(indexes, &mut out);
+            }
+            P2PMessage::BlockTxn { block_hash, transactions } => {
+                block_hash.encode_to(&mut out);
+                encode_list(transactions, &mut out);
+            }
+            P2PMessage::Hello { ephemeral } => ephemeral.encode_to(&mut out),
+            P2PMessage::Identity { key, signature } => {
+                key.encode_to(&mut out);
+                signature.encode_to(&mut out);
+            }
+        }
+        out
+    }
+
+    pub fn decode_payload(command: u8, payload: &[u8]) -> Result<Self, WireError> {
+        let mut reader = Reader::new(payload);
+        let r = &mut reader;
+        let msg = match command {
+            command::VERSION => P2PMessage::Version {
+                version: Decode::decode_from(r)?,
+                best_height: Decode::decode_from(r)?,
+                services: Decode::decode_from(r)?,
+                node: Decode::decode_from(r)?,
+                onion: if r.remaining() > 0 { Some(Decode::decode_from(r)?) } else { None },
+            },
+            command::VERACK => P2PMessage::Verack,
+            command::PING => P2PMessage::Ping,
+            command::PONG => P2PMessage::Pong,
+            command::BLOCK => P2PMessage::Block(Block::decode_from(r)?),
+            command::TRANSACTION => P2PMessage::Transaction(Transaction::decode_from(r)?),
+            command::PEER_LIST => P2PMessage::PeerList(decode_list(r)?),
+            command::GET_BLOCKS => P2PMessage::GetBlocks { from_height: Decode::decode_from(r)? },
+            command::BLOCKS => P2PMessage::Blocks(decode_list(r)?),
+            command::GET_HEADERS => P2PMessage::GetHeaders { locator: decode_list(r)? },
+            command::HEADERS => P2PMessage::Headers(decode_list::<BlockHeader>(r)?),
+            command::GET_BLOCK_DATA => P2PMessage::GetBlockData { hashes: decode_list(r)? },
+            command::GET_MEMPOOL => P2PMessage::GetMempool,
+            command::MEMPOOL => P2PMessage::Mempool(decode_list(r)?),
+            command::GET_ADDR => P2PMessage::GetAddr,
+            command::ADDR => P2PMessage::Addr(decode_list(r)?),
+            command::INV => P2PMessage::Inv(decode_list(r)?),
+            command::GET_DATA => P2PMessage::GetData(decode_list(r)?),
+            command::NOT_FOUND => P2PMessage::NotFound(decode_list(r)?),
+            command::COMPACT_BLOCK => P2PMessage::CompactBlock(CompactBlock::decode_from(r)?),
+            command::GET_BLOCK_TXN => P2PMessage::GetBlockTxn { block_hash: Decode::decode_from(r)?, indexes: decode_list(r)? },
+            command::BLOCK_TXN => P2PMessage::BlockTxn { block_hash: Decode::decode_from(r)?, transactions: decode_list(r)? },
+            command::HELLO => P2PMessage::Hello { ephemeral: Decode::decode_from(r)? },
+            command::IDENTITY => P2PMessage::Identity { key: Decode::decode_from(r)?, signature: Decode::decode_from(r)? },
+            command::STEM_TRANSACTION => P2PMessage::StemTransaction(Transaction::decode_from(r)?),
+            other => return Err(WireError::UnknownCommand(other)),
+        };
+        match reader.remaining() {
+            0 => Ok(msg),
+            n => Err(WireError::Malformed(DecodeError::TrailingBytes(n))),
+        }
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    sha256(payload)[..4].try_into().unwrap()
+}
+
+/// Complete frame for `msg` on the network identified by `magic`
+pub fn encode_frame(magic: u32, msg: &P2PMessage) -> Vec<u8> {
+    let payload = msg.encode_payload();
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&magic.to_le_bytes());
+    frame.push(msg.command());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&checksum(&payload));
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+/// `encode_frame`, refusing messages over the payload limit
+pub(crate) fn checked_frame(magic: u32, msg: &P2PMessage) -> io::Result<Vec<u8>> {
+    let frame = encode_frame(magic, msg);
+    if frame.len() - FRAME_HEADER_LEN > MAX_PAYLOAD_SIZE as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "message exceeds the maximum payload size"));
+    }
+    Ok(frame)
+}
+
+/// Write `msg` as one frame with a single `write_all`
+pub fn write_frame(writer: &mut impl Write, magic: u32, msg: &P2PMessage) -> io::Result<()> {
+    writer.write_all(&checked_frame(magic, msg)?)
+}
+
+/// Async counterpart of `write_frame`
+pub async fn write_frame_async<W: AsyncWrite + Unpin>(writer: &mut W, magic: u32, msg: &P2PMessage) -> io::Result<()> {
+    writer.write_all(&checked_frame(magic, msg)?).await
+}
+
+/// Validate a frame header; returns the command and payload length
+fn parse_header(header: &[u8; FRAME_HEADER_LEN], magic: u32) -> Result<(u8, u32), WireError> {
+    let got = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    if got != magic {
+        return Err(WireError::WrongMagic { expected: magic, got });
+    }
+    let len = u32::from_le_bytes(header[5..9].try_into().unwrap());
+    if len > MAX_PAYLOAD_SIZE {
+        return Err(WireError::PayloadTooLarge(len));
+    }
+    Ok((header[4], len))
+}
+
+fn decode_frame(header: &[u8; FRAME_HEADER_LEN], command: u8, payload: &[u8]) -> Result<P2PMessage, WireError> {
+    if checksum(payload) != header[9..13] {
+        return Err(WireError::ChecksumMismatch);
+    }
+    P2PMessage::decode_payload(command, payload)
+}
+
+/// Fail a payload that ended before the length its header announced
+fn check_complete(payload: &[u8], len: u32) -> Result<(), WireError> {
+    if payload.len() < len as usize {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "frame ended before its payload").into());
+    }
+    Ok(())
+}
+
+/// Read the next frame, checking it belongs to the network identified by `magic`.
+/// The payload buffer grows as bytes arrive, so a header merely claiming a
+/// large payload costs nothing
+pub fn read_frame(reader: &mut impl Read, magic: u32) -> Result<P2PMessage, WireError> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let (command, len) = parse_header(&header, magic)?;
+    let mut payload = Vec::new();
+    reader.take(len as u64).read_to_end(&mut payload)?;
+    check_complete(&payload, len)?;
+    decode_frame(&header, command, &payload)
+}
+
+/// Async counterpart of `read_frame` for the peer manager's connection tasks
+pub async fn read_frame_async<R: AsyncRead + Unpin>(reader: &mut R, magic: u32) -> Result<P2PMessage, WireError> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    reader.read_exact(&mut header).await?;
+    let (command, len) = parse_header(&header, magic)?;
+    let mut payload = Vec::new();
+    reader.take(len as u64).read_to_end(&mut payload).await?;
+    check_complete(&payload, len)?;
+    decode_frame(&header, command, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: u32 = crate::config::TESTNET_MAGIC;
+
+    fn round_trip(msg: &P2PMessage) -> P2PMessage {
+        let frame = encode_frame(MAGIC, msg);
+        read_frame(&mut frame.as_slice(), MAGIC).unwrap()
+    }
+
+    #[test]
+    fn test_frames_round_trip() {
+        let version = P2PMessage::Version {
+            version: PROTOCOL_VERSION,
+            best_height: 42,
+            services: services::NETWORK,
+            node: "BlackSilkNode".to_string(),
+            onion: None,
+        };
+        match round_trip(&version) {
+            P2PMessage::Version { version, best_height, services, node, onion } => {
+                assert_eq!((version, best_height, services), (PROTOCOL_VERSION, 42, services::NETWORK));
+                assert_eq!((node.as_str(), onion), ("BlackSilkNode", None));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+        let onion = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion:9334".to_string();
+        let with_onion = P2PMessage::Version {
+            version: PROTOCOL_VERSION,
+            best_height: 42,
+            services: services::NETWORK,
+            node: "BlackSilkNode".to_string(),
+            onion: Some(onion.clone()),
+        };
+        match round_trip(&with_onion) {
+            P2PMessage::Version { onion: decoded, .. } => assert_eq!(decoded, Some(onion)),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        assert!(matches!(round_trip(&P2PMessage::Verack), P2PMessage::Verack));
+        let items = vec![InvItem::block([3; 32]), InvItem::transaction([4; 32])];
+        match round_trip(&P2PMessage::Inv(items.clone())) {
+            P2PMessage::Inv(decoded) => assert_eq!(decoded, items),
+            other => panic!("unexpected message: {:?}", other),
+        }
+        match round_trip(&P2PMessage::GetBlockData { hashes: vec![[1; 32], [2; 32]] }) {
+            P2PMessage::GetBlockData { hashes } => assert_eq!(hashes, vec![[1; 32], [2; 32]]),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        match round_trip(&P2PMessage::GetBlockTxn { block_hash: [5; 32], indexes: vec![1, 3] }) {
+            P2PMessage::GetBlockTxn { block_hash, indexes } => assert_eq!((block_hash, indexes), ([5; 32], vec![1, 3])),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        // Back-to-back frames come out of one stream intact
+        let mut stream = encode_frame(MAGIC, &P2PMessage::Ping);
+        stream.extend(encode_frame(MAGIC, &P2PMessage::GetBlocks { from_height: 7 }));
+        let mut reader = stream.as_slice();
+        assert!(matches!(read_frame(&mut reader, MAGIC).unwrap(), P2PMessage::Ping));
+        assert!(matches!(read_frame(&mut reader, MAGIC).unwrap(), P2PMessage::GetBlocks { from_height: 7 }));
+    }
+
+    #[test]
+    fn test_bad_frames_are_rejected() {
+        let frame = encode_frame(MAGIC, &P2PMessage::GetBlocks { from_height: 7 });
+        assert!(matches!(
+            read_frame(&mut frame.as_slice(), crate::config::MAINNET_MAGIC),
+            Err(WireError::WrongMagic { got: MAGIC, .. })
+        ));
+
+        let mut corrupted = frame.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(read_frame(&mut corrupted.as_slice(), MAGIC), Err(WireError::ChecksumMismatch)));
+
+        let mut oversized = frame.clone();
+        oversized[5..9].copy_from_slice(&(MAX_PAYLOAD_SIZE + 1).to_le_bytes());
+        assert!(matches!(read_frame(&mut oversized.as_slice(), MAGIC), Err(WireError::PayloadTooLarge(_))));
+
+        let mut unknown = frame.clone();
+        unknown[4] = 0xFF;
+        assert!(matches!(read_frame(&mut unknown.as_slice(), MAGIC), Err(WireError::UnknownCommand(0xFF))));
+
+        // A header announcing the largest payload, followed by only a few
+        // bytes, ends in an error rather than a 32 MiB buffer
+        let mut truncated = frame[..FRAME_HEADER_LEN].to_vec();
+        truncated[5..9].copy_from_slice(&MAX_PAYLOAD_SIZE.to_le_bytes());
+        truncated.extend_from_slice(&[0; 16]);
+        for result in [
+            read_frame(&mut truncated.as_slice(), MAGIC),
+            tokio::runtime::Runtime::new().unwrap().block_on(read_frame_async(&mut truncated.as_slice(), MAGIC)),
+        ] {
+            assert!(matches!(result, Err(WireError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+        }
+    }
+}
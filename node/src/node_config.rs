@@ -0,0 +1,517 @@
+//! Node configuration
+//!
+//! `blacksilk-node` settings are resolved in layers, each overriding the one
+//! before: built-in defaults, `node_config.toml`, `BLACKSILK_*` environment
+//! variables and finally command line flags. Every layer is read as raw
+//! strings keyed by setting name, then the merged values are parsed and
+//! validated once into a typed `NodeConfig`. Each value remembers the layer
+//! it came from so `config show` can explain it.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::chain_spec::{ChainSpec, ChainSpecError};
+use crate::Network;
+
+/// Prefix of the environment variable of every setting, e.g. `BLACKSILK_MAX_PEERS`
+pub const ENV_PREFIX: &str = "BLACKSILK_";
+
+/// RPC address on every network; the miner, wallet and marketplace
+/// connect to it by default
+pub const DEFAULT_RPC_BIND: &str = "127.0.0.1:9333";
+
+/// Accepted values of `log_level`
+pub const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+/// Accepted values of `net_privacy`
+pub const PRIVACY_MODES: [&str; 4] = ["clearnet", "tor", "i2p", "auto"];
+
+/// Where a setting's value came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+/// One configurable value. `name` is also the command line flag (with
+/// dashes) and, upper-cased after `ENV_PREFIX`, the environment variable.
+struct Key {
+    name: &'static str,
+    /// Dotted path in `node_config.toml`
+    toml: Option<&'static str>,
+    /// Raw default; empty means unset or derived from the chain spec
+    default: &'static str,
+}
+
+const KEYS: &[Key] = &[
+    Key { name: "network", toml: None, default: "testnet" },
+    Key { name: "chain_spec", toml: Some("consensus.genesis_file"), default: "" },
+    Key { name: "data_dir", toml: Some("database.path"), default: "./data" },
+    Key { name: "bind", toml: Some("network.rpc_listen_address"), default: DEFAULT_RPC_BIND },
+    Key { name: "p2p_bind", toml: Some("network.peer_listen_address"), default: "" },
+    Key { name: "max_peers", toml: Some("network.max_peers"), default: "50" },
+    Key { name: "min_peers", toml: Some("network.min_peers"), default: "8" },
+    Key { name: "net_privacy", toml: Some("network.privacy_mode"), default: "auto" },
+    Key { name: "log_level", toml: Some("logging.level"), default: "info" },
+    Key { name: "db_cache", toml: Some("database.cache_mb"), default: "256" },
+    Key { name: "verify_threads", toml: Some("consensus.verify_threads"), default: "4" },
+    Key { name: "mempool_size", toml: Some("security.mempool_size_mb"), default: "100" },
+    Key { name: "min_fee_per_byte", toml: Some("security.min_fee_per_byte"), default: "" },
+    Key { name: "rpc", toml: Some("rpc.enable_http"), default: "true" },
+    Key { name: "rpc_user", toml: Some("rpc.user"), default: "" },
+    Key { name: "rpc_password", toml: Some("rpc.password"), default: "" },
+    Key { name: "rpc_ssl", toml: Some("rpc.ssl"), default: "false" },
+    Key { name: "rpc_cert", toml: Some("rpc.ssl_cert"), default: "" },
+    Key { name: "rpc_key", toml: Some("rpc.ssl_key"), default: "" },
+];
+
+/// Environment variable of setting `name`
+pub fn env_var(name: &str) -> String {
+    format!("{}{}", ENV_PREFIX, name.to_ascii_uppercase())
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// A flag or environment variable that names no setting
+    UnknownSetting(String),
+    Invalid { name: &'static str, value: String, source: Source, reason: String },
+    ChainSpec(PathBuf, ChainSpecError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "malformed {}: {}", path.display(), e),
+            ConfigError::UnknownSetting(name) => write!(f, "unknown setting {}", name),
+            ConfigError::Invalid { name, value, source, reason } => {
+                write!(f, "invalid {} = {:?} ({}): {}", name, value, source, reason)
+            }
+            ConfigError::ChainSpec(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Raw values of all layers merged so far
+struct Layers {
+    values: Vec<(&'static str, String, Source)>,
+    /// Keys of the file this node has no use for
+    ignored: Vec<String>,
+}
+
+impl Layers {
+    fn new() -> Self {
+        Layers {
+            values: KEYS.iter().map(|key| (key.name, key.default.to_string(), Source::Default)).collect(),
+            ignored: Vec::new(),
+        }
+    }
+
+    fn set(&mut self, name: &str, value: String, source: Source) -> Result<(), ConfigError> {
+        let slot = self.values.iter_mut()
+            .find(|(key, _, _)| *key == name)
+            .ok_or_else(|| ConfigError::UnknownSetting(name.to_string()))?;
+        slot.1 = value;
+        slot.2 = source;
+        Ok(())
+    }
+
+    fn apply_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        let table: toml::Table = text.parse().map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+        let mut flat = Vec::new();
+        flatten("", &toml::Value::Table(table), &mut flat);
+        for (dotted, value) in flat {
+            match KEYS.iter().find(|key| key.toml == Some(dotted.as_str())) {
+                Some(key) => {
+                    // A spec path in the file is relative to the file itself
+                    let value = match path.parent() {
+                        Some(dir) if key.name == "chain_spec" && Path::new(&value).is_relative() && !value.is_empty() => {
+                            dir.join(&value).display().to_string()
+                        }
+                        _ => value,
+                    };
+                    self.set(key.name, value, Source::File(path.to_path_buf()))?
+                }
+                None => self.ignored.push(dotted),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_env(&mut self, env: &dyn Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        for key in KEYS {
+            let var = env_var(key.name);
+            if let Some(value) = env(&var) {
+                self.set(key.name, value, Source::Env(var))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn take(&self, name: &'static str) -> (String, Source) {
+        let (_, value, source) = self.values.iter().find(|(key, _, _)| *key == name).expect("setting is declared in KEYS");
+        (value.clone(), source.clone())
+    }
+}
+
+/// `section.key = value` pairs of a TOML table; arrays become comma lists
+fn flatten(prefix: &str, value: &toml::Value, out: &mut Vec<(String, String)>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let dotted = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&dotted, value, out);
+            }
+        }
+        toml::Value::String(s) => out.push((prefix.to_string(), s.clone())),
+        toml::Value::Array(items) => {
+            let items: Vec<String> = items.iter()
+                .map(|item| item.as_str().map(str::to_string).unwrap_or_else(|| item.to_string()))
+                .collect();
+            out.push((prefix.to_string(), items.join(",")));
+        }
+        other => out.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+/// Resolved configuration of a node
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// Configuration file that was read, if any
+    pub file: Option<PathBuf>,
+    pub network: Setting<Network>,
+    /// Chain spec replacing the network's built-in one
+    pub chain_spec: Setting<Option<PathBuf>>,
+    /// `chain_spec`, loaded and validated
+    pub spec: Option<ChainSpec>,
+    pub data_dir: Setting<PathBuf>,
+    /// HTTP API listen address
+    pub bind: Setting<SocketAddr>,
+    pub p2p_bind: Setting<SocketAddr>,
+    pub max_peers: Setting<usize>,
+    pub min_peers: Setting<usize>,
+    pub net_privacy: Setting<String>,
+    pub log_level: Setting<String>,
+    /// Block store read cache in MB
+    pub db_cache: Setting<usize>,
+    pub verify_threads: Setting<usize>,
+    /// Mempool limit in MB
+    pub mempool_size: Setting<usize>,
+    pub min_fee_per_byte: Setting<u64>,
+    pub rpc: Setting<bool>,
+    pub rpc_user: Setting<Option<String>>,
+    pub rpc_password: Setting<Option<String>>,
+    pub rpc_ssl: Setting<bool>,
+    pub rpc_cert: Setting<Option<PathBuf>>,
+    pub rpc_key: Setting<Option<PathBuf>>,
+    /// Keys of the configuration file this node does not use
+    pub ignored: Vec<String>,
+}
+
+fn invalid<T>(name: &'static str, value: String, source: Source, reason: impl Into<String>) -> Result<T, ConfigError> {
+    Err(ConfigError::Invalid { name, value, source, reason: reason.into() })
+}
+
+/// Parse one raw value, reporting failures against its setting and source
+fn parse<T>(layers: &Layers, name: &'static str, parse: impl FnOnce(&str) -> Result<T, String>) -> Result<Setting<T>, ConfigError> {
+    let (raw, source) = layers.take(name);
+    match parse(raw.trim()) {
+        Ok(value) => Ok(Setting { value, source }),
+        Err(reason) => invalid(name, raw, source, reason),
+    }
+}
+
+fn parse_number<T: std::str::FromStr<Err = std::num::ParseIntError>>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|e: std::num::ParseIntError| e.to_string())
+}
+
+fn parse_bool(s: &str) -> Result<bool, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err("expected true or false".to_string()),
+    }
+}
+
+fn parse_optional(s: &str) -> Result<Option<String>, String> {
+    Ok((!s.is_empty()).then(|| s.to_string()))
+}
+
+fn parse_choice(choices: &'static [&'static str]) -> impl Fn(&str) -> Result<String, String> {
+    move |s| {
+        let s = s.to_ascii_lowercase();
+        if choices.contains(&s.as_str()) {
+            Ok(s)
+        } else {
+            Err(format!("expected one of {}", choices.join(", ")))
+        }
+    }
+}
+
+/// An address, or `default` when unset
+fn parse_addr(default: SocketAddr) -> impl Fn(&str) -> Result<SocketAddr, String> {
+    move |s| if s.is_empty() { Ok(default) } else { s.parse().map_err(|e: std::net::AddrParseError| e.to_string()) }
+}
+
+fn at_least<T: PartialOrd + fmt::Display + Copy>(setting: &Setting<T>, name: &'static str, min: T) -> Result<(), ConfigError> {
+    if setting.value < min {
+        return invalid(name, setting.value.to_string(), setting.source.clone(), format!("must be at least {}", min));
+    }
+    Ok(())
+}
+
+impl NodeConfig {
+    /// Merge defaults, `file`, environment variables (looked up through
+    /// `env`) and command line values (setting name, raw value), in that
+    /// order, and validate the result
+    pub fn resolve(
+        file: Option<&Path>,
+        env: &dyn Fn(&str) -> Option<String>,
+        cli: &[(&str, String)],
+    ) -> Result<Self, ConfigError> {
+        let mut layers = Layers::new();
+        if let Some(path) = file {
+            layers.apply_file(path)?;
+        }
+        layers.apply_env(env)?;
+        for (name, value) in cli {
+            layers.set(name, value.clone(), Source::Cli)?;
+        }
+
+        let network = parse(&layers, "network", |s| match s.to_ascii_lowercase().as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err("expected mainnet, testnet or regtest".to_string()),
+        })?;
+        let chain_spec = parse(&layers, "chain_spec", |s| parse_optional(s).map(|p| p.map(PathBuf::from)))?;
+        let spec = match &chain_spec.value {
+            Some(path) => Some(ChainSpec::load(path).map_err(|e| ConfigError::ChainSpec(path.clone(), e))?),
+            None => None,
+        };
+        // Only read the built-in spec when no other one replaces it
+        let spec_ref = spec.as_ref().unwrap_or_else(|| network.value.spec());
+        let ports = &spec_ref.network;
+        let default_min_fee = spec_ref.consensus.min_tx_fee_per_byte;
+
+        let data_dir = parse(&layers, "data_dir", |s| {
+            if s.is_empty() { Err("must not be empty".to_string()) } else { Ok(PathBuf::from(s)) }
+        })?;
+        let bind = parse(&layers, "bind", |s| s.parse::<SocketAddr>().map_err(|e| e.to_string()))?;
+        let p2p_bind = parse(&layers, "p2p_bind", parse_addr(SocketAddr::from(([0, 0, 0, 0], ports.p2p_port))))?;
+        let max_peers = parse(&layers, "max_peers", parse_number)?;
+        at_least(&max_peers, "max_peers", 1)?;
+        let min_peers: Setting<usize> = parse(&layers, "min_peers", parse_number)?;
+        if min_peers.value > max_peers.value {
+            return invalid("min_peers", min_peers.value.to_string(), min_peers.source, format!("exceeds max_peers {}", max_peers.value));
+        }
+        let net_privacy = parse(&layers, "net_privacy", parse_choice(&PRIVACY_MODES))?;
+        let log_level = parse(&layers, "log_level", parse_choice(&LOG_LEVELS))?;
+        let db_cache = parse(&layers, "db_cache", parse_number)?;
+        let verify_threads = parse(&layers, "verify_threads", parse_number)?;
+        at_least(&verify_threads, "verify_threads", 1)?;
+        let mempool_size = parse(&layers, "mempool_size", parse_number)?;
+        at_least(&mempool_size, "mempool_size", 1)?;
+        let min_fee_per_byte = parse(&layers, "min_fee_per_byte", |s| {
+            if s.is_empty() { Ok(default_min_fee) } else { parse_number(s) }
+        })?;
+        let rpc = parse(&layers, "rpc", parse_bool)?;
+        let rpc_user = parse(&layers, "rpc_user", parse_optional)?;
+        let rpc_password = parse(&layers, "rpc_password", parse_optional)?;
+        if rpc_user.value.is_some() != rpc_password.value.is_some() {
+            let (unset, other) = if rpc_user.value.is_none() { ("rpc_user", &rpc_password) } else { ("rpc_password", &rpc_user) };
+            return invalid(unset, String::new(), other.source.clone(), "rpc_user and rpc_password must be set together");
+        }
+        if rpc_user.value.as_deref().is_some_and(|user| user.contains(':')) {
+            return invalid("rpc_user", rpc_user.value.clone().unwrap_or_default(), rpc_user.source, "must not contain ':'");
+        }
+        // The RPC can ban peers, deploy contracts and submit blocks; it only
+        // answers beyond this machine when it asks for credentials
+        if rpc.value && !bind.value.ip().is_loopback() && rpc_user.value.is_none() {
+            return invalid("bind", bind.value.to_string(), bind.source, "is not a loopback address; set rpc_user and rpc_password to expose the RPC");
+        }
+        let rpc_ssl = parse(&layers, "rpc_ssl", parse_bool)?;
+        let rpc_cert = parse(&layers, "rpc_cert", |s| parse_optional(s).map(|p| p.map(PathBuf::from)))?;
+        let rpc_key = parse(&layers, "rpc_key", |s| parse_optional(s).map(|p| p.map(PathBuf::from)))?;
+        if rpc_ssl.value && (rpc_cert.value.is_none() || rpc_key.value.is_none()) {
+            return invalid("rpc_ssl", "true".to_string(), rpc_ssl.source, "needs rpc_cert and rpc_key");
+        }
+
+        Ok(NodeConfig {
+            file: file.map(Path::to_path_buf),
+            network,
+            chain_spec,
+            spec,
+            data_dir,
+            bind,
+            p2p_bind,
+            max_peers,
+            min_peers,
+            net_privacy,
+            log_level,
+            db_cache,
+            verify_threads,
+            mempool_size,
+            min_fee_per_byte,
+            rpc,
+            rpc_user,
+            rpc_password,
+            rpc_ssl,
+            rpc_cert,
+            rpc_key,
+            ignored: layers.ignored,
+        })
+    }
+
+    /// Every setting as (name, displayed value, source); secrets are masked
+    pub fn entries(&self) -> Vec<(&'static str, String, &Source)> {
+        fn path(value: &Option<PathBuf>) -> String {
+            value.as_ref().map(|p| p.display().to_string()).unwrap_or_default()
+        }
+        vec![
+            ("network", self.network.value.name().to_string(), &self.network.source),
+            ("chain_spec", path(&self.chain_spec.value), &self.chain_spec.source),
+            ("data_dir", self.data_dir.value.display().to_string(), &self.data_dir.source),
+            ("bind", self.bind.value.to_string(), &self.bind.source),
+            ("p2p_bind", self.p2p_bind.value.to_string(), &self.p2p_bind.source),
+            ("max_peers", self.max_peers.value.to_string(), &self.max_peers.source),
+            ("min_peers", self.min_peers.value.to_string(), &self.min_peers.source),
+            ("net_privacy", self.net_privacy.value.clone(), &self.net_privacy.source),
+            ("log_level", self.log_level.value.clone(), &self.log_level.source),
+            ("db_cache", self.db_cache.value.to_string(), &self.db_cache.source),
+            ("verify_threads", self.verify_threads.value.to_string(), &self.verify_threads.source),
+            ("mempool_size", self.mempool_size.value.to_string(), &self.mempool_size.source),
+            ("min_fee_per_byte", self.min_fee_per_byte.value.to_string(), &self.min_fee_per_byte.source),
+            ("rpc", self.rpc.value.to_string(), &self.rpc.source),
+            ("rpc_user", self.rpc_user.value.clone().unwrap_or_default(), &self.rpc_user.source),
+            ("rpc_password", self.rpc_password.value.as_ref().map(|_| "********".to_string()).unwrap_or_default(), &self.rpc_password.source),
+            ("rpc_ssl", self.rpc_ssl.value.to_string(), &self.rpc_ssl.source),
+            ("rpc_cert", path(&self.rpc_cert.value), &self.rpc_cert.source),
+            ("rpc_key", path(&self.rpc_key.value), &self.rpc_key.source),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    fn write_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("blacksilk_node_config_{}_{}.toml", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let path = write_file("layers", r#"
+            [network]
+            max_peers = 40
+            min_peers = 4
+            privacy_mode = "clearnet"
+            enable_nat_traversal = true
+
+            [database]
+            path = "/var/lib/blacksilk"
+
+            [rpc]
+            cors_origins = ["*"]
+        "#);
+        let env = |var: &str| match var {
+            "BLACKSILK_MAX_PEERS" => Some("30".to_string()),
+            "BLACKSILK_NETWORK" => Some("regtest".to_string()),
+            _ => None,
+        };
+        let config = NodeConfig::resolve(Some(&path), &env, &[("max_peers", "20".to_string())]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.max_peers, Setting { value: 20, source: Source::Cli });
+        assert_eq!(config.network.value, Network::Regtest);
+        assert_eq!(config.network.source, Source::Env("BLACKSILK_NETWORK".to_string()));
+        assert_eq!(config.min_peers.value, 4);
+        assert_eq!(config.min_peers.source, Source::File(path.clone()));
+        assert_eq!(config.net_privacy.value, "clearnet");
+        assert_eq!(config.data_dir.value, PathBuf::from("/var/lib/blacksilk"));
+        assert_eq!(config.verify_threads, Setting { value: 4, source: Source::Default });
+        assert_eq!(config.ignored, vec!["network.enable_nat_traversal", "rpc.cors_origins"]);
+
+        // The P2P address and fees follow the network's chain spec; the RPC
+        // address stays where the other tools look for it
+        let regtest = Network::Regtest.spec();
+        assert_eq!(config.bind.value, DEFAULT_RPC_BIND.parse::<SocketAddr>().unwrap());
+        assert_eq!(config.p2p_bind.value.port(), regtest.network.p2p_port);
+        assert_eq!(config.min_fee_per_byte.value, regtest.consensus.min_tx_fee_per_byte);
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        let resolve = |cli: &[(&str, String)]| NodeConfig::resolve(None, &no_env, cli);
+        let err = resolve(&[("max_peers", "many".to_string())]).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid { name: "max_peers", source: Source::Cli, .. }), "{}", err);
+        assert!(matches!(resolve(&[("min_peers", "60".to_string())]), Err(ConfigError::Invalid { name: "min_peers", .. })));
+        assert!(matches!(resolve(&[("log_level", "loud".to_string())]), Err(ConfigError::Invalid { name: "log_level", .. })));
+        assert!(matches!(resolve(&[("rpc_user", "alice".to_string())]), Err(ConfigError::Invalid { name: "rpc_password", .. })));
+        assert!(matches!(resolve(&[("rpc_ssl", "true".to_string())]), Err(ConfigError::Invalid { name: "rpc_ssl", .. })));
+        assert!(matches!(resolve(&[("bind", "localhost".to_string())]), Err(ConfigError::Invalid { name: "bind", .. })));
+        // Exposing the RPC takes credentials
+        let exposed = ("bind", "0.0.0.0:9333".to_string());
+        assert!(matches!(resolve(&[exposed.clone()]), Err(ConfigError::Invalid { name: "bind", .. })));
+        assert!(resolve(&[exposed.clone(), ("rpc", "false".to_string())]).is_ok());
+        let credentials = [("rpc_user", "alice".to_string()), ("rpc_password", "secret".to_string())];
+        assert!(resolve(&[exposed, credentials[0].clone(), credentials[1].clone()]).is_ok());
+        assert!(matches!(resolve(&[("verbosity", "1".to_string())]), Err(ConfigError::UnknownSetting(_))));
+
+        let env = |var: &str| (var == "BLACKSILK_VERIFY_THREADS").then(|| "0".to_string());
+        let err = NodeConfig::resolve(None, &env, &[]).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid { name: "verify_threads", source: Source::Env(_), .. }));
+
+        let path = write_file("malformed", "[network\nmax_peers = 1");
+        assert!(matches!(NodeConfig::resolve(Some(&path), &no_env, &[]), Err(ConfigError::Parse(..))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shipped_configs_resolve() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        for network in ["mainnet", "testnet"] {
+            let path = root.join("config").join(network).join("node_config.toml");
+            let cli = [("network", network.to_string())];
+            let config = NodeConfig::resolve(Some(&path), &no_env, &cli).unwrap();
+            assert_eq!(config.spec.as_ref().unwrap().network_id, format!("blacksilk_{}", network));
+            assert_eq!(config.p2p_bind.value.port(), config.spec.as_ref().unwrap().network.p2p_port);
+            assert_eq!(config.p2p_bind.source, Source::File(path.clone()));
+            // The spec path is relative to the file, not the working directory
+            assert_eq!(config.chain_spec.source, Source::File(path.clone()));
+            assert!(config.bind.value.ip().is_loopback());
+        }
+    }
+}
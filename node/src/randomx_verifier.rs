@@ -36,6 +36,14 @@ pub const RANDOMX_SUSPICIOUS_THRESHOLD: f64 = 0.3; // Flag if < 30% of baseline
 pub const RANDOMX_REJECTION_THRESHOLD: f64 = 0.08; // Reject if < 8% of baseline (stricter)
 pub const RANDOMX_MEMORY_REQUIREMENT_GB: f64 = 2.08; // Full dataset memory requirement
 
+/// RandomX key shared with the miner
+pub const RANDOMX_KEY: &[u8] = b"BlackSilk-RandomX-Key-v1";
+
+lazy_static! {
+    /// Global RandomX verifier with CPU-only enforcement
+    pub static ref RANDOMX_VERIFIER: RandomXVerifier = RandomXVerifier::new();
+}
+
 /// RandomX verification flags (updated for Rust Native implementation)
 #[derive(Debug, Clone, Copy)]
 pub struct RandomXFlags {
@@ -275,26 +283,33 @@ impl RandomXVerifier {
         }
     }
     
+    /// Consensus PoW check used when connecting blocks: recompute the hash and
+    /// compare against the difficulty target. Runs in light mode (the hash is
+    /// identical to full-dataset mode) and skips the timing/ASIC heuristics,
+    /// which are policy for direct submissions rather than consensus rules.
+    pub fn verify_consensus_pow(&self, header: &BlockHeader) -> Result<[u8; 32], String> {
+        let computed_hash = self.compute_randomx_hash_with_flags(header, header.pow.nonce, self.get_native_flags() & !RANDOMX_FLAG_FULL_MEM);
+        if computed_hash != header.pow.hash {
+            return Err("Hash mismatch - recomputed hash differs from claimed hash".to_string());
+        }
+        if !self.check_pow_target(&computed_hash, header.difficulty) {
+            return Err("Hash does not meet difficulty target".to_string());
+        }
+        Ok(computed_hash)
+    }
+    
     /// Compute RandomX hash using Rust Native implementation
     fn compute_randomx_hash(&self, header: &BlockHeader, nonce: u64) -> [u8; 32] {
-        // Prepare input data
-        let mut input = Vec::new();
-        input.extend_from_slice(&header.version.to_le_bytes());
-        input.extend_from_slice(&header.prev_hash);
-        input.extend_from_slice(&header.merkle_root);
-        input.extend_from_slice(&header.timestamp.to_le_bytes());
-        input.extend_from_slice(&header.height.to_le_bytes());
-        input.extend_from_slice(&header.difficulty.to_le_bytes());
-        input.extend_from_slice(&nonce.to_le_bytes());
-        
-        // Generate RandomX key from header
-        let mut key = Vec::new();
-        key.extend_from_slice(&header.prev_hash);
-        key.extend_from_slice(&header.height.to_le_bytes());
+        self.compute_randomx_hash_with_flags(header, nonce, self.get_native_flags())
+    }
+    
+    fn compute_randomx_hash_with_flags(&self, header: &BlockHeader, nonce: u64, flags: u32) -> [u8; 32] {
+        // Canonical header encoding followed by the nonce, exactly as the miner hashes it
+        let input = header.pow_input(nonce);
+        let key = RANDOMX_KEY;
         
         // Use Rust Native RandomX for verification
-        let flags = self.get_native_flags();
-        randomx_hash(&key, &input, flags)
+        randomx_hash(key, &input, flags)
     }
     
     /// Get native RandomX flags for verification
@@ -324,15 +339,7 @@ impl RandomXVerifier {
     
     /// Prepare block header bytes for hashing
     fn prepare_header_bytes(&self, header: &BlockHeader, nonce: u64) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(&header.version.to_le_bytes());
-        bytes.extend_from_slice(&header.prev_hash);
-        bytes.extend_from_slice(&header.merkle_root);
-        bytes.extend_from_slice(&header.timestamp.to_le_bytes());
-        bytes.extend_from_slice(&header.height.to_le_bytes());
-        bytes.extend_from_slice(&header.difficulty.to_le_bytes());
-        bytes.extend_from_slice(&nonce.to_le_bytes());
-        bytes
+        header.pow_input(nonce)
     }
     
     /// Derive RandomX key from header data
@@ -543,14 +550,12 @@ impl RandomXVerifier {
         result
     }
     
-    /// Check if hash meets difficulty target
-    fn check_pow_target(&self, hash: &[u8; 32], target: u64) -> bool {
-        // Convert first 8 bytes of hash to u64 (little-endian)
-        let hash_value = u64::from_le_bytes([
-            hash[0], hash[1], hash[2], hash[3], hash[4], hash[5], hash[6], hash[7]
-        ]);
-        
-        hash_value <= target
+    /// Check if hash meets difficulty target (256-bit target = max / difficulty,
+    /// the same rule the miner uses)
+    fn check_pow_target(&self, hash: &[u8; 32], difficulty: u64) -> bool {
+        let max_target = num_bigint::BigUint::from_bytes_be(&[0xFFu8; 32]);
+        let target = max_target / difficulty.max(1);
+        num_bigint::BigUint::from_bytes_be(hash) <= target
     }
     
     /// Verify CPU timing for suspicious behavior (enhanced production checks)
@@ -0,0 +1,924 @@
+//! Append-only block storage
+//!
+//! Blocks are kept in a single log file, `blocks.dat`, under `<data-dir>/blocks`.
+//! Every record is one atomic batch framed as
+//! `magic | payload length | checksum | payload`, where the payload carries an
+//! optional rollback height followed by the blocks appended on top of it. A
+//! reorganization is written as a single batch, so a crash can never leave
+//! half of a branch on disk.
+//!
+//! Nothing but the log is persisted: on open the log is replayed to rebuild the
+//! height, hash, transaction and key-image indexes. A torn record left at the
+//! end of the log by a crash is truncated away; damage anywhere else refuses
+//! to open until `database repair` is run.
+//!
+//! Maintenance (compaction, pruning, repair) rewrites the log into a fresh file
+//! and swaps it in with a rename. Pruned blocks keep their header and key
+//! images so links and double-spend checks still work without the body.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use primitives::encoding::{sha256, write_varint, Decode, Reader};
+use primitives::types::Hash;
+use primitives::{Block, BlockHeader};
+
+use crate::fork_choice::block_work;
+
+/// Record marker at the start of every batch
+const RECORD_MAGIC: [u8; 4] = *b"BSBK";
+
+/// magic + payload length + checksum
+const RECORD_HEADER_LEN: u64 = 12;
+
+/// Batches larger than this are treated as corruption
+const MAX_RECORD_LEN: u32 = 256 * 1024 * 1024;
+
+/// Entry kinds inside a batch
+const ENTRY_FULL: u8 = 0;
+const ENTRY_PRUNED: u8 = 1;
+
+/// Blocks per record when the log is rewritten
+const REWRITE_BATCH: usize = 256;
+
+/// Directory of the block store inside the node's data directory
+pub const BLOCKS_DIR: &str = "blocks";
+
+/// Name of the block log inside the store directory
+pub const BLOCK_LOG_FILE: &str = "blocks.dat";
+
+/// Number of recent blocks a store-backed `Chain` keeps in memory
+pub const MEMORY_WINDOW: usize = 512;
+
+/// Where a block lives in the log, plus the chain work up to and including it
+#[derive(Debug, Clone)]
+struct IndexEntry {
+    hash: Hash,
+    offset: u64,
+    len: u32,
+    pruned: bool,
+    cumulative_work: u128,
+}
+
+/// Position of a transaction inside the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxLocation {
+    pub height: u64,
+    pub index: usize,
+}
+
+/// Result of replaying the log on open
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    pub records: usize,
+    pub truncated_bytes: u64,
+}
+
+/// Outcome of `BlockStore::check`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    pub checked: u64,
+    /// First invalid height and the reason
+    pub problem: Option<(u64, String)>,
+}
+
+/// Outcome of `BlockStore::repair`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    pub truncated_bytes: u64,
+    pub dropped_blocks: u64,
+    pub blocks: u64,
+}
+
+/// Sizes and counts reported by `database stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    pub blocks: u64,
+    pub pruned_blocks: u64,
+    pub transactions: usize,
+    pub key_images: usize,
+    /// Size of the block log on disk
+    pub log_bytes: u64,
+    /// Bytes of block data still referenced by the index
+    pub live_bytes: u64,
+}
+
+/// A block as kept in the log
+#[derive(Debug, Clone)]
+pub enum StoredBlock {
+    Full(Block),
+    /// Body dropped by pruning; key images are kept for double-spend checks
+    Pruned { header: BlockHeader, key_images: Vec<Hash> },
+}
+
+impl StoredBlock {
+    pub fn header(&self) -> &BlockHeader {
+        match self {
+            StoredBlock::Full(block) => &block.header,
+            StoredBlock::Pruned { header, .. } => header,
+        }
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.header().pow.hash
+    }
+
+    fn key_images(&self) -> Vec<Hash> {
+        match self {
+            StoredBlock::Full(block) => block
+                .transactions
+                .iter()
+                .flat_map(|tx| tx.inputs.iter().map(|input| input.key_image))
+                .collect(),
+            StoredBlock::Pruned { key_images, .. } => key_images.clone(),
+        }
+    }
+
+    fn into_pruned(self) -> StoredBlock {
+        match self {
+            StoredBlock::Full(_) => {
+                let key_images = self.key_images();
+                StoredBlock::Pruned { header: self.header().clone(), key_images }
+            }
+            pruned => pruned,
+        }
+    }
+
+    fn kind(&self) -> u8 {
+        match self {
+            StoredBlock::Full(_) => ENTRY_FULL,
+            StoredBlock::Pruned { .. } => ENTRY_PRUNED,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            StoredBlock::Full(block) => block.encode(),
+            StoredBlock::Pruned { header, key_images } => {
+                let mut out = header.encode();
+                write_varint(&mut out, key_images.len() as u64);
+                for key_image in key_images {
+                    out.extend_from_slice(key_image);
+                }
+                out
+            }
+        }
+    }
+
+    fn decode(kind: u8, bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |e: primitives::encoding::DecodeError| corrupt(format!("undecodable block: {}", e));
+        match kind {
+            ENTRY_FULL => Block::decode(bytes).map(StoredBlock::Full).map_err(invalid),
+            ENTRY_PRUNED => {
+                let mut reader = Reader::new(bytes);
+                let header = BlockHeader::decode_from(&mut reader).map_err(invalid)?;
+                let count = reader.read_varint().map_err(invalid)?;
+                let mut key_images = Vec::new();
+                for _ in 0..count {
+                    key_images.push(reader.read_bytes(32).map_err(invalid)?.try_into().unwrap());
+                }
+                if reader.remaining() != 0 {
+                    return Err(corrupt("trailing bytes in pruned block"));
+                }
+                Ok(StoredBlock::Pruned { header, key_images })
+            }
+            other => Err(corrupt(format!("unknown block entry kind {}", other))),
+        }
+    }
+}
+
+/// Raw entries read recently, keyed by log offset and evicted oldest first
+/// once they exceed `limit` bytes. Offsets are never reused within a log, so
+/// entries only go stale when the log is rewritten.
+#[derive(Debug, Default)]
+struct ReadCache {
+    limit: usize,
+    used: usize,
+    entries: HashMap<u64, Vec<u8>>,
+    order: VecDeque<u64>,
+}
+
+impl ReadCache {
+    fn get(&self, offset: u64) -> Option<Vec<u8>> {
+        self.entries.get(&offset).cloned()
+    }
+
+    fn insert(&mut self, offset: u64, bytes: Vec<u8>) {
+        if bytes.len() > self.limit || self.entries.contains_key(&offset) {
+            return;
+        }
+        self.used += bytes.len();
+        self.entries.insert(offset, bytes);
+        self.order.push_back(offset);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.limit {
+            let Some(offset) = self.order.pop_front() else { break };
+            if let Some(bytes) = self.entries.remove(&offset) {
+                self.used -= bytes.len();
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockStore {
+    dir: PathBuf,
+    file: File,
+    /// Length of the log covered by valid records
+    end: u64,
+    /// Height index
+    entries: Vec<IndexEntry>,
+    /// Hash index
+    heights: HashMap<Hash, u64>,
+    /// Transaction index
+    txs: HashMap<Hash, TxLocation>,
+    /// Key image -> height of the block that spent it
+    key_images: HashMap<Hash, u64>,
+    recovery: RecoveryReport,
+    cache: Mutex<ReadCache>,
+}
+
+fn corrupt(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn invalid_input(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.into())
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let digest = sha256(payload);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+/// Decoded batch layout
+struct Batch {
+    rollback: Option<u64>,
+    /// (kind, offset in payload, length) of each entry
+    entries: Vec<(u8, usize, usize)>,
+}
+
+fn parse_payload(payload: &[u8]) -> Result<Batch, String> {
+    let take = |pos: usize, len: usize| -> Result<&[u8], String> {
+        payload.get(pos..pos + len).ok_or_else(|| "truncated batch payload".to_string())
+    };
+    let rollback = match take(0, 1)?[0] {
+        0 => None,
+        1 => Some(u64::from_le_bytes(take(1, 8)?.try_into().unwrap())),
+        flag => return Err(format!("invalid rollback flag {}", flag)),
+    };
+    let mut pos = 9;
+    let count = u32::from_le_bytes(take(pos, 4)?.try_into().unwrap());
+    pos += 4;
+    let mut entries = Vec::with_capacity(count.min(1024) as usize);
+    for _ in 0..count {
+        let kind = take(pos, 1)?[0];
+        let len = u32::from_le_bytes(take(pos + 1, 4)?.try_into().unwrap()) as usize;
+        pos += 5;
+        take(pos, len)?;
+        entries.push((kind, pos, len));
+        pos += len;
+    }
+    if pos != payload.len() {
+        return Err("trailing bytes in batch payload".to_string());
+    }
+    Ok(Batch { rollback, entries })
+}
+
+/// (offset in record, length) of an entry
+type EntrySpan = (u64, u32);
+
+/// Frame one batch. Returns the record and the span of each entry.
+fn encode_record(rollback_to: Option<u64>, entries: &[(u8, Vec<u8>)]) -> io::Result<(Vec<u8>, Vec<EntrySpan>)> {
+    let mut payload = Vec::new();
+    match rollback_to {
+        Some(height) => {
+            payload.push(1);
+            payload.extend_from_slice(&height.to_le_bytes());
+        }
+        None => payload.extend_from_slice(&[0; 9]),
+    }
+    payload.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    let mut locations = Vec::with_capacity(entries.len());
+    for (kind, bytes) in entries {
+        payload.push(*kind);
+        payload.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        locations.push((RECORD_HEADER_LEN + payload.len() as u64, bytes.len() as u32));
+        payload.extend_from_slice(bytes);
+    }
+    if payload.len() > MAX_RECORD_LEN as usize {
+        return Err(invalid_input("batch too large"));
+    }
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN as usize + payload.len());
+    record.extend_from_slice(&RECORD_MAGIC);
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&checksum(&payload));
+    record.extend_from_slice(&payload);
+    Ok((record, locations))
+}
+
+impl BlockStore {
+    /// Open (or create) the store in `dir`, replaying the log to rebuild the indexes
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        Self::open_with(dir, false)
+    }
+
+    fn open_with(dir: &Path, lenient: bool) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(BLOCK_LOG_FILE))?;
+        let mut store = BlockStore {
+            dir: dir.to_path_buf(),
+            file,
+            end: 0,
+            entries: Vec::new(),
+            heights: HashMap::new(),
+            txs: HashMap::new(),
+            key_images: HashMap::new(),
+            recovery: RecoveryReport::default(),
+            cache: Mutex::default(),
+        };
+        store.replay(lenient)?;
+        Ok(store)
+    }
+
+    /// Replay every record. A bad record at the end of the log is a torn write
+    /// and is truncated; elsewhere it is an error unless `lenient` is set.
+    fn replay(&mut self, lenient: bool) -> io::Result<()> {
+        let file_len = self.file.metadata()?.len();
+        let mut pos = 0u64;
+        while pos < file_len {
+            match self.replay_record(pos, file_len)? {
+                Ok(next) => {
+                    self.recovery.records += 1;
+                    pos = next;
+                }
+                Err(reason) => {
+                    if !lenient && !self.is_torn_tail(pos, file_len)? {
+                        return Err(corrupt(format!(
+                            "block log damaged at offset {}: {} (run `database repair`)",
+                            pos, reason
+                        )));
+                    }
+                    break;
+                }
+            }
+        }
+
+        self.end = pos;
+        if file_len > pos {
+            println!("[Store] Discarding {} byte(s) of unusable data at the end of the block log", file_len - pos);
+            self.file.set_len(pos)?;
+            self.file.sync_all()?;
+            self.recovery.truncated_bytes = file_len - pos;
+        }
+        Ok(())
+    }
+
+    /// Apply the record at `pos`, returning the offset of the next one or why it was refused
+    fn replay_record(&mut self, pos: u64, file_len: u64) -> io::Result<Result<u64, String>> {
+        if file_len - pos < RECORD_HEADER_LEN {
+            return Ok(Err("incomplete record header".to_string()));
+        }
+        let mut header = [0u8; RECORD_HEADER_LEN as usize];
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.file.read_exact(&mut header)?;
+        let len = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if header[..4] != RECORD_MAGIC {
+            return Ok(Err("bad record marker".to_string()));
+        }
+        if len > MAX_RECORD_LEN || pos + RECORD_HEADER_LEN + len as u64 > file_len {
+            return Ok(Err("record extends past the end of the log".to_string()));
+        }
+        let mut payload = vec![0u8; len as usize];
+        self.file.read_exact(&mut payload)?;
+        if header[8..12] != checksum(&payload) {
+            return Ok(Err("checksum mismatch".to_string()));
+        }
+        let batch = match parse_payload(&payload) {
+            Ok(batch) => batch,
+            Err(reason) => return Ok(Err(reason)),
+        };
+
+        // Decode and check ordering before touching the indexes
+        let payload_offset = pos + RECORD_HEADER_LEN;
+        let mut decoded = Vec::with_capacity(batch.entries.len());
+        for &(kind, start, len) in &batch.entries {
+            match StoredBlock::decode(kind, &payload[start..start + len]) {
+                Ok(stored) => decoded.push((stored, payload_offset + start as u64, len as u32)),
+                Err(e) => return Ok(Err(e.to_string())),
+            }
+        }
+        if batch.rollback.is_some_and(|height| height >= self.len()) {
+            return Ok(Err("rollback past the stored tip".to_string()));
+        }
+        let first_height = batch.rollback.map_or(self.len(), |h| h + 1);
+        for (i, (stored, _, _)) in decoded.iter().enumerate() {
+            if stored.header().height != first_height + i as u64 {
+                return Ok(Err(format!("block at height {} out of order", stored.header().height)));
+            }
+        }
+
+        if let Some(height) = batch.rollback {
+            self.unindex_above(height)?;
+        }
+        for (stored, offset, len) in &decoded {
+            self.index_entry(stored, *offset, *len);
+        }
+        Ok(Ok(pos + RECORD_HEADER_LEN + len as u64))
+    }
+
+    /// Whether the bad data at `pos` is an interrupted final write rather than damage
+    fn is_torn_tail(&self, pos: u64, file_len: u64) -> io::Result<bool> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(pos))?;
+        if file_len - pos >= RECORD_HEADER_LEN {
+            let mut header = [0u8; RECORD_HEADER_LEN as usize];
+            file.read_exact(&mut header)?;
+            let len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+            if header[..4] == RECORD_MAGIC {
+                return Ok(pos + RECORD_HEADER_LEN + len >= file_len);
+            }
+            file.seek(SeekFrom::Start(pos))?;
+        }
+        // Preallocated but never written space reads back as zeros
+        let mut chunk = vec![0u8; 64 * 1024];
+        loop {
+            let read = file.read(&mut chunk)?;
+            if read == 0 {
+                return Ok(true);
+            }
+            if chunk[..read].iter().any(|b| *b != 0) {
+                return Ok(false);
+            }
+        }
+    }
+
+    fn index_entry(&mut self, stored: &StoredBlock, offset: u64, len: u32) {
+        let height = stored.header().height;
+        let cumulative_work = self.total_work() + block_work(stored.header());
+        let pruned = matches!(stored, StoredBlock::Pruned { .. });
+        self.entries.push(IndexEntry { hash: stored.hash(), offset, len, pruned, cumulative_work });
+        self.heights.insert(stored.hash(), height);
+        if let StoredBlock::Full(block) = stored {
+            for (index, tx) in block.transactions.iter().enumerate() {
+                self.txs.insert(tx.id(), TxLocation { height, index });
+            }
+        }
+        for key_image in stored.key_images() {
+            self.key_images.insert(key_image, height);
+        }
+    }
+
+    /// Drop every index entry for blocks above `height`
+    fn unindex_above(&mut self, height: u64) -> io::Result<()> {
+        while self.len() > height + 1 {
+            let stored = self.read_stored(self.len() - 1)?;
+            if let StoredBlock::Full(block) = &stored {
+                for tx in &block.transactions {
+                    self.txs.remove(&tx.id());
+                }
+            }
+            for key_image in stored.key_images() {
+                self.key_images.remove(&key_image);
+            }
+            self.heights.remove(&stored.hash());
+            self.entries.pop();
+        }
+        Ok(())
+    }
+
+    fn read_stored(&self, height: u64) -> io::Result<StoredBlock> {
+        let entry = self.entries.get(height as usize).ok_or_else(|| corrupt(format!("no block at height {}", height)))?;
+        let cached = self.cache.lock().unwrap().get(entry.offset);
+        let buf = match cached {
+            Some(buf) => buf,
+            None => {
+                let mut buf = vec![0u8; entry.len as usize];
+                let mut file = &self.file;
+                file.seek(SeekFrom::Start(entry.offset))?;
+                file.read_exact(&mut buf)?;
+                self.cache.lock().unwrap().insert(entry.offset, buf.clone());
+                buf
+            }
+        };
+        let kind = if entry.pruned { ENTRY_PRUNED } else { ENTRY_FULL };
+        StoredBlock::decode(kind, &buf).map_err(|e| corrupt(format!("block {} is unreadable: {}", height, e)))
+    }
+
+    /// Atomically roll back to `rollback_to` (if given) and append `blocks`
+    pub fn write_batch(&mut self, rollback_to: Option<u64>, blocks: &[Block]) -> io::Result<()> {
+        if let Some(height) = rollback_to {
+            if height >= self.len() {
+                return Err(invalid_input(format!("cannot roll back to unknown height {}", height)));
+            }
+            if self.entries[height as usize..].iter().any(|e| e.pruned) {
+                return Err(invalid_input(format!("cannot roll back into pruned blocks at height {}", height)));
+            }
+        }
+        let first_height = rollback_to.map_or(self.len(), |h| h + 1);
+        for (offset, block) in blocks.iter().enumerate() {
+            let expected = first_height + offset as u64;
+            if block.header.height != expected {
+                return Err(invalid_input(format!("expected block at height {}, got {}", expected, block.header.height)));
+            }
+        }
+
+        let entries: Vec<(u8, Vec<u8>)> = blocks.iter().map(|b| (ENTRY_FULL, b.encode())).collect();
+        let (record, locations) = encode_record(rollback_to, &entries)?;
+        self.file.seek(SeekFrom::Start(self.end))?;
+        if let Err(e) = self.file.write_all(&record).and_then(|_| self.file.sync_data()) {
+            // Leave no partial record behind
+            let _ = self.file.set_len(self.end);
+            return Err(e);
+        }
+        let record_offset = self.end;
+        self.end += record.len() as u64;
+
+        if let Some(height) = rollback_to {
+            self.unindex_above(height)?;
+        }
+        for (block, (offset, len)) in blocks.iter().zip(locations) {
+            self.index_entry(&StoredBlock::Full(block.clone()), record_offset + offset, len);
+        }
+        Ok(())
+    }
+
+    pub fn append_block(&mut self, block: &Block) -> io::Result<()> {
+        self.write_batch(None, std::slice::from_ref(block))
+    }
+
+    /// Keep up to `bytes` of recently read blocks in memory (0 disables)
+    pub fn set_cache_limit(&mut self, bytes: usize) {
+        let cache = self.cache.get_mut().unwrap();
+        cache.limit = bytes;
+        cache.evict();
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Number of blocks on the stored main chain
+    pub fn len(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Height of the stored tip
+    pub fn tip_height(&self) -> Option<u64> {
+        self.len().checked_sub(1)
+    }
+
+    /// Size of the block log in bytes
+    pub fn size_on_disk(&self) -> u64 {
+        self.end
+    }
+
+    pub fn recovery(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// Full block at `height`; `None` if it does not exist or its body was pruned
+    pub fn get_block(&self, height: u64) -> io::Result<Option<Block>> {
+        if height >= self.len() {
+            return Ok(None);
+        }
+        match self.read_stored(height)? {
+            StoredBlock::Full(block) => Ok(Some(block)),
+            StoredBlock::Pruned { .. } => Ok(None),
+        }
+    }
+
+    /// Header at `height`, available for pruned blocks too
+    pub fn get_header(&self, height: u64) -> io::Result<Option<BlockHeader>> {
+        if height >= self.len() {
+            return Ok(None);
+        }
+        Ok(Some(self.read_stored(height)?.header().clone()))
+    }
+
+    pub fn hash_at(&self, height: u64) -> Option<Hash> {
+        self.entries.get(height as usize).map(|e| e.hash)
+    }
+
+    pub fn height_of(&self, hash: &Hash) -> Option<u64> {
+        self.heights.get(hash).copied()
+    }
+
+    pub fn get_block_by_hash(&self, hash: &Hash) -> io::Result<Option<Block>> {
+        match self.height_of(hash) {
+            Some(height) => self.get_block(height),
+            None => Ok(None),
+        }
+    }
+
+    pub fn transaction_location(&self, txid: &Hash) -> Option<TxLocation> {
+        self.txs.get(txid).copied()
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.txs.len()
+    }
+
+    /// Height at which a key image was spent
+    pub fn key_image_height(&self, key_image: &Hash) -> Option<u64> {
+        self.key_images.get(key_image).copied()
+    }
+
+    pub fn key_images(&self) -> impl Iterator<Item = (&Hash, &u64)> {
+        self.key_images.iter()
+    }
+
+    /// Work of the stored chain up to and including `height`
+    pub fn work_at(&self, height: u64) -> Option<u128> {
+        self.entries.get(height as usize).map(|e| e.cumulative_work)
+    }
+
+    pub fn total_work(&self) -> u128 {
+        self.entries.last().map_or(0, |e| e.cumulative_work)
+    }
+
+    /// Number of leading blocks whose bodies have been pruned
+    pub fn pruned_height(&self) -> u64 {
+        self.entries.iter().take_while(|e| e.pruned).count() as u64
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        StoreStats {
+            blocks: self.len(),
+            pruned_blocks: self.pruned_height(),
+            transactions: self.txs.len(),
+            key_images: self.key_images.len(),
+            log_bytes: self.end,
+            live_bytes: self.entries.iter().map(|e| e.len as u64).sum(),
+        }
+    }
+
+    /// Walk the stored chain verifying heights, hash links and merkle roots
+    pub fn check(&self) -> CheckReport {
+        let mut report = CheckReport::default();
+        let mut prev_hash = None;
+        for height in 0..self.len() {
+            let problem = match self.read_stored(height) {
+                Err(e) => Some(e.to_string()),
+                Ok(stored) => {
+                    let header = stored.header();
+                    let problem = if header.height != height {
+                        Some(format!("header claims height {}", header.height))
+                    } else if prev_hash.is_some_and(|prev| header.prev_hash != prev) {
+                        Some("does not link to the previous block".to_string())
+                    } else if let StoredBlock::Full(block) = &stored {
+                        (crate::calculate_merkle_root(&block.transactions) != header.merkle_root)
+                            .then(|| "merkle root does not match transactions".to_string())
+                    } else {
+                        None
+                    };
+                    prev_hash = Some(stored.hash());
+                    problem
+                }
+            };
+            if let Some(reason) = problem {
+                report.problem = Some((height, reason));
+                break;
+            }
+            report.checked += 1;
+        }
+        report
+    }
+
+    /// Write blocks `0..count` into a fresh log, pruning bodies below
+    /// `prune_below`, then swap it in and reload the indexes
+    fn rewrite(&mut self, count: u64, prune_below: u64) -> io::Result<()> {
+        let tmp_path = self.dir.join(format!("{}.tmp", BLOCK_LOG_FILE));
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        let mut batch = Vec::with_capacity(REWRITE_BATCH);
+        for height in 0..count {
+            let mut stored = self.read_stored(height)?;
+            if height < prune_below {
+                stored = stored.into_pruned();
+            }
+            batch.push((stored.kind(), stored.encode()));
+            if batch.len() == REWRITE_BATCH || height + 1 == count {
+                out.write_all(&encode_record(None, &batch)?.0)?;
+                batch.clear();
+            }
+        }
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp_path, self.dir.join(BLOCK_LOG_FILE))?;
+        let cache_limit = self.cache.lock().unwrap().limit;
+        *self = Self::open(&self.dir.clone())?;
+        self.set_cache_limit(cache_limit);
+        Ok(())
+    }
+
+    /// Rewrite the log without rolled-back blocks. Returns the bytes reclaimed.
+    pub fn compact(&mut self) -> io::Result<u64> {
+        let before = self.end;
+        self.rewrite(self.len(), self.pruned_height())?;
+        Ok(before.saturating_sub(self.end))
+    }
+
+    /// Drop block bodies, keeping only the most recent `keep` blocks intact.
+    /// Returns the number of blocks newly pruned.
+    pub fn prune(&mut self, keep: u64) -> io::Result<u64> {
+        if keep < MEMORY_WINDOW as u64 {
+            return Err(invalid_input(format!("must keep at least {} blocks", MEMORY_WINDOW)));
+        }
+        let already = self.pruned_height();
+        let prune_below = self.len().saturating_sub(keep).max(already);
+        if prune_below == already {
+            return Ok(0);
+        }
+        self.rewrite(self.len(), prune_below)?;
+        Ok(prune_below - already)
+    }
+
+    /// Open a damaged store, truncating at the first unreadable record and
+    /// dropping every block from the first one that fails `check`
+    pub fn repair(dir: &Path) -> io::Result<(Self, RepairReport)> {
+        let mut store = Self::open_with(dir, true)?;
+        let mut report = RepairReport { truncated_bytes: store.recovery.truncated_bytes, ..Default::default() };
+        if let Some((height, reason)) = store.check().problem {
+            println!("[Store] Block {} is invalid ({}), truncating the chain below it", height, reason);
+            report.dropped_blocks = store.len() - height;
+            store.rewrite(height, store.pruned_height().min(height))?;
+        } else if report.truncated_bytes > 0 {
+            // Rebuild from a clean log even if only the tail was damaged
+            store.compact()?;
+        }
+        report.blocks = store.len();
+        Ok((store, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use primitives::{Coinbase, Pow, RingSignature, TransactionInput};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("blacksilk-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn hash_of(height: u64, tag: u8) -> Hash {
+        let mut hash = [tag; 32];
+        hash[..8].copy_from_slice(&height.to_le_bytes());
+        hash
+    }
+
+    /// Block linked to `block(height - 1, tag)` whose coinbase carries key image `hash_of(height, tag)`
+    fn block(height: u64, tag: u8) -> Block {
+        let mut coinbase = crate::coinbase_transaction(height, &format!("miner-{}", tag));
+        coinbase.inputs.push(TransactionInput {
+            key_image: hash_of(height, tag),
+            ring_sig: RingSignature { ring: vec![], signature: vec![], quantum: None },
+        });
+        let transactions = vec![coinbase];
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_hash: if height == 0 { [0; 32] } else { hash_of(height - 1, tag) },
+                merkle_root: crate::calculate_merkle_root(&transactions),
+                timestamp: height * 120,
+                height,
+                difficulty: 1,
+                pow: Pow { nonce: height, hash: hash_of(height, tag) },
+            },
+            coinbase: Coinbase { reward: 1, to: "miner".to_string() },
+            transactions,
+        }
+    }
+
+    #[test]
+    fn test_store_reopens_with_indexes() {
+        let dir = temp_dir("reopen");
+        {
+            let mut store = BlockStore::open(&dir).unwrap();
+            store.write_batch(None, &[block(0, 0), block(1, 0), block(2, 0)]).unwrap();
+            // Reorg the last block away
+            store.write_batch(Some(1), &[block(2, 7), block(3, 7)]).unwrap();
+        }
+        let store = BlockStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 4);
+        assert_eq!(store.recovery().records, 2);
+        assert_eq!(store.hash_at(2), Some(block(2, 7).hash()));
+        assert_eq!(store.height_of(&block(2, 0).hash()), None);
+        assert_eq!(store.get_block(3).unwrap().unwrap().hash(), block(3, 7).hash());
+        let txid = block(3, 7).transactions[0].id();
+        assert_eq!(store.transaction_location(&txid), Some(TxLocation { height: 3, index: 0 }));
+        assert!(store.transaction_location(&block(2, 0).transactions[0].id()).is_none());
+        assert_eq!(store.total_work(), 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_is_truncated() {
+        let dir = temp_dir("torn");
+        let good_len = {
+            let mut store = BlockStore::open(&dir).unwrap();
+            store.write_batch(None, &[block(0, 0), block(1, 0)]).unwrap();
+            let good_len = store.size_on_disk();
+            store.append_block(&block(2, 0)).unwrap();
+            good_len
+        };
+        // Simulate a crash halfway through the last record
+        let path = dir.join(BLOCK_LOG_FILE);
+        let full_len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(full_len - 5).unwrap();
+
+        let mut store = BlockStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.size_on_disk(), good_len);
+        assert_eq!(store.recovery().truncated_bytes, full_len - 5 - good_len);
+        store.append_block(&block(2, 1)).unwrap();
+        drop(store);
+        assert_eq!(BlockStore::open(&dir).unwrap().len(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_damage_inside_log_requires_repair() {
+        let dir = temp_dir("repair");
+        let damaged_at = {
+            let mut store = BlockStore::open(&dir).unwrap();
+            store.write_batch(None, &[block(0, 0), block(1, 0)]).unwrap();
+            let damaged_at = store.size_on_disk();
+            store.append_block(&block(2, 0)).unwrap();
+            store.append_block(&block(3, 0)).unwrap();
+            assert!(store.append_block(&block(5, 0)).is_err());
+            damaged_at
+        };
+        // Flip a byte inside the payload of the record holding block 2
+        let path = dir.join(BLOCK_LOG_FILE);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[damaged_at as usize + RECORD_HEADER_LEN as usize + 20] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let err = BlockStore::open(&dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let (store, report) = BlockStore::repair(&dir).unwrap();
+        assert_eq!(store.len(), 2);
+        assert!(report.truncated_bytes > 0);
+        assert_eq!(store.check(), CheckReport { checked: 2, problem: None });
+        drop(store);
+        assert_eq!(BlockStore::open(&dir).unwrap().len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_and_compact_keep_headers_and_key_images() {
+        let dir = temp_dir("prune");
+        let mut store = BlockStore::open(&dir).unwrap();
+        let blocks: Vec<Block> = (0..MEMORY_WINDOW as u64 + 20).map(|h| block(h, 0)).collect();
+        store.write_batch(None, &blocks).unwrap();
+        // Cached reads must not survive the rewrites below
+        store.set_cache_limit(4096);
+        for height in 0..blocks.len() as u64 {
+            assert_eq!(store.get_block(height).unwrap().unwrap().header.height, height);
+        }
+        assert!(store.cache.lock().unwrap().used <= 4096);
+        // Leave a rolled-back block behind for compaction to reclaim
+        store.write_batch(Some(blocks.len() as u64 - 2), &[blocks.last().unwrap().clone()]).unwrap();
+        assert!(store.compact().unwrap() > 0);
+
+        assert!(store.prune(10).is_err());
+        assert_eq!(store.prune(MEMORY_WINDOW as u64).unwrap(), 20);
+        assert_eq!(store.prune(MEMORY_WINDOW as u64).unwrap(), 0);
+        assert!(store.get_block(5).unwrap().is_none());
+        assert_eq!(store.get_header(5).unwrap().unwrap().height, 5);
+        assert_eq!(store.key_image_height(&hash_of(5, 0)), Some(5));
+        assert!(store.transaction_location(&blocks[5].transactions[0].id()).is_none());
+        assert!(store.get_block(20).unwrap().is_some());
+
+        let before = store.stats();
+        assert_eq!(store.compact().unwrap(), 0);
+        assert_eq!(store.stats(), before);
+        assert_eq!(store.check().problem, None);
+
+        drop(store);
+        let store = BlockStore::open(&dir).unwrap();
+        assert_eq!(store.pruned_height(), 20);
+        assert_eq!(store.total_work(), blocks.len() as u128);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,439 @@
+//! Headers-first block download
+//!
+//! A node catching up first asks its peers for headers (`GetHeaders` with a
+//! block locator) and validates them on their own: linkage, height,
+//! difficulty, timestamps and proof of work. Only once a header chain is known
+//! are the bodies requested with `GetBlockData`, in batches of
+//! `BLOCKS_PER_REQUEST` spread across every peer that has them. Bodies may
+//! arrive out of order; they are buffered and handed to the chain strictly in
+//! header order.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use primitives::types::Hash;
+use primitives::{Block, BlockHeader};
+
+use crate::fork_choice::BlockAcceptance;
+use crate::network::peer_manager::{penalty, PeerManager};
+use crate::{config, BlockValidationError, Chain, P2PMessage};
+
+/// Maximum headers returned for a single `GetHeaders`
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+
+/// Block bodies requested from one peer at a time
+pub const BLOCKS_PER_REQUEST: usize = 16;
+
+/// Bodies are only requested this far ahead of the next block to apply,
+/// which bounds the memory used for out-of-order blocks
+pub const DOWNLOAD_WINDOW: u64 = 1024;
+
+/// A peer that has not answered a block request by then loses it to another peer
+pub const BLOCK_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Peers are identified by their socket address
+pub type PeerId = String;
+
+#[derive(Debug)]
+pub enum SyncError {
+    /// The headers do not build on our chain or on the headers we have
+    Unconnected { height: u64 },
+    InvalidHeader { height: u64, error: BlockValidationError },
+}
+
+impl std::fmt::Display for SyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncError::Unconnected { height } => write!(f, "header {} does not connect to a known block", height),
+            SyncError::InvalidHeader { height, error } => write!(f, "header {} rejected: {}", height, error),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+/// Snapshot of the download, reported by `/info` and `blacksilk-node sync`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncProgress {
+    /// Caught up with every peer that has reported its chain
+    pub synced: bool,
+    pub local_height: u64,
+    /// Highest validated header
+    pub header_height: u64,
+    /// Highest block any peer is known to have
+    pub target_height: u64,
+    pub blocks_in_flight: usize,
+    pub downloading_peers: usize,
+    pub percent: f64,
+}
+
+struct BlockRequest {
+    hashes: Vec<Hash>,
+    sent: Instant,
+}
+
+/// Header chain and body download bookkeeping
+#[derive(Default)]
+pub struct SyncManager {
+    /// Validated headers whose blocks have not been handed to the chain yet,
+    /// oldest first. The first one builds on a main-chain block.
+    headers: VecDeque<BlockHeader>,
+    /// Heights of the headers above, by hash
+    index: HashMap<Hash, u64>,
+    /// Bodies waiting to be requested, by height
+    pending: BTreeMap<u64, Hash>,
+    in_flight: HashMap<PeerId, BlockRequest>,
+    /// Bodies received ahead of the next block to apply
+    bodies: HashMap<Hash, Block>,
+    /// Best height each peer is known to have
+    peer_heights: HashMap<PeerId, u64>,
+}
+
+/// Header rules that need no block body: linkage, height, difficulty,
+/// timestamps and proof of work. `header_at` looks up the headers the new
+/// one builds on by height.
+pub fn check_header(
+    chain: &Chain,
+    header: &BlockHeader,
+    parent_height: u64,
+    header_at: impl Fn(u64) -> Option<BlockHeader>,
+) -> Result<(), BlockValidationError> {
+    if header.height != parent_height + 1 {
+        return Err(BlockValidationError::InvalidHeight { expected: parent_height + 1, got: header.height });
+    }
+    let window = chain.network.spec().consensus.difficulty_adjustment_window as usize;
+    let span = (window + 1).max(config::MEDIAN_TIME_SPAN);
+    let recent: Vec<BlockHeader> = (0..span as u64)
+        .map_while(|back| parent_height.checked_sub(back))
+        .map_while(&header_at)
+        .collect();
+    let Some(parent) = recent.first() else {
+        return Err(BlockValidationError::InvalidPrevHash);
+    };
+    if parent.pow.hash != header.prev_hash {
+        return Err(BlockValidationError::InvalidPrevHash);
+    }
+
+    let recent_refs: Vec<&BlockHeader> = recent.iter().take(window + 1).collect();
+    let expected_difficulty = chain.network.next_difficulty(&recent_refs);
+    if header.difficulty != expected_difficulty {
+        return Err(BlockValidationError::InvalidDifficulty { expected: expected_difficulty, got: header.difficulty });
+    }
+
+    let mut times: Vec<u64> = recent.iter().take(config::MEDIAN_TIME_SPAN).map(|h| h.timestamp).collect();
+    times.sort_unstable();
+    let median_time_past = times[times.len() / 2];
+    if header.timestamp <= median_time_past {
+        return Err(BlockValidationError::TimestampTooOld { timestamp: header.timestamp, median_time_past });
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let max_allowed = now + config::MAX_FUTURE_BLOCK_TIME_SEC;
+    if header.timestamp > max_allowed {
+        return Err(BlockValidationError::TimestampTooFarInFuture { timestamp: header.timestamp, max_allowed });
+    }
+
+    if !chain.skip_pow_check {
+        crate::randomx_verifier::RANDOMX_VERIFIER
+            .verify_consensus_pow(header)
+            .map_err(BlockValidationError::InvalidPow)?;
+    }
+    Ok(())
+}
+
+impl SyncManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hash of the header or main-chain block at `height`
+    fn hash_at(&self, chain: &Chain, height: u64) -> Option<Hash> {
+        self.header_at(chain, height).map(|h| h.pow.hash)
+    }
+
+    fn header_at(&self, chain: &Chain, height: u64) -> Option<BlockHeader> {
+        match self.headers.front() {
+            Some(first) if height >= first.height => self.headers.get((height - first.height) as usize).cloned(),
+            _ => chain.header_at(height),
+        }
+    }
+
+    /// Height of the best header we know, validated or on the main chain
+    pub fn best_header_height(&self, chain: &Chain) -> u64 {
+        self.headers.back().map_or(chain.tip().header.height, |h| h.height)
+    }
+
+    /// Block locator: the last ten hashes, then exponentially sparser ones back to genesis
+    pub fn locator(&self, chain: &Chain) -> Vec<Hash> {
+        let mut locator = Vec::new();
+        let mut height = self.best_header_height(chain);
+        let mut step = 1;
+        loop {
+            if let Some(hash) = self.hash_at(chain, height) {
+                locator.push(hash);
+            }
+            if height == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        locator
+    }
+
+    /// Validate and store headers received from `peer`. Returns how many were new.
+    pub fn on_headers(&mut self, chain: &Chain, peer: &str, headers: &[BlockHeader]) -> Result<usize, SyncError> {
+        // A peer with nothing past our locator is at most as far as we are
+        let peer_height = self.peer_heights.entry(peer.to_string()).or_insert(0);
+        *peer_height = (*peer_height).max(chain.tip().header.height);
+
+        let mut added = 0;
+        for header in headers {
+            let hash = header.pow.hash;
+            if self.index.contains_key(&hash) || chain.contains_block(&hash) {
+                self.note_peer_height(peer, header.height);
+                continue;
+            }
+            let parent_height = match self.headers.back() {
+                Some(last) if last.pow.hash == header.prev_hash => last.height,
+                Some(_) => return Err(SyncError::Unconnected { height: header.height }),
+                None => chain.height_of(&header.prev_hash).ok_or(SyncError::Unconnected { height: header.height })?,
+            };
+            check_header(chain, header, parent_height, |height| self.header_at(chain, height))
+                .map_err(|error| SyncError::InvalidHeader { height: header.height, error })?;
+            self.index.insert(hash, header.height);
+            self.pending.insert(header.height, hash);
+            self.headers.push_back(header.clone());
+            self.note_peer_height(peer, header.height);
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    pub fn note_peer_height(&mut self, peer: &str, height: u64) {
+        let known = self.peer_heights.entry(peer.to_string()).or_insert(0);
+        *known = (*known).max(height);
+    }
+
+    /// Next batch of bodies to request from `peer`, if it is idle and has any we need
+    pub fn next_request(&mut self, peer: &str) -> Option<Vec<Hash>> {
+        if self.in_flight.contains_key(peer) {
+            return None;
+        }
+        let peer_height = *self.peer_heights.get(peer)?;
+        let first_unapplied = self.headers.front()?.height;
+        let limit = peer_height.min(first_unapplied + DOWNLOAD_WINDOW - 1);
+        let heights: Vec<u64> = self.pending.range(..=limit).take(BLOCKS_PER_REQUEST).map(|(h, _)| *h).collect();
+        if heights.is_empty() {
+            return None;
+        }
+        let hashes: Vec<Hash> = heights.iter().filter_map(|h| self.pending.remove(h)).collect();
+        self.in_flight.insert(peer.to_string(), BlockRequest { hashes: hashes.clone(), sent: Instant::now() });
+        Some(hashes)
+    }
+
+    /// Take the bodies `peer` was asked for out of `blocks`. Anything it did
+    /// not deliver goes back to the queue; blocks that were never requested
+    /// are returned to the caller.
+    pub fn on_blocks(&mut self, peer: &str, blocks: Vec<Block>) -> Vec<Block> {
+        let Some(request) = self.in_flight.remove(peer) else {
+            return blocks;
+        };
+        let mut outstanding: HashSet<Hash> = request.hashes.iter().copied().collect();
+        let mut unrequested = Vec::new();
+        for block in blocks {
+            let hash = block.hash();
+            let matches_header = self.index.get(&hash)
+                .and_then(|height| self.headers.get((height - self.headers.front()?.height) as usize))
+                .is_some_and(|header| header.encode() == block.header.encode());
+            if outstanding.contains(&hash) && matches_header {
+                outstanding.remove(&hash);
+                self.bodies.insert(hash, block);
+            } else {
+                unrequested.push(block);
+            }
+        }
+        self.requeue(outstanding);
+        unrequested
+    }
+
+    fn requeue(&mut self, hashes: impl IntoIterator<Item = Hash>) {
+        for hash in hashes {
+            if let Some(height) = self.index.get(&hash) {
+                self.pending.insert(*height, hash);
+            }
+        }
+    }
+
+    /// Bodies that can be applied now, in chain order
+    pub fn take_ready(&mut self) -> Vec<Block> {
+        let mut ready = Vec::new();
+        while let Some(block) = self.headers.front().and_then(|h| self.bodies.remove(&h.pow.hash)) {
+            self.headers.pop_front();
+            self.index.remove(&block.hash());
+            ready.push(block);
+        }
+        ready
+    }
+
+    /// Release requests that have been outstanding too long; returns the peers that timed out
+    pub fn expire_requests(&mut self, now: Instant) -> Vec<PeerId> {
+        let expired: Vec<PeerId> = self.in_flight
+            .iter()
+            .filter(|(_, request)| now.duration_since(request.sent) >= BLOCK_REQUEST_TIMEOUT)
+            .map(|(peer, _)| peer.clone())
+            .collect();
+        for peer in &expired {
+            if let Some(request) = self.in_flight.remove(peer) {
+                self.requeue(request.hashes);
+            }
+        }
+        expired
+    }
+
+    pub fn peer_disconnected(&mut self, peer: &str) {
+        self.peer_heights.remove(peer);
+        if let Some(request) = self.in_flight.remove(peer) {
+            self.requeue(request.hashes);
+        }
+    }
+
+    /// Drop the header chain, e.g. after one of its blocks failed validation
+    pub fn reset(&mut self) {
+        self.headers.clear();
+        self.index.clear();
+        self.pending.clear();
+        self.in_flight.clear();
+        self.bodies.clear();
+    }
+
+    pub fn progress(&self, chain: &Chain) -> SyncProgress {
+        let local_height = chain.tip().header.height;
+        let header_height = self.best_header_height(chain);
+        let target_height = self.peer_heights.values().copied().max().unwrap_or(local_height).max(header_height);
+        SyncProgress {
+            synced: !self.peer_heights.is_empty() && self.headers.is_empty() && local_height >= target_height,
+            local_height,
+            header_height,
+            target_height,
+            blocks_in_flight: self.in_flight.values().map(|r| r.hashes.len()).sum(),
+            downloading_peers: self.in_flight.len(),
+            percent: if target_height == 0 { 100.0 } else { local_height.min(target_height) as f64 * 100.0 / target_height as f64 },
+        }
+    }
+}
+
+impl Chain {
+    /// Main-chain headers following the first locator hash we recognise
+    pub fn headers_after(&self, locator: &[Hash], max: usize) -> Vec<BlockHeader> {
+        let start = locator.iter().find_map(|hash| self.height_of(hash)).unwrap_or(0) + 1;
+        let end = self.tip().header.height.min(start + max as u64 - 1);
+        (start..=end).map_while(|height| self.header_at(height)).collect()
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref SYNC: Mutex<SyncManager> = Mutex::new(SyncManager::new());
+}
+
+/// Current sync state of the global chain
+pub fn progress() -> SyncProgress {
+    let chain = crate::CHAIN.lock().unwrap();
+    SYNC.lock().unwrap().progress(&chain)
+}
+
+/// Ask `peer` for the headers after our best known header
+pub fn request_headers(manager: &PeerManager, peer: SocketAddr) {
+    let locator = {
+        let chain = crate::CHAIN.lock().unwrap();
+        SYNC.lock().unwrap().locator(&chain)
+    };
+    manager.send(peer, P2PMessage::GetHeaders { locator });
+}
+
+/// Feed a `Headers` message from `peer` into the header chain, continue
+/// with the next batch of headers if the peer has more, and start fetching bodies
+pub fn handle_headers(manager: &PeerManager, peer: SocketAddr, headers: Vec<BlockHeader>) {
+    let result = {
+        let chain = crate::CHAIN.lock().unwrap();
+        SYNC.lock().unwrap().on_headers(&chain, &peer.to_string(), &headers)
+    };
+    match result {
+        Ok(added) => {
+            if added > 0 {
+                println!("[Sync] {} new header(s) from {}, best header {}",
+                    added, peer, headers.last().map_or(0, |h| h.height));
+            }
+            if headers.len() == MAX_HEADERS_PER_MESSAGE {
+                request_headers(manager, peer);
+            }
+        }
+        Err(e @ SyncError::InvalidHeader { .. }) => {
+            println!("[Sync] Headers from {} rejected: {}", peer, e);
+            manager.misbehaving(peer, penalty::INVALID_HEADERS, &e.to_string());
+        }
+        Err(e) => println!("[Sync] Headers from {} rejected: {}", peer, e),
+    }
+    schedule_downloads(manager);
+}
+
+/// Route a `Blocks` message: requested bodies go to the download queue,
+/// anything else through fork choice as before
+pub fn handle_blocks(manager: &PeerManager, peer: SocketAddr, blocks: Vec<Block>) {
+    let unrequested = SYNC.lock().unwrap().on_blocks(&peer.to_string(), blocks);
+    apply_ready_blocks();
+    if !unrequested.is_empty() {
+        crate::maybe_reorg_chain(unrequested);
+    }
+    schedule_downloads(manager);
+}
+
+fn apply_ready_blocks() {
+    loop {
+        let ready = SYNC.lock().unwrap().take_ready();
+        if ready.is_empty() {
+            return;
+        }
+        for block in ready {
+            let height = block.header.height;
+            if matches!(crate::accept_network_block(block), BlockAcceptance::Rejected | BlockAcceptance::Orphan) {
+                println!("[Sync] Downloaded block {} was rejected, discarding its header chain", height);
+                SYNC.lock().unwrap().reset();
+                return;
+            }
+        }
+    }
+}
+
+/// Hand out block requests to every idle peer
+pub fn schedule_downloads(manager: &PeerManager) {
+    for addr in manager.ready_peers() {
+        let request = SYNC.lock().unwrap().next_request(&addr.to_string());
+        if let Some(hashes) = request {
+            manager.send(addr, P2PMessage::GetBlockData { hashes });
+        }
+    }
+}
+
+pub fn peer_disconnected(manager: &PeerManager, peer: SocketAddr) {
+    SYNC.lock().unwrap().peer_disconnected(&peer.to_string());
+    schedule_downloads(manager);
+}
+
+/// Periodic housekeeping run by the peer manager: retry stalled block
+/// requests on other peers
+pub fn tick(manager: &PeerManager) {
+    let expired = SYNC.lock().unwrap().expire_requests(Instant::now());
+    for peer in &expired {
+        println!("[Sync] Block request to {} timed out, reassigning", peer);
+    }
+    schedule_downloads(manager);
+}
@@ -18,14 +18,14 @@ use serde::{Serialize, Deserialize};
 use wasmer::wasmparser::Operator;
 
 /// Represents a deployed contract
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WasmContract {
     pub code: Vec<u8>,
     pub address: String, // Could be hash or UUID
     pub metadata: ContractMetadata,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ContractMetadata {
     pub creator: String,
     pub deployed_at: u64,
@@ -74,7 +74,7 @@ pub fn deploy_contract(wasm_bytes: Vec<u8>, creator: String) -> Result<String, S
     if Module::new(&store, &wasm_bytes).is_err() {
         return Err("Invalid WASM module".to_string());
     }
-    let address = format!("0x{}", blake2b_256_hex(&wasm_bytes));
+    let address = contract_address(&wasm_bytes);
     let metadata = ContractMetadata {
         creator,
         deployed_at: chrono::Utc::now().timestamp() as u64,
@@ -88,6 +88,31 @@ pub fn deploy_contract(wasm_bytes: Vec<u8>, creator: String) -> Result<String, S
     Ok(address)
 }
 
+/// Address a contract with this code is deployed under
+pub fn contract_address(wasm_bytes: &[u8]) -> String {
+    format!("0x{}", blake2b_256_hex(wasm_bytes))
+}
+
+/// The contract deployed at `address`, if any
+pub fn contract(address: &str) -> Option<WasmContract> {
+    CONTRACT_REGISTRY.lock().unwrap().get(address).cloned()
+}
+
+/// Put back what was deployed at `address` before a deployment that is
+/// being undone (a block disconnected by a reorg)
+pub fn restore_contract(address: &str, previous: Option<WasmContract>) {
+    {
+        let mut registry = CONTRACT_REGISTRY.lock().unwrap();
+        match previous {
+            Some(contract) => registry.insert(address.to_string(), contract),
+            None => registry.remove(address),
+        };
+    }
+    if let Err(e) = save_contract_registry() {
+        eprintln!("[WARN] Failed to save contract registry: {}", e);
+    }
+}
+
 /// Convert serde_json::Value to wasmer::Value (only basic types supported)
 pub fn json_to_wasmer_value(val: &JsonValue) -> Option<wasmer::Value> {
     match val {
@@ -22,9 +22,9 @@ impl PQSignatureScheme for Dilithium2 {
 
 impl Dilithium2 {
     pub fn public_key_from_bytes(bytes: &[u8]) -> Result<<Self as PQSignatureScheme>::PublicKey, &'static str> {
-        crystals_dilithium::dilithium2::PublicKey::from_bytes(bytes).map_err(|_| "Invalid Dilithium2 public key")
+        if bytes.len() != crystals_dilithium::dilithium2::PUBLICKEYBYTES { return Err("Invalid Dilithium2 public key"); } Ok(crystals_dilithium::dilithium2::PublicKey::from_bytes(bytes))
     }
     pub fn signature_from_bytes(bytes: &[u8]) -> Result<<Self as PQSignatureScheme>::Signature, &'static str> {
-        crystals_dilithium::dilithium2::Signature::mut_from_bytes(bytes).map_err(|_| "Invalid Dilithium2 signature")
+        let mut s = [0u8; crystals_dilithium::dilithium2::SIGNBYTES]; if bytes.len() != s.len() { return Err("Invalid Dilithium2 signature"); } s.copy_from_slice(bytes); Ok(s)
     }
 }
@@ -0,0 +1,765 @@
+//! Canonical binary encoding for consensus objects
+//!
+//! Every hash that ends up in consensus (transaction ids, merkle roots, PoW
+//! input) is computed over this encoding rather than JSON, so all crates agree
+//! byte-for-byte. Rules:
+//! - integers are fixed-width little-endian
+//! - lengths are LEB128 varints
+//! - enums are a one-byte variant tag followed by the fields in order
+//! - `Option` is a `0`/`1` tag followed by the value
+//! - top-level objects (`Transaction`, `BlockHeader`, `Block`) start with
+//!   `ENCODING_VERSION`
+
+use sha2::{Digest, Sha256};
+
+use crate::types::{Hash, PublicKey, QuantumScheme, StealthAddress};
+use crate::{
+    Block, BlockHeader, Coinbase, ContractTx, Pow, QuantumSignature, RingSignature, Transaction,
+    TransactionInput, TransactionKind, TransactionOutput,
+};
+
+/// Version byte prefixed to every top-level encoding
+pub const ENCODING_VERSION: u8 = 1;
+
+/// Upper bound on any single length prefix, to stop hostile input from
+/// triggering huge allocations before the data runs out
+const MAX_LENGTH: u64 = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEof,
+    UnsupportedVersion(u8),
+    InvalidTag { kind: &'static str, tag: u8 },
+    LengthTooLarge(u64),
+    InvalidUtf8,
+    TrailingBytes(usize),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof => write!(f, "unexpected end of input"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported encoding version {}", v),
+            DecodeError::InvalidTag { kind, tag } => write!(f, "invalid {} tag {}", kind, tag),
+            DecodeError::LengthTooLarge(len) => write!(f, "length {} exceeds limit", len),
+            DecodeError::InvalidUtf8 => write!(f, "invalid UTF-8 string"),
+            DecodeError::TrailingBytes(n) => write!(f, "{} trailing bytes after object", n),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Append the canonical encoding of `self` to `out`
+pub trait Encode {
+    fn encode_to(&self, out: &mut Vec<u8>);
+}
+
+/// Read a value back from its canonical encoding
+pub trait Decode: Sized {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError>;
+}
+
+/// Cursor over an encoded byte slice
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.remaining() < len {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::LengthTooLarge(value))
+    }
+
+    fn read_len(&mut self) -> Result<usize, DecodeError> {
+        let len = self.read_varint()?;
+        if len > MAX_LENGTH {
+            return Err(DecodeError::LengthTooLarge(len));
+        }
+        Ok(len as usize)
+    }
+
+    fn read_version(&mut self) -> Result<(), DecodeError> {
+        match self.read_u8()? {
+            ENCODING_VERSION => Ok(()),
+            v => Err(DecodeError::UnsupportedVersion(v)),
+        }
+    }
+}
+
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Decode a complete object, rejecting trailing bytes
+pub fn decode_exact<T: Decode>(data: &[u8]) -> Result<T, DecodeError> {
+    let mut reader = Reader::new(data);
+    let value = T::decode_from(&mut reader)?;
+    match reader.remaining() {
+        0 => Ok(value),
+        n => Err(DecodeError::TrailingBytes(n)),
+    }
+}
+
+pub fn sha256(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {$(
+        impl Encode for $t {
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+        impl Decode for $t {
+            fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+                let bytes = reader.read_bytes(std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+impl_int!(u8, u16, u32, u64);
+
+impl Encode for [u8; 32] {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl Decode for [u8; 32] {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(reader.read_bytes(32)?.try_into().unwrap())
+    }
+}
+
+impl Encode for Vec<u8> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        write_varint(out, self.len() as u64);
+        out.extend_from_slice(self);
+    }
+}
+
+impl Decode for Vec<u8> {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let len = reader.read_len()?;
+        Ok(reader.read_bytes(len)?.to_vec())
+    }
+}
+
+impl Encode for String {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        write_varint(out, self.len() as u64);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let len = reader.read_len()?;
+        String::from_utf8(reader.read_bytes(len)?.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_from(reader)?)),
+            tag => Err(DecodeError::InvalidTag { kind: "option", tag }),
+        }
+    }
+}
+
+/// Most memory `decode_list` reserves before items have been read
+const MAX_LIST_PREALLOC: usize = 1 << 20;
+
+/// Lists of structured items (byte vectors use the specialised impl above)
+/// Varint count followed by each item
+pub fn encode_list<T: Encode>(items: &[T], out: &mut Vec<u8>) {
+    write_varint(out, items.len() as u64);
+    for item in items {
+        item.encode_to(out);
+    }
+}
+
+/// Read a list written by `encode_list`
+pub fn decode_list<T: Decode>(reader: &mut Reader<'_>) -> Result<Vec<T>, DecodeError> {
+    let len = reader.read_len()?;
+    // The count is untrusted: every item takes at least one byte, and the
+    // decoded size of that many items is bounded too, so a short frame with
+    // a huge count cannot reserve gigabytes. Beyond that the Vec grows as
+    // items actually decode.
+    let fits = MAX_LIST_PREALLOC / std::mem::size_of::<T>().max(1);
+    let mut items = Vec::with_capacity(len.min(reader.remaining()).min(fits));
+    for _ in 0..len {
+        items.push(T::decode_from(reader)?);
+    }
+    Ok(items)
+}
+
+impl Encode for QuantumScheme {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            QuantumScheme::Dilithium2 => 0,
+            QuantumScheme::Falcon512 => 1,
+            QuantumScheme::MLDSA44 => 2,
+        });
+    }
+}
+
+impl Decode for QuantumScheme {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(QuantumScheme::Dilithium2),
+            1 => Ok(QuantumScheme::Falcon512),
+            2 => Ok(QuantumScheme::MLDSA44),
+            tag => Err(DecodeError::InvalidTag { kind: "quantum scheme", tag }),
+        }
+    }
+}
+
+impl Encode for PublicKey {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            PublicKey::Ed25519(key) => {
+                out.push(0);
+                key.encode_to(out);
+            }
+            PublicKey::Dilithium2(key) => {
+                out.push(1);
+                key.encode_to(out);
+            }
+            PublicKey::Falcon512(key) => {
+                out.push(2);
+                key.encode_to(out);
+            }
+            PublicKey::MLDSA44(key) => {
+                out.push(3);
+                key.encode_to(out);
+            }
+            PublicKey::Hybrid { classical, quantum, scheme } => {
+                out.push(4);
+                classical.encode_to(out);
+                quantum.encode_to(out);
+                scheme.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for PublicKey {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(PublicKey::Ed25519(Decode::decode_from(reader)?)),
+            1 => Ok(PublicKey::Dilithium2(Decode::decode_from(reader)?)),
+            2 => Ok(PublicKey::Falcon512(Decode::decode_from(reader)?)),
+            3 => Ok(PublicKey::MLDSA44(Decode::decode_from(reader)?)),
+            4 => Ok(PublicKey::Hybrid {
+                classical: Decode::decode_from(reader)?,
+                quantum: Decode::decode_from(reader)?,
+                scheme: Decode::decode_from(reader)?,
+            }),
+            tag => Err(DecodeError::InvalidTag { kind: "public key", tag }),
+        }
+    }
+}
+
+impl Encode for StealthAddress {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.view_key.encode_to(out);
+        self.spend_key.encode_to(out);
+    }
+}
+
+impl Decode for StealthAddress {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(StealthAddress {
+            view_key: Decode::decode_from(reader)?,
+            spend_key: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for QuantumSignature {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let (tag, pk, sig) = match self {
+            QuantumSignature::Dilithium2 { pk, sig } => (0, pk, sig),
+            QuantumSignature::Falcon512 { pk, sig } => (1, pk, sig),
+            QuantumSignature::MLDSA44 { pk, sig } => (2, pk, sig),
+        };
+        out.push(tag);
+        pk.encode_to(out);
+        sig.encode_to(out);
+    }
+}
+
+impl Decode for QuantumSignature {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let tag = reader.read_u8()?;
+        let pk = Decode::decode_from(reader)?;
+        let sig = Decode::decode_from(reader)?;
+        match tag {
+            0 => Ok(QuantumSignature::Dilithium2 { pk, sig }),
+            1 => Ok(QuantumSignature::Falcon512 { pk, sig }),
+            2 => Ok(QuantumSignature::MLDSA44 { pk, sig }),
+            tag => Err(DecodeError::InvalidTag { kind: "quantum signature", tag }),
+        }
+    }
+}
+
+impl Encode for RingSignature {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_list(&self.ring, out);
+        self.signature.encode_to(out);
+        self.quantum.encode_to(out);
+    }
+}
+
+impl Decode for RingSignature {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(RingSignature {
+            ring: decode_list(reader)?,
+            signature: Decode::decode_from(reader)?,
+            quantum: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for TransactionInput {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.key_image.encode_to(out);
+        self.ring_sig.encode_to(out);
+    }
+}
+
+impl Decode for TransactionInput {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(TransactionInput {
+            key_image: Decode::decode_from(reader)?,
+            ring_sig: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for TransactionOutput {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.amount_commitment.encode_to(out);
+        self.stealth_address.encode_to(out);
+        self.range_proof.encode_to(out);
+    }
+}
+
+impl Decode for TransactionOutput {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(TransactionOutput {
+            amount_commitment: Decode::decode_from(reader)?,
+            stealth_address: Decode::decode_from(reader)?,
+            range_proof: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for ContractTx {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            ContractTx::Deploy { wasm_code, creator, metadata } => {
+                out.push(0);
+                wasm_code.encode_to(out);
+                creator.encode_to(out);
+                metadata.encode_to(out);
+            }
+            ContractTx::Invoke { contract_address, function, params, caller, metadata } => {
+                out.push(1);
+                contract_address.encode_to(out);
+                function.encode_to(out);
+                params.encode_to(out);
+                caller.encode_to(out);
+                metadata.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for ContractTx {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(ContractTx::Deploy {
+                wasm_code: Decode::decode_from(reader)?,
+                creator: Decode::decode_from(reader)?,
+                metadata: Decode::decode_from(reader)?,
+            }),
+            1 => Ok(ContractTx::Invoke {
+                contract_address: Decode::decode_from(reader)?,
+                function: Decode::decode_from(reader)?,
+                params: Decode::decode_from(reader)?,
+                caller: Decode::decode_from(reader)?,
+                metadata: Decode::decode_from(reader)?,
+            }),
+            tag => Err(DecodeError::InvalidTag { kind: "contract tx", tag }),
+        }
+    }
+}
+
+impl Encode for TransactionKind {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            TransactionKind::Payment => out.push(0),
+            TransactionKind::Contract(contract) => {
+                out.push(1);
+                contract.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for TransactionKind {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(TransactionKind::Payment),
+            1 => Ok(TransactionKind::Contract(Decode::decode_from(reader)?)),
+            tag => Err(DecodeError::InvalidTag { kind: "transaction kind", tag }),
+        }
+    }
+}
+
+impl Transaction {
+    /// Fields covered by the transaction's signatures (everything except
+    /// `signature` and `quantum_signature`)
+    fn encode_unsigned_to(&self, out: &mut Vec<u8>) {
+        out.push(ENCODING_VERSION);
+        self.kind.encode_to(out);
+        encode_list(&self.inputs, out);
+        encode_list(&self.outputs, out);
+        self.fee.encode_to(out);
+        self.extra.encode_to(out);
+        self.metadata.encode_to(out);
+    }
+
+    /// Canonical encoding of the whole transaction
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+
+    /// Bytes a wallet signs for this transaction
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_unsigned_to(&mut out);
+        out
+    }
+
+    /// Transaction id: SHA-256 of the canonical encoding
+    pub fn id(&self) -> Hash {
+        sha256(&self.encode())
+    }
+
+    /// Decode a transaction produced by `encode`
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        decode_exact(data)
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.encode_unsigned_to(out);
+        self.signature.encode_to(out);
+        self.quantum_signature.encode_to(out);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        Ok(Transaction {
+            kind: Decode::decode_from(reader)?,
+            inputs: decode_list(reader)?,
+            outputs: decode_list(reader)?,
+            fee: Decode::decode_from(reader)?,
+            extra: Decode::decode_from(reader)?,
+            metadata: Decode::decode_from(reader)?,
+            signature: Decode::decode_from(reader)?,
+            quantum_signature: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl BlockHeader {
+    /// Header fields covered by proof-of-work (everything except `pow`).
+    /// The PoW hash is RandomX over `signing_bytes() || nonce_le`.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.push(ENCODING_VERSION);
+        self.version.encode_to(&mut out);
+        self.prev_hash.encode_to(&mut out);
+        self.merkle_root.encode_to(&mut out);
+        self.timestamp.encode_to(&mut out);
+        self.height.encode_to(&mut out);
+        self.difficulty.encode_to(&mut out);
+        out
+    }
+
+    /// Input to the PoW hash for a given nonce
+    pub fn pow_input(&self, nonce: u64) -> Vec<u8> {
+        let mut out = self.signing_bytes();
+        nonce.encode_to(&mut out);
+        out
+    }
+
+    /// Canonical encoding of the whole header
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+impl Encode for Pow {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.nonce.encode_to(out);
+        self.hash.encode_to(out);
+    }
+}
+
+impl Decode for Pow {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Pow {
+            nonce: Decode::decode_from(reader)?,
+            hash: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.signing_bytes());
+        self.pow.encode_to(out);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        Ok(BlockHeader {
+            version: Decode::decode_from(reader)?,
+            prev_hash: Decode::decode_from(reader)?,
+            merkle_root: Decode::decode_from(reader)?,
+            timestamp: Decode::decode_from(reader)?,
+            height: Decode::decode_from(reader)?,
+            difficulty: Decode::decode_from(reader)?,
+            pow: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for Coinbase {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.reward.encode_to(out);
+        self.to.encode_to(out);
+    }
+}
+
+impl Decode for Coinbase {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Coinbase {
+            reward: Decode::decode_from(reader)?,
+            to: Decode::decode_from(reader)?,
+        })
+    }
+}
+
+impl Block {
+    /// Block identifier: the PoW hash recorded in the header, which is what
+    /// `prev_hash` of the next block points at
+    pub fn hash(&self) -> Hash {
+        self.header.pow.hash
+    }
+
+    /// Canonical encoding of the whole block
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+
+    /// Decode a block produced by `encode`
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        decode_exact(data)
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(ENCODING_VERSION);
+        self.header.encode_to(out);
+        self.coinbase.encode_to(out);
+        encode_list(&self.transactions, out);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        reader.read_version()?;
+        Ok(Block {
+            header: Decode::decode_from(reader)?,
+            coinbase: Decode::decode_from(reader)?,
+            transactions: decode_list(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_tx() -> Transaction {
+        Transaction {
+            kind: TransactionKind::Contract(ContractTx::Invoke {
+                contract_address: "contract".to_string(),
+                function: "transfer".to_string(),
+                params: vec![1, 2, 3],
+                caller: "caller".to_string(),
+                metadata: Some("memo".to_string()),
+            }),
+            inputs: vec![TransactionInput {
+                key_image: [7u8; 32],
+                ring_sig: RingSignature {
+                    ring: vec![[1u8; 32], [2u8; 32]],
+                    signature: vec![9; 128],
+                    quantum: Some(QuantumSignature::Falcon512 { pk: vec![4; 8], sig: vec![5; 8] }),
+                },
+            }],
+            outputs: vec![TransactionOutput {
+                amount_commitment: [3u8; 32],
+                stealth_address: StealthAddress {
+                    view_key: PublicKey::Ed25519([6u8; 32]),
+                    spend_key: PublicKey::Hybrid { classical: [8u8; 32], quantum: vec![1; 4], scheme: QuantumScheme::MLDSA44 },
+                },
+                range_proof: vec![0xAB; 16],
+            }],
+            fee: 42,
+            extra: vec![0xEE],
+            metadata: None,
+            signature: "sig".to_string(),
+            quantum_signature: None,
+        }
+    }
+
+    fn sample_block() -> Block {
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_hash: [1u8; 32],
+                merkle_root: [2u8; 32],
+                timestamp: 1_735_689_600,
+                height: 12,
+                difficulty: 1000,
+                pow: Pow { nonce: 99, hash: [3u8; 32] },
+            },
+            coinbase: Coinbase { reward: 5_000_000, to: "miner".to_string() },
+            transactions: vec![sample_tx()],
+        }
+    }
+
+    #[test]
+    fn test_transaction_round_trip_and_id() {
+        let tx = sample_tx();
+        let bytes = tx.encode();
+        assert_eq!(bytes[0], ENCODING_VERSION);
+        let decoded = Transaction::decode(&bytes).unwrap();
+        assert_eq!(decoded.encode(), bytes);
+        assert_eq!(decoded.id(), tx.id());
+
+        // Signatures are not part of the signing bytes but are part of the id
+        let mut resigned = tx.clone();
+        resigned.signature = "other".to_string();
+        assert_eq!(resigned.signing_bytes(), tx.signing_bytes());
+        assert_ne!(resigned.id(), tx.id());
+    }
+
+    #[test]
+    fn test_block_round_trip_and_header_bytes() {
+        let block = sample_block();
+        let decoded = Block::decode(&block.encode()).unwrap();
+        assert_eq!(decoded.encode(), block.encode());
+        assert_eq!(decoded.hash(), [3u8; 32]);
+
+        // Signing bytes are stable and exclude the PoW fields
+        let header = &block.header;
+        assert_eq!(header.signing_bytes().len(), 1 + 2 + 32 + 32 + 8 + 8 + 8);
+        let mut mined = header.clone();
+        mined.pow = Pow { nonce: 1, hash: [0u8; 32] };
+        assert_eq!(mined.signing_bytes(), header.signing_bytes());
+        assert_eq!(&header.pow_input(5)[header.signing_bytes().len()..], &5u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_input() {
+        let bytes = sample_tx().encode();
+        assert!(matches!(Transaction::decode(&bytes[..bytes.len() - 1]), Err(DecodeError::UnexpectedEof)));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(Transaction::decode(&trailing), Err(DecodeError::TrailingBytes(1))));
+        let mut bad_version = bytes;
+        bad_version[0] = 2;
+        assert!(matches!(Transaction::decode(&bad_version), Err(DecodeError::UnsupportedVersion(2))));
+
+        // A large count over a short payload fails without a large reservation
+        let mut huge_count = Vec::new();
+        write_varint(&mut huge_count, MAX_LENGTH);
+        huge_count.extend_from_slice(&[0; 64]);
+        let mut reader = Reader::new(&huge_count);
+        assert!(decode_list::<Transaction>(&mut reader).is_err());
+    }
+}
@@ -217,6 +217,8 @@ pub struct RingSignature {
 pub mod zkp; // zk-SNARKs and advanced ZKP integration
 pub mod escrow; // Escrow contract and dispute voting
 pub mod ring_sig;
+pub mod encoding; // Canonical binary encoding, transaction and block ids
+pub mod merkle; // Merkle tree and transaction inclusion proofs
 
 pub use crate::types::{StealthAddress, Address};
 
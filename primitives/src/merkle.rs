@@ -0,0 +1,182 @@
+//! Binary Merkle tree over transaction ids
+//!
+//! Leaves and interior nodes are hashed with distinct prefixes so a leaf can
+//! never be passed off as an interior node. When a level has an odd number of
+//! nodes the last one is carried up unchanged instead of being paired with a
+//! copy of itself, which keeps two different transaction lists from sharing
+//! a root.
+
+use crate::types::Hash;
+use sha2::{Digest, Sha256};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Root of an empty tree
+pub const EMPTY_ROOT: Hash = [0; 32];
+
+fn hash_leaf(leaf: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(leaf);
+    hasher.finalize().into()
+}
+
+fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Merkle tree with every level kept so proofs can be extracted
+#[derive(Clone, Debug)]
+pub struct MerkleTree {
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn new(leaves: &[Hash]) -> Self {
+        let mut levels = vec![leaves.iter().map(hash_leaf).collect::<Vec<_>>()];
+        while levels.last().is_some_and(|level| level.len() > 1) {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { levels }
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn root(&self) -> Hash {
+        self.levels.last().and_then(|level| level.first()).copied().unwrap_or(EMPTY_ROOT)
+    }
+
+    /// Inclusion proof for the leaf at `index`
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaf_count() {
+            return None;
+        }
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = position ^ 1;
+            if sibling < level.len() {
+                siblings.push(level[sibling]);
+            }
+            position /= 2;
+        }
+        Some(MerkleProof {
+            index: index as u64,
+            leaf_count: self.leaf_count() as u64,
+            siblings,
+        })
+    }
+}
+
+/// Merkle root of a list of leaves (transaction ids)
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    MerkleTree::new(leaves).root()
+}
+
+/// Path from a leaf to the root
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Position of the leaf in the block's transaction list
+    pub index: u64,
+    /// Number of leaves in the tree
+    pub leaf_count: u64,
+    /// Sibling hashes from the leaf level upwards; levels where the node is
+    /// carried up without a partner contribute no entry
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Recompute the root from `leaf` and compare it with `root`
+    pub fn verify(&self, leaf: &Hash, root: &Hash) -> bool {
+        if self.index >= self.leaf_count {
+            return false;
+        }
+        let mut hash = hash_leaf(leaf);
+        let mut position = self.index;
+        let mut width = self.leaf_count;
+        let mut siblings = self.siblings.iter();
+        while width > 1 {
+            let sibling = position ^ 1;
+            if sibling < width {
+                let Some(other) = siblings.next() else {
+                    return false;
+                };
+                hash = if position.is_multiple_of(2) {
+                    hash_node(&hash, other)
+                } else {
+                    hash_node(other, &hash)
+                };
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none() && hash == *root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: u8) -> Vec<Hash> {
+        (0..n).map(|i| [i; 32]).collect()
+    }
+
+    #[test]
+    fn test_proofs_verify_for_every_leaf() {
+        for n in 1..=9 {
+            let leaves = leaves(n);
+            let tree = MerkleTree::new(&leaves);
+            let root = tree.root();
+            assert_eq!(root, merkle_root(&leaves));
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+                assert!(proof.verify(leaf, &root), "leaf {} of {}", i, n);
+                assert!(!proof.verify(&[0xFF; 32], &root));
+            }
+            assert!(tree.proof(n as usize).is_none());
+        }
+        assert_eq!(merkle_root(&[]), EMPTY_ROOT);
+    }
+
+    #[test]
+    fn test_tampered_proof_is_rejected() {
+        let leaves = leaves(5);
+        let tree = MerkleTree::new(&leaves);
+        let root = tree.root();
+
+        let mut proof = tree.proof(2).unwrap();
+        proof.siblings[0][0] ^= 1;
+        assert!(!proof.verify(&leaves[2], &root));
+
+        let mut proof = tree.proof(2).unwrap();
+        proof.index = 3;
+        assert!(!proof.verify(&leaves[2], &root));
+
+        let mut proof = tree.proof(2).unwrap();
+        proof.siblings.push([0; 32]);
+        assert!(!proof.verify(&leaves[2], &root));
+
+        // Duplicating the last leaf must change the root
+        let mut padded = leaves.clone();
+        padded.push(leaves[4]);
+        assert_ne!(merkle_root(&padded), root);
+    }
+}
@@ -491,7 +491,9 @@ fn encode_address(public_view: &[u8; 32], public_spend: &[u8; 32]) -> String {
 /// CryptoNote-style output detection: checks if output belongs to this wallet using one-time address recovery
 fn is_output_mine(out: &primitives::TransactionOutput, _my_pub_view: &[u8; 32], my_pub_spend: &[u8; 32], my_priv_view: &[u8; 32]) -> bool {
     // استخدم المفتاح العام من stealth_address
-    let out_pubkey_bytes = out.stealth_address.public_spend;
+    let primitives::types::PublicKey::Ed25519(out_pubkey_bytes) = out.stealth_address.spend_key else {
+        return false;
+    };
     let out_pubkey = CompressedEdwardsY(out_pubkey_bytes).decompress();
     if out_pubkey.is_none() { return false; }
     let out_pubkey = out_pubkey.unwrap();
@@ -526,14 +528,90 @@ fn scan_blocks_for_balance(blocks: &[primitives::Block], my_pub_view: &[u8; 32],
     balance
 }
 
-/// Return all outputs belonging to this wallet (placeholder logic)
-fn get_spendable_outputs<'a>(blocks: &'a [primitives::Block], my_pub_view: &[u8; 32], my_pub_spend: &[u8; 32], my_priv_view: &[u8; 32]) -> Vec<&'a primitives::TransactionOutput> {
+/// Tag opening the amount hints a payment carries in `extra`
+const AMOUNT_HINTS_TAG: u8 = 0x01;
+
+/// Secret shared by a payment's one-time key and one recipient's view key
+fn amount_secret(shared_point: curve25519_dalek::edwards::EdwardsPoint, index: usize) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(shared_point.compress().as_bytes());
+    hasher.update((index as u64).to_le_bytes());
+    hasher.finalize().into()
+}
+
+/// Mask hiding the amount of an output and the blinding of its commitment
+fn amount_mask(secret: &[u8; 32]) -> ([u8; 8], Scalar) {
+    let mask: [u8; 32] = Sha256::new().chain_update(b"amount").chain_update(secret).finalize().into();
+    let blinding: [u8; 32] = Sha256::new().chain_update(b"blinding").chain_update(secret).finalize().into();
+    (mask[..8].try_into().unwrap(), Scalar::from_bytes_mod_order(blinding))
+}
+
+/// `extra` of a payment to `recipients` (public view key, amount) and the
+/// blinding of each output's commitment. Each recipient can recover its
+/// amount and blinding with its private view key.
+fn hide_amounts(recipients: &[(&[u8; 32], u64)]) -> Result<(Vec<u8>, Vec<Scalar>), String> {
+    let r = Scalar::random(&mut OsRng);
+    let mut extra = vec![AMOUNT_HINTS_TAG];
+    extra.extend_from_slice((ED25519_BASEPOINT_POINT * r).compress().as_bytes());
+    let mut blindings = Vec::new();
+    for (index, (view_key, amount)) in recipients.iter().enumerate() {
+        let view_key = CompressedEdwardsY(**view_key).decompress().ok_or("Invalid recipient view key")?;
+        let (mask, blinding) = amount_mask(&amount_secret(view_key * r, index));
+        let hidden: Vec<u8> = amount.to_le_bytes().iter().zip(mask).map(|(a, m)| a ^ m).collect();
+        extra.extend_from_slice(&hidden);
+        blindings.push(blinding);
+    }
+    Ok((extra, blindings))
+}
+
+/// Amount of output `index` of `tx` if it is hidden for `priv_view` and
+/// opens the output's commitment
+fn reveal_amount(tx: &primitives::Transaction, index: usize, priv_view: &[u8; 32]) -> Option<u64> {
+    let hints = tx.extra.strip_prefix(&[AMOUNT_HINTS_TAG])?;
+    let one_time_key = CompressedEdwardsY(hints.get(..32)?.try_into().ok()?).decompress()?;
+    let hidden = hints.get(32 + index * 8..32 + (index + 1) * 8)?;
+    let shared_point = one_time_key * Scalar::from_bytes_mod_order(*priv_view);
+    let (mask, blinding) = amount_mask(&amount_secret(shared_point, index));
+    let amount = u64::from_le_bytes(std::array::from_fn(|i| hidden[i] ^ mask[i]));
+    let commitment = PedersenGens::default().commit(Scalar::from(amount), blinding).compress();
+    (commitment.to_bytes() == tx.outputs.get(index)?.amount_commitment).then_some(amount)
+}
+
+/// Key image of one of the wallet's outputs: the same output always gives
+/// the same image, so spending it twice is caught, and no two outputs share
+/// one
+fn output_key_image(priv_spend: &[u8; 32], output: &primitives::TransactionOutput) -> [u8; 32] {
+    let secret: [u8; 32] = Sha256::new().chain_update(priv_spend).chain_update(output.amount_commitment).finalize().into();
+    generate_key_image(&secret)
+}
+
+/// Output the wallet can spend, with its amount
+#[derive(Clone, Copy)]
+struct OwnedOutput<'a> {
+    output: &'a primitives::TransactionOutput,
+    amount: u64,
+}
+
+/// Unspent outputs paid to this wallet whose amounts it can recover
+fn get_spendable_outputs<'a>(blocks: &'a [primitives::Block], my_pub_spend: &[u8; 32], my_priv_view: &[u8; 32], my_priv_spend: &[u8; 32]) -> Vec<OwnedOutput<'a>> {
+    let spent: std::collections::HashSet<[u8; 32]> = blocks
+        .iter()
+        .flat_map(|block| &block.transactions)
+        .flat_map(|tx| &tx.inputs)
+        .map(|input| input.key_image)
+        .collect();
     let mut outputs = Vec::new();
     for block in blocks {
         for tx in &block.transactions {
-            for out in &tx.outputs {
-                if is_output_mine(out, my_pub_view, my_pub_spend, my_priv_view) {
-                    outputs.push(out);
+            for (index, out) in tx.outputs.iter().enumerate() {
+                if !matches!(out.stealth_address.spend_key, primitives::types::PublicKey::Ed25519(key) if key == *my_pub_spend) {
+                    continue;
+                }
+                if spent.contains(&output_key_image(my_priv_spend, out)) {
+                    continue;
+                }
+                if let Some(amount) = reveal_amount(tx, index, my_priv_view) {
+                    outputs.push(OwnedOutput { output: out, amount });
                 }
             }
         }
@@ -541,16 +619,16 @@ fn get_spendable_outputs<'a>(blocks: &'a [primitives::Block], my_pub_view: &[u8;
     outputs
 }
 
-/// Select minimal outputs to cover the amount (greedy algorithm)
-fn select_inputs<'a>(outputs: &'a [&primitives::TransactionOutput], amount: u64) -> (Vec<&'a primitives::TransactionOutput>, u64) {
+/// Select outputs to cover the amount, largest first
+fn select_inputs<'a>(outputs: &[OwnedOutput<'a>], amount: u64) -> (Vec<OwnedOutput<'a>>, u64) {
     let mut selected = Vec::new();
     let mut total = 0u64;
-    for out in outputs.iter().copied().sorted_by_key(|o| o.amount_commitment).rev() {
-        selected.push(out);
-        total += 1; // في testnet: كل مخرج قيمته 1
+    for out in outputs.iter().copied().sorted_by_key(|o| o.amount).rev() {
         if total >= amount {
             break;
         }
+        selected.push(out);
+        total = total.saturating_add(out.amount);
     }
     (selected, total)
 }
@@ -563,98 +641,19 @@ fn send_transaction(node_addr: &str, wallet: &WalletFile, to_address: &str, amou
     if to_address.is_empty() {
         return Err("Destination address is required".to_string());
     }
-    // Decode keys
-    let pub_view: &[u8; 32] = wallet.pub_view.as_bytes().try_into().unwrap();
-    let pub_spend: &[u8; 32] = wallet.pub_spend.as_bytes().try_into().unwrap();
-    let priv_view = hex::decode(&wallet.priv_view).map_err(|_| "Invalid priv_view in wallet file")?;
-    let mut arr_view = [0u8; 32];
-    let mut arr_spend = [0u8; 32];
-    let mut arr_priv_view = [0u8; 32];
-    arr_view.copy_from_slice(&*pub_view);
-    arr_spend.copy_from_slice(&*pub_spend);
-    arr_priv_view.copy_from_slice(&priv_view);
+    let pub_view = hex_to_32_bytes(&wallet.pub_view).map_err(|_| "Invalid pub_view in wallet file")?;
+    let pub_spend = hex_to_32_bytes(&wallet.pub_spend).map_err(|_| "Invalid pub_spend in wallet file")?;
+    let priv_view = hex_to_32_bytes(&wallet.priv_view).map_err(|_| "Invalid priv_view in wallet file")?;
+    let priv_spend = hex_to_32_bytes(&wallet.priv_spend).map_err(|_| "Invalid priv_spend in wallet file")?;
     // Sync blocks and collect spendable outputs
-    let blocks = sync_with_node(node_addr, 0, &arr_view, &arr_spend);
-    let outputs = get_spendable_outputs(&blocks, &arr_view, &arr_spend, &arr_priv_view);
-    let total_balance: u64 = outputs.len() as u64; // كل مخرج قيمته 1
+    let blocks = sync_with_node(node_addr, 0, &pub_view, &pub_spend);
+    let outputs = get_spendable_outputs(&blocks, &pub_spend, &priv_view, &priv_spend);
+    let total_balance: u64 = outputs.iter().map(|out| out.amount).sum();
     if total_balance < amount {
         return Err(format!("Insufficient balance: have {}, need {}", total_balance, amount));
     }
-    // Select minimal inputs
-    let (selected, selected_total) = select_inputs(&outputs, amount);
-    if selected_total < amount {
-        return Err("Could not select enough inputs".to_string());
-    }
-    let fee = 1; // ثابت في testnet
-    let change = selected_total - amount - fee;
-    use primitives::ring_sig::generate_ring_signature;
-    let priv_spend = hex::decode(&wallet.priv_spend).map_err(|_| "Invalid priv_spend in wallet file")?;
-    let mut arr_priv_spend = [0u8; 32];
-    arr_priv_spend.copy_from_slice(&priv_spend);
-    let mut tx_inputs = Vec::new();
-    for inp in &selected {
-        // استخدم المفتاح العام من stealth_address
-        let ring = vec![inp.stealth_address.public_spend];
-        let ki = generate_key_image(&arr_priv_spend);
-        let msg = b"blacksilk_tx";
-        let ring_sig = generate_ring_signature(msg, &ring, &arr_priv_spend, 0);
-        tx_inputs.push(primitives::TransactionInput {
-            key_image: ki,
-            ring_sig: primitives::RingSignature { ring, signature: ring_sig },
-        });
-    }
-    // --- Build outputs (Pedersen commitment + Bulletproofs) ---
-    use curve25519_dalek::scalar::Scalar;
-    use rand::rngs::OsRng;
-    use bulletproofs::{BulletproofGens, PedersenGens};
-    let mut tx_outputs = Vec::new();
-    let pc_gens = PedersenGens::default();
-    let bp_gens = BulletproofGens::new(64, 1);
-    // المخرج الرئيسي
-    let blinding = Scalar::random(&mut OsRng);
-    let (range_proof, commitment) = generate_range_proof(amount, &blinding);
-    let addr_bytes = base58::FromBase58::from_base58(&to_address[3..]).unwrap();
-    let pub_view = addr_bytes[1..33].try_into().unwrap();
-    let pub_spend = addr_bytes[33..65].try_into().unwrap();
-    tx_outputs.push(primitives::TransactionOutput {
-        amount_commitment: commitment.to_bytes(),
-        stealth_address: primitives::StealthAddress { public_view: pub_view, public_spend: pub_spend },
-        range_proof: range_proof.to_bytes(),
-    });
-    // التغيير
-    if change > 0 {
-        let blinding = Scalar::random(&mut OsRng);
-        let (range_proof, commitment) = generate_range_proof(change, &blinding);
-        let pub_view = arr_view;
-        let pub_spend = arr_spend;
-        tx_outputs.push(primitives::TransactionOutput {
-            amount_commitment: commitment.to_bytes(),
-            stealth_address: primitives::StealthAddress { public_view: pub_view, public_spend: pub_spend },
-            range_proof: range_proof.to_bytes(),
-        });
-    }
-    
-    // Generate a proper transaction signature
-    use sha2::{Sha256, Digest};
-    let mut tx_hasher = Sha256::new();
-    
-    // Hash transaction components for signature
-    tx_hasher.update(&serde_json::to_vec(&tx_inputs).unwrap_or_default());
-    tx_hasher.update(&serde_json::to_vec(&tx_outputs).unwrap_or_default());
-    tx_hasher.update(&fee.to_le_bytes());
-    tx_hasher.update(&chrono::Utc::now().timestamp().to_le_bytes());
-    
-    let tx_signature = hex::encode(tx_hasher.finalize());
-    
-    let tx = primitives::Transaction {
-        kind: primitives::TransactionKind::Payment,
-        inputs: tx_inputs,
-        outputs: tx_outputs,
-        fee,
-        extra: vec![],
-        metadata: None,
-        signature: tx_signature,
-    };
+    let fee_per_kb = fee_rate(node_addr, "normal")?;
+    let tx = build_payment(&outputs, &priv_spend, (&pub_view, &pub_spend), to_address, amount, fee_per_kb)?;
     let tx_json = serde_json::to_string(&tx).map_err(|e| format!("Failed to serialize tx: {}", e))?;
     let url = format!("http://{}/submit_tx", node_addr);
     let resp = reqwest::blocking::Client::new()
@@ -671,6 +670,104 @@ fn send_transaction(node_addr: &str, wallet: &WalletFile, to_address: &str, amou
     }
 }
 
+/// Fee for `size` bytes at `fee_per_kb`, rounded up like the node does
+fn fee_for_size(fee_per_kb: u64, size: usize) -> u64 {
+    (fee_per_kb as u128 * size as u128).div_ceil(1000) as u64
+}
+
+/// Build and sign a payment of `amount` to `to_address` from `outputs`,
+/// returning change to `change_keys` (public view, public spend). The fee
+/// is paid at `fee_per_kb` on the size of the transaction as built: inputs
+/// are selected for the amount plus the fee so far, and while the result
+/// needs a higher fee than it pays, selection starts over with that fee.
+fn build_payment(
+    outputs: &[OwnedOutput],
+    priv_spend: &[u8; 32],
+    change_keys: (&[u8; 32], &[u8; 32]),
+    to_address: &str,
+    amount: u64,
+    fee_per_kb: u64,
+) -> Result<primitives::Transaction, String> {
+    use primitives::ring_sig::generate_ring_signature;
+    let addr_bytes = to_address
+        .strip_prefix("Blk")
+        .and_then(|encoded| base58::FromBase58::from_base58(encoded).ok())
+        .filter(|bytes| bytes.len() >= 65)
+        .ok_or_else(|| format!("Invalid destination address: {}", to_address))?;
+    let to_view: [u8; 32] = addr_bytes[1..33].try_into().unwrap();
+    let to_keys = primitives::StealthAddress {
+        view_key: primitives::types::PublicKey::Ed25519(to_view),
+        spend_key: primitives::types::PublicKey::Ed25519(addr_bytes[33..65].try_into().unwrap()),
+    };
+    let change_address = primitives::StealthAddress {
+        view_key: primitives::types::PublicKey::Ed25519(*change_keys.0),
+        spend_key: primitives::types::PublicKey::Ed25519(*change_keys.1),
+    };
+    let balance: u64 = outputs.iter().map(|out| out.amount).sum();
+
+    let mut fee = 0;
+    loop {
+        let needed = amount.checked_add(fee).ok_or("Amount too large")?;
+        let (selected, selected_total) = select_inputs(outputs, needed);
+        if selected_total < needed {
+            return Err(format!("Insufficient balance for amount and fee: have {}, need {}", balance, needed));
+        }
+        let change = selected_total - needed;
+
+        // --- Build outputs (Pedersen commitment + Bulletproofs) ---
+        let mut payees = vec![(&to_keys, &to_view, amount)];
+        if change > 0 {
+            payees.push((&change_address, change_keys.0, change));
+        }
+        let hints: Vec<(&[u8; 32], u64)> = payees.iter().map(|(_, view_key, value)| (*view_key, *value)).collect();
+        let (extra, blindings) = hide_amounts(&hints)?;
+        let tx_outputs = payees
+            .iter()
+            .zip(&blindings)
+            .map(|((address, _, value), blinding)| {
+                let (range_proof, commitment) = generate_range_proof(*value, blinding);
+                primitives::TransactionOutput {
+                    amount_commitment: commitment.to_bytes(),
+                    stealth_address: (*address).clone(),
+                    range_proof: range_proof.to_bytes(),
+                }
+            })
+            .collect();
+
+        // Inputs sign the `extra` the node verifies them against
+        let mut tx_inputs = Vec::new();
+        for inp in &selected {
+            let primitives::types::PublicKey::Ed25519(spend_key) = inp.output.stealth_address.spend_key else {
+                return Err("Output has no classical spend key".to_string());
+            };
+            let ring = vec![spend_key];
+            let ring_sig = generate_ring_signature(&extra, &ring, priv_spend, 0);
+            tx_inputs.push(primitives::TransactionInput {
+                key_image: output_key_image(priv_spend, inp.output),
+                ring_sig: primitives::RingSignature { ring, signature: ring_sig, quantum: None },
+            });
+        }
+
+        let mut tx = primitives::Transaction {
+            kind: primitives::TransactionKind::Payment,
+            inputs: tx_inputs,
+            outputs: tx_outputs,
+            fee,
+            extra,
+            metadata: None,
+            signature: String::new(),
+            quantum_signature: None,
+        };
+        // Sign the canonical encoding of everything except the signatures themselves
+        tx.signature = hex::encode(primitives::encoding::sha256(&tx.signing_bytes()));
+        let required = fee_for_size(fee_per_kb, node::transaction_size(&tx));
+        if fee >= required {
+            return Ok(tx);
+        }
+        fee = required;
+    }
+}
+
 /// Calculate real wallet balance by scanning blockchain
 fn calculate_wallet_balance(wallet: &WalletFile, node_addr: &str) -> (u64, u64, u64) {
     // Convert hex strings to byte arrays
@@ -699,10 +796,10 @@ fn calculate_wallet_balance(wallet: &WalletFile, node_addr: &str) -> (u64, u64,
     let locked_balance = 0u64; // Implement based on ring signature maturity
     
     // Scan all blocks for outputs belonging to this wallet
-    let spendable_outputs = get_spendable_outputs(&blocks, &pub_view, &pub_spend, &priv_view);
+    let spendable_outputs = get_spendable_outputs(&blocks, &pub_spend, &priv_view, &priv_spend);
     
     for output in spendable_outputs {
-        confirmed_balance += u64::from_le_bytes(output.amount_commitment[0..8].try_into().unwrap());
+        confirmed_balance += output.amount;
     }
     
     // Check mempool for unconfirmed transactions
@@ -747,6 +844,32 @@ fn get_mempool_balance(node_addr: &str, pub_view: &[u8; 32], pub_spend: &[u8; 32
     Ok(unconfirmed)
 }
 
+/// Ask the node what a transaction should pay per 1000 bytes to confirm
+/// at `priority` (low, normal or high)
+fn fee_rate(node_addr: &str, priority: &str) -> Result<u64, String> {
+    let url = format!("http://{}/api/estimate_fee?priority={}", node_addr, priority);
+    let client = reqwest::blocking::Client::new();
+    
+    let resp = client.get(&url)
+        .timeout(std::time::Duration::from_secs(10))
+        .send()
+        .map_err(|e| format!("Failed to connect to node: {}", e))?;
+    
+    if !resp.status().is_success() {
+        return Err(format!("Node could not estimate fee: {}", resp.status()));
+    }
+    
+    #[derive(Deserialize)]
+    struct FeeEstimate {
+        fee_per_kb: u64,
+    }
+    
+    let estimate: FeeEstimate = resp.json()
+        .map_err(|e| format!("Failed to parse fee estimate: {}", e))?;
+    
+    Ok(estimate.fee_per_kb)
+}
+
 /// Get current network height from node
 fn get_network_height(node_addr: &str) -> Result<u64, String> {
     let url = format!("http://{}/get_info", node_addr);
@@ -1622,3 +1745,97 @@ fn print_wallet_info(cli: &Cli) {
     // Placeholder implementation
     println!("Printing wallet info");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Private and public (view, spend) keys of a test wallet
+    struct Keys {
+        priv_view: [u8; 32],
+        priv_spend: [u8; 32],
+        pub_view: [u8; 32],
+        pub_spend: [u8; 32],
+    }
+
+    fn keys(seed: u8) -> Keys {
+        let public = |secret: [u8; 32]| (ED25519_BASEPOINT_POINT * Scalar::from_bytes_mod_order(secret)).compress().to_bytes();
+        let (priv_view, priv_spend) = ([seed; 32], [seed + 1; 32]);
+        Keys { priv_view, priv_spend, pub_view: public(priv_view), pub_spend: public(priv_spend) }
+    }
+
+    /// Block paying `amounts` to `keys`, one output each
+    fn funding_block(keys: &Keys, amounts: &[u64]) -> primitives::Block {
+        let address = primitives::StealthAddress {
+            view_key: primitives::types::PublicKey::Ed25519(keys.pub_view),
+            spend_key: primitives::types::PublicKey::Ed25519(keys.pub_spend),
+        };
+        let hints: Vec<(&[u8; 32], u64)> = amounts.iter().map(|amount| (&keys.pub_view, *amount)).collect();
+        let (extra, blindings) = hide_amounts(&hints).unwrap();
+        let outputs = amounts
+            .iter()
+            .zip(&blindings)
+            .map(|(amount, blinding)| {
+                let (range_proof, commitment) = generate_range_proof(*amount, blinding);
+                primitives::TransactionOutput {
+                    amount_commitment: commitment.to_bytes(),
+                    stealth_address: address.clone(),
+                    range_proof: range_proof.to_bytes(),
+                }
+            })
+            .collect();
+        let mut block = node::Chain::new_for_network(node::Network::Testnet).tip().clone();
+        block.transactions = vec![primitives::Transaction {
+            kind: primitives::TransactionKind::Payment,
+            inputs: vec![],
+            outputs,
+            fee: 0,
+            extra,
+            metadata: None,
+            signature: String::new(),
+            quantum_signature: None,
+        }];
+        block
+    }
+
+    #[test]
+    fn test_payments_are_accepted_by_the_node() {
+        let (wallet, payee) = (keys(1), keys(7));
+        let to = encode_address(&payee.pub_view, &payee.pub_spend);
+        let mut blocks = vec![funding_block(&wallet, &[50_000, 30_000, 20_000, 400])];
+        let outputs = get_spendable_outputs(&blocks, &wallet.pub_spend, &wallet.priv_view, &wallet.priv_spend);
+        assert_eq!(outputs.iter().map(|out| out.amount).sum::<u64>(), 100_400);
+        // Nobody else can read the amounts
+        assert!(get_spendable_outputs(&blocks, &wallet.pub_spend, &payee.priv_view, &wallet.priv_spend).is_empty());
+
+        // The testnet minimum relay fee is 1 per byte
+        let fee_per_kb = 1000;
+        let change_keys = (&wallet.pub_view, &wallet.pub_spend);
+        let tx = build_payment(&outputs, &wallet.priv_spend, change_keys, &to, 60_000, fee_per_kb).unwrap();
+        assert_eq!(tx.inputs.len(), 2);
+        assert!(tx.inputs[0].key_image != tx.inputs[1].key_image);
+        assert!(tx.fee >= fee_for_size(fee_per_kb, node::transaction_size(&tx)));
+        assert!(node::check_transaction(&tx));
+        let mut mempool = node::mempool::Mempool::new(1_000_000);
+        mempool.set_min_fee_per_byte(1);
+        assert!(mempool.check(&tx).is_ok());
+
+        // The payee reads its amount, the change comes back to the wallet
+        assert_eq!(reveal_amount(&tx, 0, &payee.priv_view), Some(60_000));
+        assert_eq!(reveal_amount(&tx, 1, &wallet.priv_view), Some(80_000 - 60_000 - tx.fee));
+        let mut spent_block = blocks[0].clone();
+        spent_block.transactions = vec![tx.clone()];
+        blocks.push(spent_block);
+        let left = get_spendable_outputs(&blocks, &wallet.pub_spend, &wallet.priv_view, &wallet.priv_spend);
+        assert_eq!(left.iter().map(|out| out.amount).sum::<u64>(), 100_400 - 60_000 - tx.fee);
+
+        // The fee has to come out of the outputs too
+        let exact = [funding_block(&wallet, &[60_000])];
+        let outputs = get_spendable_outputs(&exact, &wallet.pub_spend, &wallet.priv_view, &wallet.priv_spend);
+        let err = build_payment(&outputs, &wallet.priv_spend, change_keys, &to, 60_000, fee_per_kb).unwrap_err();
+        assert!(err.contains("Insufficient balance"), "{}", err);
+        let tx = build_payment(&outputs, &wallet.priv_spend, change_keys, &to, 60_000, 0).unwrap();
+        assert_eq!((tx.inputs.len(), tx.outputs.len(), tx.fee), (1, 1, 0));
+        assert!(build_payment(&outputs, &wallet.priv_spend, change_keys, "nonsense", 1, 0).is_err());
+    }
+}